@@ -3,10 +3,10 @@
 /// A target architecture supported by the BF compiler
 #[derive(Copy, Clone, Debug)]
 pub enum Architecture {
-    // /// The AArch64 (Armv8-A) architecture
-    // AArch64,
+    /// The AArch64 (Armv8-A) architecture
+    AArch64,
     /// The AMD64 (x86-64) architecture
     AMD64,
-    // /// WebAssembly
-    // WASM,
+    /// WebAssembly
+    WASM,
 }
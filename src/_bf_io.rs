@@ -0,0 +1,155 @@
+//! Defines the input and output of a running BF program
+
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Stdin, Stdout, Write};
+
+/// The input and output of a running BF program
+///
+/// The interpreter calls these methods directly, and JIT-compiled code calls them through
+/// trampolines. Like `getchar` and `putchar`, they can't fail; implementations report the end
+/// of the input for a failed read, and drop the output of a failed write.
+pub trait BfIo {
+    /// Reads the next byte of input, or returns `None` at the end of the input
+    fn read_byte(&mut self) -> Option<u8>;
+
+    /// Writes a byte of output
+    fn write_byte(&mut self, byte: u8);
+
+    /// Writes out any output that is buffered
+    fn flush(&mut self);
+}
+
+impl<T: BfIo + ?Sized> BfIo for &mut T {
+    fn read_byte(&mut self) -> Option<u8> {
+        (**self).read_byte()
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        (**self).write_byte(byte)
+    }
+
+    fn flush(&mut self) {
+        (**self).flush()
+    }
+}
+
+/// Buffered input and output over a reader and a writer
+///
+/// The output is flushed before every read that has to wait for more input, so that an
+/// interactive program's prompts show up before it waits for an answer. It is also flushed when
+/// this is dropped.
+pub struct BufferedIo<R: Read, W: Write> {
+    input: BufReader<R>,
+    output: BufWriter<W>,
+    unbuffered: bool,
+}
+
+impl<R: Read, W: Write> BufferedIo<R, W> {
+    /// Creates buffered input and output over the given reader and writer
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input: BufReader::new(input),
+            output: BufWriter::new(output),
+            unbuffered: false,
+        }
+    }
+
+    /// Sets whether every byte of output is flushed as soon as it is written
+    pub fn unbuffered(mut self, unbuffered: bool) -> Self {
+        self.unbuffered = unbuffered;
+        self
+    }
+}
+
+impl BufferedIo<Stdin, Stdout> {
+    /// Creates buffered input and output over stdin and stdout
+    pub fn stdio() -> Self {
+        Self::new(io::stdin(), io::stdout())
+    }
+}
+
+impl<R: Read, W: Write> BfIo for BufferedIo<R, W> {
+    fn read_byte(&mut self) -> Option<u8> {
+        if self.input.buffer().is_empty() {
+            self.output.flush().ok();
+        }
+        let byte = *self.input.fill_buf().ok()?.first()?;
+        self.input.consume(1);
+        Some(byte)
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.output.write_all(&[byte]).ok();
+        if self.unbuffered {
+            self.output.flush().ok();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.output.flush().ok();
+    }
+}
+
+/// Input from a slice of bytes, with the output captured in memory
+#[derive(Clone, Debug, Default)]
+pub struct MemoryIo<'a> {
+    /// The input that hasn't been read yet
+    pub input: &'a [u8],
+
+    /// Everything written so far
+    pub output: Vec<u8>,
+}
+
+impl<'a> MemoryIo<'a> {
+    /// Creates in-memory input and output that reads the given bytes
+    pub fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            output: vec![],
+        }
+    }
+}
+
+impl BfIo for MemoryIo<'_> {
+    fn read_byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.input.split_first()?;
+        self.input = rest;
+        Some(byte)
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.output.push(byte);
+    }
+
+    fn flush(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BfIo, BufferedIo, MemoryIo};
+
+    #[test]
+    fn test_memory_io() {
+        let mut io = MemoryIo::new(b"ab");
+        assert_eq!(io.read_byte(), Some(b'a'));
+        io.write_byte(b'x');
+        assert_eq!(io.read_byte(), Some(b'b'));
+        assert_eq!(io.read_byte(), None);
+        io.write_byte(b'y');
+        assert_eq!(io.output, b"xy");
+    }
+
+    #[test]
+    fn test_buffered_io() {
+        let mut output = vec![];
+        {
+            let mut io = BufferedIo::new(&b"hi"[..], &mut output);
+            assert_eq!(io.read_byte(), Some(b'h'));
+            io.write_byte(b'1');
+            assert_eq!(io.read_byte(), Some(b'i'));
+            io.write_byte(b'2');
+            assert_eq!(io.read_byte(), None);
+            io.write_byte(b'3');
+        }
+        assert_eq!(output, b"123");
+    }
+}
@@ -0,0 +1,61 @@
+//! Defines the widths of memory cells supported by the BF compiler
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// The width of each cell on the memory tape
+///
+/// Arithmetic on cells wraps around at the chosen width
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CellWidth {
+    /// 8-bit cells
+    #[default]
+    Bits8,
+    /// 16-bit cells
+    Bits16,
+    /// 32-bit cells
+    Bits32,
+}
+
+impl CellWidth {
+    /// Returns the number of bits in a cell
+    pub fn bits(&self) -> u32 {
+        match self {
+            CellWidth::Bits8 => 8,
+            CellWidth::Bits16 => 16,
+            CellWidth::Bits32 => 32,
+        }
+    }
+
+    /// Returns the number of bytes in a cell
+    pub fn bytes(&self) -> usize {
+        self.bits() as usize / 8
+    }
+
+    /// Returns the largest value that a cell can hold
+    pub fn mask(&self) -> u32 {
+        u32::MAX >> (32 - self.bits())
+    }
+}
+
+impl FromStr for CellWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(CellWidth::Bits8),
+            "16" => Ok(CellWidth::Bits16),
+            "32" => Ok(CellWidth::Bits32),
+            _ => Err(format!(
+                "invalid cell width `{}` (expected 8, 16, or 32)",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for CellWidth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.bits())
+    }
+}
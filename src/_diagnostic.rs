@@ -0,0 +1,191 @@
+//! Renders errors as diagnostics that point into the source program
+
+use crate::{input, line_start, BFError, BFParseError, BFResult, SourceLocation};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Renders a snippet of the source program with a caret under the given location,
+/// followed by the given label
+fn snippet(
+    src: &[u8],
+    filename: &str,
+    location: SourceLocation,
+    label: &str,
+    gutter: usize,
+) -> String {
+    let start = line_start(src, location.offset);
+    let end = src[start..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(src.len(), |index| start + index);
+    let line = String::from_utf8_lossy(&src[start..end]);
+    let line = line.trim_end_matches('\r');
+
+    // Tabs are kept in the padding, so that the caret lines up with the line above it
+    let padding = line
+        .chars()
+        .take(location.column - 1)
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+
+    [
+        format!(
+            "{:gutter$}--> {}:{}:{}",
+            "", filename, location.line, location.column
+        ),
+        format!("{:gutter$} |", ""),
+        format!("{:>gutter$} | {}", location.line, line),
+        format!("{:gutter$} | {}^ {}", "", padding, label),
+    ]
+    .map(|line| line.trim_end().to_string() + "\n")
+    .concat()
+}
+
+impl BFError {
+    /// Renders this error as a report that points into the given source program,
+    /// with a caret under the offending instruction
+    ///
+    /// Errors without a location in the source program are rendered as a single line
+    pub fn render(&self, src: &[u8], filename: &str) -> String {
+        // The headline, the labeled location, and a note with an optional location
+        let (headline, location, label, note) = match self {
+            BFError::ParseError(BFParseError::UnmatchedLBrace { location, partner }) => (
+                "unmatched `[`".to_string(),
+                *location,
+                "this `[` is never closed",
+                Some(("expected a matching `]` by the end of the program", Some(*partner))),
+            ),
+            BFError::ParseError(BFParseError::UnmatchedRBrace { location, partner }) => (
+                "unmatched `]`".to_string(),
+                *location,
+                "this `]` has no matching `[`",
+                partner.map(|partner| {
+                    (
+                        "this `]` may belong to the loop that starts here, which is closed too early",
+                        Some(partner),
+                    )
+                }),
+            ),
+            BFError::ParseError(BFParseError::InvalidIr { location, message }) => {
+                ("invalid IR".to_string(), *location, *message, None)
+            }
+            BFError::TapeOverflow { position, ptr } => (
+                format!("the data pointer moved off the memory tape, to cell {}", ptr),
+                SourceLocation::new(src, *position),
+                "this move left the tape",
+                None,
+            ),
+            _ => return format!("error: {}\n", self),
+        };
+
+        let lines = [Some(location), note.and_then(|note| note.1)];
+        let gutter = lines
+            .iter()
+            .flatten()
+            .map(|location| location.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+
+        let mut report = format!(
+            "error: {}\n{}",
+            headline,
+            snippet(src, filename, location, label, gutter)
+        );
+        match note {
+            Some((note, Some(location))) => {
+                report += &format!(
+                    "{:gutter$} |\nnote: {}\n{}",
+                    "",
+                    note,
+                    snippet(src, filename, location, "", gutter)
+                );
+            }
+            Some((note, None)) => report += &format!("{:gutter$} |\nnote: {}\n", "", note),
+            None => {}
+        }
+        report
+    }
+}
+
+impl SourceLocation {
+    /// Renders a snippet of the given source program with a caret under this location,
+    /// followed by the given label
+    pub fn render(&self, src: &[u8], filename: &str, label: &str) -> String {
+        snippet(src, filename, *self, label, self.line.to_string().len())
+    }
+}
+
+/// Reads the source program from the given file (or stdin) and runs the given function on it
+///
+/// Any error is printed to stderr as a report that points into the source program,
+/// and makes the process exit with status 1
+pub fn run_with_diagnostics(
+    file: Option<PathBuf>,
+    run: impl FnOnce(&[u8]) -> BFResult<()>,
+) -> ExitCode {
+    let filename = file
+        .as_ref()
+        .map_or("<stdin>".to_string(), |path| path.display().to_string());
+
+    let result = input(file).map(|src| (run(&src), src));
+    let (err, src) = match result {
+        Ok((Ok(()), _)) => return ExitCode::SUCCESS,
+        Ok((Err(err), src)) => (err, src),
+        Err(err) => (err, vec![]),
+    };
+    eprint!("{}", err.render(&src, &filename));
+    ExitCode::FAILURE
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instruction::IntermediateInstruction;
+
+    #[test]
+    fn test_render_unmatched_lbrace() {
+        let src = b"+[\n\t>[-]\n";
+        let err = IntermediateInstruction::parse_instrs(src).unwrap_err();
+        assert_eq!(
+            err.render(src, "prgm.bf"),
+            [
+                "error: unmatched `[`",
+                " --> prgm.bf:1:2",
+                "  |",
+                "1 | +[",
+                "  |  ^ this `[` is never closed",
+                "  |",
+                "note: expected a matching `]` by the end of the program",
+                " --> prgm.bf:2:6",
+                "  |",
+                "2 | \t>[-]",
+                "  | \t    ^",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_render_unmatched_rbrace() {
+        let src = b"[-]+[>]<]";
+        let err = IntermediateInstruction::parse_instrs(src).unwrap_err();
+        assert_eq!(
+            err.render(src, "prgm.bf"),
+            [
+                "error: unmatched `]`",
+                " --> prgm.bf:1:9",
+                "  |",
+                "1 | [-]+[>]<]",
+                "  |         ^ this `]` has no matching `[`",
+                "  |",
+                "note: this `]` may belong to the loop that starts here, which is closed too early",
+                " --> prgm.bf:1:5",
+                "  |",
+                "1 | [-]+[>]<]",
+                "  |     ^",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
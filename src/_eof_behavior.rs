@@ -0,0 +1,45 @@
+//! Defines the behaviors of the read instruction at the end of the input
+
+use clap::ValueEnum;
+use std::fmt::{Display, Formatter};
+
+/// What the read instruction (`,`) does to the current cell once the input is exhausted
+///
+/// BF dialects disagree on this, and programs depend on it to detect the end of the input
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum EofBehavior {
+    /// Leave the current cell unchanged
+    Unchanged,
+    /// Set the current cell to 0
+    Zero,
+    /// Set the current cell to -1 (every bit set, so 255 for 8-bit cells)
+    #[default]
+    MinusOne,
+}
+
+impl EofBehavior {
+    /// Returns the new value of a cell holding `current`, after reading the given result of
+    /// `getchar` (a byte, or -1 at the end of the input)
+    pub fn apply(&self, ch: i32, current: u32) -> u32 {
+        match (ch < 0, self) {
+            (false, _) => ch as u32,
+            (true, EofBehavior::Unchanged) => current,
+            (true, EofBehavior::Zero) => 0,
+            (true, EofBehavior::MinusOne) => u32::MAX,
+        }
+    }
+}
+
+impl Display for EofBehavior {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                EofBehavior::Unchanged => "unchanged",
+                EofBehavior::Zero => "zero",
+                EofBehavior::MinusOne => "minus-one",
+            }
+        )
+    }
+}
@@ -4,18 +4,71 @@
 
 use crate::assembly::amd64::AMD64Instruction;
 use crate::instruction::IntermediateInstruction;
+use crate::{Architecture, SourceLocation};
+use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
 
 /// Errors raised when parsing a program
 #[derive(Copy, Clone, Debug)]
 pub enum BFParseError {
     /// When a `[` doesn't have a matching `]`
-    UnmatchedLBrace(usize),
+    UnmatchedLBrace {
+        /// The location of the `[`
+        location: SourceLocation,
+
+        /// The end of the program, where a matching `]` is missing
+        partner: SourceLocation,
+    },
 
     /// When a `]` doesn't have a matching `[`
-    UnmatchedRBrace(usize),
+    UnmatchedRBrace {
+        /// The location of the `]`
+        location: SourceLocation,
+
+        /// The `[` of the last loop before the `]` that isn't nested in another loop, if any.
+        /// The `]` most likely belongs to this loop, which was closed too early
+        partner: Option<SourceLocation>,
+    },
+
+    /// When IR text doesn't parse
+    InvalidIr {
+        /// The location of the offending text
+        location: SourceLocation,
+
+        /// What is wrong there
+        message: &'static str,
+    },
+}
+
+impl BFParseError {
+    /// Returns the location of the unmatched bracket
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            BFParseError::UnmatchedLBrace { location, .. }
+            | BFParseError::UnmatchedRBrace { location, .. }
+            | BFParseError::InvalidIr { location, .. } => *location,
+        }
+    }
+}
+
+impl Display for BFParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BFParseError::UnmatchedLBrace { location, .. } => {
+                write!(f, "unmatched `[` at {}", location)
+            }
+            BFParseError::UnmatchedRBrace { location, .. } => {
+                write!(f, "unmatched `]` at {}", location)
+            }
+            BFParseError::InvalidIr { location, message } => {
+                write!(f, "invalid IR at {}: {}", location, message)
+            }
+        }
+    }
 }
 
+impl std::error::Error for BFParseError {}
+
 /// All types of BrainForge errors
 #[derive(Debug)]
 pub enum BFError {
@@ -34,12 +87,57 @@ pub enum BFError {
     /// Errors raised when generating assembly from an intermediate instruction
     GenerateError(IntermediateInstruction),
 
+    /// Errors raised when the target architecture doesn't support the requested output
+    UnsupportedArchitecture(Architecture),
+
     /// Errors raised when encoding assembly instructions
     EncodeError(AMD64Instruction),
 
     /// Errors raised when invoking LLVM
     LlvmError(String),
+
+    /// Errors raised when a checked program moves the data pointer off either end of the tape
+    TapeOverflow {
+        /// The position (character index) of the `<` or `>` instruction that overflowed
+        position: usize,
+
+        /// The cell index that the data pointer would have moved to
+        ptr: isize,
+    },
+
+    /// Errors raised when the memory for a growable tape can't be reserved,
+    /// with the size of the reservation in bytes
+    TapeAllocationError(usize),
+}
+
+impl Display for BFError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BFError::InputReadError => write!(f, "failed to read from stdin"),
+            BFError::FileReadError(path) => write!(f, "failed to read `{}`", path.display()),
+            BFError::FileWriteError(path) => write!(f, "failed to write `{}`", path.display()),
+            BFError::ParseError(err) => write!(f, "{}", err),
+            BFError::GenerateError(instr) => {
+                write!(f, "failed to generate assembly for `{:?}`", instr)
+            }
+            BFError::UnsupportedArchitecture(arch) => {
+                write!(f, "unsupported target architecture `{:?}`", arch)
+            }
+            BFError::EncodeError(instr) => write!(f, "failed to encode `{}`", instr.to_string()),
+            BFError::LlvmError(message) => write!(f, "LLVM error: {}", message),
+            BFError::TapeOverflow { position, ptr } => write!(
+                f,
+                "the data pointer moved off the memory tape, to cell {} (at offset {})",
+                ptr, position
+            ),
+            BFError::TapeAllocationError(size) => {
+                write!(f, "failed to reserve {} bytes for the memory tape", size)
+            }
+        }
+    }
 }
 
+impl std::error::Error for BFError {}
+
 /// Wrapper around [`Result`], specialized for a [`BFError`]
 pub type BFResult<T> = Result<T, BFError>;
@@ -1,5 +1,6 @@
 //! Gets the source BF program
 
+use crate::instruction::IntermediateInstruction;
 use crate::{BFError, BFResult};
 use std::fs::File;
 use std::io::{stdin, stdout, Read, Write};
@@ -40,3 +41,17 @@ pub fn output(path: &PathBuf) -> BFResult<Box<dyn Write>> {
         Ok(Box::new(file))
     }
 }
+
+/// Writes the given instructions as IR text to the given path (stdout for `-`),
+/// or to stderr if there is no path
+pub fn dump_ir(path: Option<&PathBuf>, instrs: &[IntermediateInstruction]) -> BFResult<()> {
+    let ir = IntermediateInstruction::print_ir(instrs);
+    let Some(path) = path else {
+        eprint!("{}", ir);
+        return Ok(());
+    };
+    if output(path)?.write_all(ir.as_bytes()).is_err() {
+        return Err(BFError::FileWriteError(path.clone()));
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! Locations in the source program, which instructions keep through the optimizer
+
+use std::fmt::{Display, Formatter};
+
+/// A location in the source program
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// The byte offset of the location in the source program
+    pub offset: usize,
+
+    /// The line of the location, starting at 1
+    pub line: usize,
+
+    /// The column of the location in characters, starting at 1
+    pub column: usize,
+}
+
+impl SourceLocation {
+    /// Finds the line and column of the given byte offset in the given source program
+    pub fn new(src: &[u8], offset: usize) -> Self {
+        let before = &src[..offset.min(src.len())];
+        let line_start = line_start(src, offset);
+        Self {
+            offset,
+            line: before.iter().filter(|&&byte| byte == b'\n').count() + 1,
+            column: count_chars(&before[line_start..]) + 1,
+        }
+    }
+
+    /// Returns the location of the end of the given source program, before any trailing newline
+    pub fn end(src: &[u8]) -> Self {
+        Self::new(src, src.strip_suffix(b"\n").unwrap_or(src).len())
+    }
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Returns the byte offset of the start of the line containing the given byte offset
+pub(crate) fn line_start(src: &[u8], offset: usize) -> usize {
+    src[..offset.min(src.len())]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |index| index + 1)
+}
+
+/// Counts the UTF-8 characters in the given bytes
+fn count_chars(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|&&byte| byte & 0xC0 != 0x80).count()
+}
+
+/// A range of the source program that an instruction was parsed from
+///
+/// Instructions that the optimizer combines cover the union of their spans
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    /// The location of the first character in the range
+    pub start: SourceLocation,
+
+    /// The byte offset just past the last character in the range
+    pub end: usize,
+}
+
+impl Span {
+    /// Creates a span of the given number of bytes, starting at the given location
+    pub fn new(start: SourceLocation, len: usize) -> Self {
+        Self {
+            start,
+            end: start.offset + len,
+        }
+    }
+
+    /// Returns the smallest span that covers both this span and the given span
+    pub fn merge(self, other: Span) -> Self {
+        Self {
+            start: if other.start.offset < self.start.offset {
+                other.start
+            } else {
+                self.start
+            },
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.start.line, self.start.column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SourceLocation, Span};
+
+    #[test]
+    fn test_source_location() {
+        let src = "+\n++[\n\t→>".as_bytes();
+        assert_eq!(
+            SourceLocation::new(src, 4),
+            SourceLocation {
+                offset: 4,
+                line: 2,
+                column: 3
+            }
+        );
+        assert_eq!(SourceLocation::new(src, 10).column, 3);
+        assert_eq!(SourceLocation::end(b"+\n").column, 2);
+    }
+
+    #[test]
+    fn test_merge_spans() {
+        let src = b"+[-]\n>>";
+        let left = Span::new(SourceLocation::new(src, 1), 3);
+        let right = Span::new(SourceLocation::new(src, 5), 2);
+        let merged = right.merge(left);
+        assert_eq!(merged.start, left.start);
+        assert_eq!(merged.end, 7);
+        assert_eq!(merged.to_string(), "1:2");
+    }
+}
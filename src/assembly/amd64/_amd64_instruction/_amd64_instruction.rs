@@ -1,18 +1,44 @@
-use crate::assembly::amd64::{AMD64Operand, AMD64Register, MemorySize, ModRM, Rex, Sib};
+use crate::assembly::amd64::{AMD64Operand, AMD64Register, MemorySize, ModRM, Rex, Sib, Vex};
 use crate::instruction::IntermediateInstruction;
 
-use crate::{BFError, BFResult};
+use crate::{BFError, BFResult, CellWidth, EofBehavior};
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 
 /// Represents a callable function from the C standard library
 #[derive(Copy, Clone, Debug)]
 pub enum Function {
+    /// `calloc` from the C standard library
+    Calloc,
+
     /// `getchar` from the C standard library
     GetChar,
 
     /// `putchar` from the C standard library
     PutChar,
+
+    /// `exit` from the C standard library
+    Exit,
+
+    /// The runtime routine that reports a failed bounds check and exits
+    ///
+    /// Takes the position of the instruction in `RDI`, and the cell index in `RSI`
+    TapeOverflow,
+
+    /// The runtime routine that writes out the buffered output
+    Flush,
+
+    /// The runtime routine that writes out the buffered output, then calls `getchar`
+    BufferedGetChar,
+
+    /// The runtime routine that appends a byte to the buffered output
+    BufferedPutChar,
+
+    /// The JIT's routine that grows a growable tape, or reports a failed bounds check
+    /// if the tape can't grow
+    ///
+    /// Takes the same arguments as `TapeOverflow`, but returns if the tape grew
+    GrowTape,
 }
 
 impl Display for Function {
@@ -21,8 +47,15 @@ impl Display for Function {
             f,
             "{}",
             match self {
+                Function::Calloc => "calloc",
                 Function::GetChar => "getchar",
                 Function::PutChar => "putchar",
+                Function::Exit => "exit",
+                Function::TapeOverflow => "bf_tape_overflow",
+                Function::Flush => "bf_flush",
+                Function::BufferedGetChar => "bf_getchar",
+                Function::BufferedPutChar => "bf_putchar",
+                Function::GrowTape => "bf_grow_tape",
             }
         )
     }
@@ -33,6 +66,8 @@ impl Display for Function {
 pub enum AMD64Instruction {
     /// `call <function>`
     Call(Function),
+    /// `jae <offset>`
+    Jae(isize, Option<String>),
     /// `je <offset>`
     Je(isize, Option<String>),
     /// `jmp <offset>`
@@ -50,10 +85,16 @@ pub enum AMD64Instruction {
     Bsr(AMD64Operand, AMD64Operand),
     /// `cmp <dst>, <src>`
     Cmp(AMD64Operand, AMD64Operand),
+    /// `div <src>`
+    Div(AMD64Operand),
     /// `imul <dst>, <src>`
     Imul(AMD64Operand, AMD64Operand),
     /// `not <dst>`
     Not(AMD64Operand),
+    /// `sar <dst>, <src>`
+    Sar(AMD64Operand, AMD64Operand),
+    /// `sub <dst>, <src>`
+    Sub(AMD64Operand, AMD64Operand),
     /// `test <op1>, <op2>`
     Test(AMD64Operand, AMD64Operand),
     /// `xor <dst>, <src>`
@@ -85,13 +126,23 @@ pub enum AMD64Instruction {
     Pop(AMD64Operand),
     /// `ret`
     Ret(),
+    /// `syscall`
+    Syscall(),
+
+    /// `# <text>`, a comment that is encoded as no bytes
+    Comment(String),
 }
 
 use AMD64Instruction::*;
 
 impl AMD64Instruction {
-    /// Converts a single abstract BF instruction into a vector of assembly instructions
-    fn convert_instruction(instr: &IntermediateInstruction) -> Vec<AMD64Instruction> {
+    /// Converts a single abstract BF instruction into a vector of assembly instructions,
+    /// headed by a comment with the instruction's position in the source program
+    fn convert_instruction(
+        instr: &IntermediateInstruction,
+        cell_width: CellWidth,
+        eof_behavior: EofBehavior,
+    ) -> Vec<AMD64Instruction> {
         use AMD64Instruction::*;
         use AMD64Operand::*;
         use AMD64Register::*;
@@ -104,59 +155,114 @@ impl AMD64Instruction {
             Memory(size, Some(base), None, None, Some(offset))
         };
 
+        let cell_size = match cell_width {
+            CellWidth::Bits8 => MemorySize::Byte,
+            CellWidth::Bits16 => MemorySize::Word,
+            CellWidth::Bits32 => MemorySize::DWord,
+        };
+        let cell_bytes = cell_width.bytes() as i32;
+        // Wraps an immediate value to the cell width
+        let cell_imm = |val: i32| match cell_width {
+            CellWidth::Bits8 => imm(val as i8 as isize),
+            CellWidth::Bits16 => imm(val as i16 as isize),
+            CellWidth::Bits32 => imm(val as isize),
+        };
+
         let mem_pos = reg(R12);
-        let mem_val = memory(Some(MemorySize::Byte), R12, 0);
+        let mem_val = memory(Some(cell_size), R12, 0);
+        // The cell that the instruction acts on
+        let cell_val = memory(Some(cell_size), R12, instr.cell_offset() * cell_bytes);
 
-        match instr {
-            Loop(instrs) => {
-                let body = Self::convert_instructions(instrs).concat();
+        let code = match instr {
+            Loop(_, instrs, _) => {
+                let body = Self::convert_instructions(instrs, cell_width, eof_behavior).concat();
                 let body_len = body.len();
                 vec![
-                    // If the current cell's value is zero,
+                    // If the loop's cell's value is zero,
                     // jump *over* the body *and* the following loop condition
-                    vec![Cmp(mem_val, imm(0)), Je(body_len as isize + 2, None)],
+                    vec![Cmp(cell_val, imm(0)), Je(body_len as isize + 2, None)],
                     body,
-                    // If the current cell's value is zero,
+                    // If the loop's cell's value is zero,
                     // jump back to the beginning of the body
-                    vec![Cmp(mem_val, imm(0)), Jne(-(body_len as isize + 2), None)],
+                    vec![Cmp(cell_val, imm(0)), Jne(-(body_len as isize + 2), None)],
                 ]
                 .concat()
             }
 
-            Move(offset) => {
-                vec![AMD64Instruction::Add(mem_pos, imm(*offset as isize))]
+            Move(offset, _) => {
+                vec![AMD64Instruction::Add(
+                    mem_pos,
+                    imm((*offset * cell_bytes) as isize),
+                )]
             }
 
-            IntermediateInstruction::Add(offset) => {
-                vec![AMD64Instruction::Add(mem_val, imm(*offset as isize))]
+            IntermediateInstruction::Add(_, delta, _) => {
+                vec![AMD64Instruction::Add(cell_val, cell_imm(*delta))]
             }
 
-            Read => {
+            // `getchar` returns -1 at the end of the input, in which case the value in `EBX`
+            // is stored instead
+            Read(_, _) => {
+                let eof_value = match (eof_behavior, cell_width) {
+                    (EofBehavior::Unchanged, CellWidth::Bits32) => Mov(Register(EBX), cell_val),
+                    (EofBehavior::Unchanged, _) => Movzx(Register(EBX), cell_val),
+                    (EofBehavior::Zero, _) => Mov(Register(EBX), Immediate(0)),
+                    (EofBehavior::MinusOne, _) => Mov(Register(EBX), Immediate(-1)),
+                };
                 vec![
                     Call(GetChar),
                     Cmp(Register(EAX), Immediate(0)),
-                    Mov(Register(EBX), Immediate(-1)),
+                    eof_value,
                     Cmovge(Register(EBX), Register(EAX)),
-                    Mov(mem_val, reg(BL)),
+                    Mov(
+                        cell_val,
+                        reg(match cell_width {
+                            CellWidth::Bits8 => BL,
+                            CellWidth::Bits16 => BX,
+                            CellWidth::Bits32 => EBX,
+                        }),
+                    ),
                 ]
             }
 
-            Write => vec![
-                Xor(reg(RDI), reg(RDI)),
-                Mov(reg(DIL), mem_val),
-                Call(PutChar),
-            ],
-
-            AddDynamic(target, multiplier) => {
-                vec![
-                    Movzx(reg(R13D), mem_val),
-                    Imul(reg(R13D), imm(*multiplier as isize)),
-                    AMD64Instruction::Add(memory(None, R12, *target), reg(R13B)),
-                ]
+            Write(_, _) => match cell_width {
+                CellWidth::Bits8 => vec![
+                    Xor(reg(RDI), reg(RDI)),
+                    Mov(reg(DIL), cell_val),
+                    Call(PutChar),
+                ],
+                CellWidth::Bits16 => vec![Movzx(reg(EDI), cell_val), Call(PutChar)],
+                CellWidth::Bits32 => vec![Mov(reg(EDI), cell_val), Call(PutChar)],
+            },
+
+            Output(bytes, _) => bytes
+                .iter()
+                .flat_map(|byte| [Mov(reg(EDI), imm(*byte as isize)), Call(PutChar)])
+                .collect(),
+
+            AddDynamic(target, multiplier, _) => {
+                let target = memory(None, R12, *target * cell_bytes);
+                match cell_width {
+                    CellWidth::Bits8 => vec![
+                        Movzx(reg(R13D), mem_val),
+                        Imul(reg(R13D), imm(*multiplier as isize)),
+                        AMD64Instruction::Add(target, reg(R13B)),
+                    ],
+                    CellWidth::Bits16 => vec![
+                        Movzx(reg(R13D), mem_val),
+                        Imul(reg(R13D), imm(*multiplier as isize)),
+                        AMD64Instruction::Add(target, reg(R13W)),
+                    ],
+                    CellWidth::Bits32 => vec![
+                        Mov(reg(R13D), mem_val),
+                        Imul(reg(R13D), imm(*multiplier as isize)),
+                        AMD64Instruction::Add(target, reg(R13D)),
+                    ],
+                }
             }
 
-            SimpleLoop(instrs) => {
-                let body = Self::convert_instructions(instrs).concat();
+            SimpleLoop(instrs, _) => {
+                let body = Self::convert_instructions(instrs, cell_width, eof_behavior).concat();
                 vec![
                     // Jump *over* the simple loop if the current cell's value is zero
                     vec![Cmp(mem_val, imm(0)), Je(body.len() as isize, None)],
@@ -165,11 +271,29 @@ impl AMD64Instruction {
                 .concat()
             }
 
-            Zero => {
-                vec![Mov(mem_val, imm(0))]
+            Zero(_, _) => {
+                vec![Mov(cell_val, imm(0))]
+            }
+
+            Set(_, value, _) => {
+                vec![Mov(cell_val, cell_imm(*value))]
+            }
+
+            // Vectorized scans compare bytes, and only strides of 1, 2 and 4 line up with the
+            // vector lanes, so wider cells and other strides are checked one cell at a time
+            Scan(stride, _)
+                if cell_width != CellWidth::Bits8
+                    || !matches!(stride, -4 | -2 | -1 | 1 | 2 | 4) =>
+            {
+                vec![
+                    Cmp(mem_val, imm(0)),
+                    Je(2, None),
+                    AMD64Instruction::Add(mem_pos, imm((*stride * cell_bytes) as isize)),
+                    Jmp(-4, None),
+                ]
             }
 
-            Scan(stride) => {
+            Scan(stride, _) => {
                 // Perform a "memory scan" of the given stride (+-1, +-2, or +-4)
                 // starting at address `R12`. Forwards if `stride` > 0, backwards if `stride` < 0.
                 // The memory "offset" is saved in `RCX`, and address `R12 + RCX` is the start
@@ -197,13 +321,13 @@ impl AMD64Instruction {
                         Vpmovmskb(reg(EDX), reg(YMM1)),
                     ],
                     // Maybe instruction 5 -- Mask `EDX` depending on the stride
-                    match stride.abs() {
+                    match stride {
                         -4 => vec![And(reg(EDX), imm(0x88888888))],
                         -2 => vec![And(reg(EDX), imm(0xAAAAAAAA))],
                         -1 | 1 => vec![],
                         2 => vec![And(reg(EDX), imm(0x55555555))],
                         4 => vec![And(reg(EDX), imm(0x11111111))],
-                        other => panic!("Invalid scan stride: {}", other),
+                        other => unreachable!("Invalid scan stride: {}", other),
                     },
                     vec![
                         // Instruction 5 or 6 -- Test `EDX` to see whether there were any zero bytes
@@ -228,7 +352,74 @@ impl AMD64Instruction {
                 ]
                 .concat()
             }
-        }
+
+            // The tape starts at address `R14` and is `R15` bytes long. Byte offsets from the
+            // start of the tape are compared as unsigned values, so negative offsets are too large
+            CheckBounds(probes, _) => {
+                // Sets `RSI` to the byte offset of the probed cell, and compares it against `R15`
+                let probe = |offset: i32| {
+                    vec![
+                        Lea(reg(RSI), memory(None, R12, offset * cell_bytes)),
+                        Sub(reg(RSI), reg(R14)),
+                        Cmp(reg(RSI), reg(R15)),
+                    ]
+                };
+
+                // Reports the probed cell as a cell index. The report is followed by a jump back
+                // to the first probe, since the JIT's growable tape returns after growing
+                let shift = match cell_width {
+                    CellWidth::Bits8 => vec![],
+                    CellWidth::Bits16 => vec![Sar(reg(RSI), imm(1))],
+                    CellWidth::Bits32 => vec![Sar(reg(RSI), imm(2))],
+                };
+                let report_len = shift.len() + 2;
+
+                // Checks each probe in order, with its position in `EDI`,
+                // until one is off the tape
+                let slow = [
+                    probes
+                        .iter()
+                        .enumerate()
+                        .map(|(index, (offset, position))| {
+                            [
+                                vec![Mov(reg(EDI), imm(*position as isize))],
+                                probe(*offset),
+                                vec![Jae((5 * (probes.len() - 1 - index) + 1) as isize, None)],
+                            ]
+                            .concat()
+                        })
+                        .collect::<Vec<Vec<AMD64Instruction>>>()
+                        .concat(),
+                    vec![Jmp(report_len as isize, None)],
+                ]
+                .concat();
+
+                // With more probes, only the lowest and highest are checked, unless one fails
+                let fast = if probes.len() > 2 {
+                    let min = probes.iter().map(|probe| probe.0).min().unwrap_or(0);
+                    let max = probes.iter().map(|probe| probe.0).max().unwrap_or(0);
+                    [
+                        probe(min),
+                        vec![Jae(5, None)],
+                        probe(max),
+                        vec![Jae(1, None), Jmp((slow.len() + report_len) as isize, None)],
+                    ]
+                    .concat()
+                } else {
+                    vec![]
+                };
+
+                let check_len = fast.len() + slow.len() + report_len;
+                [
+                    fast,
+                    slow,
+                    shift,
+                    vec![Call(TapeOverflow), Jmp(-(check_len as isize), None)],
+                ]
+                .concat()
+            }
+        };
+        [vec![Comment(instr.span().to_string())], code].concat()
     }
 
     /// Converts a set of abstract BF instructions to a vector of blocks of assembly instructions
@@ -236,19 +427,27 @@ impl AMD64Instruction {
     /// Important note: all jump instructions must be patched. The offset field, rather than
     /// encoding a label name or number of displacement bytes, reports the number of instructions
     /// that must be jumped over.
-    pub fn convert_instructions(instrs: &[IntermediateInstruction]) -> Vec<Vec<AMD64Instruction>> {
+    pub fn convert_instructions(
+        instrs: &[IntermediateInstruction],
+        cell_width: CellWidth,
+        eof_behavior: EofBehavior,
+    ) -> Vec<Vec<AMD64Instruction>> {
         let mut blocks = vec![];
         let mut current_basic_block = vec![];
 
         for instr in instrs {
-            if matches!(instr, IntermediateInstruction::Loop(_)) {
+            if matches!(instr, IntermediateInstruction::Loop(..)) {
                 if !current_basic_block.is_empty() {
                     blocks.push(current_basic_block);
                     current_basic_block = vec![];
                 }
-                blocks.push(Self::convert_instruction(instr));
+                blocks.push(Self::convert_instruction(instr, cell_width, eof_behavior));
             } else {
-                current_basic_block.extend(Self::convert_instruction(instr));
+                current_basic_block.extend(Self::convert_instruction(
+                    instr,
+                    cell_width,
+                    eof_behavior,
+                ));
             }
         }
         if !current_basic_block.is_empty() {
@@ -260,6 +459,20 @@ impl AMD64Instruction {
     /// Converts an abstract BF instruction to a vector of strings of assembly instructions
     pub fn bf_to_assembly(
         instr: &IntermediateInstruction,
+        cell_width: CellWidth,
+        eof_behavior: EofBehavior,
+        label_counter: &mut usize,
+    ) -> Vec<String> {
+        Self::block_to_assembly(
+            Self::convert_instruction(instr, cell_width, eof_behavior),
+            label_counter,
+        )
+    }
+
+    /// Converts a block of assembly instructions to a vector of strings, replacing the offset of
+    /// each jump instruction with a label
+    pub fn block_to_assembly(
+        mut instrs: Vec<AMD64Instruction>,
         label_counter: &mut usize,
     ) -> Vec<String> {
         let mut mk_label = || {
@@ -268,18 +481,17 @@ impl AMD64Instruction {
             format!(".label_{}", result)
         };
 
-        let mut instrs = Self::convert_instruction(instr);
-
         let mut labels: HashMap<usize, String> = HashMap::new();
 
+        // Jumps to the same target share a label
         let mut apply_label = |index: usize, offset: isize, label: &mut Option<String>| {
-            let new_label = mk_label();
-            *label = Some(new_label.clone());
-            labels.insert(((index as isize) + offset + 1) as usize, new_label);
+            let target = ((index as isize) + offset + 1) as usize;
+            *label = Some(labels.entry(target).or_insert_with(&mut mk_label).clone());
         };
 
         for index in 0..instrs.len() {
             match &mut instrs[index] {
+                Jae(offset, label) => apply_label(index, *offset, label),
                 Je(offset, label) => apply_label(index, *offset, label),
                 Jmp(offset, label) => apply_label(index, *offset, label),
                 Jne(offset, label) => apply_label(index, *offset, label),
@@ -311,22 +523,21 @@ impl AMD64Instruction {
             .map(|instr| instr.to_binary())
             .collect::<BFResult<Vec<Vec<u8>>>>()?;
 
+        // The byte offset of each instruction in the block. Jumps always take a 32-bit
+        // displacement, so their size doesn't depend on the displacement
+        let mut starts = vec![0];
+        for instr_bytes in &bytes {
+            starts.push(starts[starts.len() - 1] + instr_bytes.len() as isize);
+        }
+
         for index in 0..instrs.len() {
-            let byte_displacement = |offset: &isize| {
-                let index = index as isize;
-                let (sign, from, to) = if *offset > 0 {
-                    (1, index + 1, index + offset)
-                } else {
-                    (-1, index + offset + 1, index)
-                };
-                let size: usize = bytes[(from as usize)..=(to as usize)]
-                    .iter()
-                    .map(|bv| bv.len())
-                    .sum();
-                sign * (size as isize)
-            };
+            let byte_displacement =
+                |offset: &isize| starts[(index as isize + offset + 1) as usize] - starts[index + 1];
             let instr = &instrs[index];
             match instr {
+                Jae(offset, _) => {
+                    bytes[index] = Jae(byte_displacement(offset), None).to_binary()?
+                }
                 Jmp(offset, _) => {
                     bytes[index] = Jmp(byte_displacement(offset), None).to_binary()?
                 }
@@ -379,6 +590,53 @@ impl AMD64Instruction {
         Ok(rex.as_byte())
     }
 
+    /// Encodes a VEX prefix for 256-bit SIMD instructions
+    ///
+    /// `reg` is encoded in the ModR/M `reg` field, `vvvv` is the extra source register (if any),
+    /// and `rm` is encoded in the ModR/M `r/m` field. `pp` is the implied legacy prefix.
+    pub(crate) fn encode_vex(
+        &self,
+        reg: Option<&AMD64Operand>,
+        vvvv: Option<&AMD64Operand>,
+        rm: Option<&AMD64Operand>,
+        pp: u8,
+    ) -> BFResult<Vec<u8>> {
+        let mut vex = Vex::new();
+        vex.pp = pp;
+        match reg {
+            Some(AMD64Operand::Register(reg)) => {
+                if reg.id() > 7 {
+                    vex.r();
+                }
+            }
+            None => {}
+            _ => return self.encoding_err(),
+        }
+        match vvvv {
+            Some(AMD64Operand::Register(reg)) => vex.reg = Some(*reg),
+            None => {}
+            _ => return self.encoding_err(),
+        }
+        match rm {
+            Some(AMD64Operand::Register(b_reg)) => {
+                if b_reg.id() > 7 {
+                    vex.b();
+                }
+            }
+            Some(AMD64Operand::Memory(_, b_reg, x_reg, _, _)) => {
+                if b_reg.is_some_and(|b_reg| b_reg.id() > 7) {
+                    vex.b();
+                }
+                if x_reg.is_some_and(|x_reg| x_reg.id() > 7) {
+                    vex.x();
+                }
+            }
+            None => {}
+            _ => return self.encoding_err(),
+        }
+        vex.as_byte().or_else(|_| self.encoding_err())
+    }
+
     /// Encodes the ModR/M byte, SIB byte, and immediate bytes for binary instructions
     pub(crate) fn encode_reg_rmi(
         &self,
@@ -524,6 +782,9 @@ impl AMD64Instruction {
         use AMD64Instruction::*;
         match self {
             Call(func) => format!("call {}", func),
+            Jae(displacement, label) => {
+                format!("jae {}", label.clone().unwrap_or(displacement.to_string()))
+            }
             Je(displacement, label) => {
                 format!("je {}", label.clone().unwrap_or(displacement.to_string()))
             }
@@ -539,8 +800,11 @@ impl AMD64Instruction {
             Bsf(dst, src) => format!("bsf {}, {}", dst, src),
             Bsr(dst, src) => format!("bsr {}, {}", dst, src),
             Cmp(dst, src) => format!("cmp {}, {}", dst, src),
+            Div(src) => format!("div {}", src),
             Imul(dst, src) => format!("imul {}, {}", dst, src),
             Not(dst) => format!("not {}", dst),
+            Sar(dst, src) => format!("sar {}, {}", dst, src),
+            Sub(dst, src) => format!("sub {}, {}", dst, src),
             Test(op1, op2) => format!("test {}, {}", op1, op2),
             Xor(dst, src) => format!("xor {}, {}", dst, src),
 
@@ -562,6 +826,9 @@ impl AMD64Instruction {
             Push(src) => format!("push {}", src),
             Pop(dst) => format!("pop {}", dst),
             Ret() => "ret".into(),
+            Syscall() => "syscall".into(),
+
+            Comment(text) => format!("# {}", text),
         }
     }
 
@@ -571,6 +838,7 @@ impl AMD64Instruction {
 
         match self {
             Call(func) => self.encode_call(func),
+            Jae(displacement, _) => self.encode_jae(*displacement),
             Je(displacement, _) => self.encode_je(*displacement),
             Jmp(displacement, _) => self.encode_jmp(*displacement),
             Jne(displacement, _) => self.encode_jne(*displacement),
@@ -580,8 +848,11 @@ impl AMD64Instruction {
             Bsf(dst, src) => self.encode_bsf(dst, src),
             Bsr(dst, src) => self.encode_bsr(dst, src),
             Cmp(dst, src) => self.encode_cmp(dst, src),
+            Div(src) => self.encode_div(src),
             Imul(dst, src) => self.encode_imul(dst, src),
             Not(dst) => self.encode_not(dst),
+            Sar(dst, src) => self.encode_sar(dst, src),
+            Sub(dst, src) => self.encode_sub(dst, src),
             Test(op1, op2) => self.encode_test(op1, op2),
             Xor(dst, src) => self.encode_xor(dst, src),
 
@@ -599,6 +870,9 @@ impl AMD64Instruction {
             Push(src) => self.encode_push(src),
             Pop(dst) => self.encode_pop(dst),
             Ret() => Ok(vec![0xC3]),
+            Syscall() => Ok(vec![0x0F, 0x05]),
+
+            Comment(_) => Ok(vec![]),
         }
     }
 }
@@ -72,7 +72,12 @@ impl AMD64Instruction {
                     (_, _) => 0x81,
                 };
 
-                let rmi = self.encode_reg_rmi(None, Some(dst), dst_reg.size())?;
+                // The accumulator's short forms have no ModRM byte
+                let rmi = if dst_reg.id() == 0 {
+                    vec![]
+                } else {
+                    self.encode_reg_rmi(None, Some(dst), dst_reg.size())?
+                };
 
                 let imm = self.encode_imm(*imm, dst_reg.size().min(32))?;
 
@@ -567,6 +572,31 @@ pub mod tests {
         run_tests(tests);
     }
 
+    #[test]
+    fn test_encode_add_reg_imm() {
+        let tests: Tests = vec![
+            (
+                Add(Register(RAX), Immediate(1)),
+                vec![0x48, 0x05, 0x01, 0x00, 0x00, 0x00],
+            ),
+            (
+                Add(Register(EAX), Immediate(1)),
+                vec![0x05, 0x01, 0x00, 0x00, 0x00],
+            ),
+            (Add(Register(AL), Immediate(0x30)), vec![0x04, 0x30]),
+            (Add(Register(DL), Immediate(0x30)), vec![0x80, 0xC2, 0x30]),
+            (
+                Add(Register(RBX), Immediate(-1)),
+                vec![0x48, 0x81, 0xC3, 0xFF, 0xFF, 0xFF, 0xFF],
+            ),
+            (
+                Add(Register(R8), Immediate(1)),
+                vec![0x49, 0x81, 0xC0, 0x01, 0x00, 0x00, 0x00],
+            ),
+        ];
+        run_tests(tests);
+    }
+
     #[test]
     fn test_encode_add_mem_reg() {
         let tests: Tests = vec![
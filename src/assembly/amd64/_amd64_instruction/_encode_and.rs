@@ -1,6 +1,7 @@
-use crate::assembly::amd64::{AMD64Instruction, AMD64Operand};
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
 use crate::BFResult;
 
+use AMD64Operand::*;
 
 impl AMD64Instruction {
     pub(crate) fn encode_and(
@@ -8,18 +9,66 @@ impl AMD64Instruction {
         dst: &AMD64Operand,
         src: &AMD64Operand,
     ) -> BFResult<Vec<u8>> {
-        todo!()
+        match (dst, src) {
+            // and <reg>, <reg>
+            (Register(dst_reg), Register(src_reg)) => {
+                if dst_reg.size() != src_reg.size() {
+                    return self.encoding_err();
+                }
+
+                let prefix_reg_16 = (dst_reg.size() == 16).then_some(0x66);
+
+                let rex = self.encode_rex(Some(src), Some(dst))?;
+
+                let opcode: u8 = if dst_reg.size() == 8 { 0x20 } else { 0x21 };
+
+                let rmi = self.encode_reg_rmi(Some(src), Some(dst), dst_reg.size())?;
+
+                Ok(vec![prefix_reg_16, rex, Some(opcode)]
+                    .into_iter()
+                    .flatten()
+                    .chain(rmi)
+                    .collect())
+            }
+
+            // and <reg>, <imm>
+            (Register(dst_reg), Immediate(imm)) => {
+                let prefix_reg_16 = (dst_reg.size() == 16).then_some(0x66);
+
+                let rex = self.encode_rex(None, Some(dst))?;
+
+                let rmi = self.encode_reg_rmi(
+                    Some(&Register(AMD64Register::RSP)),
+                    Some(dst),
+                    dst_reg.size(),
+                )?;
+
+                let (opcode, imm): (u8, Vec<u8>) = match (dst_reg.size(), *imm) {
+                    (8, _) => (0x80, self.encode_imm(*imm, 8)?),
+                    (_, -0x80..0x80) => (0x83, self.encode_imm(*imm, 8)?),
+                    (_, _) => (0x81, self.encode_imm(*imm, dst_reg.size().min(32))?),
+                };
+
+                Ok(vec![prefix_reg_16, rex, Some(opcode)]
+                    .into_iter()
+                    .flatten()
+                    .chain(rmi)
+                    .chain(imm)
+                    .collect())
+            }
+
+            (_, _) => self.encoding_err(),
+        }
     }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register, MemorySize};
+    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
 
     use AMD64Instruction::*;
     use AMD64Operand::*;
     use AMD64Register::*;
-    use MemorySize::*;
 
     type Tests = Vec<(AMD64Instruction, Vec<u8>)>;
 
@@ -32,10 +81,38 @@ pub mod tests {
         }
     }
 
-    // #[test]
-    // fn test_encode_and_reg_reg() {
-    //     let tests: Tests = vec![
-    //     ];
-    //     run_tests(tests);
-    // }
+    #[test]
+    fn test_encode_and_reg_reg() {
+        let tests: Tests = vec![
+            (And(Register(CL), Register(DL)), vec![0x20, 0xD1]),
+            (And(Register(ECX), Register(EDX)), vec![0x21, 0xD1]),
+            (And(Register(RAX), Register(RCX)), vec![0x48, 0x21, 0xC8]),
+            (And(Register(R13D), Register(R8D)), vec![0x45, 0x21, 0xC5]),
+        ];
+        run_tests(tests);
+    }
+
+    #[test]
+    fn test_encode_and_reg_imm() {
+        let tests: Tests = vec![
+            (And(Register(ECX), Immediate(0x11)), vec![0x83, 0xE1, 0x11]),
+            (
+                And(Register(RAX), Immediate(0x11)),
+                vec![0x48, 0x83, 0xE0, 0x11],
+            ),
+            (
+                And(Register(EDX), Immediate(0x55555555)),
+                vec![0x81, 0xE2, 0x55, 0x55, 0x55, 0x55],
+            ),
+            (
+                And(Register(EDX), Immediate(0x88888888)),
+                vec![0x81, 0xE2, 0x88, 0x88, 0x88, 0x88],
+            ),
+            (
+                And(Register(R13D), Immediate(0x11111111)),
+                vec![0x41, 0x81, 0xE5, 0x11, 0x11, 0x11, 0x11],
+            ),
+        ];
+        run_tests(tests);
+    }
 }
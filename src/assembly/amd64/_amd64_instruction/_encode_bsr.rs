@@ -1,12 +1,70 @@
 use crate::assembly::amd64::{AMD64Instruction, AMD64Operand};
 use crate::BFResult;
 
+use AMD64Operand::*;
+
 impl AMD64Instruction {
     pub(crate) fn encode_bsr(
         self: &AMD64Instruction,
         dst: &AMD64Operand,
         src: &AMD64Operand,
     ) -> BFResult<Vec<u8>> {
-        todo!()
+        match (dst, src) {
+            // bsr <reg>, <reg>
+            (Register(dst_reg), Register(src_reg)) => {
+                if dst_reg.size() != src_reg.size() || dst_reg.size() == 8 {
+                    return self.encoding_err();
+                }
+
+                let prefix_reg_16 = (dst_reg.size() == 16).then_some(0x66);
+
+                let rex = self.encode_rex(Some(dst), Some(src))?;
+
+                let opcode: Vec<u8> = vec![0x0F, 0xBD];
+
+                let rmi = self.encode_reg_rmi(Some(dst), Some(src), dst_reg.size())?;
+
+                Ok(vec![prefix_reg_16, rex]
+                    .into_iter()
+                    .flatten()
+                    .chain(opcode)
+                    .chain(rmi)
+                    .collect())
+            }
+
+            (_, _) => self.encoding_err(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
+
+    use AMD64Instruction::*;
+    use AMD64Operand::*;
+    use AMD64Register::*;
+
+    type Tests = Vec<(AMD64Instruction, Vec<u8>)>;
+
+    fn run_tests(tests: Tests) {
+        for (instruction, expected) in tests {
+            match instruction.to_binary() {
+                Ok(actual) => assert_eq!(actual, expected, "{}", instruction.to_string()),
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_bsr_reg_reg() {
+        let tests: Tests = vec![
+            (Bsr(Register(EDX), Register(EDX)), vec![0x0F, 0xBD, 0xD2]),
+            (
+                Bsr(Register(R9D), Register(EAX)),
+                vec![0x44, 0x0F, 0xBD, 0xC8],
+            ),
+        ];
+        run_tests(tests);
     }
 }
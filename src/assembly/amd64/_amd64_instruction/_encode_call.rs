@@ -1,12 +1,49 @@
 use crate::assembly::amd64::{AMD64Instruction, Function};
 use crate::BFResult;
 
+/// The size of an encoded `call` instruction
+///
+/// `call <function>` is encoded as `mov rax, <address>; call rax`
+const CALL_SIZE: usize = 12;
+
 impl AMD64Instruction {
+    /// Finds every `call` instruction in the given block
+    ///
+    /// Returns the byte offset of each call within the encoded block,
+    /// so that the call can be patched after encoding
+    pub fn call_sites(instrs: &[AMD64Instruction]) -> BFResult<Vec<(usize, Function)>> {
+        let mut offset = 0;
+        let mut sites = vec![];
+        for instr in instrs {
+            if let AMD64Instruction::Call(func) = instr {
+                sites.push((offset, *func));
+            }
+            offset += instr.to_binary()?.len();
+        }
+        Ok(sites)
+    }
+
+    /// Patches the encoded `call` at the given offset to call the given absolute address
+    pub fn patch_call_absolute(bytes: &mut [u8], offset: usize, address: u64) {
+        bytes[offset + 2..offset + 10].copy_from_slice(&address.to_le_bytes());
+    }
+
+    /// Patches the encoded `call` at the given offset into a relative `call rel32`
+    ///
+    /// The instruction is padded with a `nop` so that its size doesn't change.
+    /// Returns the offset of the 4-byte displacement, which is relative to the end of the
+    /// `call rel32` (i.e., 4 bytes past the displacement)
+    pub fn patch_call_relative(bytes: &mut [u8], offset: usize, displacement: i32) -> usize {
+        let mut patch = vec![0xE8];
+        patch.extend(displacement.to_le_bytes());
+        // 7-byte `nop dword ptr [rax + 0]`
+        patch.extend([0x0F, 0x1F, 0x80, 0x00, 0x00, 0x00, 0x00]);
+        bytes[offset..offset + CALL_SIZE].copy_from_slice(&patch);
+        offset + 1
+    }
+
     pub(crate) fn encode_call(self: &AMD64Instruction, tgt: &Function) -> BFResult<Vec<u8>> {
-        let tgt_name = match tgt {
-            Function::GetChar => std::ffi::CString::new("getchar").unwrap(),
-            Function::PutChar => std::ffi::CString::new("putchar").unwrap(),
-        };
+        let tgt_name = std::ffi::CString::new(tgt.to_string()).unwrap();
 
         let tgt_addr = unsafe { libc::dlsym(libc::RTLD_DEFAULT, tgt_name.as_ptr()) as usize };
         let imm = self.encode_imm(tgt_addr as isize, 64)?;
@@ -18,3 +55,51 @@ impl AMD64Instruction {
             .collect())
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::CALL_SIZE;
+    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register, Function};
+
+    use AMD64Instruction::*;
+    use AMD64Operand::*;
+    use AMD64Register::*;
+
+    #[test]
+    fn test_call_sites() {
+        let instrs = vec![
+            Xor(Register(RDI), Register(RDI)),
+            Call(Function::PutChar),
+            Call(Function::GetChar),
+            Ret(),
+        ];
+        let sites = AMD64Instruction::call_sites(&instrs)
+            .unwrap()
+            .into_iter()
+            .map(|(offset, func)| (offset, func.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sites,
+            vec![(3, "putchar".into()), (3 + CALL_SIZE, "getchar".into())]
+        );
+    }
+
+    #[test]
+    fn test_patch_call() {
+        let mut bytes = Call(Function::GetChar).to_binary().unwrap();
+        assert_eq!(bytes.len(), CALL_SIZE);
+
+        AMD64Instruction::patch_call_absolute(&mut bytes, 0, 0x1122334455667788);
+        assert_eq!(
+            bytes,
+            vec![0x48, 0xB8, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0xFF, 0xD0]
+        );
+
+        let displacement_offset = AMD64Instruction::patch_call_relative(&mut bytes, 0, -4);
+        assert_eq!(displacement_offset, 1);
+        assert_eq!(
+            bytes,
+            vec![0xE8, 0xFC, 0xFF, 0xFF, 0xFF, 0x0F, 0x1F, 0x80, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+}
@@ -68,6 +68,27 @@ impl AMD64Instruction {
                     .collect())
             }
 
+            // cmp <reg>, <reg>
+            (Register(dst_reg), Register(src_reg)) => {
+                if dst_reg.size() != src_reg.size() {
+                    return self.encoding_err();
+                }
+
+                let prefix_reg_16 = (dst_reg.size() == 16).then_some(0x66);
+
+                let rex = self.encode_rex(Some(src), Some(dst))?;
+
+                let opcode: u8 = if dst_reg.size() == 8 { 0x38 } else { 0x39 };
+
+                let rmi = self.encode_reg_rmi(Some(src), Some(dst), dst_reg.size())?;
+
+                Ok(vec![prefix_reg_16, rex, Some(opcode)]
+                    .into_iter()
+                    .flatten()
+                    .chain(rmi)
+                    .collect())
+            }
+
             (_, _) => todo!(),
         }
     }
@@ -346,4 +367,15 @@ pub mod tests {
         ];
         run_tests(tests);
     }
+
+    #[test]
+    fn test_encode_cmp_reg_reg() {
+        let tests: Tests = vec![
+            (Cmp(Register(RSI), Register(R15)), vec![0x4C, 0x39, 0xFE]),
+            (Cmp(Register(RDX), Register(RBX)), vec![0x48, 0x39, 0xDA]),
+            (Cmp(Register(ECX), Register(EAX)), vec![0x39, 0xC1]),
+            (Cmp(Register(AL), Register(BL)), vec![0x38, 0xD8]),
+        ];
+        run_tests(tests);
+    }
 }
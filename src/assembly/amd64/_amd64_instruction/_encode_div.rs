@@ -0,0 +1,64 @@
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
+use crate::BFResult;
+
+use AMD64Operand::*;
+
+impl AMD64Instruction {
+    pub(crate) fn encode_div(self: &AMD64Instruction, src: &AMD64Operand) -> BFResult<Vec<u8>> {
+        match src {
+            // div <reg>
+            Register(src_reg) => {
+                let prefix_reg_16 = (src_reg.size() == 16).then_some(0x66);
+
+                let rex = self.encode_rex(None, Some(src))?;
+
+                let opcode: u8 = if src_reg.size() == 8 { 0xF6 } else { 0xF7 };
+
+                let rmi = self.encode_reg_rmi(
+                    Some(&Register(AMD64Register::RSI)),
+                    Some(src),
+                    src_reg.size(),
+                )?;
+
+                Ok(vec![prefix_reg_16, rex, Some(opcode)]
+                    .into_iter()
+                    .flatten()
+                    .chain(rmi)
+                    .collect())
+            }
+
+            _ => self.encoding_err(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
+
+    use AMD64Instruction::*;
+    use AMD64Operand::*;
+    use AMD64Register::*;
+
+    type Tests = Vec<(AMD64Instruction, Vec<u8>)>;
+
+    fn run_tests(tests: Tests) {
+        for (instruction, expected) in tests {
+            match instruction.to_binary() {
+                Ok(actual) => assert_eq!(actual, expected, "{}", instruction.to_string()),
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_div_reg() {
+        let tests: Tests = vec![
+            (Div(Register(ECX)), vec![0xF7, 0xF1]),
+            (Div(Register(RCX)), vec![0x48, 0xF7, 0xF1]),
+            (Div(Register(R9)), vec![0x49, 0xF7, 0xF1]),
+            (Div(Register(BL)), vec![0xF6, 0xF3]),
+        ];
+        run_tests(tests);
+    }
+}
@@ -1,12 +1,86 @@
 use crate::assembly::amd64::{AMD64Instruction, AMD64Operand};
 use crate::BFResult;
 
+use AMD64Operand::*;
+
 impl AMD64Instruction {
     pub(crate) fn encode_lea(
         self: &AMD64Instruction,
         dst: &AMD64Operand,
         src: &AMD64Operand,
     ) -> BFResult<Vec<u8>> {
-        todo!()
+        match (dst, src) {
+            // lea <reg>, <mem>
+            (Register(dst_reg), Memory(_, base_reg, index_reg, _, _)) => {
+                if dst_reg.size() == 8 {
+                    return self.encoding_err();
+                }
+
+                let prefix_reg_16 = (dst_reg.size() == 16).then_some(0x66);
+
+                let prefix_addr_32 = self.encode_prefix_addr_32(base_reg, index_reg)?;
+
+                let rex = self.encode_rex(Some(dst), Some(src))?;
+
+                let rmi = self.encode_reg_rmi(Some(dst), Some(src), dst_reg.size())?;
+
+                Ok(vec![prefix_reg_16, prefix_addr_32, rex, Some(0x8D)]
+                    .into_iter()
+                    .flatten()
+                    .chain(rmi)
+                    .collect())
+            }
+
+            _ => self.encoding_err(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
+
+    use AMD64Instruction::*;
+    use AMD64Operand::*;
+    use AMD64Register::*;
+
+    type Tests = Vec<(AMD64Instruction, Vec<u8>)>;
+
+    fn run_tests(tests: Tests) {
+        for (instruction, expected) in tests {
+            match instruction.to_binary() {
+                Ok(actual) => assert_eq!(actual, expected, "{}", instruction.to_string()),
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_lea_reg_mem() {
+        let tests: Tests = vec![
+            (
+                Lea(Register(RSI), Memory(None, Some(R12), None, None, Some(-1))),
+                vec![0x49, 0x8D, 0x74, 0x24, 0xFF],
+            ),
+            (
+                Lea(
+                    Register(RSI),
+                    Memory(None, Some(R12), None, None, Some(0x1000)),
+                ),
+                vec![0x49, 0x8D, 0xB4, 0x24, 0x00, 0x10, 0x00, 0x00],
+            ),
+            (
+                Lea(Register(RAX), Memory(None, Some(RBX), None, None, Some(1))),
+                vec![0x48, 0x8D, 0x43, 0x01],
+            ),
+            (
+                Lea(
+                    Register(RDX),
+                    Memory(None, Some(RSP), None, None, Some(128)),
+                ),
+                vec![0x48, 0x8D, 0x94, 0x24, 0x80, 0x00, 0x00, 0x00],
+            ),
+        ];
+        run_tests(tests);
     }
 }
@@ -181,6 +181,9 @@ pub mod tests {
             (Mov(Register(RBP), Register(RCX)), vec![0x48, 0x89, 0xCD]),
             (Mov(Register(RBP), Register(RSP)), vec![0x48, 0x89, 0xE5]),
             (Mov(Register(RBP), Register(RBP)), vec![0x48, 0x89, 0xED]),
+            //
+            (Mov(Register(CL), Register(DIL)), vec![0x40, 0x88, 0xF9]),
+            (Mov(Register(SIL), Register(DL)), vec![0x40, 0x88, 0xD6]),
         ];
         run_tests(tests);
     }
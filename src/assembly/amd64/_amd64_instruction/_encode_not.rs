@@ -1,24 +1,44 @@
-use crate::assembly::amd64::{AMD64Instruction, AMD64Operand};
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
 use crate::BFResult;
 
+use AMD64Operand::*;
 
 impl AMD64Instruction {
-    pub(crate) fn encode_not(
-        self: &AMD64Instruction,
-        dst: &AMD64Operand,
-    ) -> BFResult<Vec<u8>> {
-        todo!()
+    pub(crate) fn encode_not(self: &AMD64Instruction, dst: &AMD64Operand) -> BFResult<Vec<u8>> {
+        match dst {
+            // not <reg>
+            Register(dst_reg) => {
+                let prefix_reg_16 = (dst_reg.size() == 16).then_some(0x66);
+
+                let rex = self.encode_rex(None, Some(dst))?;
+
+                let opcode: u8 = if dst_reg.size() == 8 { 0xF6 } else { 0xF7 };
+
+                let rmi = self.encode_reg_rmi(
+                    Some(&Register(AMD64Register::RDX)),
+                    Some(dst),
+                    dst_reg.size(),
+                )?;
+
+                Ok(vec![prefix_reg_16, rex, Some(opcode)]
+                    .into_iter()
+                    .flatten()
+                    .chain(rmi)
+                    .collect())
+            }
+
+            _ => self.encoding_err(),
+        }
     }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register, MemorySize};
+    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
 
     use AMD64Instruction::*;
     use AMD64Operand::*;
     use AMD64Register::*;
-    use MemorySize::*;
 
     type Tests = Vec<(AMD64Instruction, Vec<u8>)>;
 
@@ -31,10 +51,13 @@ pub mod tests {
         }
     }
 
-    // #[test]
-    // fn test_encode_and_reg_reg() {
-    //     let tests: Tests = vec![
-    //     ];
-    //     run_tests(tests);
-    // }
+    #[test]
+    fn test_encode_not_reg() {
+        let tests: Tests = vec![
+            (Not(Register(ECX)), vec![0xF7, 0xD1]),
+            (Not(Register(RAX)), vec![0x48, 0xF7, 0xD0]),
+            (Not(Register(R12B)), vec![0x41, 0xF6, 0xD4]),
+        ];
+        run_tests(tests);
+    }
 }
@@ -0,0 +1,80 @@
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
+use crate::BFResult;
+
+use AMD64Operand::*;
+
+impl AMD64Instruction {
+    pub(crate) fn encode_sar(
+        self: &AMD64Instruction,
+        dst: &AMD64Operand,
+        src: &AMD64Operand,
+    ) -> BFResult<Vec<u8>> {
+        match (dst, src) {
+            // register >>= immediate
+            (Register(dst_reg), Immediate(imm)) => {
+                let prefix_reg_16 = (dst_reg.size() == 16).then_some(0x66);
+
+                let rex = self.encode_rex(None, Some(dst))?;
+
+                let opcode: u8 = if dst_reg.size() == 8 { 0xC0 } else { 0xC1 };
+
+                let rmi = self.encode_reg_rmi(
+                    Some(&Register(AMD64Register::RDI)),
+                    Some(dst),
+                    dst_reg.size(),
+                )?;
+
+                let imm = self.encode_imm(*imm, 8)?;
+
+                Ok(vec![prefix_reg_16, rex, Some(opcode)]
+                    .into_iter()
+                    .flatten()
+                    .chain(rmi)
+                    .chain(imm)
+                    .collect())
+            }
+
+            _ => self.encoding_err(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
+
+    use AMD64Instruction::*;
+    use AMD64Operand::*;
+    use AMD64Register::*;
+
+    type Tests = Vec<(AMD64Instruction, Vec<u8>)>;
+
+    fn run_tests(tests: Tests) {
+        for (instruction, expected) in tests {
+            match instruction.to_binary() {
+                Ok(actual) => assert_eq!(actual, expected, "{}", instruction.to_string()),
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_sar_reg_imm() {
+        let tests: Tests = vec![
+            (
+                Sar(Register(RSI), Immediate(1)),
+                vec![0x48, 0xC1, 0xFE, 0x01],
+            ),
+            (
+                Sar(Register(RSI), Immediate(2)),
+                vec![0x48, 0xC1, 0xFE, 0x02],
+            ),
+            (Sar(Register(EAX), Immediate(3)), vec![0xC1, 0xF8, 0x03]),
+            (
+                Sar(Register(R13), Immediate(4)),
+                vec![0x49, 0xC1, 0xFD, 0x04],
+            ),
+        ];
+        run_tests(tests);
+    }
+}
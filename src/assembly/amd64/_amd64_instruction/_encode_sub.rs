@@ -0,0 +1,68 @@
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand};
+use crate::BFResult;
+
+use AMD64Operand::*;
+
+impl AMD64Instruction {
+    pub(crate) fn encode_sub(
+        self: &AMD64Instruction,
+        dst: &AMD64Operand,
+        src: &AMD64Operand,
+    ) -> BFResult<Vec<u8>> {
+        match (dst, src) {
+            // register -= register
+            (Register(dst_reg), Register(src_reg)) => {
+                if dst_reg.size() != src_reg.size() {
+                    return self.encoding_err();
+                }
+
+                let prefix_reg_16 = (dst_reg.size() == 16).then_some(0x66);
+
+                let rex = self.encode_rex(Some(src), Some(dst))?;
+
+                let opcode: u8 = if dst_reg.size() == 8 { 0x28 } else { 0x29 };
+
+                let rmi = self.encode_reg_rmi(Some(src), Some(dst), dst_reg.size())?;
+
+                Ok(vec![prefix_reg_16, rex, Some(opcode)]
+                    .into_iter()
+                    .flatten()
+                    .chain(rmi)
+                    .collect())
+            }
+
+            _ => self.encoding_err(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
+
+    use AMD64Instruction::*;
+    use AMD64Operand::*;
+    use AMD64Register::*;
+
+    type Tests = Vec<(AMD64Instruction, Vec<u8>)>;
+
+    fn run_tests(tests: Tests) {
+        for (instruction, expected) in tests {
+            match instruction.to_binary() {
+                Ok(actual) => assert_eq!(actual, expected, "{}", instruction.to_string()),
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_sub_reg_reg() {
+        let tests: Tests = vec![
+            (Sub(Register(RSI), Register(R14)), vec![0x4C, 0x29, 0xF6]),
+            (Sub(Register(RDX), Register(RBX)), vec![0x48, 0x29, 0xDA]),
+            (Sub(Register(ECX), Register(EAX)), vec![0x29, 0xC1]),
+            (Sub(Register(AL), Register(BL)), vec![0x28, 0xD8]),
+        ];
+        run_tests(tests);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::assembly::amd64::{AMD64Instruction, AMD64Operand};
 use crate::BFResult;
 
+use AMD64Operand::*;
 
 impl AMD64Instruction {
     pub(crate) fn encode_test(
@@ -8,18 +9,40 @@ impl AMD64Instruction {
         op1: &AMD64Operand,
         op2: &AMD64Operand,
     ) -> BFResult<Vec<u8>> {
-        todo!()
+        match (op1, op2) {
+            // test <reg>, <reg>
+            (Register(op1_reg), Register(op2_reg)) => {
+                if op1_reg.size() != op2_reg.size() {
+                    return self.encoding_err();
+                }
+
+                let prefix_reg_16 = (op1_reg.size() == 16).then_some(0x66);
+
+                let rex = self.encode_rex(Some(op2), Some(op1))?;
+
+                let opcode: u8 = if op1_reg.size() == 8 { 0x84 } else { 0x85 };
+
+                let rmi = self.encode_reg_rmi(Some(op2), Some(op1), op1_reg.size())?;
+
+                Ok(vec![prefix_reg_16, rex, Some(opcode)]
+                    .into_iter()
+                    .flatten()
+                    .chain(rmi)
+                    .collect())
+            }
+
+            (_, _) => self.encoding_err(),
+        }
     }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register, MemorySize};
+    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
 
     use AMD64Instruction::*;
     use AMD64Operand::*;
     use AMD64Register::*;
-    use MemorySize::*;
 
     type Tests = Vec<(AMD64Instruction, Vec<u8>)>;
 
@@ -32,10 +55,14 @@ pub mod tests {
         }
     }
 
-    // #[test]
-    // fn test_encode_and_reg_reg() {
-    //     let tests: Tests = vec![
-    //     ];
-    //     run_tests(tests);
-    // }
+    #[test]
+    fn test_encode_test_reg_reg() {
+        let tests: Tests = vec![
+            (Test(Register(DL), Register(DL)), vec![0x84, 0xD2]),
+            (Test(Register(EDX), Register(EDX)), vec![0x85, 0xD2]),
+            (Test(Register(RAX), Register(RCX)), vec![0x48, 0x85, 0xC8]),
+            (Test(Register(R13D), Register(R8D)), vec![0x45, 0x85, 0xC5]),
+        ];
+        run_tests(tests);
+    }
 }
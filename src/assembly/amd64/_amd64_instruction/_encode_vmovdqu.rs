@@ -1,4 +1,4 @@
-use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, MemorySize, Vex};
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, MemorySize};
 use crate::BFResult;
 use AMD64Operand::*;
 use MemorySize::*;
@@ -9,29 +9,90 @@ impl AMD64Instruction {
         dst: &AMD64Operand,
         src: &AMD64Operand,
     ) -> BFResult<Vec<u8>> {
-        match (dst, src) {
-            (Register(dst_reg), Memory(size, base_reg, index_reg, index_scale, displacement)) => {
-                if let Some(size) = size {
-                    if size.size() != YMMWord.size() {
-                        return self.encoding_err();
-                    }
-                }
-                if dst_reg.size() != YMMWord.size() {
-                    return self.encoding_err();
-                }
-
-                let vex = Vex::new();
-                // vex.b = 
-                // VEX.256.F3.0F.WIG 6F /r
-                // c4 c1 7e 6f 1c 24
-                // c1: 1 1 0 00001
-                // b
-
-                // 7e: 0 1111 1 10
-                // l, 0xF3
-                todo!()
+        let (reg, rm, opcode) = match (dst, src) {
+            // vmovdqu <ymm>, <ymm/mem>: VEX.256.F3.0F 6F /r
+            (Register(_), Register(_) | Memory(..)) => (dst, src, 0x6F),
+            // vmovdqu <mem>, <ymm>: VEX.256.F3.0F 7F /r
+            (Memory(..), Register(_)) => (src, dst, 0x7F),
+            (_, _) => return self.encoding_err(),
+        };
+
+        let Register(reg_reg) = reg else {
+            return self.encoding_err();
+        };
+        if reg_reg.size() != YMMWord.size() {
+            return self.encoding_err();
+        }
+        match rm {
+            Register(rm_reg) if rm_reg.size() != YMMWord.size() => return self.encoding_err(),
+            Memory(Some(size), _, _, _, _) if size.size() != YMMWord.size() => {
+                return self.encoding_err()
             }
-            (_, _) => self.encoding_err(),
+            _ => {}
         }
+
+        let prefix_addr_32 = match rm {
+            Memory(_, base_reg, index_reg, _, _) => {
+                self.encode_prefix_addr_32(base_reg, index_reg)?
+            }
+            _ => None,
+        };
+
+        let vex = self.encode_vex(Some(reg), None, Some(rm), 0b10)?;
+
+        let rmi = self.encode_reg_rmi(Some(reg), Some(rm), YMMWord.size())?;
+
+        Ok(prefix_addr_32
+            .into_iter()
+            .chain(vex)
+            .chain([opcode])
+            .chain(rmi)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register, MemorySize};
+
+    use AMD64Instruction::*;
+    use AMD64Operand::*;
+    use AMD64Register::*;
+    use MemorySize::*;
+
+    type Tests = Vec<(AMD64Instruction, Vec<u8>)>;
+
+    fn run_tests(tests: Tests) {
+        for (instruction, expected) in tests {
+            match instruction.to_binary() {
+                Ok(actual) => assert_eq!(actual, expected, "{}", instruction.to_string()),
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_vmovdqu_ymm_mem() {
+        let tests: Tests = vec![
+            (
+                Vmovdqu(
+                    Register(YMM1),
+                    Memory(Some(YMMWord), Some(R12), Some(RCX), None, None),
+                ),
+                vec![0xC4, 0xC1, 0x7E, 0x6F, 0x0C, 0x0C],
+            ),
+            (
+                Vmovdqu(Register(YMM1), Memory(None, Some(RAX), None, None, None)),
+                vec![0xC5, 0xFE, 0x6F, 0x08],
+            ),
+            (
+                Vmovdqu(
+                    Register(YMM10),
+                    Memory(None, Some(RAX), None, None, Some(8)),
+                ),
+                vec![0xC5, 0x7E, 0x6F, 0x50, 0x08],
+            ),
+        ];
+        run_tests(tests);
     }
 }
@@ -1,6 +1,9 @@
-use crate::assembly::amd64::{AMD64Instruction, AMD64Operand};
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, MemorySize};
 use crate::BFResult;
 
+use AMD64Operand::*;
+use MemorySize::*;
+
 impl AMD64Instruction {
     pub(crate) fn encode_vpcmpeqb(
         self: &AMD64Instruction,
@@ -8,6 +11,60 @@ impl AMD64Instruction {
         op1: &AMD64Operand,
         op2: &AMD64Operand,
     ) -> BFResult<Vec<u8>> {
-        todo!()
+        match (dst, op1, op2) {
+            // vpcmpeqb <ymm>, <ymm>, <ymm>
+            (Register(dst_reg), Register(op1_reg), Register(op2_reg)) => {
+                if [dst_reg, op1_reg, op2_reg]
+                    .iter()
+                    .any(|reg| reg.size() != YMMWord.size())
+                {
+                    return self.encoding_err();
+                }
+
+                // VEX.256.66.0F 74 /r
+                let vex = self.encode_vex(Some(dst), Some(op1), Some(op2), 0b01)?;
+
+                let rmi = self.encode_reg_rmi(Some(dst), Some(op2), YMMWord.size())?;
+
+                Ok(vex.into_iter().chain([0x74]).chain(rmi).collect())
+            }
+
+            (_, _, _) => self.encoding_err(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
+
+    use AMD64Instruction::*;
+    use AMD64Operand::*;
+    use AMD64Register::*;
+
+    type Tests = Vec<(AMD64Instruction, Vec<u8>)>;
+
+    fn run_tests(tests: Tests) {
+        for (instruction, expected) in tests {
+            match instruction.to_binary() {
+                Ok(actual) => assert_eq!(actual, expected, "{}", instruction.to_string()),
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_vpcmpeqb_ymm_ymm_ymm() {
+        let tests: Tests = vec![
+            (
+                Vpcmpeqb(Register(YMM1), Register(YMM1), Register(YMM0)),
+                vec![0xC5, 0xF5, 0x74, 0xC8],
+            ),
+            (
+                Vpcmpeqb(Register(YMM8), Register(YMM9), Register(YMM10)),
+                vec![0xC4, 0x41, 0x35, 0x74, 0xC2],
+            ),
+        ];
+        run_tests(tests);
     }
 }
@@ -1,12 +1,66 @@
-use crate::assembly::amd64::{AMD64Instruction, AMD64Operand};
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, MemorySize};
 use crate::BFResult;
 
+use AMD64Operand::*;
+use MemorySize::*;
+
 impl AMD64Instruction {
     pub(crate) fn encode_vpmovmskb(
         self: &AMD64Instruction,
         dst: &AMD64Operand,
         src: &AMD64Operand,
     ) -> BFResult<Vec<u8>> {
-        todo!()
+        match (dst, src) {
+            // vpmovmskb <reg32>, <ymm>
+            (Register(dst_reg), Register(src_reg)) => {
+                if dst_reg.size() != 32 || src_reg.size() != YMMWord.size() {
+                    return self.encoding_err();
+                }
+
+                // VEX.256.66.0F D7 /r
+                let vex = self.encode_vex(Some(dst), None, Some(src), 0b01)?;
+
+                let rmi = self.encode_reg_rmi(Some(dst), Some(src), YMMWord.size())?;
+
+                Ok(vex.into_iter().chain([0xD7]).chain(rmi).collect())
+            }
+
+            (_, _) => self.encoding_err(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
+
+    use AMD64Instruction::*;
+    use AMD64Operand::*;
+    use AMD64Register::*;
+
+    type Tests = Vec<(AMD64Instruction, Vec<u8>)>;
+
+    fn run_tests(tests: Tests) {
+        for (instruction, expected) in tests {
+            match instruction.to_binary() {
+                Ok(actual) => assert_eq!(actual, expected, "{}", instruction.to_string()),
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_vpmovmskb_reg_ymm() {
+        let tests: Tests = vec![
+            (
+                Vpmovmskb(Register(EDX), Register(YMM1)),
+                vec![0xC5, 0xFD, 0xD7, 0xD1],
+            ),
+            (
+                Vpmovmskb(Register(R9D), Register(YMM11)),
+                vec![0xC4, 0x41, 0x7D, 0xD7, 0xCB],
+            ),
+        ];
+        run_tests(tests);
     }
 }
@@ -2,6 +2,7 @@ mod _amd64_instruction;
 pub use _amd64_instruction::*;
 
 mod _encode_call;
+mod _encode_jae;
 mod _encode_je;
 mod _encode_jmp;
 mod _encode_jne;
@@ -11,8 +12,11 @@ mod _encode_and;
 mod _encode_bsf;
 mod _encode_bsr;
 mod _encode_cmp;
+mod _encode_div;
 mod _encode_imul;
 mod _encode_not;
+mod _encode_sar;
+mod _encode_sub;
 mod _encode_test;
 mod _encode_xor;
 
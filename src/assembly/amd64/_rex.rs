@@ -7,6 +7,9 @@ pub struct Rex {
     r: bool,
     x: bool,
     b: bool,
+    /// Whether the prefix is needed even if no bits are set, to address `SPL`, `BPL`, `SIL`
+    /// or `DIL` rather than `AH`, `CH`, `DH` or `BH`
+    required: bool,
 }
 
 impl Rex {
@@ -16,6 +19,7 @@ impl Rex {
             r: false,
             x: false,
             b: false,
+            required: false,
         }
     }
 
@@ -34,6 +38,7 @@ impl Rex {
         if reg.id() > 7 {
             self.r();
         }
+        self.require_for(reg);
     }
 
     pub fn x_reg(&mut self, reg: &AMD64Register) {
@@ -46,6 +51,16 @@ impl Rex {
         if reg.id() > 7 {
             self.b();
         }
+        self.require_for(reg);
+    }
+
+    fn require_for(&mut self, reg: &AMD64Register) {
+        if matches!(
+            reg,
+            AMD64Register::SPL | AMD64Register::BPL | AMD64Register::SIL | AMD64Register::DIL
+        ) {
+            self.required = true;
+        }
     }
 
     pub fn w(&mut self) {
@@ -65,6 +80,6 @@ impl Rex {
     }
 
     pub fn is_some(&self) -> bool {
-        self.w || self.r || self.x || self.b
+        self.w || self.r || self.x || self.b || self.required
     }
 }
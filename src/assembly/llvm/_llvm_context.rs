@@ -1,12 +1,14 @@
-use crate::{BFError, BFResult};
+use crate::assembly::llvm::{LlvmDebugInfo, LlvmOutputBuffer};
+use crate::{BFError, BFResult, CellWidth, EofBehavior, Span};
 use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::module::Module;
-use inkwell::types::{ArrayType, BasicType, FunctionType, PointerType};
+use inkwell::types::{ArrayType, BasicType, FunctionType, IntType, PointerType};
 use inkwell::values::{FunctionValue, PointerValue};
 use inkwell::AddressSpace;
 use std::collections::HashMap;
+use std::path::Path;
 
 pub struct LlvmValue<T, V> {
     pub typ: T,
@@ -21,17 +23,42 @@ pub struct LlvmFn<'c> {
 
 pub struct LlvmContext<'c> {
     pub ctx: &'c Context,
+
+    /// The debug info for the module, if it is compiled with debug info
+    ///
+    /// The debug info builder must be dropped before the module that it builds debug info for
+    pub debug_info: Option<LlvmDebugInfo<'c>>,
+
     pub module: Module<'c>,
     pub builder: Builder<'c>,
 
     pub fns: HashMap<String, LlvmFn<'c>>,
 
+    /// The width of each memory cell
+    pub cell_width: CellWidth,
+    /// The integer type of each memory cell
+    pub cell_type: IntType<'c>,
+    /// What reads do to the current cell at the end of the input
+    pub eof_behavior: EofBehavior,
+
     pub mem: LlvmValue<ArrayType<'c>, PointerValue<'c>>,
     pub mem_ptr: LlvmValue<PointerType<'c>, PointerValue<'c>>,
+
+    /// The output buffer, if output is buffered
+    ///
+    /// Output is written with `putchar` if there is no output buffer
+    pub output_buffer: Option<LlvmOutputBuffer<'c>>,
 }
 
 impl<'c> LlvmContext<'c> {
-    pub fn new(ctx: &'c Context, mem_size: usize) -> BFResult<Self> {
+    pub fn new(
+        ctx: &'c Context,
+        mem_size: usize,
+        cell_width: CellWidth,
+        eof_behavior: EofBehavior,
+        buffered: bool,
+        debug_file: Option<&Path>,
+    ) -> BFResult<Self> {
         let module = ctx.create_module("mod_bf");
         let builder = ctx.create_builder();
 
@@ -63,6 +90,12 @@ impl<'c> LlvmContext<'c> {
         };
         fns.insert("putchar".into(), fn_putchar);
 
+        let output_buffer = if buffered {
+            Some(LlvmOutputBuffer::new(ctx, &module)?)
+        } else {
+            None
+        };
+
         let fn_main = {
             let typ = ctx.i32_type().fn_type(&[], false);
             let val = module.add_function("main", typ, None);
@@ -71,10 +104,17 @@ impl<'c> LlvmContext<'c> {
             let blocks = Some(vec![bb_main_entry]);
             LlvmFn { typ, val, blocks }
         };
-        fns.insert("main".into(), fn_main);
+
+        // The setup of `main` isn't compiled from any BF instruction, so it has no location
+        let debug_info = debug_file.map(|path| LlvmDebugInfo::new(ctx, &module, fn_main.val, path));
+        if let Some(debug_info) = &debug_info {
+            builder.set_current_debug_location(debug_info.location(ctx, Span::default()));
+        }
+
+        let cell_type = ctx.custom_width_int_type(cell_width.bits());
 
         let mem = {
-            let typ = ctx.i8_type().array_type(mem_size as u32);
+            let typ = cell_type.array_type(mem_size as u32);
             let val = builder
                 .build_alloca(typ, "mem")
                 .map_err(|_| BFError::LlvmError("Failed to build `mem` array allocation".into()))?;
@@ -84,7 +124,8 @@ impl<'c> LlvmContext<'c> {
                     val,
                     1,
                     ctx.i8_type().const_zero(),
-                    ctx.i32_type().const_int(mem_size as u64, false),
+                    ctx.i32_type()
+                        .const_int((mem_size * cell_width.bytes()) as u64, false),
                 )
                 .map_err(|_| {
                     BFError::LlvmError("Failed to build `memset` for `mem` initialization".into())
@@ -102,7 +143,7 @@ impl<'c> LlvmContext<'c> {
             let ptr = unsafe {
                 builder
                     .build_gep(
-                        ctx.i8_type(),
+                        cell_type,
                         mem.val,
                         &[ctx.i32_type().const_int(mem_size as u64 / 2, false)],
                         "mem_ptr",
@@ -118,15 +159,52 @@ impl<'c> LlvmContext<'c> {
             LlvmValue { typ, val }
         };
 
+        if let (Some(debug_info), Some(blocks)) = (&debug_info, &fn_main.blocks) {
+            debug_info.declare_variables(
+                ctx,
+                blocks[0],
+                mem.val,
+                mem_size,
+                mem_ptr.val,
+                cell_width,
+            );
+        }
+        fns.insert("main".into(), fn_main);
+
         Ok(Self {
             ctx,
+            debug_info,
             module,
             builder,
 
             fns,
 
+            cell_width,
+            cell_type,
+            eof_behavior,
+
             mem,
             mem_ptr,
+
+            output_buffer,
         })
     }
+
+    /// Builds a call that writes out the buffered output, if the output is buffered
+    pub fn flush_output(&self) -> BFResult<()> {
+        if let Some(output_buffer) = &self.output_buffer {
+            self.builder
+                .build_call(output_buffer.fn_flush, &[], "")
+                .map_err(|_| BFError::LlvmError("Failed to build call to `bf_flush`".into()))?;
+        }
+        Ok(())
+    }
+
+    /// Sets the debug location of the instructions that are built next to the given span
+    /// of the BF source file, if the module has debug info
+    pub fn set_location(&self, span: Span) {
+        if let Some(debug_info) = &self.debug_info {
+            debug_info.set_location(self.ctx, &self.builder, span);
+        }
+    }
 }
@@ -0,0 +1,190 @@
+use crate::{CellWidth, Span};
+use inkwell::basic_block::BasicBlock;
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::debug_info::{
+    debug_metadata_version, AsDIScope, DICompileUnit, DIFile, DIFlags, DIFlagsConstants,
+    DILocation, DISubprogram, DWARFEmissionKind, DWARFSourceLanguage, DebugInfoBuilder,
+};
+use inkwell::module::{FlagBehavior, Module};
+use inkwell::values::{FunctionValue, PointerValue};
+use inkwell::AddressSpace;
+use std::path::Path;
+
+/// `DW_ATE_signed`, the DWARF encoding of signed integers
+const DW_ATE_SIGNED: u32 = 0x05;
+
+/// `DW_ATE_unsigned`, the DWARF encoding of unsigned integers
+const DW_ATE_UNSIGNED: u32 = 0x07;
+
+/// `DW_ATE_unsigned_char`, the DWARF encoding of unsigned characters
+const DW_ATE_UNSIGNED_CHAR: u32 = 0x08;
+
+/// The DWARF debug info for a module compiled from a BF program
+///
+/// All of the program's code is in `main`, which is described as a subprogram in the BF source
+/// file. Each instruction is located at the line and column of the BF instruction that it was
+/// compiled from.
+pub struct LlvmDebugInfo<'c> {
+    /// The builder that creates the module's debug info
+    pub builder: DebugInfoBuilder<'c>,
+    /// The compile unit for the BF source file
+    pub compile_unit: DICompileUnit<'c>,
+    /// The BF source file
+    pub file: DIFile<'c>,
+    /// The subprogram that describes `main`
+    pub subprogram: DISubprogram<'c>,
+}
+
+impl<'c> LlvmDebugInfo<'c> {
+    /// Creates a compile unit for the BF source file at the given path, and attaches a
+    /// subprogram for `main` to the given function
+    pub fn new(
+        ctx: &'c Context,
+        module: &Module<'c>,
+        fn_main: FunctionValue<'c>,
+        path: &Path,
+    ) -> Self {
+        let filename = path
+            .file_name()
+            .map_or("<stdin>".into(), |name| name.to_string_lossy());
+        let directory = path
+            .parent()
+            .and_then(|dir| dir.canonicalize().ok())
+            .or_else(|| std::env::current_dir().ok())
+            .map_or(String::new(), |dir| dir.display().to_string());
+
+        module.add_basic_value_flag(
+            "Debug Info Version",
+            FlagBehavior::Warning,
+            ctx.i32_type()
+                .const_int(debug_metadata_version() as u64, false),
+        );
+        module.add_basic_value_flag(
+            "Dwarf Version",
+            FlagBehavior::Warning,
+            ctx.i32_type().const_int(4, false),
+        );
+
+        let (builder, compile_unit) = module.create_debug_info_builder(
+            true,
+            DWARFSourceLanguage::C,
+            &filename,
+            &directory,
+            "brainforge",
+            false,
+            "",
+            0,
+            "",
+            DWARFEmissionKind::Full,
+            0,
+            false,
+            false,
+            "",
+            "",
+        );
+        let file = compile_unit.get_file();
+
+        let int_type = builder
+            .create_basic_type("int", 32, DW_ATE_SIGNED, DIFlags::PUBLIC)
+            .expect("`int` is a valid basic type");
+        let fn_type =
+            builder.create_subroutine_type(file, Some(int_type.as_type()), &[], DIFlags::PUBLIC);
+        let subprogram = builder.create_function(
+            compile_unit.as_debug_info_scope(),
+            "main",
+            None,
+            file,
+            1,
+            fn_type,
+            false,
+            true,
+            1,
+            DIFlags::PUBLIC,
+            false,
+        );
+        fn_main.set_subprogram(subprogram);
+
+        Self {
+            builder,
+            compile_unit,
+            file,
+            subprogram,
+        }
+    }
+
+    /// Returns the debug location of the given span of the BF source file
+    pub fn location(&self, ctx: &'c Context, span: Span) -> DILocation<'c> {
+        self.builder.create_debug_location(
+            ctx,
+            span.start.line as u32,
+            span.start.column as u32,
+            self.subprogram.as_debug_info_scope(),
+            None,
+        )
+    }
+
+    /// Sets the debug location of the instructions that the given builder builds next
+    /// to the given span of the BF source file
+    pub fn set_location(&self, ctx: &'c Context, builder: &Builder<'c>, span: Span) {
+        builder.set_current_debug_location(self.location(ctx, span));
+    }
+
+    /// Declares the memory tape and the pointer to the current cell as variables of `main`,
+    /// at the end of the given block
+    ///
+    /// The tape is an array of `mem_size` cells, and the pointer is `ptr`, which points
+    /// into the tape.
+    pub fn declare_variables(
+        &self,
+        ctx: &'c Context,
+        block: BasicBlock<'c>,
+        mem: PointerValue<'c>,
+        mem_size: usize,
+        mem_ptr: PointerValue<'c>,
+        cell_width: CellWidth,
+    ) {
+        let cell_bits = cell_width.bits() as u64;
+        let (cell_name, encoding) = match cell_width {
+            CellWidth::Bits8 => ("unsigned char", DW_ATE_UNSIGNED_CHAR),
+            CellWidth::Bits16 => ("unsigned short", DW_ATE_UNSIGNED),
+            CellWidth::Bits32 => ("unsigned int", DW_ATE_UNSIGNED),
+        };
+        let cell_type = self
+            .builder
+            .create_basic_type(cell_name, cell_bits, encoding, DIFlags::PUBLIC)
+            .expect("cell types are valid basic types");
+        let tape_type = self.builder.create_array_type(
+            cell_type.as_type(),
+            cell_bits * mem_size as u64,
+            0,
+            &[0..mem_size as i64],
+        );
+        let ptr_type = self.builder.create_pointer_type(
+            "",
+            cell_type.as_type(),
+            64,
+            0,
+            AddressSpace::default(),
+        );
+
+        let location = self.location(ctx, Span::default());
+        for (storage, name, typ) in [
+            (mem, "tape", tape_type.as_type()),
+            (mem_ptr, "ptr", ptr_type.as_type()),
+        ] {
+            let variable = self.builder.create_auto_variable(
+                self.subprogram.as_debug_info_scope(),
+                name,
+                self.file,
+                1,
+                typ,
+                true,
+                DIFlags::ZERO,
+                0,
+            );
+            self.builder
+                .insert_declare_at_end(storage, Some(variable), None, location, block);
+        }
+    }
+}
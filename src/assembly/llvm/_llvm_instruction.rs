@@ -1,19 +1,29 @@
-use crate::assembly::llvm::LlvmContext;
+use crate::assembly::llvm::{LlvmContext, OUTPUT_BUFFER_SIZE};
 use crate::instruction::IntermediateInstruction;
-use crate::{BFError, BFResult};
+use crate::optimizer::invariant_loads;
+use crate::{BFError, BFResult, CellWidth, EofBehavior};
 use inkwell::basic_block::BasicBlock;
+use inkwell::intrinsics::Intrinsic;
+use inkwell::module::Linkage;
 use inkwell::types::BasicType;
 use inkwell::values::{
     BasicMetadataValueEnum, BasicValue, BasicValueEnum, CallSiteValue, FunctionValue,
     InstructionValue, IntValue, PointerValue,
 };
-use inkwell::IntPredicate;
+use inkwell::{AddressSpace, IntPredicate};
+use std::collections::HashMap;
 
 #[derive(Clone, Debug)]
 pub enum LLVMInstruction {}
 
 impl LLVMInstruction {
-    fn build_instruction(ctx: &LlvmContext, instr: &IntermediateInstruction) -> BFResult<()> {
+    /// Builds the given instruction, reading the cells that an enclosing loop loaded ahead of
+    /// time from the given values instead of the tape
+    fn build_instruction<'c>(
+        ctx: &'c LlvmContext,
+        instr: &IntermediateInstruction,
+        loaded: &HashMap<i32, IntValue<'c>>,
+    ) -> BFResult<()> {
         fn load<'c, T: BasicType<'c>>(
             ctx: &'c LlvmContext,
             name: &str,
@@ -36,18 +46,27 @@ impl LLVMInstruction {
                 .map_err(|_| BFError::LlvmError(format!("Failed to build store to `{}`", name)))
         }
 
-        fn load_mem_val<'c>(ctx: &'c LlvmContext) -> BFResult<IntValue<'c>> {
+        // Returns a pointer to the cell at the given offset from the data pointer
+        fn cell_ptr<'c>(ctx: &'c LlvmContext, offset: i32) -> BFResult<PointerValue<'c>> {
             let mem_val_ptr =
                 load(ctx, "mem_val_ptr", ctx.mem_ptr.typ, ctx.mem_ptr.val)?.into_pointer_value();
-            Ok(load(ctx, "mem_val", ctx.ctx.i8_type(), mem_val_ptr)?.into_int_value())
+            match offset {
+                0 => Ok(mem_val_ptr),
+                _ => shift_ptr(ctx, "mem_val_ptr", ctx.cell_type, mem_val_ptr, offset),
+            }
+        }
+
+        fn load_mem_val<'c>(ctx: &'c LlvmContext, offset: i32) -> BFResult<IntValue<'c>> {
+            let mem_val_ptr = cell_ptr(ctx, offset)?;
+            Ok(load(ctx, "mem_val", ctx.cell_type, mem_val_ptr)?.into_int_value())
         }
 
         fn store_mem_val<'c, V: BasicValue<'c>>(
             ctx: &'c LlvmContext,
+            offset: i32,
             val: V,
         ) -> BFResult<InstructionValue<'c>> {
-            let mem_val_ptr =
-                load(ctx, "mem_val_ptr", ctx.mem_ptr.typ, ctx.mem_ptr.val)?.into_pointer_value();
+            let mem_val_ptr = cell_ptr(ctx, offset)?;
             store(ctx, "mem_val", mem_val_ptr, val)
         }
 
@@ -96,21 +115,28 @@ impl LLVMInstruction {
             Ok(fn_curr)
         }
 
-        let i8_val = |val: u64| ctx.ctx.i8_type().const_int(val, false);
+        let cell_val = |val: u64| ctx.cell_type.const_int(val, false);
+        // The offset of the cell that the instruction acts on
+        let offset = instr.cell_offset();
+        // The value of the cell at the given offset
+        let load_cell = |offset: i32| match loaded.get(&offset) {
+            Some(mem_val) => Ok(*mem_val),
+            None => load_mem_val(ctx, offset),
+        };
 
+        // Branches on whether the given cell value is 0
         fn cond_branch<'c>(
             ctx: &'c LlvmContext,
+            mem_val: IntValue<'c>,
             bb_zero: BasicBlock<'c>,
             bb_not_zero: BasicBlock<'c>,
         ) -> BFResult<InstructionValue<'c>> {
-            let mem_val = load_mem_val(ctx)?;
-
             let branch_cond_loop = ctx
                 .builder
                 .build_int_compare(
                     IntPredicate::EQ,
                     mem_val,
-                    ctx.ctx.i8_type().const_zero(),
+                    ctx.cell_type.const_zero(),
                     "loop_jmp_cond",
                 )
                 .map_err(|_| BFError::LlvmError("Failed to build loop jmp condition".into()))?;
@@ -120,25 +146,26 @@ impl LLVMInstruction {
                 .map_err(|_| BFError::LlvmError("Failed to build conditional branch".into()))
         }
 
-        fn extend_i8_i32<'c>(
+        // 32-bit cells are already `i32`, so these may be no-op casts
+        fn extend_cell_i32<'c>(
             ctx: &'c LlvmContext,
             name: &str,
             val: IntValue<'c>,
         ) -> BFResult<IntValue<'c>> {
             ctx.builder
-                .build_int_z_extend(val, ctx.ctx.i32_type(), name)
+                .build_int_z_extend_or_bit_cast(val, ctx.ctx.i32_type(), name)
                 .map_err(|_| {
                     BFError::LlvmError(format!("Failed to build sign extend for `{}`", name))
                 })
         }
 
-        fn truncate_i32_i8<'c>(
+        fn truncate_i32_cell<'c>(
             ctx: &'c LlvmContext,
             name: &str,
             val: IntValue<'c>,
         ) -> BFResult<IntValue<'c>> {
             ctx.builder
-                .build_int_truncate(val, ctx.ctx.i8_type(), name)
+                .build_int_truncate_or_bit_cast(val, ctx.cell_type, name)
                 .map_err(|_| BFError::LlvmError(format!("Failed to build truncate for `{}`", name)))
         }
 
@@ -153,27 +180,100 @@ impl LLVMInstruction {
                 .map_err(|_| BFError::LlvmError(format!("Failed to build add for `{}`", name)))
         }
 
+        // Writes the given `i8` to stdout, through the output buffer if there is one
+        fn write_byte<'c>(ctx: &'c LlvmContext, byte_val: IntValue<'c>) -> BFResult<()> {
+            let Some(output_buffer) = &ctx.output_buffer else {
+                let byte_val_i32 = ctx
+                    .builder
+                    .build_int_z_extend(byte_val, ctx.ctx.i32_type(), "byte_val_i32")
+                    .map_err(|_| BFError::LlvmError("Failed to build output byte".into()))?;
+                call(ctx, "putchar", &[byte_val_i32.into()])?;
+                return Ok(());
+            };
+
+            // Append the byte to the buffer, and write the buffer out once it is full
+            let i64_type = ctx.ctx.i64_type();
+            let len = load(ctx, "output_len", i64_type, output_buffer.len)?.into_int_value();
+            let byte_ptr = unsafe {
+                ctx.builder
+                    .build_gep(ctx.ctx.i8_type(), output_buffer.buffer, &[len], "byte_ptr")
+                    .map_err(|_| BFError::LlvmError("Failed to build output buffer `gep`".into()))?
+            };
+            store(ctx, "output_byte", byte_ptr, byte_val)?;
+            let new_len = int_add(ctx, "new_output_len", len, i64_type.const_int(1, false))?;
+            store(ctx, "output_len", output_buffer.len, new_len)?;
+
+            let fn_curr = get_curr_fn(ctx)?;
+            let bb_flush = ctx.ctx.append_basic_block(fn_curr, "bb_flush");
+            let bb_write_end = ctx.ctx.append_basic_block(fn_curr, "bb_write_end");
+            let is_full = ctx
+                .builder
+                .build_int_compare(
+                    IntPredicate::EQ,
+                    new_len,
+                    i64_type.const_int(OUTPUT_BUFFER_SIZE as u64, false),
+                    "is_full",
+                )
+                .map_err(|_| BFError::LlvmError("Failed to build output buffer check".into()))?;
+            ctx.builder
+                .build_conditional_branch(is_full, bb_flush, bb_write_end)
+                .map_err(|_| BFError::LlvmError("Failed to build output buffer branch".into()))?;
+            ctx.builder.position_at_end(bb_flush);
+            ctx.flush_output()?;
+            ctx.builder
+                .build_unconditional_branch(bb_write_end)
+                .map_err(|_| BFError::LlvmError("Failed to build jump after flush".into()))?;
+            ctx.builder.position_at_end(bb_write_end);
+            Ok(())
+        }
+
         match instr {
-            IntermediateInstruction::Loop(sub_instrs) => {
+            IntermediateInstruction::Loop(_, sub_instrs, span) => {
                 let fn_curr = get_curr_fn(ctx)?;
+                let loads = invariant_loads(sub_instrs)
+                    .into_iter()
+                    .filter(|offset| !loaded.contains_key(offset))
+                    .collect::<Vec<_>>();
 
-                // Branch past the end of the loop if the current cell holds 0,
-                // or to the body otherwise
+                // Branch past the end of the loop if the loop's cell holds 0,
+                // or to the body otherwise, through a preheader if it loads any cells
+                let bb_loop_preheader = (!loads.is_empty())
+                    .then(|| ctx.ctx.append_basic_block(fn_curr, "bb_loop_preheader"));
                 let bb_loop_body = ctx.ctx.append_basic_block(fn_curr, "bb_loop_body");
                 let bb_loop_end = ctx.ctx.append_basic_block(fn_curr, "bb_loop_end");
-                cond_branch(ctx, bb_loop_end, bb_loop_body)?;
+                cond_branch(
+                    ctx,
+                    load_cell(offset)?,
+                    bb_loop_end,
+                    bb_loop_preheader.unwrap_or(bb_loop_body),
+                )?;
+
+                // In the preheader, load the cells that every iteration reads but never changes
+                let mut body_loaded = loaded.clone();
+                if let Some(bb_loop_preheader) = bb_loop_preheader {
+                    ctx.builder.position_at_end(bb_loop_preheader);
+                    for cell in loads {
+                        body_loaded.insert(cell, load_mem_val(ctx, cell)?);
+                    }
+                    ctx.builder
+                        .build_unconditional_branch(bb_loop_body)
+                        .map_err(|_| {
+                            BFError::LlvmError("Failed to build jump into loop body".into())
+                        })?;
+                }
 
                 // In the loop body, encode the sub-instructions and conditionally branch again
                 ctx.builder.position_at_end(bb_loop_body);
-                Self::build_instructions(ctx, sub_instrs)?;
-                cond_branch(ctx, bb_loop_end, bb_loop_body)?;
+                Self::build_block(ctx, sub_instrs, &body_loaded)?;
+                ctx.set_location(*span);
+                cond_branch(ctx, load_cell(offset)?, bb_loop_end, bb_loop_body)?;
 
                 // Finally, at `bb_loop_end`, do nothing (future instructions will be added here)
                 ctx.builder.position_at_end(bb_loop_end);
             }
-            IntermediateInstruction::AddDynamic(target, multiplier) => {
-                let mem_val = load_mem_val(ctx)?;
-                let mem_val_i32 = extend_i8_i32(ctx, "mem_val_i32", mem_val)?;
+            IntermediateInstruction::AddDynamic(target, multiplier, _) => {
+                let mem_val = load_cell(0)?;
+                let mem_val_i32 = extend_cell_i32(ctx, "mem_val_i32", mem_val)?;
                 let product_val_i32 = ctx
                     .builder
                     .build_int_mul(
@@ -185,29 +285,146 @@ impl LLVMInstruction {
 
                 let mem_val_ptr = load(ctx, "mem_val_ptr", ctx.mem_ptr.typ, ctx.mem_ptr.val)?
                     .into_pointer_value();
-                let dst_ptr =
-                    shift_ptr(ctx, "mem_val_ptr", ctx.ctx.i8_type(), mem_val_ptr, *target)?;
-                let dst_val = load(ctx, "dst_val", ctx.ctx.i8_type(), dst_ptr)?.into_int_value();
-                let dst_val_i32 = extend_i8_i32(ctx, "dst_val_i32", dst_val)?;
+                let dst_ptr = shift_ptr(ctx, "mem_val_ptr", ctx.cell_type, mem_val_ptr, *target)?;
+                let dst_val = load(ctx, "dst_val", ctx.cell_type, dst_ptr)?.into_int_value();
+                let dst_val_i32 = extend_cell_i32(ctx, "dst_val_i32", dst_val)?;
 
                 let sum_val_i32 = int_add(ctx, "sum_val", product_val_i32, dst_val_i32)?;
-                let sum_val = truncate_i32_i8(ctx, "sum_val", sum_val_i32)?;
+                let sum_val = truncate_i32_cell(ctx, "sum_val", sum_val_i32)?;
                 store(ctx, "sum_val", dst_ptr, sum_val)?;
             }
-            IntermediateInstruction::Zero => {
-                store_mem_val(ctx, i8_val(0))?;
+            IntermediateInstruction::Zero(_, _) => {
+                store_mem_val(ctx, offset, cell_val(0))?;
+            }
+            IntermediateInstruction::Set(_, value, _) => {
+                store_mem_val(ctx, offset, cell_val(*value as u32 as u64))?;
             }
-            IntermediateInstruction::SimpleLoop(sub_instrs) => {
+            IntermediateInstruction::CheckBounds(probes, _) => {
+                let fn_curr = get_curr_fn(ctx)?;
+                let i64_type = ctx.ctx.i64_type();
+                let i64_val = |val: i64| i64_type.const_int(val as u64, true);
+
+                // The index of the current cell, from the start of the tape
+                let mem_val_ptr = load(ctx, "mem_val_ptr", ctx.mem_ptr.typ, ctx.mem_ptr.val)?
+                    .into_pointer_value();
+                let ptr_int = ctx
+                    .builder
+                    .build_ptr_to_int(mem_val_ptr, i64_type, "ptr_int")
+                    .map_err(|_| BFError::LlvmError("Failed to build `mem_ptr` cast".into()))?;
+                let mem_int = ctx
+                    .builder
+                    .build_ptr_to_int(ctx.mem.val, i64_type, "mem_int")
+                    .map_err(|_| BFError::LlvmError("Failed to build `mem` cast".into()))?;
+                let byte_offset = ctx
+                    .builder
+                    .build_int_sub(ptr_int, mem_int, "byte_offset")
+                    .map_err(|_| BFError::LlvmError("Failed to build byte offset".into()))?;
+                let index = ctx
+                    .builder
+                    .build_right_shift(
+                        byte_offset,
+                        i64_val(ctx.cell_width.bytes().trailing_zeros() as i64),
+                        true,
+                        "index",
+                    )
+                    .map_err(|_| BFError::LlvmError("Failed to build cell index".into()))?;
+
+                // `dprintf`, `exit`, and the error message are only added to the module
+                // if it has bounds checks
+                let fn_dprintf = ctx.module.get_function("dprintf").unwrap_or_else(|| {
+                    let typ = ctx.ctx.i32_type().fn_type(
+                        &[
+                            ctx.ctx.i32_type().into(),
+                            ctx.ctx.ptr_type(AddressSpace::default()).into(),
+                        ],
+                        true,
+                    );
+                    ctx.module.add_function("dprintf", typ, None)
+                });
+                let fn_exit = ctx.module.get_function("exit").unwrap_or_else(|| {
+                    let typ = ctx
+                        .ctx
+                        .void_type()
+                        .fn_type(&[ctx.ctx.i32_type().into()], false);
+                    ctx.module.add_function("exit", typ, None)
+                });
+                let message = match ctx.module.get_global("tape_overflow_message") {
+                    Some(message) => message,
+                    None => ctx
+                        .builder
+                        .build_global_string_ptr(
+                            "error: the data pointer moved off the memory tape, to cell %ld (at offset %ld)\n",
+                            "tape_overflow_message",
+                        )
+                        .map_err(|_| {
+                            BFError::LlvmError("Failed to build tape overflow message".into())
+                        })?,
+                };
+
+                // Indices are compared as unsigned values, so negative indices are too large
+                for (offset, position) in probes {
+                    let probe_index = int_add(ctx, "probe_index", index, i64_val(*offset as i64))?;
+                    let overflow_cond = ctx
+                        .builder
+                        .build_int_compare(
+                            IntPredicate::UGE,
+                            probe_index,
+                            i64_val(ctx.mem.typ.len() as i64),
+                            "overflow_cond",
+                        )
+                        .map_err(|_| BFError::LlvmError("Failed to build bounds check".into()))?;
+
+                    let bb_overflow = ctx.ctx.append_basic_block(fn_curr, "bb_overflow");
+                    let bb_in_bounds = ctx.ctx.append_basic_block(fn_curr, "bb_in_bounds");
+                    ctx.builder
+                        .build_conditional_branch(overflow_cond, bb_overflow, bb_in_bounds)
+                        .map_err(|_| {
+                            BFError::LlvmError("Failed to build conditional branch".into())
+                        })?;
+
+                    // Write out the buffered output, then print the error to stderr and exit
+                    // with status 1
+                    ctx.builder.position_at_end(bb_overflow);
+                    ctx.flush_output()?;
+                    ctx.builder
+                        .build_call(
+                            fn_dprintf,
+                            &[
+                                ctx.ctx.i32_type().const_int(2, false).into(),
+                                message.as_pointer_value().into(),
+                                probe_index.into(),
+                                i64_val(*position as i64).into(),
+                            ],
+                            "fn_dprintf_call",
+                        )
+                        .map_err(|_| {
+                            BFError::LlvmError("Failed to build call to `dprintf`".into())
+                        })?;
+                    ctx.builder
+                        .build_call(
+                            fn_exit,
+                            &[ctx.ctx.i32_type().const_int(1, false).into()],
+                            "fn_exit_call",
+                        )
+                        .map_err(|_| BFError::LlvmError("Failed to build call to `exit`".into()))?;
+                    ctx.builder
+                        .build_unreachable()
+                        .map_err(|_| BFError::LlvmError("Failed to build unreachable".into()))?;
+
+                    ctx.builder.position_at_end(bb_in_bounds);
+                }
+            }
+            IntermediateInstruction::SimpleLoop(sub_instrs, _) => {
                 let fn_curr = get_curr_fn(ctx)?;
 
                 // Branch past the simple loop contents if the current cell holds 0
                 let bb_loop_body = ctx.ctx.append_basic_block(fn_curr, "bb_loop_body");
                 let bb_loop_end = ctx.ctx.append_basic_block(fn_curr, "bb_loop_end");
-                cond_branch(ctx, bb_loop_end, bb_loop_body)?;
+                cond_branch(ctx, load_cell(0)?, bb_loop_end, bb_loop_body)?;
 
                 // In the loop body, encode the sub-instructions and unconditionally branch
                 ctx.builder.position_at_end(bb_loop_body);
-                Self::build_instructions(ctx, sub_instrs)?;
+                Self::build_block(ctx, sub_instrs, loaded)?;
                 ctx.builder
                     .build_unconditional_branch(bb_loop_end)
                     .map_err(|_| {
@@ -217,34 +434,309 @@ impl LLVMInstruction {
                 // Finally, at `bb_loop_end`, do nothing (future instructions will be added here)
                 ctx.builder.position_at_end(bb_loop_end);
             }
-            IntermediateInstruction::Move(stride) => {
+            IntermediateInstruction::Move(stride, _) => {
                 let mem_val_ptr = load(ctx, "mem_val_ptr", ctx.mem_ptr.typ, ctx.mem_ptr.val)?
                     .into_pointer_value();
                 let mem_val_ptr_shifted =
-                    shift_ptr(ctx, "mem_val_ptr", ctx.ctx.i8_type(), mem_val_ptr, *stride)?;
+                    shift_ptr(ctx, "mem_val_ptr", ctx.cell_type, mem_val_ptr, *stride)?;
                 store(ctx, "mem_val_ptr", ctx.mem_ptr.val, mem_val_ptr_shifted)?;
             }
-            IntermediateInstruction::Add(offset) => {
-                let mem_val = load_mem_val(ctx)?;
-                let sum_val = int_add(ctx, "sum_val", mem_val, i8_val(*offset as u64))?;
-                store_mem_val(ctx, sum_val)?;
+            IntermediateInstruction::Add(_, delta, _) => {
+                let mem_val = load_mem_val(ctx, offset)?;
+                let sum_val = int_add(ctx, "sum_val", mem_val, cell_val(*delta as u64))?;
+                store_mem_val(ctx, offset, sum_val)?;
             }
-            IntermediateInstruction::Read => {
+            IntermediateInstruction::Read(_, _) => {
+                // Buffered output has to be written out before waiting for input
+                ctx.flush_output()?;
                 let Some(ch_val) = call(ctx, "getchar", &[])?.try_as_basic_value().left() else {
                     return Err(BFError::LlvmError(
                         "Failed to get basic value from `getchar` call".into(),
                     ));
                 };
-                let ch_val_i8 = truncate_i32_i8(ctx, "ch_val_i8", ch_val.into_int_value())?;
-                store_mem_val(ctx, ch_val_i8)?;
+                let ch_val = ch_val.into_int_value();
+                // `getchar` returns -1 at the end of input, which sets every bit of the cell
+                // unless it's replaced with another value
+                let ch_val_cell = truncate_i32_cell(ctx, "ch_val_cell", ch_val)?;
+                let eof_val = match ctx.eof_behavior {
+                    EofBehavior::Unchanged => Some(load_mem_val(ctx, offset)?),
+                    EofBehavior::Zero => Some(cell_val(0)),
+                    EofBehavior::MinusOne => None,
+                };
+                let read_val = match eof_val {
+                    Some(eof_val) => {
+                        let is_eof = ctx
+                            .builder
+                            .build_int_compare(
+                                IntPredicate::EQ,
+                                ch_val,
+                                ctx.ctx.i32_type().const_all_ones(),
+                                "is_eof",
+                            )
+                            .map_err(|_| BFError::LlvmError("Failed to build EOF check".into()))?;
+                        ctx.builder
+                            .build_select(is_eof, eof_val, ch_val_cell, "read_val")
+                            .map_err(|_| BFError::LlvmError("Failed to build EOF select".into()))?
+                            .into_int_value()
+                    }
+                    None => ch_val_cell,
+                };
+                store_mem_val(ctx, offset, read_val)?;
             }
-            IntermediateInstruction::Write => {
-                let mem_val = load_mem_val(ctx)?;
-                let mem_val_i32 = extend_i8_i32(ctx, "mem_val_i32", mem_val)?;
-                call(ctx, "putchar", &[mem_val_i32.into()])?;
+            IntermediateInstruction::Write(_, _) => {
+                let mem_val = load_cell(offset)?;
+                let byte_val = ctx
+                    .builder
+                    .build_int_truncate_or_bit_cast(mem_val, ctx.ctx.i8_type(), "byte_val")
+                    .map_err(|_| BFError::LlvmError("Failed to build output byte".into()))?;
+                write_byte(ctx, byte_val)?;
             }
-            IntermediateInstruction::Scan(_) => {
-                todo!() //
+            IntermediateInstruction::Output(bytes, _) => {
+                let fn_curr = get_curr_fn(ctx)?;
+                let Some(bb_output_entry) = ctx.builder.get_insert_block() else {
+                    return Err(BFError::LlvmError("Builder is not in a basic block".into()));
+                };
+                let bb_output_loop = ctx.ctx.append_basic_block(fn_curr, "bb_output_loop");
+                let bb_output_end = ctx.ctx.append_basic_block(fn_curr, "bb_output_end");
+
+                let output_str = {
+                    let val = ctx.ctx.const_string(bytes, false);
+                    let global = ctx.module.add_global(val.get_type(), None, "bf_output_str");
+                    global.set_initializer(&val);
+                    global.set_constant(true);
+                    global.set_linkage(Linkage::Private);
+                    global.as_pointer_value()
+                };
+
+                // Write each byte of the string in turn
+                let i64_type = ctx.ctx.i64_type();
+                ctx.builder
+                    .build_unconditional_branch(bb_output_loop)
+                    .map_err(|_| BFError::LlvmError("Failed to build jump into output".into()))?;
+                ctx.builder.position_at_end(bb_output_loop);
+                let index = ctx
+                    .builder
+                    .build_phi(i64_type, "output_index")
+                    .map_err(|_| BFError::LlvmError("Failed to build output index".into()))?;
+                let index_val = index.as_basic_value().into_int_value();
+                let byte_ptr = unsafe {
+                    ctx.builder
+                        .build_gep(ctx.ctx.i8_type(), output_str, &[index_val], "output_ptr")
+                        .map_err(|_| BFError::LlvmError("Failed to build output `gep`".into()))?
+                };
+                let byte_val = load(ctx, "output_byte", ctx.ctx.i8_type(), byte_ptr)?;
+                write_byte(ctx, byte_val.into_int_value())?;
+
+                let next_index = int_add(
+                    ctx,
+                    "next_output_index",
+                    index_val,
+                    i64_type.const_int(1, false),
+                )?;
+                let is_done = ctx
+                    .builder
+                    .build_int_compare(
+                        IntPredicate::EQ,
+                        next_index,
+                        i64_type.const_int(bytes.len() as u64, false),
+                        "output_done",
+                    )
+                    .map_err(|_| BFError::LlvmError("Failed to build output condition".into()))?;
+                let Some(bb_output_next) = ctx.builder.get_insert_block() else {
+                    return Err(BFError::LlvmError("Builder is not in a basic block".into()));
+                };
+                ctx.builder
+                    .build_conditional_branch(is_done, bb_output_end, bb_output_loop)
+                    .map_err(|_| BFError::LlvmError("Failed to build output branch".into()))?;
+                index.add_incoming(&[
+                    (&i64_type.const_zero(), bb_output_entry),
+                    (&next_index, bb_output_next),
+                ]);
+
+                ctx.builder.position_at_end(bb_output_end);
+            }
+            IntermediateInstruction::Scan(stride, _) => {
+                let fn_curr = get_curr_fn(ctx)?;
+                let Some(bb_scan_entry) = ctx.builder.get_insert_block() else {
+                    return Err(BFError::LlvmError("Builder is not in a basic block".into()));
+                };
+                let bb_scan_loop = ctx.ctx.append_basic_block(fn_curr, "bb_scan_loop");
+                let bb_scan_end = ctx.ctx.append_basic_block(fn_curr, "bb_scan_end");
+
+                let mem_val_ptr = load(ctx, "mem_val_ptr", ctx.mem_ptr.typ, ctx.mem_ptr.val)?
+                    .into_pointer_value();
+                ctx.builder
+                    .build_unconditional_branch(bb_scan_loop)
+                    .map_err(|_| BFError::LlvmError("Failed to build jump into scan".into()))?;
+                ctx.builder.position_at_end(bb_scan_loop);
+
+                // Bits of the 32-byte comparison mask that correspond to cells in the scan.
+                // Backward scans load the 32 bytes *ending* at the current cell.
+                // Only 8-bit cells are vectorized.
+                let stride_mask: Option<u64> = match (ctx.cell_width, stride) {
+                    (CellWidth::Bits8, 1 | -1) => Some(0xFFFFFFFF),
+                    (CellWidth::Bits8, 2) => Some(0x55555555),
+                    (CellWidth::Bits8, -2) => Some(0xAAAAAAAA),
+                    (CellWidth::Bits8, 4) => Some(0x11111111),
+                    (CellWidth::Bits8, -4) => Some(0x88888888),
+                    _ => None,
+                };
+
+                let Some(stride_mask) = stride_mask else {
+                    // Scalar fallback: check one cell per iteration
+                    let cell_ptr = ctx
+                        .builder
+                        .build_phi(ctx.mem_ptr.typ, "scan_cell_ptr")
+                        .map_err(|_| BFError::LlvmError("Failed to build scan phi".into()))?;
+                    let cell_ptr_val = cell_ptr.as_basic_value().into_pointer_value();
+                    let scan_cell_val =
+                        load(ctx, "scan_cell_val", ctx.cell_type, cell_ptr_val)?.into_int_value();
+                    let next_ptr =
+                        shift_ptr(ctx, "scan_cell_ptr", ctx.cell_type, cell_ptr_val, *stride)?;
+                    cell_ptr
+                        .add_incoming(&[(&mem_val_ptr, bb_scan_entry), (&next_ptr, bb_scan_loop)]);
+
+                    let is_zero = ctx
+                        .builder
+                        .build_int_compare(
+                            IntPredicate::EQ,
+                            scan_cell_val,
+                            cell_val(0),
+                            "scan_is_zero",
+                        )
+                        .map_err(|_| BFError::LlvmError("Failed to build scan condition".into()))?;
+                    ctx.builder
+                        .build_conditional_branch(is_zero, bb_scan_end, bb_scan_loop)
+                        .map_err(|_| BFError::LlvmError("Failed to build scan branch".into()))?;
+
+                    ctx.builder.position_at_end(bb_scan_end);
+                    store(ctx, "mem_val_ptr", ctx.mem_ptr.val, cell_ptr_val)?;
+                    return Ok(());
+                };
+
+                // Vectorized scan: compare 32 cells against zero per iteration
+                let i32_val = |val: i64| ctx.ctx.i32_type().const_int(val as u64, true);
+                let vec_type = ctx.ctx.i8_type().vec_type(32);
+                let (start_offset, step) = if *stride > 0 { (0, 32) } else { (-31, -32) };
+
+                let offset = ctx
+                    .builder
+                    .build_phi(ctx.ctx.i32_type(), "scan_offset")
+                    .map_err(|_| BFError::LlvmError("Failed to build scan phi".into()))?;
+                let offset_val = offset.as_basic_value().into_int_value();
+                let block_ptr = unsafe {
+                    ctx.builder
+                        .build_gep(
+                            ctx.ctx.i8_type(),
+                            mem_val_ptr,
+                            &[offset_val],
+                            "scan_block_ptr",
+                        )
+                        .map_err(|_| BFError::LlvmError("Failed to build scan `gep`".into()))?
+                };
+                let block_val = load(ctx, "scan_block", vec_type, block_ptr)?;
+                if let Some(block_load) = block_val.as_instruction_value() {
+                    block_load.set_alignment(1).map_err(|err| {
+                        BFError::LlvmError(format!("Failed to align scan load: `{}`", err))
+                    })?;
+                }
+                let next_offset = int_add(ctx, "scan_next_offset", offset_val, i32_val(step))?;
+                offset.add_incoming(&[
+                    (&i32_val(start_offset), bb_scan_entry),
+                    (&next_offset, bb_scan_loop),
+                ]);
+
+                let zeroes = ctx
+                    .builder
+                    .build_int_compare(
+                        IntPredicate::EQ,
+                        block_val.into_vector_value(),
+                        vec_type.const_zero(),
+                        "scan_zeroes",
+                    )
+                    .map_err(|_| BFError::LlvmError("Failed to build scan comparison".into()))?;
+                let zero_mask = ctx
+                    .builder
+                    .build_bit_cast(zeroes, ctx.ctx.i32_type(), "scan_zero_mask")
+                    .map_err(|_| BFError::LlvmError("Failed to build scan bitmask".into()))?
+                    .into_int_value();
+                let found_mask = ctx
+                    .builder
+                    .build_and(
+                        zero_mask,
+                        ctx.ctx.i32_type().const_int(stride_mask, false),
+                        "scan_found_mask",
+                    )
+                    .map_err(|_| BFError::LlvmError("Failed to build scan stride mask".into()))?;
+                let found = ctx
+                    .builder
+                    .build_int_compare(
+                        IntPredicate::NE,
+                        found_mask,
+                        ctx.ctx.i32_type().const_zero(),
+                        "scan_found",
+                    )
+                    .map_err(|_| BFError::LlvmError("Failed to build scan condition".into()))?;
+                ctx.builder
+                    .build_conditional_branch(found, bb_scan_end, bb_scan_loop)
+                    .map_err(|_| BFError::LlvmError("Failed to build scan branch".into()))?;
+
+                // Once a match is found, count the trailing (forwards) or leading (backwards)
+                // zeroes of the mask to find the matching cell within the block
+                ctx.builder.position_at_end(bb_scan_end);
+                let intrinsic_name = if *stride > 0 {
+                    "llvm.cttz"
+                } else {
+                    "llvm.ctlz"
+                };
+                let Some(count_fn) = Intrinsic::find(intrinsic_name).and_then(|intrinsic| {
+                    intrinsic.get_declaration(&ctx.module, &[ctx.ctx.i32_type().into()])
+                }) else {
+                    return Err(BFError::LlvmError(format!(
+                        "Failed to declare intrinsic `{}`",
+                        intrinsic_name
+                    )));
+                };
+                let Some(zero_count) = ctx
+                    .builder
+                    .build_call(
+                        count_fn,
+                        &[
+                            found_mask.into(),
+                            ctx.ctx.bool_type().const_int(1, false).into(),
+                        ],
+                        "scan_zero_count",
+                    )
+                    .map_err(|_| {
+                        BFError::LlvmError(format!("Failed to build call to `{}`", intrinsic_name))
+                    })?
+                    .try_as_basic_value()
+                    .left()
+                else {
+                    return Err(BFError::LlvmError(format!(
+                        "Failed to get basic value from `{}` call",
+                        intrinsic_name
+                    )));
+                };
+                let match_index = if *stride > 0 {
+                    zero_count.into_int_value()
+                } else {
+                    ctx.builder
+                        .build_int_sub(i32_val(31), zero_count.into_int_value(), "scan_index")
+                        .map_err(|_| BFError::LlvmError("Failed to build scan index".into()))?
+                };
+                let match_offset = int_add(ctx, "scan_match_offset", offset_val, match_index)?;
+                let match_ptr = unsafe {
+                    ctx.builder
+                        .build_gep(
+                            ctx.ctx.i8_type(),
+                            mem_val_ptr,
+                            &[match_offset],
+                            "scan_match_ptr",
+                        )
+                        .map_err(|_| BFError::LlvmError("Failed to build scan `gep`".into()))?
+                };
+                store(ctx, "mem_val_ptr", ctx.mem_ptr.val, match_ptr)?;
             }
         }
         Ok(())
@@ -253,9 +745,18 @@ impl LLVMInstruction {
     pub fn build_instructions(
         ctx: &LlvmContext,
         instrs: &[IntermediateInstruction],
+    ) -> BFResult<()> {
+        Self::build_block(ctx, instrs, &HashMap::new())
+    }
+
+    fn build_block<'c>(
+        ctx: &'c LlvmContext,
+        instrs: &[IntermediateInstruction],
+        loaded: &HashMap<i32, IntValue<'c>>,
     ) -> BFResult<()> {
         for instr in instrs {
-            Self::build_instruction(ctx, instr)?;
+            ctx.set_location(instr.span());
+            Self::build_instruction(ctx, instr, loaded)?;
         }
         Ok(())
     }
@@ -0,0 +1,156 @@
+use crate::{BFError, BFResult};
+use inkwell::context::Context;
+use inkwell::module::{Linkage, Module};
+use inkwell::values::{FunctionValue, PointerValue};
+use inkwell::{AddressSpace, IntPredicate};
+
+/// The number of bytes of output that are buffered before they are written out
+pub(crate) const OUTPUT_BUFFER_SIZE: u32 = 4096;
+
+/// The output buffer of a module compiled from a BF program
+///
+/// Output is collected in `bf_output`, with the number of buffered bytes in `bf_output_len`.
+/// `bf_flush` writes the buffer out to stdout with `write`, and empties it.
+pub struct LlvmOutputBuffer<'c> {
+    /// A pointer to `bf_output`
+    pub buffer: PointerValue<'c>,
+    /// A pointer to `bf_output_len`
+    pub len: PointerValue<'c>,
+    /// The `bf_flush` function
+    pub fn_flush: FunctionValue<'c>,
+}
+
+impl<'c> LlvmOutputBuffer<'c> {
+    /// Adds the output buffer and `bf_flush` to the given module
+    pub fn new(ctx: &'c Context, module: &Module<'c>) -> BFResult<Self> {
+        let err =
+            |what: &str| BFError::LlvmError(format!("Failed to build {} in `bf_flush`", what));
+        let i64_type = ctx.i64_type();
+        let ptr_type = ctx.ptr_type(AddressSpace::default());
+
+        let buffer = {
+            let typ = ctx.i8_type().array_type(OUTPUT_BUFFER_SIZE);
+            let global = module.add_global(typ, None, "bf_output");
+            global.set_initializer(&typ.const_zero());
+            global.set_linkage(Linkage::Internal);
+            global.as_pointer_value()
+        };
+        let len = {
+            let global = module.add_global(i64_type, None, "bf_output_len");
+            global.set_initializer(&i64_type.const_zero());
+            global.set_linkage(Linkage::Internal);
+            global.as_pointer_value()
+        };
+
+        let fn_write = module.add_function(
+            "write",
+            i64_type.fn_type(
+                &[ctx.i32_type().into(), ptr_type.into(), i64_type.into()],
+                false,
+            ),
+            None,
+        );
+        let fn_flush = module.add_function(
+            "bf_flush",
+            ctx.void_type().fn_type(&[], false),
+            Some(Linkage::Internal),
+        );
+
+        // `write` may write fewer bytes than asked, so it is repeated until the whole buffer is
+        // written, or until it fails
+        let builder = ctx.create_builder();
+        let bb_entry = ctx.append_basic_block(fn_flush, "bb_entry");
+        let bb_loop = ctx.append_basic_block(fn_flush, "bb_loop");
+        let bb_write = ctx.append_basic_block(fn_flush, "bb_write");
+        let bb_advance = ctx.append_basic_block(fn_flush, "bb_advance");
+        let bb_done = ctx.append_basic_block(fn_flush, "bb_done");
+
+        builder.position_at_end(bb_entry);
+        let total = builder
+            .build_load(i64_type, len, "total")
+            .map_err(|_| err("load of the buffer length"))?
+            .into_int_value();
+        builder
+            .build_unconditional_branch(bb_loop)
+            .map_err(|_| err("jump into the loop"))?;
+
+        builder.position_at_end(bb_loop);
+        let written = builder
+            .build_phi(i64_type, "written")
+            .map_err(|_| err("`phi` for the written length"))?;
+        let written_val = written.as_basic_value().into_int_value();
+        let remaining = builder
+            .build_int_sub(total, written_val, "remaining")
+            .map_err(|_| err("remaining length"))?;
+        let is_done = builder
+            .build_int_compare(
+                IntPredicate::EQ,
+                remaining,
+                i64_type.const_zero(),
+                "is_done",
+            )
+            .map_err(|_| err("loop condition"))?;
+        builder
+            .build_conditional_branch(is_done, bb_done, bb_write)
+            .map_err(|_| err("loop branch"))?;
+
+        builder.position_at_end(bb_write);
+        let start = unsafe {
+            builder
+                .build_gep(ctx.i8_type(), buffer, &[written_val], "start")
+                .map_err(|_| err("`gep` into the buffer"))?
+        };
+        let Some(count) = builder
+            .build_call(
+                fn_write,
+                &[
+                    ctx.i32_type().const_int(1, false).into(),
+                    start.into(),
+                    remaining.into(),
+                ],
+                "count",
+            )
+            .map_err(|_| err("call to `write`"))?
+            .try_as_basic_value()
+            .left()
+        else {
+            return Err(err("result of `write`"));
+        };
+        let count = count.into_int_value();
+        let is_written = builder
+            .build_int_compare(
+                IntPredicate::SGT,
+                count,
+                i64_type.const_zero(),
+                "is_written",
+            )
+            .map_err(|_| err("check of the result of `write`"))?;
+        builder
+            .build_conditional_branch(is_written, bb_advance, bb_done)
+            .map_err(|_| err("branch on the result of `write`"))?;
+
+        builder.position_at_end(bb_advance);
+        let next_written = builder
+            .build_int_add(written_val, count, "next_written")
+            .map_err(|_| err("written length"))?;
+        builder
+            .build_unconditional_branch(bb_loop)
+            .map_err(|_| err("jump back into the loop"))?;
+        written.add_incoming(&[
+            (&i64_type.const_zero(), bb_entry),
+            (&next_written, bb_advance),
+        ]);
+
+        builder.position_at_end(bb_done);
+        builder
+            .build_store(len, i64_type.const_zero())
+            .map_err(|_| err("reset of the buffer length"))?;
+        builder.build_return(None).map_err(|_| err("return"))?;
+
+        Ok(Self {
+            buffer,
+            len,
+            fn_flush,
+        })
+    }
+}
@@ -1,5 +1,11 @@
 mod _llvm_context;
 pub use _llvm_context::*;
 
+mod _llvm_debug_info;
+pub use _llvm_debug_info::*;
+
 mod _llvm_instruction;
 pub use _llvm_instruction::*;
+
+mod _llvm_output_buffer;
+pub use _llvm_output_buffer::*;
@@ -1,2 +1,3 @@
 pub mod amd64;
 pub mod llvm;
+pub mod wasm;
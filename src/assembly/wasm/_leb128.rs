@@ -0,0 +1,54 @@
+//! LEB128 variable-length integer encoding, as used throughout the WebAssembly binary format
+
+/// Encodes the given value as an unsigned LEB128 integer
+pub fn encode_unsigned(mut value: u64) -> Vec<u8> {
+    let mut bytes = vec![];
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Encodes the given value as a signed LEB128 integer
+pub fn encode_signed(mut value: i64) -> Vec<u8> {
+    let mut bytes = vec![];
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        // Done once the remaining bits are all copies of the sign bit of `byte`
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_signed, encode_unsigned};
+
+    #[test]
+    fn test_encode_unsigned() {
+        assert_eq!(encode_unsigned(0), vec![0x00]);
+        assert_eq!(encode_unsigned(127), vec![0x7F]);
+        assert_eq!(encode_unsigned(128), vec![0x80, 0x01]);
+        assert_eq!(encode_unsigned(624485), vec![0xE5, 0x8E, 0x26]);
+    }
+
+    #[test]
+    fn test_encode_signed() {
+        assert_eq!(encode_signed(0), vec![0x00]);
+        assert_eq!(encode_signed(63), vec![0x3F]);
+        assert_eq!(encode_signed(64), vec![0xC0, 0x00]);
+        assert_eq!(encode_signed(-1), vec![0x7F]);
+        assert_eq!(encode_signed(-64), vec![0x40]);
+        assert_eq!(encode_signed(-65), vec![0xBF, 0x7F]);
+        assert_eq!(encode_signed(-123456), vec![0xC0, 0xBB, 0x78]);
+    }
+}
@@ -0,0 +1,504 @@
+use super::{encode_signed, encode_unsigned};
+use crate::instruction::IntermediateInstruction;
+use crate::{CellWidth, EofBehavior};
+use std::fmt::{Display, Formatter};
+
+/// The index of the local variable that holds the data pointer
+pub const POINTER_LOCAL: u32 = 0;
+
+/// The index of the local variable that holds the character just read
+pub const READ_LOCAL: u32 = 1;
+
+/// The index of the local variable that holds the size of the tape in bytes,
+/// which is only declared for programs with bounds checks
+pub const TAPE_SIZE_LOCAL: u32 = 2;
+
+/// Represents a function imported from the host environment
+#[derive(Copy, Clone, Debug)]
+pub enum WasmImport {
+    /// `read: () -> i32`, which returns the next byte of input, or -1 at the end of the input
+    Read,
+
+    /// `write: (i32) -> ()`, which writes the given byte to the output
+    Write,
+
+    /// `tape_overflow: (i32, i32) -> ()`, which reports that the check at the given position
+    /// found the given cell index off the tape. It is only imported for programs
+    /// with bounds checks, and must not return
+    TapeOverflow,
+}
+
+impl WasmImport {
+    /// All imported functions, in order of their function index
+    pub const ALL: [WasmImport; 3] = [
+        WasmImport::Read,
+        WasmImport::Write,
+        WasmImport::TapeOverflow,
+    ];
+
+    /// Returns the functions imported by the given program, in order of their function index
+    pub fn imports(src: &[IntermediateInstruction]) -> &'static [WasmImport] {
+        if IntermediateInstruction::has_bounds_checks(src) {
+            &Self::ALL
+        } else {
+            &Self::ALL[..2]
+        }
+    }
+
+    /// Returns the function index of this import
+    pub fn index(&self) -> u32 {
+        match self {
+            WasmImport::Read => 0,
+            WasmImport::Write => 1,
+            WasmImport::TapeOverflow => 2,
+        }
+    }
+}
+
+impl Display for WasmImport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                WasmImport::Read => "read",
+                WasmImport::Write => "write",
+                WasmImport::TapeOverflow => "tape_overflow",
+            }
+        )
+    }
+}
+
+/// Represents a single WebAssembly instruction
+#[derive(Clone, Debug)]
+pub enum WasmInstruction {
+    /// `block`, with an empty block type
+    Block,
+    /// `loop`, with an empty block type
+    Loop,
+    /// `if`, with an empty block type
+    If,
+    /// `end`
+    End,
+    /// `br <depth>`
+    Br(u32),
+    /// `br_if <depth>`
+    BrIf(u32),
+    /// `call <function>`
+    Call(WasmImport),
+
+    /// `local.get <index>`
+    LocalGet(u32),
+    /// `local.set <index>`
+    LocalSet(u32),
+    /// `local.tee <index>`
+    LocalTee(u32),
+    /// `select`
+    Select,
+
+    /// `i32.load8_u offset=<offset>`
+    I32Load8U(u32),
+    /// `i32.store8 offset=<offset>`
+    I32Store8(u32),
+    /// `i32.load16_u offset=<offset>`
+    I32Load16U(u32),
+    /// `i32.store16 offset=<offset>`
+    I32Store16(u32),
+    /// `i32.load offset=<offset>`
+    I32Load(u32),
+    /// `i32.store offset=<offset>`
+    I32Store(u32),
+
+    /// `i32.const <value>`
+    I32Const(i32),
+    /// `i32.eqz`
+    I32Eqz,
+    /// `i32.ne`
+    I32Ne,
+    /// `i32.add`
+    I32Add,
+    /// `i32.mul`
+    I32Mul,
+    /// `i32.ge_u`
+    I32GeU,
+    /// `i32.shr_s`
+    I32ShrS,
+    /// `unreachable`
+    Unreachable,
+}
+
+use WasmInstruction::*;
+
+impl WasmInstruction {
+    /// Returns the instruction that loads a cell of the given width
+    fn load(cell_width: CellWidth) -> WasmInstruction {
+        match cell_width {
+            CellWidth::Bits8 => I32Load8U(0),
+            CellWidth::Bits16 => I32Load16U(0),
+            CellWidth::Bits32 => I32Load(0),
+        }
+    }
+
+    /// Returns the instruction that stores a cell of the given width
+    fn store(cell_width: CellWidth) -> WasmInstruction {
+        match cell_width {
+            CellWidth::Bits8 => I32Store8(0),
+            CellWidth::Bits16 => I32Store16(0),
+            CellWidth::Bits32 => I32Store(0),
+        }
+    }
+
+    /// Returns instructions that push the address of the cell at the given offset
+    /// from the data pointer
+    fn address(offset: i32, cell_width: CellWidth) -> Vec<WasmInstruction> {
+        if offset == 0 {
+            vec![LocalGet(POINTER_LOCAL)]
+        } else {
+            vec![
+                LocalGet(POINTER_LOCAL),
+                I32Const(offset * cell_width.bytes() as i32),
+                I32Add,
+            ]
+        }
+    }
+
+    /// Returns instructions that push the value of the current cell
+    fn load_cell(cell_width: CellWidth) -> Vec<WasmInstruction> {
+        vec![LocalGet(POINTER_LOCAL), Self::load(cell_width)]
+    }
+
+    /// Converts a single abstract BF instruction into a vector of WebAssembly instructions
+    ///
+    /// Stores truncate values to the cell width, so arithmetic on cells wraps around
+    pub fn convert_instruction(
+        instr: &IntermediateInstruction,
+        cell_width: CellWidth,
+        eof_behavior: EofBehavior,
+    ) -> Vec<WasmInstruction> {
+        use IntermediateInstruction::*;
+
+        let cell_bytes = cell_width.bytes() as i32;
+        let load_cell = || Self::load_cell(cell_width);
+        let store = Self::store(cell_width);
+        // The address of the cell that the instruction acts on
+        let cell = || Self::address(instr.cell_offset(), cell_width);
+
+        match instr {
+            Loop(_, instrs, _) => [
+                // If the loop's cell's value is zero, break out of the block
+                vec![WasmInstruction::Block],
+                cell(),
+                vec![
+                    Self::load(cell_width),
+                    I32Eqz,
+                    BrIf(0),
+                    WasmInstruction::Loop,
+                ],
+                Self::convert_instructions(instrs, cell_width, eof_behavior),
+                // If the loop's cell's value is nonzero, continue the loop
+                cell(),
+                vec![Self::load(cell_width), BrIf(0), End, End],
+            ]
+            .concat(),
+
+            Move(offset, _) => vec![
+                LocalGet(POINTER_LOCAL),
+                I32Const(*offset * cell_bytes),
+                I32Add,
+                LocalSet(POINTER_LOCAL),
+            ],
+
+            Add(_, delta, _) => [
+                cell(),
+                cell(),
+                vec![Self::load(cell_width), I32Const(*delta), I32Add, store],
+            ]
+            .concat(),
+
+            // `read` returns -1 at the end of input, which sets every bit of the cell
+            // unless `select` replaces it with another value
+            Read(_, _) => [
+                cell(),
+                vec![Call(WasmImport::Read)],
+                match eof_behavior {
+                    EofBehavior::Unchanged => [
+                        vec![LocalTee(READ_LOCAL)],
+                        cell(),
+                        vec![
+                            Self::load(cell_width),
+                            LocalGet(READ_LOCAL),
+                            I32Const(-1),
+                            I32Ne,
+                            Select,
+                        ],
+                    ]
+                    .concat(),
+                    EofBehavior::Zero => vec![
+                        LocalTee(READ_LOCAL),
+                        I32Const(0),
+                        LocalGet(READ_LOCAL),
+                        I32Const(-1),
+                        I32Ne,
+                        Select,
+                    ],
+                    EofBehavior::MinusOne => vec![],
+                },
+                vec![store],
+            ]
+            .concat(),
+
+            Write(_, _) => [
+                cell(),
+                vec![Self::load(cell_width), Call(WasmImport::Write)],
+            ]
+            .concat(),
+
+            Output(bytes, _) => bytes
+                .iter()
+                .flat_map(|byte| [I32Const(*byte as i32), Call(WasmImport::Write)])
+                .collect(),
+
+            AddDynamic(target, multiplier, _) => [
+                Self::address(*target, cell_width),
+                Self::address(*target, cell_width),
+                vec![Self::load(cell_width)],
+                load_cell(),
+                vec![I32Const(*multiplier), I32Mul, I32Add, store],
+            ]
+            .concat(),
+
+            // Skip the simple loop if the current cell's value is zero
+            SimpleLoop(instrs, _) => [
+                load_cell(),
+                vec![If],
+                Self::convert_instructions(instrs, cell_width, eof_behavior),
+                vec![End],
+            ]
+            .concat(),
+
+            Zero(_, _) => [cell(), vec![I32Const(0), store]].concat(),
+
+            Set(_, value, _) => [cell(), vec![I32Const(*value), store]].concat(),
+
+            // Byte offsets from the start of the tape are compared as unsigned values,
+            // so negative offsets are too large
+            CheckBounds(probes, _) => probes
+                .iter()
+                .map(|(offset, position)| {
+                    [
+                        Self::address(*offset, cell_width),
+                        vec![LocalGet(TAPE_SIZE_LOCAL), I32GeU, If],
+                        vec![I32Const(*position as i32)],
+                        Self::address(*offset, cell_width),
+                        vec![
+                            I32Const(cell_bytes.trailing_zeros() as i32),
+                            I32ShrS,
+                            Call(WasmImport::TapeOverflow),
+                            Unreachable,
+                            End,
+                        ],
+                    ]
+                    .concat()
+                })
+                .collect::<Vec<Vec<WasmInstruction>>>()
+                .concat(),
+
+            // Step the data pointer by the stride until a zero cell is found
+            Scan(stride, _) => [
+                vec![WasmInstruction::Block, WasmInstruction::Loop],
+                load_cell(),
+                vec![
+                    I32Eqz,
+                    BrIf(1),
+                    LocalGet(POINTER_LOCAL),
+                    I32Const(*stride * cell_bytes),
+                    I32Add,
+                    LocalSet(POINTER_LOCAL),
+                    Br(0),
+                    End,
+                    End,
+                ],
+            ]
+            .concat(),
+        }
+    }
+
+    /// Converts a set of abstract BF instructions into a vector of WebAssembly instructions
+    pub fn convert_instructions(
+        instrs: &[IntermediateInstruction],
+        cell_width: CellWidth,
+        eof_behavior: EofBehavior,
+    ) -> Vec<WasmInstruction> {
+        instrs
+            .iter()
+            .map(|instr| Self::convert_instruction(instr, cell_width, eof_behavior))
+            .collect::<Vec<Vec<WasmInstruction>>>()
+            .concat()
+    }
+
+    /// Encodes this instruction in binary
+    pub fn to_binary(&self) -> Vec<u8> {
+        // The empty block type
+        const EMPTY: u8 = 0x40;
+
+        // Encodes a memory argument: the alignment (as a power of 2), then the offset
+        let memarg = |opcode: u8, align: u8, offset: u32| {
+            [vec![opcode, align], encode_unsigned(offset as u64)].concat()
+        };
+
+        match self {
+            Block => vec![0x02, EMPTY],
+            Loop => vec![0x03, EMPTY],
+            If => vec![0x04, EMPTY],
+            End => vec![0x0B],
+            Br(depth) => [vec![0x0C], encode_unsigned(*depth as u64)].concat(),
+            BrIf(depth) => [vec![0x0D], encode_unsigned(*depth as u64)].concat(),
+            Call(func) => [vec![0x10], encode_unsigned(func.index() as u64)].concat(),
+
+            LocalGet(index) => [vec![0x20], encode_unsigned(*index as u64)].concat(),
+            LocalSet(index) => [vec![0x21], encode_unsigned(*index as u64)].concat(),
+            LocalTee(index) => [vec![0x22], encode_unsigned(*index as u64)].concat(),
+            Select => vec![0x1B],
+
+            I32Load8U(offset) => memarg(0x2D, 0, *offset),
+            I32Store8(offset) => memarg(0x3A, 0, *offset),
+            I32Load16U(offset) => memarg(0x2F, 1, *offset),
+            I32Store16(offset) => memarg(0x3B, 1, *offset),
+            I32Load(offset) => memarg(0x28, 2, *offset),
+            I32Store(offset) => memarg(0x36, 2, *offset),
+
+            I32Const(value) => [vec![0x41], encode_signed(*value as i64)].concat(),
+            I32Eqz => vec![0x45],
+            I32Ne => vec![0x47],
+            I32Add => vec![0x6A],
+            I32Mul => vec![0x6C],
+            I32GeU => vec![0x4F],
+            I32ShrS => vec![0x75],
+            Unreachable => vec![0x00],
+        }
+    }
+}
+
+impl Display for WasmInstruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Block => write!(f, "block"),
+            Loop => write!(f, "loop"),
+            If => write!(f, "if"),
+            End => write!(f, "end"),
+            Br(depth) => write!(f, "br {}", depth),
+            BrIf(depth) => write!(f, "br_if {}", depth),
+            Call(func) => write!(f, "call ${}", func),
+
+            LocalGet(index) => write!(f, "local.get {}", index),
+            LocalSet(index) => write!(f, "local.set {}", index),
+            LocalTee(index) => write!(f, "local.tee {}", index),
+            Select => write!(f, "select"),
+
+            I32Load8U(0) => write!(f, "i32.load8_u"),
+            I32Load8U(offset) => write!(f, "i32.load8_u offset={}", offset),
+            I32Store8(0) => write!(f, "i32.store8"),
+            I32Store8(offset) => write!(f, "i32.store8 offset={}", offset),
+            I32Load16U(0) => write!(f, "i32.load16_u"),
+            I32Load16U(offset) => write!(f, "i32.load16_u offset={}", offset),
+            I32Store16(0) => write!(f, "i32.store16"),
+            I32Store16(offset) => write!(f, "i32.store16 offset={}", offset),
+            I32Load(0) => write!(f, "i32.load"),
+            I32Load(offset) => write!(f, "i32.load offset={}", offset),
+            I32Store(0) => write!(f, "i32.store"),
+            I32Store(offset) => write!(f, "i32.store offset={}", offset),
+
+            I32Const(value) => write!(f, "i32.const {}", value),
+            I32Eqz => write!(f, "i32.eqz"),
+            I32Ne => write!(f, "i32.ne"),
+            I32Add => write!(f, "i32.add"),
+            I32Mul => write!(f, "i32.mul"),
+            I32GeU => write!(f, "i32.ge_u"),
+            I32ShrS => write!(f, "i32.shr_s"),
+            Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WasmImport, WasmInstruction};
+    use crate::instruction::IntermediateInstruction;
+    use crate::{CellWidth, EofBehavior, Span};
+
+    #[test]
+    fn test_encode_instructions() {
+        use WasmInstruction::*;
+
+        let instrs = vec![
+            Block,
+            Loop,
+            LocalGet(0),
+            I32Load8U(0),
+            I32Eqz,
+            BrIf(1),
+            I32Const(-300),
+            Call(WasmImport::Write),
+            I32Store8(3),
+            Br(0),
+            End,
+            End,
+        ];
+        assert_eq!(
+            instrs
+                .iter()
+                .map(|i| i.to_binary())
+                .collect::<Vec<_>>()
+                .concat(),
+            vec![
+                0x02, 0x40, 0x03, 0x40, 0x20, 0x00, 0x2D, 0x00, 0x00, 0x45, 0x0D, 0x01, 0x41, 0xD4,
+                0x7D, 0x10, 0x01, 0x3A, 0x00, 0x03, 0x0C, 0x00, 0x0B, 0x0B
+            ]
+        );
+        assert_eq!(
+            instrs
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            "block\nloop\nlocal.get 0\ni32.load8_u\ni32.eqz\nbr_if 1\ni32.const -300\n\
+             call $write\ni32.store8 offset=3\nbr 0\nend\nend"
+        );
+    }
+
+    #[test]
+    fn test_convert_loop_is_balanced() {
+        use IntermediateInstruction::*;
+
+        let span = Span::default();
+        let src = vec![
+            Loop(
+                0,
+                vec![
+                    CheckBounds(vec![(-1, 0), (4, 1)], span),
+                    Scan(-2, span),
+                    SimpleLoop(vec![AddDynamic(3, 2, span), Zero(0, span)], span),
+                ],
+                span,
+            ),
+            Read(0, span),
+        ];
+        let instrs =
+            WasmInstruction::convert_instructions(&src, CellWidth::Bits8, EofBehavior::MinusOne);
+        let opened = instrs
+            .iter()
+            .filter(|i| {
+                matches!(
+                    i,
+                    WasmInstruction::Block | WasmInstruction::Loop | WasmInstruction::If
+                )
+            })
+            .count();
+        let closed = instrs
+            .iter()
+            .filter(|i| matches!(i, WasmInstruction::End))
+            .count();
+        assert_eq!(opened, 7);
+        assert_eq!(closed, 7);
+    }
+}
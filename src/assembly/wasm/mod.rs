@@ -0,0 +1,6 @@
+//! Types and methods to represent and encode WebAssembly instructions
+
+mod _leb128;
+pub use _leb128::{encode_signed, encode_unsigned};
+mod _wasm_instruction;
+pub use _wasm_instruction::*;
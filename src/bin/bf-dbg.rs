@@ -0,0 +1,286 @@
+//! An executable that debugs the given BF program interactively
+//!
+//! Author: Cayden Lund (cayden.lund@utah.edu)
+
+use brainforge::interpreter::{Debugger, Stop};
+use brainforge::{run_with_diagnostics, BFError, BFResult, CellWidth, EofBehavior, SourceLocation};
+use clap::Parser;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// The command-line arguments used
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct CliArgs {
+    /// The file to debug
+    file: PathBuf,
+
+    /// The file that the program reads its input from
+    ///
+    /// Debugger commands are read from stdin, so the program sees the end of its input
+    /// right away if this is not provided
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    /// The size of the memory tape
+    #[arg(short, long, default_value_t = 8192)]
+    memsize: usize,
+
+    /// The width of each memory cell, in bits (8, 16, or 32)
+    #[arg(long, default_value_t = CellWidth::Bits8)]
+    cell_bits: CellWidth,
+
+    /// What reading past the end of the input does to the current cell
+    #[arg(long, value_enum, default_value_t = EofBehavior::MinusOne)]
+    eof: EofBehavior,
+}
+
+/// The commands that the debugger understands
+const HELP: &str = "\
+Commands:
+  s, step [N]          Execute the next N instructions (default 1)
+  n, next              Execute the next instruction, or the whole loop that it starts
+  f, finish            Run until the innermost loop around the next instruction exits
+  c, continue          Run until a breakpoint, a watchpoint, or the end of the program
+  rs, reverse-step [N] Undo the last N instructions (default 1)
+  b, break LOC         Set a breakpoint at a source offset, or at LINE:COLUMN
+  d, delete LOC        Remove the breakpoint at a source offset, or at LINE:COLUMN
+  w, watch CELL        Stop whenever the value of the given cell changes
+  u, unwatch CELL      Stop watching the given cell
+  i, info              List the breakpoints and watchpoints
+  t, tape [N]          Show the cells within N of the memory pointer (default 8)
+  p, print CELL        Show the value of the given cell
+  l, list              Show the next instruction
+  q, quit              Exit the debugger
+
+`#` characters in the program set breakpoints on the instructions after them.
+An empty line repeats the last command.";
+
+/// Main program entry point.
+fn main() -> ExitCode {
+    let args = CliArgs::parse();
+    let file = args.file.clone();
+    run_with_diagnostics(Some(file), |src| run(args, src))
+}
+
+/// Debugs the given program with the given arguments
+fn run(args: CliArgs, src: &[u8]) -> BFResult<()> {
+    let input = match &args.input {
+        Some(path) => fs::read(path).map_err(|_| BFError::FileReadError(path.clone()))?,
+        None => vec![],
+    };
+    let mut debugger = Debugger::new(src, input, args.memsize, args.cell_bits, args.eof)?;
+    let filename = args.file.display().to_string();
+
+    println!(
+        "Debugging {}. Type `help` for a list of commands.",
+        filename
+    );
+    show_location(&debugger, &filename);
+
+    let mut written = 0;
+    let mut last_command = String::new();
+    let mut stdin = io::stdin().lock();
+    loop {
+        print!("(bf-dbg) ");
+        io::stdout().flush().ok();
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+            println!();
+            return Ok(());
+        }
+        let line = match line.trim() {
+            "" => last_command.clone(),
+            line => line.to_string(),
+        };
+        last_command = line.clone();
+
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let arg = words.next();
+        let stop = match (command, arg) {
+            ("" | "l" | "list", _) => {
+                show_location(&debugger, &filename);
+                None
+            }
+            ("s" | "step", _) => parse_count(arg).map(|count| debugger.step(count)),
+            ("n" | "next", _) => Some(debugger.step_over()),
+            ("f" | "finish", _) => Some(debugger.step_out()),
+            ("c" | "continue", _) => Some(debugger.cont()),
+            ("rs" | "reverse-step", _) => {
+                if let Some(count) = parse_count(arg) {
+                    let undone = debugger.reverse_step(count);
+                    if undone < count {
+                        println!("Reached the start of the recorded history");
+                    }
+                    written = written.min(debugger.output.len());
+                    show_location(&debugger, &filename);
+                }
+                None
+            }
+            ("b" | "break", Some(arg)) => {
+                match parse_offset(src, arg).and_then(|offset| debugger.add_breakpoint(offset)) {
+                    Some(index) => println!("Breakpoint set at {}", describe(&debugger, index)),
+                    None => println!("No instruction at `{}`", arg),
+                }
+                None
+            }
+            ("d" | "delete", Some(arg)) => {
+                let index = parse_offset(src, arg).and_then(|offset| {
+                    debugger
+                        .instrs
+                        .iter()
+                        .position(|instr| instr.position >= offset)
+                });
+                match index {
+                    Some(index) if debugger.remove_breakpoint(index) => {
+                        println!("Breakpoint removed from {}", describe(&debugger, index))
+                    }
+                    _ => println!("No breakpoint at `{}`", arg),
+                }
+                None
+            }
+            ("w" | "watch", Some(arg)) => {
+                match arg.parse() {
+                    Ok(cell) if debugger.add_watchpoint(cell) => println!("Watching cell {}", cell),
+                    _ => println!("No cell `{}` on the tape", arg),
+                }
+                None
+            }
+            ("u" | "unwatch", Some(arg)) => {
+                match arg.parse() {
+                    Ok(cell) if debugger.remove_watchpoint(cell) => {
+                        println!("Stopped watching cell {}", cell)
+                    }
+                    _ => println!("Cell `{}` is not watched", arg),
+                }
+                None
+            }
+            ("i" | "info", _) => {
+                for index in debugger.breakpoints() {
+                    println!("Breakpoint at {}", describe(&debugger, index));
+                }
+                for cell in debugger.watchpoints() {
+                    println!("Watchpoint on cell {}", cell);
+                }
+                None
+            }
+            ("t" | "tape", _) => {
+                match arg.map_or(Ok(8), |arg| arg.parse()) {
+                    Ok(radius) => println!("{}", debugger.tape(radius)),
+                    Err(_) => println!("Invalid distance `{}`", arg.unwrap_or("")),
+                }
+                None
+            }
+            ("p" | "print", Some(arg)) => {
+                match arg.parse() {
+                    Ok(cell) if cell < debugger.cells() => {
+                        println!("cell {} = {}", cell, debugger.state.cell(cell))
+                    }
+                    _ => println!("No cell `{}` on the tape", arg),
+                }
+                None
+            }
+            ("h" | "help", _) => {
+                println!("{}", HELP);
+                None
+            }
+            ("q" | "quit", _) => return Ok(()),
+            _ => {
+                println!(
+                    "Unknown command `{}`. Type `help` for a list of commands.",
+                    line
+                );
+                None
+            }
+        };
+
+        // Show the program's output as soon as it is written
+        if debugger.output.len() > written {
+            io::stdout().write_all(&debugger.output[written..]).ok();
+            if !debugger.output.ends_with(b"\n") {
+                println!();
+            }
+            written = debugger.output.len();
+        }
+
+        match stop {
+            Some(Stop::Breakpoint(_)) => {
+                println!("Breakpoint hit");
+                show_location(&debugger, &filename);
+            }
+            Some(Stop::Watchpoint { cell, old, new }) => {
+                println!("Cell {} changed from {} to {}", cell, old, new);
+                show_location(&debugger, &filename);
+            }
+            Some(Stop::Finished) => println!("The program finished"),
+            Some(Stop::Error(err)) => eprint!("{}", err.render(src, &filename)),
+            Some(Stop::Stepped) => show_location(&debugger, &filename),
+            None => {}
+        }
+    }
+}
+
+/// Shows the next instruction in the source program, and the current cell
+fn show_location(debugger: &Debugger, filename: &str) {
+    if debugger.finished() {
+        println!("The program finished");
+        return;
+    }
+    let cell = format!(
+        "cell {} = {}",
+        debugger.state.ptr,
+        debugger.state.cell(debugger.state.ptr)
+    );
+    print!(
+        "{}",
+        debugger.location().render(&debugger.src, filename, &cell)
+    );
+}
+
+/// Describes the location of the given instruction
+fn describe(debugger: &Debugger, index: usize) -> String {
+    let location = SourceLocation::new(&debugger.src, debugger.instrs[index].position);
+    format!("{}:{}", location.line, location.column)
+}
+
+/// Parses an optional count of instructions, which defaults to 1
+fn parse_count(arg: Option<&str>) -> Option<usize> {
+    match arg.map_or(Ok(1), |arg| arg.parse()) {
+        Ok(count) => Some(count),
+        Err(_) => {
+            println!("Invalid count `{}`", arg.unwrap_or(""));
+            None
+        }
+    }
+}
+
+/// Parses a source location, as either a byte offset or `LINE:COLUMN`, into a byte offset
+fn parse_offset(src: &[u8], arg: &str) -> Option<usize> {
+    let Some((line, column)) = arg.split_once(':') else {
+        return arg.parse().ok();
+    };
+    let (line, column): (usize, usize) = (line.parse().ok()?, column.parse().ok()?);
+
+    let line_start = if line == 1 {
+        0
+    } else {
+        src.iter()
+            .enumerate()
+            .filter(|(_, &byte)| byte == b'\n')
+            .nth(line.checked_sub(2)?)?
+            .0
+            + 1
+    };
+
+    // Columns count characters, so skip the continuation bytes of UTF-8 characters
+    src[line_start..]
+        .iter()
+        .enumerate()
+        .filter(|(_, &byte)| byte & 0xC0 != 0x80)
+        .nth(column.checked_sub(1)?)
+        .map(|(index, _)| line_start + index)
+}
@@ -1,10 +1,11 @@
 use brainforge::instruction::IntermediateInstruction;
-use brainforge::jit::JitProgram;
-use brainforge::optimizer::{optimize, OptimizerOptions};
-use brainforge::{input, BFResult};
+use brainforge::jit::{JitProgram, JitTape};
+use brainforge::optimizer::{OptimizerOptions, PassKind, PassManager};
+use brainforge::{dump_ir, run_with_diagnostics, BFResult, BufferedIo, CellWidth, EofBehavior};
 
 use clap::Parser;
 use std::path::PathBuf;
+use std::process::ExitCode;
 
 /// The command-line arguments used
 #[derive(Parser)]
@@ -19,6 +20,45 @@ struct CliArgs {
     #[arg(short, long, default_value_t = 8192)]
     memsize: usize,
 
+    /// The width of each memory cell, in bits (8, 16, or 32)
+    #[arg(long, default_value_t = CellWidth::Bits8)]
+    cell_bits: CellWidth,
+
+    /// What reading past the end of the input does to the current cell
+    #[arg(long, value_enum, default_value_t = EofBehavior::MinusOne)]
+    eof: EofBehavior,
+
+    /// The optimization level, from 0 to 3
+    ///
+    /// `-O1` coalesces adjacent instructions; `-O2` also flattens simple loops, vectorizes scans
+    /// and defers moves; and `-O3` also tracks known cells and runs the program ahead of time
+    /// until it first reads input. The flags for single optimizations add to these
+    #[arg(short = 'O', default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=3))]
+    opt_level: u8,
+
+    /// The optimization passes to run, in order, instead of those of the optimization level
+    ///
+    /// Passes that repeat run over and over until none of them changes the program;
+    /// `partial-evaluation` and `offsets` run once each, after them
+    #[arg(long, value_enum, value_delimiter = ',')]
+    passes: Option<Vec<PassKind>>,
+
+    /// Whether to print what each optimization pass did, and how long it took, to stderr
+    #[arg(long)]
+    stats: bool,
+
+    /// Whether to print the optimized program as IR text, to stderr or to the given file
+    /// (`-` for stdout)
+    #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+    dump_ir: Option<Option<PathBuf>>,
+
+    /// Whether the given file holds IR text, as printed by `--dump-ir`, rather than a BF program
+    ///
+    /// The IR is optimized further, but no bounds checks are added to it: only its own `check`
+    /// instructions run, so it can't be combined with `--checked` or `--grow`
+    #[arg(long, conflicts_with_all = ["checked", "grow"])]
+    load_ir: bool,
+
     /// Whether to perform simple loop flattening
     #[arg(short, long)]
     loops: bool,
@@ -26,27 +66,87 @@ struct CliArgs {
     /// Whether to perform memory scan vectorization
     #[arg(short, long)]
     scan: bool,
+
+    /// Whether to address cells by their offsets, deferring moves to the end of each run
+    #[arg(short, long)]
+    defer_moves: bool,
+
+    /// Whether to track the values of cells, removing loops that never run and redundant changes
+    #[arg(short, long)]
+    known_cells: bool,
+
+    /// Whether to check that the data pointer stays on the memory tape
+    ///
+    /// Scans are not vectorized in this mode
+    #[arg(long)]
+    checked: bool,
+
+    /// Whether to grow the memory tape whenever the data pointer moves off it
+    ///
+    /// Implies `--checked`, with the memory size as the initial size of the tape
+    #[arg(long)]
+    grow: bool,
+
+    /// Whether to write each byte of output as soon as it is written, for interactive programs
+    ///
+    /// Otherwise, the output is buffered, and written out when the buffer is full, before
+    /// reading input, and at exit
+    #[arg(long)]
+    unbuffered: bool,
 }
 
 /// Main program entry point
-fn main() -> BFResult<()> {
+fn main() -> ExitCode {
     let args = CliArgs::parse();
+    let file = args.file.clone();
+    run_with_diagnostics(file, |src| run(args, src))
+}
 
-    let src = input(args.file)?;
-
-    let instrs = IntermediateInstruction::parse_instrs(&src)?;
+/// Runs the given program with the given arguments
+fn run(args: CliArgs, src: &[u8]) -> BFResult<()> {
+    let checked = args.checked || args.grow;
+    let instrs = if args.load_ir {
+        IntermediateInstruction::parse_ir(src)?
+    } else if checked {
+        IntermediateInstruction::parse_instrs_checked(src)?
+    } else {
+        IntermediateInstruction::parse_instrs(src)?
+    };
     let optimizer_opts = OptimizerOptions::new()
-        .coalesce(true)
+        .opt_level(args.opt_level)
+        .passes(args.passes.clone())
         .simple_loops(args.loops)
-        .scans(args.scan);
-    let optimized_instrs = optimize(instrs, optimizer_opts);
-
-    let memory: Vec<u8> = vec![0; args.memsize];
-    let memory_center =
-        unsafe { memory.as_ptr().offset((args.memsize / 2) as isize) as *mut libc::c_void };
+        .scans(args.scan)
+        .offsets(args.defer_moves)
+        .known_cells(args.known_cells)
+        .hoist_checks(checked)
+        .mem_size(args.memsize)
+        .cell_width(args.cell_bits);
+    let (optimized_instrs, stats) = PassManager::from_options(&optimizer_opts).run(instrs);
+    if args.stats {
+        eprint!("{}", stats);
+    }
+    if let Some(path) = &args.dump_ir {
+        dump_ir(path.as_ref(), &optimized_instrs)?;
+    }
 
     // Should be plenty of room
     let num_pages = 100_000;
-    let mut program = JitProgram::new(&*optimized_instrs, num_pages)?;
-    program.run(memory_center)
+    let mut program = JitProgram::new(
+        &optimized_instrs,
+        args.cell_bits,
+        args.eof,
+        num_pages,
+        !args.unbuffered,
+    )?;
+
+    let tape_size = args.memsize * args.cell_bits.bytes();
+    let mut io = BufferedIo::stdio().unbuffered(args.unbuffered);
+    if args.grow {
+        let mut tape = JitTape::new(tape_size, JitTape::DEFAULT_RESERVED)?;
+        program.run_growable(&mut tape, &mut io)
+    } else {
+        let mut memory: Vec<u8> = vec![0; tape_size];
+        program.run(&mut memory, &mut io)
+    }
 }
@@ -4,8 +4,8 @@
 
 use brainforge::assembly::llvm::{LLVMInstruction, LlvmContext};
 use brainforge::instruction::IntermediateInstruction;
-use brainforge::optimizer::{optimize, OptimizerOptions};
-use brainforge::{input, BFError, BFResult};
+use brainforge::optimizer::{OptimizerOptions, PassKind, PassManager};
+use brainforge::{dump_ir, run_with_diagnostics, BFError, BFResult, CellWidth, EofBehavior};
 use clap::Parser;
 use inkwell::context::Context;
 use inkwell::targets::{
@@ -13,6 +13,7 @@ use inkwell::targets::{
 };
 use inkwell::OptimizationLevel;
 use std::path::PathBuf;
+use std::process::ExitCode;
 
 /// The command-line arguments used
 #[derive(Parser)]
@@ -33,6 +34,37 @@ struct CliArgs {
     #[arg(short, long, default_value_t = 8192)]
     memsize: usize,
 
+    /// The width of each memory cell, in bits (8, 16, or 32)
+    #[arg(long, default_value_t = CellWidth::Bits8)]
+    cell_bits: CellWidth,
+
+    /// What reading past the end of the input does to the current cell
+    #[arg(long, value_enum, default_value_t = EofBehavior::MinusOne)]
+    eof: EofBehavior,
+
+    /// The optimization passes to run, in order, instead of those of the optimization level
+    ///
+    /// Passes that repeat run over and over until none of them changes the program;
+    /// `partial-evaluation` and `offsets` run once each, after them
+    #[arg(long, value_enum, value_delimiter = ',')]
+    passes: Option<Vec<PassKind>>,
+
+    /// Whether to print what each optimization pass did, and how long it took, to stderr
+    #[arg(long)]
+    stats: bool,
+
+    /// Whether to print the optimized program as IR text, to stderr or to the given file
+    /// (`-` for stdout)
+    #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+    dump_ir: Option<Option<PathBuf>>,
+
+    /// Whether the given file holds IR text, as printed by `--dump-ir`, rather than a BF program
+    ///
+    /// The IR is optimized further, but no bounds checks are added to it: only its own `check`
+    /// instructions run, so it can't be combined with `--checked`
+    #[arg(long, conflicts_with = "checked")]
+    load_ir: bool,
+
     /// Whether to perform simple loop flattening
     #[arg(short, long)]
     loops: bool,
@@ -41,15 +73,47 @@ struct CliArgs {
     #[arg(short, long)]
     scan: bool,
 
+    /// Whether to address cells by their offsets, deferring moves to the end of each run
+    #[arg(short, long)]
+    defer_moves: bool,
+
+    /// Whether to track the values of cells, removing loops that never run and redundant changes
+    #[arg(short, long)]
+    known_cells: bool,
+
+    /// Whether to check that the data pointer stays on the memory tape
+    ///
+    /// Scans are not vectorized in this mode
+    #[arg(long)]
+    checked: bool,
+
     /// Whether to perform partial evaluation
+    ///
+    /// Runs the program ahead of time until it first reads input, so that the output up to that
+    /// point is written all at once
     #[arg(short, long)]
     partial_evaluation: bool,
 
-    /// The optimization level (0-3)
+    /// The optimization level (0-3), for LLVM and for the optimization passes
     ///
-    /// Defaults to 0 (none)
-    #[arg(short = 'O', long, default_value_t = 0)]
+    /// Defaults to 1, as for `bfc`. The optimization passes of each level are those of `bfc`,
+    /// except that adjacent instructions are coalesced even at level 0
+    #[arg(short = 'O', long, default_value_t = 1)]
     opt_level: usize,
+
+    /// Whether to emit DWARF debug info, which maps the object code back to the BF source
+    ///
+    /// The memory tape and the pointer to the current cell are visible in a debugger
+    /// as `tape` and `ptr`
+    #[arg(short = 'g', long)]
+    debug: bool,
+
+    /// Whether to write each byte of output as soon as it is written, for interactive programs
+    ///
+    /// Otherwise, the output is buffered, and written out when the buffer is full, before
+    /// reading input, and at exit
+    #[arg(long)]
+    unbuffered: bool,
 }
 
 fn parse_opt_level(num: usize) -> BFResult<OptimizationLevel> {
@@ -66,20 +130,56 @@ fn parse_opt_level(num: usize) -> BFResult<OptimizationLevel> {
 }
 
 /// Main program entry point.
-fn main() -> BFResult<()> {
+fn main() -> ExitCode {
     let args = CliArgs::parse();
+    let file = args.file.clone();
+    run_with_diagnostics(file, |src| run(args, src))
+}
 
-    let src = input(args.file)?;
-
-    let instrs = IntermediateInstruction::parse_instrs(&src)?;
+/// Runs the given program with the given arguments
+fn run(args: CliArgs, src: &[u8]) -> BFResult<()> {
+    let instrs = if args.load_ir {
+        IntermediateInstruction::parse_ir(src)?
+    } else if args.checked {
+        IntermediateInstruction::parse_instrs_checked(src)?
+    } else {
+        IntermediateInstruction::parse_instrs(src)?
+    };
     let optimizer_opts = OptimizerOptions::new()
+        .opt_level(args.opt_level as u8)
         .coalesce(true)
+        .passes(args.passes.clone())
         .simple_loops(args.loops)
-        .scans(args.scan);
-    let optimized_instrs = optimize(instrs, optimizer_opts);
+        .scans(args.scan)
+        .offsets(args.defer_moves)
+        .known_cells(args.known_cells)
+        .hoist_checks(args.checked)
+        .partial_evaluation(args.partial_evaluation)
+        .mem_size(args.memsize)
+        .cell_width(args.cell_bits);
+    let (optimized_instrs, stats) = PassManager::from_options(&optimizer_opts).run(instrs);
+    if args.stats {
+        eprint!("{}", stats);
+    }
+    if let Some(path) = &args.dump_ir {
+        dump_ir(path.as_ref(), &optimized_instrs)?;
+    }
+
+    let debug_file = args.debug.then(|| {
+        args.file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("<stdin>"))
+    });
 
     let ctx = Context::create();
-    let ctx = LlvmContext::new(&ctx, args.memsize)?;
+    let ctx = LlvmContext::new(
+        &ctx,
+        args.memsize,
+        args.cell_bits,
+        args.eof,
+        !args.unbuffered,
+        debug_file.as_deref(),
+    )?;
 
     Target::initialize_native(&InitializationConfig::default()).map_err(|err| {
         BFError::LlvmError(format!("Failed to initialize native target: `{}`", err))
@@ -101,11 +201,15 @@ fn main() -> BFResult<()> {
         ));
     };
     LLVMInstruction::build_instructions(&ctx, &optimized_instrs)?;
+    ctx.flush_output()?;
 
     ctx.builder
         .build_return(Some(&ctx.ctx.i32_type().const_zero()))
         .map_err(|_| BFError::LlvmError("Failed to build return from main".into()))?;
 
+    if let Some(debug_info) = &ctx.debug_info {
+        debug_info.builder.finalize();
+    }
     ctx.module
         .verify()
         .map_err(|err| BFError::LlvmError(format!("Verification error: `{}`", err)))?;
@@ -3,10 +3,58 @@
 //! Author: Cayden Lund (cayden.lund@utah.edu)
 
 use brainforge::instruction::IntermediateInstruction;
-use brainforge::optimizer::{optimize, OptimizerOptions};
-use brainforge::{generator::*, input, output, Architecture, BFError, BFResult};
-use clap::Parser;
-use std::{io::Write, path::PathBuf};
+use brainforge::optimizer::{OptimizerOptions, PassKind, PassManager};
+use brainforge::{
+    dump_ir, generator::*, output, run_with_diagnostics, Architecture, BFError, BFResult,
+    CellWidth, EofBehavior,
+};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
+use std::fs::{set_permissions, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::{io::Write, path::PathBuf, process::ExitCode};
+
+/// The kinds of output that the compiler can produce
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum Emit {
+    /// A statically-linked executable, or a binary module for WebAssembly
+    Exe,
+
+    /// A relocatable object file, which defines `main` and links against the C standard library
+    Obj,
+
+    /// Assembly source text (Intel syntax on AMD64, or the text format for WebAssembly)
+    Asm,
+
+    /// Portable C99 source text, for any target
+    C,
+
+    /// The optimized program's intermediate representation, as IR text, for any target
+    Ir,
+}
+
+/// The architectures that the compiler can target
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum Target {
+    /// AMD64 (x86-64) Linux
+    Amd64,
+
+    /// AArch64 (Armv8-A) Linux; only assembly output is supported
+    Aarch64,
+
+    /// WebAssembly, importing `read` and `write` from the host
+    Wasm32,
+}
+
+impl From<Target> for Architecture {
+    fn from(target: Target) -> Self {
+        match target {
+            Target::Amd64 => Architecture::AMD64,
+            Target::Aarch64 => Architecture::AArch64,
+            Target::Wasm32 => Architecture::WASM,
+        }
+    }
+}
 
 /// The command-line arguments used
 #[derive(Parser)]
@@ -19,14 +67,63 @@ struct CliArgs {
 
     /// The output file
     ///
-    /// Use `-` for stdout
-    #[arg(short, long, default_value = "a.s")]
-    output: PathBuf,
+    /// Use `-` for stdout.
+    /// Defaults to `a.out` for executables, `a.o` for objects, `a.s` for assembly, `a.c` for C,
+    /// and `a.ir` for IR, or `a.wasm` and `a.wat` for WebAssembly
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// The kind of output to produce
+    #[arg(short, long, value_enum, default_value_t = Emit::Exe)]
+    emit: Emit,
+
+    /// The target architecture
+    #[arg(short, long, value_enum, default_value_t = Target::Amd64)]
+    target: Target,
 
     /// The size of the memory tape
     #[arg(short, long, default_value_t = 8192)]
     memsize: usize,
 
+    /// The width of each memory cell, in bits (8, 16, or 32)
+    #[arg(long, default_value_t = CellWidth::Bits8)]
+    cell_bits: CellWidth,
+
+    /// What reading past the end of the input does to the current cell
+    #[arg(long, value_enum, default_value_t = EofBehavior::MinusOne)]
+    eof: EofBehavior,
+
+    /// The optimization level, from 0 to 3
+    ///
+    /// `-O1` coalesces adjacent instructions; `-O2` also flattens simple loops, vectorizes scans
+    /// and defers moves; and `-O3` also tracks known cells and runs the program ahead of time
+    /// until it first reads input. The flags for single optimizations add to these
+    #[arg(short = 'O', default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=3))]
+    opt_level: u8,
+
+    /// The optimization passes to run, in order, instead of those of the optimization level
+    ///
+    /// Passes that repeat run over and over until none of them changes the program;
+    /// `partial-evaluation` and `offsets` run once each, after them
+    #[arg(long, value_enum, value_delimiter = ',')]
+    passes: Option<Vec<PassKind>>,
+
+    /// Whether to print what each optimization pass did, and how long it took, to stderr
+    #[arg(long)]
+    stats: bool,
+
+    /// Whether to print the optimized program as IR text, to stderr or to the given file
+    /// (`-` for stdout)
+    #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+    dump_ir: Option<Option<PathBuf>>,
+
+    /// Whether the given file holds IR text, as printed by `--dump-ir`, rather than a BF program
+    ///
+    /// The IR is optimized further, but no bounds checks are added to it: only its own `check`
+    /// instructions run, so it can't be combined with `--checked`
+    #[arg(long, conflicts_with = "checked")]
+    load_ir: bool,
+
     /// Whether to perform simple loop flattening
     #[arg(short, long)]
     loops: bool,
@@ -35,37 +132,139 @@ struct CliArgs {
     #[arg(short, long)]
     scan: bool,
 
+    /// Whether to address cells by their offsets, deferring moves to the end of each run
+    #[arg(short, long)]
+    defer_moves: bool,
+
+    /// Whether to track the values of cells, removing loops that never run and redundant changes
+    #[arg(short, long)]
+    known_cells: bool,
+
+    /// Whether to check that the data pointer stays on the memory tape
+    ///
+    /// Scans are not vectorized in this mode
+    #[arg(long)]
+    checked: bool,
+
     /// Whether to perform partial evaluation
+    ///
+    /// Runs the program ahead of time until it first reads input, so that the output up to that
+    /// point is written all at once
     #[arg(short, long)]
     partial_evaluation: bool,
+
+    /// Whether to write each byte of output as soon as it is written, for interactive programs
+    ///
+    /// Otherwise, AMD64 executables, objects and assembly buffer their output, and write it out
+    /// when the buffer is full, before reading input, and at exit. Other output always writes
+    /// each byte with `putchar` (or the host's `write` for WebAssembly), so this can't be used
+    /// with it
+    #[arg(long)]
+    unbuffered: bool,
 }
 
 /// Main program entry point.
-fn main() -> BFResult<()> {
+fn main() -> ExitCode {
     let args = CliArgs::parse();
+    let buffers = args.target == Target::Amd64 && !matches!(args.emit, Emit::C | Emit::Ir);
+    if args.unbuffered && !buffers {
+        CliArgs::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "`--unbuffered` only applies to AMD64 executables, objects and assembly, \
+                 which buffer their output",
+            )
+            .exit();
+    }
+    let file = args.file.clone();
+    run_with_diagnostics(file, |src| run(args, src))
+}
 
-    let src = input(args.file)?;
-
-    let instrs = IntermediateInstruction::parse_instrs(&src)?;
+/// Runs the given program with the given arguments
+fn run(args: CliArgs, src: &[u8]) -> BFResult<()> {
+    let instrs = if args.load_ir {
+        IntermediateInstruction::parse_ir(src)?
+    } else if args.checked {
+        IntermediateInstruction::parse_instrs_checked(src)?
+    } else {
+        IntermediateInstruction::parse_instrs(src)?
+    };
     let optimizer_opts = OptimizerOptions::new()
-        .coalesce(true)
+        .opt_level(args.opt_level)
+        .passes(args.passes.clone())
         .simple_loops(args.loops)
-        .scans(args.scan);
-    let optimized_instrs = optimize(instrs, optimizer_opts);
+        .scans(args.scan)
+        .offsets(args.defer_moves)
+        .known_cells(args.known_cells)
+        .hoist_checks(args.checked)
+        .partial_evaluation(args.partial_evaluation)
+        .mem_size(args.memsize)
+        .cell_width(args.cell_bits);
+    let (optimized_instrs, stats) = PassManager::from_options(&optimizer_opts).run(instrs);
+    if args.stats {
+        eprint!("{}", stats);
+    }
+    if let Some(path) = &args.dump_ir {
+        dump_ir(path.as_ref(), &optimized_instrs)?;
+    }
 
-    let mut output = output(&args.output)?;
+    let output_path = args
+        .output
+        .unwrap_or_else(|| match (args.target, args.emit) {
+            (Target::Wasm32, Emit::Exe) => PathBuf::from("a.wasm"),
+            (Target::Wasm32, Emit::Asm) => PathBuf::from("a.wat"),
+            (_, Emit::Exe) => PathBuf::from("a.out"),
+            (_, Emit::Obj) => PathBuf::from("a.o"),
+            (_, Emit::Asm) => PathBuf::from("a.s"),
+            (_, Emit::C) => PathBuf::from("a.c"),
+            (_, Emit::Ir) => PathBuf::from("a.ir"),
+        });
 
-    match output.write(
-        generate(
+    let arch = args.target.into();
+    let bytes = match args.emit {
+        Emit::Exe => generate_executable(
+            &optimized_instrs,
+            args.memsize,
+            args.cell_bits,
+            args.eof,
+            !args.unbuffered,
+            arch,
+        )?,
+        Emit::Obj => generate_object(
             &optimized_instrs,
-            args.partial_evaluation,
             args.memsize,
-            Architecture::AMD64,
+            args.cell_bits,
+            args.eof,
+            !args.unbuffered,
+            arch,
+        )?,
+        Emit::Asm => generate(
+            &optimized_instrs,
+            args.memsize,
+            args.cell_bits,
+            args.eof,
+            !args.unbuffered,
+            arch,
         )?
-        .as_bytes(),
-    ) {
-        Err(_) => return Err(BFError::FileWriteError(args.output)),
-        _ => {}
+        .into_bytes(),
+        Emit::C => {
+            c::generate(&optimized_instrs, args.memsize, args.cell_bits, args.eof)?.into_bytes()
+        }
+        Emit::Ir => IntermediateInstruction::print_ir(&optimized_instrs).into_bytes(),
+    };
+
+    let mut output = output(&output_path)?;
+    if output.write_all(&bytes).is_err() {
+        return Err(BFError::FileWriteError(output_path));
+    }
+
+    // Make the executable runnable
+    if args.emit == Emit::Exe
+        && args.target == Target::Amd64
+        && output_path.as_os_str() != "-"
+        && set_permissions(&output_path, Permissions::from_mode(0o755)).is_err()
+    {
+        return Err(BFError::FileWriteError(output_path));
     }
 
     Ok(())
@@ -0,0 +1,176 @@
+//! A minimal, statically-linked ELF64 executable
+
+use super::{
+    align_up, FileHeader, ProgramHeader, SectionHeader, StringTable, ET_EXEC, FILE_HEADER_SIZE,
+    PF_R, PF_W, PF_X, PROGRAM_HEADER_SIZE, PT_LOAD, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE,
+    SHT_NOBITS, SHT_PROGBITS, SHT_STRTAB,
+};
+
+/// The size of a memory page, to which loadable segments are aligned
+const PAGE_SIZE: usize = 0x1000;
+
+/// The virtual address at which the `.text` section of an [`ElfExecutable`] is loaded
+pub const TEXT_ADDRESS: u64 = 0x401000;
+
+/// The file offset of the `.text` section of an [`ElfExecutable`]
+const TEXT_OFFSET: usize = PAGE_SIZE;
+
+/// A minimal, statically-linked ELF64 executable for AMD64 Linux
+///
+/// Holds two segments: the read-only, executable `.text` section, loaded at [`TEXT_ADDRESS`],
+/// and the writable, zero-initialized `.bss` section, loaded on the next page boundary after it
+pub struct ElfExecutable {
+    /// The machine code of the program
+    pub text: Vec<u8>,
+
+    /// The offset of the program entry point within `text`
+    pub entry: usize,
+
+    /// The size of the zero-initialized `.bss` section
+    pub bss_size: usize,
+}
+
+impl ElfExecutable {
+    /// Returns the virtual address of the `.bss` section, given the size of the `.text` section
+    pub fn bss_address(text_size: usize) -> u64 {
+        align_up(TEXT_ADDRESS as usize + text_size, PAGE_SIZE) as u64
+    }
+
+    /// Encodes this executable in binary
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let bss_address = Self::bss_address(self.text.len());
+
+        let mut section_names = StringTable::new();
+        let text_name = section_names.add(".text");
+        let bss_name = section_names.add(".bss");
+        let shstrtab_name = section_names.add(".shstrtab");
+
+        let shstrtab_offset = TEXT_OFFSET + self.text.len();
+        let sh_offset = align_up(shstrtab_offset + section_names.bytes.len(), 8);
+
+        let program_headers = [
+            ProgramHeader {
+                segment_type: PT_LOAD,
+                flags: PF_R | PF_X,
+                offset: TEXT_OFFSET as u64,
+                address: TEXT_ADDRESS,
+                file_size: self.text.len() as u64,
+                memory_size: self.text.len() as u64,
+                align: PAGE_SIZE as u64,
+            },
+            ProgramHeader {
+                segment_type: PT_LOAD,
+                flags: PF_R | PF_W,
+                offset: 0,
+                address: bss_address,
+                file_size: 0,
+                memory_size: self.bss_size as u64,
+                align: PAGE_SIZE as u64,
+            },
+        ];
+
+        let section_headers = [
+            SectionHeader::default(),
+            SectionHeader {
+                name: text_name,
+                section_type: SHT_PROGBITS,
+                flags: SHF_ALLOC | SHF_EXECINSTR,
+                address: TEXT_ADDRESS,
+                offset: TEXT_OFFSET as u64,
+                size: self.text.len() as u64,
+                align: 16,
+                ..Default::default()
+            },
+            SectionHeader {
+                name: bss_name,
+                section_type: SHT_NOBITS,
+                flags: SHF_ALLOC | SHF_WRITE,
+                address: bss_address,
+                offset: shstrtab_offset as u64,
+                size: self.bss_size as u64,
+                align: 32,
+                ..Default::default()
+            },
+            SectionHeader {
+                name: shstrtab_name,
+                section_type: SHT_STRTAB,
+                offset: shstrtab_offset as u64,
+                size: section_names.bytes.len() as u64,
+                align: 1,
+                ..Default::default()
+            },
+        ];
+
+        let file_header = FileHeader {
+            file_type: ET_EXEC,
+            entry: TEXT_ADDRESS + self.entry as u64,
+            ph_offset: FILE_HEADER_SIZE as u64,
+            sh_offset: sh_offset as u64,
+            ph_count: program_headers.len() as u16,
+            sh_count: section_headers.len() as u16,
+            sh_str_index: 3,
+        };
+
+        let mut bytes = file_header.to_bytes();
+        for header in &program_headers {
+            bytes.extend(header.to_bytes());
+        }
+        debug_assert!(bytes.len() == FILE_HEADER_SIZE + 2 * PROGRAM_HEADER_SIZE);
+        bytes.resize(TEXT_OFFSET, 0);
+        bytes.extend(&self.text);
+        bytes.extend(&section_names.bytes);
+        bytes.resize(sh_offset, 0);
+        for header in &section_headers {
+            bytes.extend(header.to_bytes());
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ElfExecutable, TEXT_ADDRESS};
+
+    fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_elf_executable_layout() {
+        let text = vec![0xC3; 0x1234];
+        let executable = ElfExecutable {
+            text: text.clone(),
+            entry: 0x10,
+            bss_size: 0x800,
+        };
+        let bytes = executable.to_bytes();
+
+        assert_eq!(&bytes[0..4], b"\x7FELF");
+        // Executable file, AMD64
+        assert_eq!(read_u16(&bytes, 16), 2);
+        assert_eq!(read_u16(&bytes, 18), 62);
+        assert_eq!(read_u64(&bytes, 24), TEXT_ADDRESS + 0x10);
+
+        // The text segment is loaded from its file offset to `TEXT_ADDRESS`
+        let text_offset = read_u64(&bytes, 64 + 8) as usize;
+        assert_eq!(read_u64(&bytes, 64 + 16), TEXT_ADDRESS);
+        assert_eq!(&bytes[text_offset..text_offset + text.len()], &text[..]);
+
+        // The bss segment takes no file space, and starts on the page after the text segment
+        let bss_address = read_u64(&bytes, 64 + 56 + 16);
+        assert_eq!(bss_address, ElfExecutable::bss_address(text.len()));
+        assert_eq!(bss_address % 0x1000, 0);
+        assert!(bss_address >= TEXT_ADDRESS + text.len() as u64);
+        assert_eq!(read_u64(&bytes, 64 + 56 + 32), 0);
+        assert_eq!(read_u64(&bytes, 64 + 56 + 40), 0x800);
+
+        // The section header table is at the end of the file
+        let sh_offset = read_u64(&bytes, 40) as usize;
+        let sh_count = read_u16(&bytes, 60) as usize;
+        assert_eq!(bytes.len(), sh_offset + sh_count * 64);
+    }
+}
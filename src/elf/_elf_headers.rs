@@ -0,0 +1,301 @@
+//! The raw headers and tables that make up an ELF64 file
+
+/// The size of an ELF64 file header, in bytes
+pub(crate) const FILE_HEADER_SIZE: usize = 64;
+
+/// The size of an ELF64 program header, in bytes
+pub(crate) const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// The size of an ELF64 section header, in bytes
+pub(crate) const SECTION_HEADER_SIZE: usize = 64;
+
+/// The size of an ELF64 symbol table entry, in bytes
+pub(crate) const SYMBOL_SIZE: usize = 24;
+
+/// The size of an ELF64 relocation entry with an addend, in bytes
+pub(crate) const RELA_SIZE: usize = 24;
+
+/// `e_type` of a relocatable object file
+pub(crate) const ET_REL: u16 = 1;
+
+/// `e_type` of an executable file
+pub(crate) const ET_EXEC: u16 = 2;
+
+/// `p_type` of a loadable segment
+pub(crate) const PT_LOAD: u32 = 1;
+
+/// `p_flags` bits: executable, writable, and readable
+pub(crate) const PF_X: u32 = 1;
+pub(crate) const PF_W: u32 = 2;
+pub(crate) const PF_R: u32 = 4;
+
+/// `sh_type` of a section of program-defined contents
+pub(crate) const SHT_PROGBITS: u32 = 1;
+
+/// `sh_type` of a symbol table
+pub(crate) const SHT_SYMTAB: u32 = 2;
+
+/// `sh_type` of a string table
+pub(crate) const SHT_STRTAB: u32 = 3;
+
+/// `sh_type` of a table of relocations with explicit addends
+pub(crate) const SHT_RELA: u32 = 4;
+
+/// `sh_type` of a zero-initialized section that occupies no file space
+pub(crate) const SHT_NOBITS: u32 = 8;
+
+/// `sh_flags` bits: writable, occupies memory at runtime, and executable
+pub(crate) const SHF_WRITE: u64 = 1;
+pub(crate) const SHF_ALLOC: u64 = 2;
+pub(crate) const SHF_EXECINSTR: u64 = 4;
+
+/// `sh_flags` bit: `sh_info` holds a section header table index
+pub(crate) const SHF_INFO_LINK: u64 = 0x40;
+
+/// `st_info` symbol binding of a global symbol
+pub(crate) const STB_GLOBAL: u8 = 1;
+
+/// `st_info` symbol types: unspecified, data object, and function
+pub(crate) const STT_NOTYPE: u8 = 0;
+pub(crate) const STT_OBJECT: u8 = 1;
+pub(crate) const STT_FUNC: u8 = 2;
+
+/// AMD64 relocation type: `S + A - P`, through the PLT if needed
+pub(crate) const R_X86_64_PLT32: u32 = 4;
+
+/// The ELF64 file header
+pub(crate) struct FileHeader {
+    /// The object file type (`ET_*`)
+    pub file_type: u16,
+
+    /// The virtual address of the program entry point
+    pub entry: u64,
+
+    /// The file offset of the program header table
+    pub ph_offset: u64,
+
+    /// The file offset of the section header table
+    pub sh_offset: u64,
+
+    /// The number of entries in the program header table
+    pub ph_count: u16,
+
+    /// The number of entries in the section header table
+    pub sh_count: u16,
+
+    /// The section header table index of the section name string table
+    pub sh_str_index: u16,
+}
+
+impl FileHeader {
+    /// Encodes this file header in binary
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![
+            0x7F, b'E', b'L', b'F', // Magic number
+            2,    // 64-bit
+            1,    // Little-endian
+            1,    // ELF version 1
+            0,    // System V ABI
+        ];
+        bytes.resize(16, 0);
+        bytes.extend(self.file_type.to_le_bytes());
+        bytes.extend(62_u16.to_le_bytes()); // AMD64
+        bytes.extend(1_u32.to_le_bytes()); // ELF version 1
+        bytes.extend(self.entry.to_le_bytes());
+        bytes.extend(self.ph_offset.to_le_bytes());
+        bytes.extend(self.sh_offset.to_le_bytes());
+        bytes.extend(0_u32.to_le_bytes()); // Flags
+        bytes.extend((FILE_HEADER_SIZE as u16).to_le_bytes());
+        let ph_size = if self.ph_count > 0 {
+            PROGRAM_HEADER_SIZE
+        } else {
+            0
+        };
+        bytes.extend((ph_size as u16).to_le_bytes());
+        bytes.extend(self.ph_count.to_le_bytes());
+        bytes.extend((SECTION_HEADER_SIZE as u16).to_le_bytes());
+        bytes.extend(self.sh_count.to_le_bytes());
+        bytes.extend(self.sh_str_index.to_le_bytes());
+        bytes
+    }
+}
+
+/// An entry in the ELF64 program header table, describing a segment
+pub(crate) struct ProgramHeader {
+    /// The segment type (`PT_*`)
+    pub segment_type: u32,
+
+    /// The segment permissions (`PF_*`)
+    pub flags: u32,
+
+    /// The file offset of the segment contents
+    pub offset: u64,
+
+    /// The virtual address at which the segment is loaded
+    pub address: u64,
+
+    /// The number of bytes of the segment in the file
+    pub file_size: u64,
+
+    /// The number of bytes of the segment in memory
+    ///
+    /// Any bytes past `file_size` are zero-initialized
+    pub memory_size: u64,
+
+    /// The alignment of the segment, in both the file and memory
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    /// Encodes this program header in binary
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend(self.segment_type.to_le_bytes());
+        bytes.extend(self.flags.to_le_bytes());
+        bytes.extend(self.offset.to_le_bytes());
+        bytes.extend(self.address.to_le_bytes());
+        bytes.extend(self.address.to_le_bytes()); // Physical address
+        bytes.extend(self.file_size.to_le_bytes());
+        bytes.extend(self.memory_size.to_le_bytes());
+        bytes.extend(self.align.to_le_bytes());
+        bytes
+    }
+}
+
+/// An entry in the ELF64 section header table
+#[derive(Default)]
+pub(crate) struct SectionHeader {
+    /// The offset of the section name in the section name string table
+    pub name: u32,
+
+    /// The section type (`SHT_*`)
+    pub section_type: u32,
+
+    /// The section attributes (`SHF_*`)
+    pub flags: u64,
+
+    /// The virtual address of the section at runtime, if it is loaded
+    pub address: u64,
+
+    /// The file offset of the section contents
+    pub offset: u64,
+
+    /// The size of the section, in bytes
+    pub size: u64,
+
+    /// The index of an associated section
+    pub link: u32,
+
+    /// Extra section-dependent information
+    pub info: u32,
+
+    /// The alignment of the section
+    pub align: u64,
+
+    /// The size of each entry, for sections that hold a table of fixed-size entries
+    pub entry_size: u64,
+}
+
+impl SectionHeader {
+    /// Encodes this section header in binary
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend(self.name.to_le_bytes());
+        bytes.extend(self.section_type.to_le_bytes());
+        bytes.extend(self.flags.to_le_bytes());
+        bytes.extend(self.address.to_le_bytes());
+        bytes.extend(self.offset.to_le_bytes());
+        bytes.extend(self.size.to_le_bytes());
+        bytes.extend(self.link.to_le_bytes());
+        bytes.extend(self.info.to_le_bytes());
+        bytes.extend(self.align.to_le_bytes());
+        bytes.extend(self.entry_size.to_le_bytes());
+        bytes
+    }
+}
+
+/// An entry in an ELF64 symbol table
+#[derive(Default)]
+pub(crate) struct Symbol {
+    /// The offset of the symbol name in the string table
+    pub name: u32,
+
+    /// The symbol binding (`STB_*`) and type (`STT_*`)
+    pub info: u8,
+
+    /// The index of the section that defines the symbol, or 0 if it is undefined
+    pub section: u16,
+
+    /// The offset of the symbol within its section
+    pub value: u64,
+
+    /// The size of the symbol, in bytes
+    pub size: u64,
+}
+
+impl Symbol {
+    /// Encodes this symbol in binary
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend(self.name.to_le_bytes());
+        bytes.push(self.info);
+        bytes.push(0); // Default visibility
+        bytes.extend(self.section.to_le_bytes());
+        bytes.extend(self.value.to_le_bytes());
+        bytes.extend(self.size.to_le_bytes());
+        bytes
+    }
+}
+
+/// An ELF64 relocation entry with an explicit addend
+pub(crate) struct Rela {
+    /// The offset within the section of the bytes to patch
+    pub offset: u64,
+
+    /// The index of the referenced symbol in the symbol table
+    pub symbol: u32,
+
+    /// The relocation type (`R_X86_64_*`)
+    pub relocation_type: u32,
+
+    /// The constant addend used to compute the patched value
+    pub addend: i64,
+}
+
+impl Rela {
+    /// Encodes this relocation in binary
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let info = ((self.symbol as u64) << 32) | self.relocation_type as u64;
+        let mut bytes = vec![];
+        bytes.extend(self.offset.to_le_bytes());
+        bytes.extend(info.to_le_bytes());
+        bytes.extend(self.addend.to_le_bytes());
+        bytes
+    }
+}
+
+/// A table of null-terminated strings, referenced by offset
+pub(crate) struct StringTable {
+    /// The encoded contents of the table
+    pub bytes: Vec<u8>,
+}
+
+impl StringTable {
+    /// Creates a new string table, which always begins with the empty string
+    pub fn new() -> Self {
+        Self { bytes: vec![0] }
+    }
+
+    /// Adds the given string to the table and returns its offset
+    pub fn add(&mut self, name: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend(name.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+/// Rounds `value` up to the nearest multiple of `align`
+pub(crate) fn align_up(value: usize, align: usize) -> usize {
+    value.div_ceil(align) * align
+}
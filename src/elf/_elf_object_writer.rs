@@ -0,0 +1,387 @@
+//! A writer for relocatable ELF64 object files
+
+use super::{
+    align_up, FileHeader, Rela, SectionHeader, StringTable, Symbol, ET_REL, FILE_HEADER_SIZE,
+    RELA_SIZE, R_X86_64_PLT32, SHF_ALLOC, SHF_EXECINSTR, SHF_INFO_LINK, SHT_PROGBITS, SHT_RELA,
+    SHT_STRTAB, SHT_SYMTAB, STB_GLOBAL, STT_FUNC, STT_NOTYPE, STT_OBJECT, SYMBOL_SIZE,
+};
+use crate::assembly::amd64::AMD64Instruction;
+use crate::BFResult;
+
+/// The section header table indices of the sections of an [`ElfObjectWriter`]
+const TEXT_INDEX: u16 = 1;
+const RODATA_INDEX: u16 = 2;
+const SYMTAB_INDEX: u32 = 4;
+const STRTAB_INDEX: u32 = 5;
+const SHSTRTAB_INDEX: u16 = 6;
+
+/// The alignment of each function in `.text`, and of each object in `.rodata`
+const FUNCTION_ALIGN: usize = 16;
+
+/// A global symbol defined by the object file
+struct DefinedSymbol {
+    /// The name of the symbol
+    name: String,
+
+    /// The symbol type (`STT_*`)
+    symbol_type: u8,
+
+    /// The index of the section that defines the symbol
+    section: u16,
+
+    /// The offset of the symbol within its section
+    offset: usize,
+
+    /// The size of the symbol, in bytes
+    size: usize,
+}
+
+/// A call from `.text` to an external function
+struct CallRelocation {
+    /// The offset within `.text` of the 4-byte call displacement
+    offset: usize,
+
+    /// The name of the called function
+    target: String,
+}
+
+/// Builds a relocatable ELF64 object file for AMD64 Linux
+///
+/// Functions are added as blocks of [`AMD64Instruction`]s and defined as global symbols.
+/// Each `call` to a C standard library [`Function`](crate::assembly::amd64::Function) is
+/// rewritten as a relative call, and a relocation is recorded against an undefined symbol
+/// so that the linker can resolve it. Calls to functions already defined in the object are
+/// resolved directly
+pub struct ElfObjectWriter {
+    /// The contents of the `.text` section
+    text: Vec<u8>,
+
+    /// The contents of the `.rodata` section
+    rodata: Vec<u8>,
+
+    /// The global symbols defined in `.text` and `.rodata`
+    symbols: Vec<DefinedSymbol>,
+
+    /// The relocations to apply to `.text`
+    relocations: Vec<CallRelocation>,
+}
+
+impl Default for ElfObjectWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ElfObjectWriter {
+    /// Creates a new, empty object file
+    pub fn new() -> Self {
+        Self {
+            text: vec![],
+            rodata: vec![],
+            symbols: vec![],
+            relocations: vec![],
+        }
+    }
+
+    /// Encodes the given instructions and adds them to `.text` as a global function
+    pub fn add_function(&mut self, name: &str, instrs: &[AMD64Instruction]) -> BFResult<()> {
+        let mut bytes = AMD64Instruction::encode_block(instrs)?;
+        let offset = align_up(self.text.len(), FUNCTION_ALIGN);
+
+        for (call_offset, func) in AMD64Instruction::call_sites(instrs)? {
+            let target = func.to_string();
+            if let Some(symbol) = self
+                .symbols
+                .iter()
+                .find(|symbol| symbol.section == TEXT_INDEX && symbol.name == target)
+            {
+                // The displacement is relative to the end of the 5-byte `call rel32`
+                let displacement = symbol.offset as i32 - (offset + call_offset + 5) as i32;
+                AMD64Instruction::patch_call_relative(&mut bytes, call_offset, displacement);
+                continue;
+            }
+
+            let displacement_offset =
+                AMD64Instruction::patch_call_relative(&mut bytes, call_offset, 0);
+            self.relocations.push(CallRelocation {
+                offset: offset + displacement_offset,
+                target,
+            });
+        }
+
+        // Pad with `nop`s up to the start of the function
+        self.text.resize(offset, 0x90);
+        self.text.extend(&bytes);
+        self.symbols.push(DefinedSymbol {
+            name: name.into(),
+            symbol_type: STT_FUNC,
+            section: TEXT_INDEX,
+            offset,
+            size: bytes.len(),
+        });
+        Ok(())
+    }
+
+    /// Adds the given bytes to `.rodata` as a global data object
+    pub fn add_rodata(&mut self, name: &str, bytes: &[u8]) {
+        let offset = align_up(self.rodata.len(), FUNCTION_ALIGN);
+        self.rodata.resize(offset, 0);
+        self.rodata.extend(bytes);
+        self.symbols.push(DefinedSymbol {
+            name: name.into(),
+            symbol_type: STT_OBJECT,
+            section: RODATA_INDEX,
+            offset,
+            size: bytes.len(),
+        });
+    }
+
+    /// Encodes this object file in binary
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut names = StringTable::new();
+
+        // The symbol table begins with the null symbol, the only local symbol,
+        // followed by the defined symbols and then the undefined ones
+        let mut symbols = vec![Symbol::default()];
+        for symbol in &self.symbols {
+            symbols.push(Symbol {
+                name: names.add(&symbol.name),
+                info: (STB_GLOBAL << 4) | symbol.symbol_type,
+                section: symbol.section,
+                value: symbol.offset as u64,
+                size: symbol.size as u64,
+            });
+        }
+        let mut undefined: Vec<&str> = vec![];
+        let mut relocations = vec![];
+        for relocation in &self.relocations {
+            let index = match undefined.iter().position(|&name| name == relocation.target) {
+                Some(index) => index,
+                None => {
+                    undefined.push(&relocation.target);
+                    symbols.push(Symbol {
+                        name: names.add(&relocation.target),
+                        info: (STB_GLOBAL << 4) | STT_NOTYPE,
+                        ..Default::default()
+                    });
+                    undefined.len() - 1
+                }
+            };
+            relocations.push(Rela {
+                offset: relocation.offset as u64,
+                symbol: (1 + self.symbols.len() + index) as u32,
+                relocation_type: R_X86_64_PLT32,
+                // The displacement is relative to the end of the 4-byte field
+                addend: -4,
+            });
+        }
+
+        let mut section_names = StringTable::new();
+        let text_name = section_names.add(".text");
+        let rodata_name = section_names.add(".rodata");
+        let rela_text_name = section_names.add(".rela.text");
+        let symtab_name = section_names.add(".symtab");
+        let strtab_name = section_names.add(".strtab");
+        let shstrtab_name = section_names.add(".shstrtab");
+        let note_name = section_names.add(".note.GNU-stack");
+
+        let text_offset = align_up(FILE_HEADER_SIZE, FUNCTION_ALIGN);
+        let rodata_offset = align_up(text_offset + self.text.len(), FUNCTION_ALIGN);
+        let rela_text_offset = align_up(rodata_offset + self.rodata.len(), 8);
+        let symtab_offset = rela_text_offset + relocations.len() * RELA_SIZE;
+        let strtab_offset = symtab_offset + symbols.len() * SYMBOL_SIZE;
+        let shstrtab_offset = strtab_offset + names.bytes.len();
+        let sh_offset = align_up(shstrtab_offset + section_names.bytes.len(), 8);
+
+        let section_headers = [
+            SectionHeader::default(),
+            SectionHeader {
+                name: text_name,
+                section_type: SHT_PROGBITS,
+                flags: SHF_ALLOC | SHF_EXECINSTR,
+                offset: text_offset as u64,
+                size: self.text.len() as u64,
+                align: FUNCTION_ALIGN as u64,
+                ..Default::default()
+            },
+            SectionHeader {
+                name: rodata_name,
+                section_type: SHT_PROGBITS,
+                flags: SHF_ALLOC,
+                offset: rodata_offset as u64,
+                size: self.rodata.len() as u64,
+                align: FUNCTION_ALIGN as u64,
+                ..Default::default()
+            },
+            SectionHeader {
+                name: rela_text_name,
+                section_type: SHT_RELA,
+                flags: SHF_INFO_LINK,
+                offset: rela_text_offset as u64,
+                size: (relocations.len() * RELA_SIZE) as u64,
+                link: SYMTAB_INDEX,
+                info: TEXT_INDEX as u32,
+                align: 8,
+                entry_size: RELA_SIZE as u64,
+                ..Default::default()
+            },
+            SectionHeader {
+                name: symtab_name,
+                section_type: SHT_SYMTAB,
+                offset: symtab_offset as u64,
+                size: (symbols.len() * SYMBOL_SIZE) as u64,
+                link: STRTAB_INDEX,
+                // The index of the first global symbol
+                info: 1,
+                align: 8,
+                entry_size: SYMBOL_SIZE as u64,
+                ..Default::default()
+            },
+            SectionHeader {
+                name: strtab_name,
+                section_type: SHT_STRTAB,
+                offset: strtab_offset as u64,
+                size: names.bytes.len() as u64,
+                align: 1,
+                ..Default::default()
+            },
+            SectionHeader {
+                name: shstrtab_name,
+                section_type: SHT_STRTAB,
+                offset: shstrtab_offset as u64,
+                size: section_names.bytes.len() as u64,
+                align: 1,
+                ..Default::default()
+            },
+            // Marks the stack as non-executable
+            SectionHeader {
+                name: note_name,
+                section_type: SHT_PROGBITS,
+                offset: sh_offset as u64,
+                align: 1,
+                ..Default::default()
+            },
+        ];
+
+        let file_header = FileHeader {
+            file_type: ET_REL,
+            entry: 0,
+            ph_offset: 0,
+            sh_offset: sh_offset as u64,
+            ph_count: 0,
+            sh_count: section_headers.len() as u16,
+            sh_str_index: SHSTRTAB_INDEX,
+        };
+
+        let mut bytes = file_header.to_bytes();
+        bytes.resize(text_offset, 0);
+        bytes.extend(&self.text);
+        bytes.resize(rodata_offset, 0);
+        bytes.extend(&self.rodata);
+        bytes.resize(rela_text_offset, 0);
+        for relocation in &relocations {
+            bytes.extend(relocation.to_bytes());
+        }
+        for symbol in &symbols {
+            bytes.extend(symbol.to_bytes());
+        }
+        bytes.extend(&names.bytes);
+        bytes.extend(&section_names.bytes);
+        bytes.resize(sh_offset, 0);
+        for header in &section_headers {
+            bytes.extend(header.to_bytes());
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::ElfObjectWriter;
+    use crate::assembly::amd64::{AMD64Instruction, Function};
+
+    fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// Returns the file offset of the contents of the section header at the given index
+    fn section_offset(bytes: &[u8], index: usize) -> usize {
+        let sh_offset = read_u64(bytes, 40) as usize;
+        read_u64(bytes, sh_offset + index * 64 + 24) as usize
+    }
+
+    #[test]
+    fn test_elf_object_relocations() {
+        use AMD64Instruction::*;
+
+        let mut writer = ElfObjectWriter::new();
+        writer.add_function("helper", &[Ret()]).unwrap();
+        writer
+            .add_function(
+                "main",
+                &[
+                    Call(Function::GetChar),
+                    Call(Function::PutChar),
+                    Call(Function::GetChar),
+                    Ret(),
+                ],
+            )
+            .unwrap();
+        let bytes = writer.to_bytes();
+
+        assert_eq!(&bytes[0..4], b"\x7FELF");
+        // Relocatable file, with no program headers
+        assert_eq!(read_u16(&bytes, 16), 1);
+        assert_eq!(read_u16(&bytes, 56), 0);
+
+        // `main` is aligned after `helper`, and each call is relative
+        let text = section_offset(&bytes, 1);
+        assert_eq!(bytes[text], 0xC3);
+        assert_eq!(bytes[text + 16], 0xE8);
+        assert_eq!(bytes[text + 28], 0xE8);
+
+        // One relocation per call, with `getchar` only defined once in the symbol table
+        let rela = section_offset(&bytes, 3);
+        let relocations = (0..3)
+            .map(|i| {
+                let entry = rela + i * 24;
+                (
+                    read_u64(&bytes, entry),
+                    read_u64(&bytes, entry + 8),
+                    read_u64(&bytes, entry + 16) as i64,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            relocations,
+            vec![
+                (17, 3 << 32 | 4, -4),
+                (29, 4 << 32 | 4, -4),
+                (41, 3 << 32 | 4, -4)
+            ]
+        );
+
+        // Null, `helper`, `main`, `getchar`, `putchar`
+        let sh_offset = read_u64(&bytes, 40) as usize;
+        assert_eq!(read_u64(&bytes, sh_offset + 4 * 64 + 32), 5 * 24);
+        let symtab = section_offset(&bytes, 4);
+        let strtab = section_offset(&bytes, 5);
+        let name = |index: usize| {
+            let start = strtab + read_u32(&bytes, symtab + index * 24) as usize;
+            let end = start + bytes[start..].iter().position(|&b| b == 0).unwrap();
+            String::from_utf8(bytes[start..end].to_vec()).unwrap()
+        };
+        assert_eq!(
+            (1..5).map(name).collect::<Vec<_>>(),
+            vec!["helper", "main", "getchar", "putchar"]
+        );
+    }
+}
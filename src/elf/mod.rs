@@ -0,0 +1,10 @@
+//! Types and methods to write ELF64 files for AMD64 Linux
+//!
+//! Author: Cayden Lund (cayden.lund@utah.edu)
+
+mod _elf_executable;
+pub use _elf_executable::*;
+mod _elf_object_writer;
+pub use _elf_object_writer::ElfObjectWriter;
+mod _elf_headers;
+pub(crate) use _elf_headers::*;
@@ -0,0 +1,25 @@
+use super::{
+    generate_bf_prog, generate_main, generate_postamble, generate_preamble, generate_tape_overflow,
+};
+use crate::instruction::IntermediateInstruction;
+use crate::{BFResult, CellWidth, EofBehavior};
+
+/// Generates GNU assembly for the AArch64 architecture for the given program, memory size,
+/// cell width, and EOF behavior
+pub fn generate(
+    src: &[IntermediateInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+) -> BFResult<String> {
+    let mut sections = vec![
+        generate_preamble(),
+        generate_main(mem_size, cell_width),
+        generate_bf_prog(src, cell_width, eof_behavior),
+    ];
+    if IntermediateInstruction::has_bounds_checks(src) {
+        sections.push(generate_tape_overflow());
+    }
+    sections.push(generate_postamble());
+    Ok(sections.join("\n\n") + "\n")
+}
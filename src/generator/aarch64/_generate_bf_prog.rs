@@ -0,0 +1,35 @@
+//! Generates the main BF program body as a callable function
+
+use super::generate_instrs;
+use crate::instruction::IntermediateInstruction;
+use crate::{CellWidth, EofBehavior};
+
+/// Generates the BF program body as a single function, `bf_prog`
+///
+/// `bf_prog` accepts 3 arguments: a pointer to the center of a tape of memory,
+/// a pointer to the start of the tape, and the size of the tape in bytes.
+/// These are kept in the callee-saved registers `x19`, `x20`, and `x21`
+pub(crate) fn generate_bf_prog(
+    src: &[IntermediateInstruction],
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+) -> String {
+    format!(
+        r#"// <<<Begin BF Program>>>
+bf_prog:
+    stp x29, x30, [sp, #-48]!
+    mov x29, sp
+    str x19, [sp, #16]
+    stp x20, x21, [sp, #32]
+    mov x19, x0
+    mov x20, x1
+    mov x21, x2
+{}
+    ldp x20, x21, [sp, #32]
+    ldr x19, [sp, #16]
+    ldp x29, x30, [sp], #48
+    ret
+// <<<End BF Program>>>"#,
+        generate_instrs(src, cell_width, eof_behavior).join("\n")
+    )
+}
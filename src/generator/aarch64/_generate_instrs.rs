@@ -0,0 +1,661 @@
+//! Assembly generation for the BF instructions for AArch64
+//!
+//! The data pointer is kept in `x19`, and bounds checks expect the address of the tape in `x20`
+//! and its size in bytes in `x21`. Registers `x9`-`x12` (and their 32-bit views) are
+//! used as scratch registers within a single instruction, and `v0`-`v2` for memory scans.
+
+use crate::instruction::IntermediateInstruction;
+use crate::{CellWidth, EofBehavior};
+
+/// The largest immediate that fits in an `add`/`sub` instruction
+const MAX_ADD_IMMEDIATE: i32 = 4095;
+
+/// The smallest immediate that fits in a `sub` instruction, negated
+const MIN_ADD_IMMEDIATE: i32 = -MAX_ADD_IMMEDIATE;
+
+/// Generates (string) assembly instructions for the given abstract BF instructions
+pub(crate) fn generate_instrs(
+    src: &[IntermediateInstruction],
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+) -> Vec<String> {
+    let mut label_counter = 0;
+    src.iter()
+        .map(|bf_instr| bf_to_assembly(bf_instr, cell_width, eof_behavior, &mut label_counter))
+        .collect::<Vec<Vec<String>>>()
+        .concat()
+}
+
+/// Formats a single instruction, with indentation
+fn instr(text: impl AsRef<str>) -> String {
+    format!("    {}", text.as_ref())
+}
+
+/// Formats a label definition
+fn label(name: &str) -> String {
+    format!("{}:", name)
+}
+
+/// Generates instructions to add the given constant to the 64-bit register `reg`
+fn add_immediate(reg: &str, value: i32) -> Vec<String> {
+    match value {
+        0 => vec![],
+        1..=MAX_ADD_IMMEDIATE => vec![instr(format!("add {reg}, {reg}, #{value}"))],
+        MIN_ADD_IMMEDIATE..=-1 => vec![instr(format!("sub {reg}, {reg}, #{}", -value))],
+        _ => vec![
+            instr(format!("ldr x12, ={value}")),
+            instr(format!("add {reg}, {reg}, x12")),
+        ],
+    }
+}
+
+/// Returns the load and store instructions for a cell of the given width
+fn load_store(cell_width: CellWidth) -> (&'static str, &'static str) {
+    match cell_width {
+        CellWidth::Bits8 => ("ldrb", "strb"),
+        CellWidth::Bits16 => ("ldrh", "strh"),
+        CellWidth::Bits32 => ("ldr", "str"),
+    }
+}
+
+/// Generates instructions to add the given constant to the 32-bit register `reg`,
+/// wrapping around at the cell width
+fn add_cell_immediate(reg: &str, value: i32, cell_width: CellWidth) -> Vec<String> {
+    match (cell_width, value) {
+        (CellWidth::Bits8, _) => vec![instr(format!(
+            "add {reg}, {reg}, #{}",
+            value.rem_euclid(256)
+        ))],
+        (_, 1..=MAX_ADD_IMMEDIATE) => vec![instr(format!("add {reg}, {reg}, #{value}"))],
+        (_, MIN_ADD_IMMEDIATE..=-1) => vec![instr(format!("sub {reg}, {reg}, #{}", -value))],
+        _ => vec![
+            instr(format!("ldr w12, ={}", value as u32 & cell_width.mask())),
+            instr(format!("add {reg}, {reg}, w12")),
+        ],
+    }
+}
+
+/// Generates instructions to address the cell at the given byte offset from the data pointer
+///
+/// Returns the setup instructions, if any, and the memory operand
+fn cell(offset: i32) -> (Vec<String>, String) {
+    match offset {
+        0 => (vec![], "[x19]".into()),
+        // Loads and stores take an unsigned 12-bit offset (scaled by the access size),
+        // or a signed 9-bit one (as `ldur`/`stur`)
+        -256..=MAX_ADD_IMMEDIATE => (vec![], format!("[x19, #{}]", offset)),
+        _ => (
+            [vec![instr("mov x11, x19")], add_immediate("x11", offset)].concat(),
+            "[x11]".into(),
+        ),
+    }
+}
+
+/// Generates instructions for a vectorized memory scan of stride +-1, +-2, or +-4
+///
+/// Checks 16 cells at a time: compares them against zero with `cmeq`, masks out the cells that
+/// aren't a multiple of the stride away, and uses `umaxv` to test for any zero cell.
+/// Once one is found, the comparison mask is narrowed to 4 bits per cell so that
+/// the position of the first (or last) zero cell can be found with `clz`
+fn scan(stride: i32, scan_label: &str, end_label: &str) -> Vec<String> {
+    // Bytes of 0xFF at the cells to check; the pattern repeats every 8 cells
+    let mask = match stride {
+        -4 => Some("0xff000000ff000000"),
+        -2 => Some("0xff00ff00ff00ff00"),
+        2 => Some("0x00ff00ff00ff00ff"),
+        4 => Some("0x000000ff000000ff"),
+        _ => None,
+    };
+    let forward = stride > 0;
+
+    [
+        // Backwards scans check the 16 cells ending at the data pointer
+        if forward {
+            vec![]
+        } else {
+            vec![instr("sub x19, x19, #15")]
+        },
+        mask.map_or(vec![], |mask| vec![instr(format!("movi v2.2d, #{}", mask))]),
+        vec![
+            label(scan_label),
+            instr("ld1 {v0.16b}, [x19]"),
+            instr("cmeq v0.16b, v0.16b, #0"),
+        ],
+        mask.map_or(vec![], |_| vec![instr("and v0.16b, v0.16b, v2.16b")]),
+        vec![
+            instr("umaxv b1, v0.16b"),
+            instr("fmov w9, s1"),
+            instr(format!("cbnz w9, {}", end_label)),
+            instr(if forward {
+                "add x19, x19, #16"
+            } else {
+                "sub x19, x19, #16"
+            }),
+            instr(format!("b {}", scan_label)),
+            label(end_label),
+            // Narrow each byte of the mask to 4 bits
+            instr("shrn v0.8b, v0.8h, #4"),
+            instr("fmov x9, d0"),
+        ],
+        if forward {
+            vec![
+                instr("rbit x9, x9"),
+                instr("clz x9, x9"),
+                instr("add x19, x19, x9, lsr #2"),
+            ]
+        } else {
+            vec![
+                instr("clz x9, x9"),
+                instr("sub x19, x19, x9, lsr #2"),
+                instr("add x19, x19, #15"),
+            ]
+        },
+    ]
+    .concat()
+}
+
+/// Converts an abstract BF instruction to a vector of strings of assembly instructions,
+/// headed by a comment with the instruction's position in the source program
+fn bf_to_assembly(
+    bf_instr: &IntermediateInstruction,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    label_counter: &mut usize,
+) -> Vec<String> {
+    use IntermediateInstruction::*;
+
+    let (load, store) = load_store(cell_width);
+    let cell_bytes = cell_width.bytes() as i32;
+    // Addresses the cell that the instruction acts on
+    let (cell_setup, operand) = cell(bf_instr.cell_offset() * cell_bytes);
+
+    let mut mk_label = || {
+        let result = *label_counter;
+        *label_counter += 1;
+        format!(".label_{}", result)
+    };
+
+    let code = match bf_instr {
+        Loop(_, instrs, _) => {
+            let start_label = mk_label();
+            let end_label = mk_label();
+            [
+                // If the loop's cell's value is zero, jump *over* the loop
+                cell_setup.clone(),
+                vec![
+                    instr(format!("{load} w9, {operand}")),
+                    instr(format!("cbz w9, {}", end_label)),
+                    label(&start_label),
+                ],
+                instrs
+                    .iter()
+                    .map(|bf_instr| {
+                        bf_to_assembly(bf_instr, cell_width, eof_behavior, label_counter)
+                    })
+                    .collect::<Vec<Vec<String>>>()
+                    .concat(),
+                // If the loop's cell's value is nonzero, jump back to the beginning of the body
+                cell_setup,
+                vec![
+                    instr(format!("{load} w9, {operand}")),
+                    instr(format!("cbnz w9, {}", start_label)),
+                    label(&end_label),
+                ],
+            ]
+            .concat()
+        }
+
+        Move(offset, _) => add_immediate("x19", *offset * cell_bytes),
+
+        Add(_, delta, _) => [
+            cell_setup,
+            vec![instr(format!("{load} w9, {operand}"))],
+            add_cell_immediate("w9", *delta, cell_width),
+            vec![instr(format!("{store} w9, {operand}"))],
+        ]
+        .concat(),
+
+        // `getchar` returns -1 at the end of input, which sets every bit of the cell
+        // unless it's replaced with another value. The call may overwrite the cell's address,
+        // so it is set up afterwards
+        Read(_, _) => [
+            vec![instr("bl getchar")],
+            cell_setup,
+            match eof_behavior {
+                EofBehavior::Unchanged => vec![
+                    instr(format!("{load} w9, {operand}")),
+                    instr("cmn w0, #1"),
+                    instr("csel w0, w9, w0, eq"),
+                ],
+                EofBehavior::Zero => vec![instr("cmn w0, #1"), instr("csel w0, wzr, w0, eq")],
+                EofBehavior::MinusOne => vec![],
+            },
+            vec![instr(format!("{store} w0, {operand}"))],
+        ]
+        .concat(),
+
+        Write(_, _) => [
+            cell_setup,
+            vec![instr(format!("{load} w0, {operand}")), instr("bl putchar")],
+        ]
+        .concat(),
+
+        Output(bytes, _) => bytes
+            .iter()
+            .flat_map(|byte| [instr(format!("mov w0, #{}", byte)), instr("bl putchar")])
+            .collect(),
+
+        AddDynamic(target, multiplier, _) => {
+            let (setup, target) = cell(*target * cell_bytes);
+            let multiplier = *multiplier as u32 & cell_width.mask();
+            [
+                setup,
+                vec![
+                    instr(format!("{load} w9, [x19]")),
+                    // `mov` can only encode a 16-bit immediate
+                    instr(if multiplier <= u16::MAX as u32 {
+                        format!("mov w10, #{}", multiplier)
+                    } else {
+                        format!("ldr w10, ={}", multiplier)
+                    }),
+                    instr("mul w9, w9, w10"),
+                    instr(format!("{load} w10, {}", target)),
+                    instr("add w10, w10, w9"),
+                    instr(format!("{store} w10, {}", target)),
+                ],
+            ]
+            .concat()
+        }
+
+        SimpleLoop(instrs, _) => {
+            let end_label = mk_label();
+            [
+                // Jump *over* the simple loop if the current cell's value is zero
+                vec![
+                    instr(format!("{load} w9, [x19]")),
+                    instr(format!("cbz w9, {}", end_label)),
+                ],
+                instrs
+                    .iter()
+                    .map(|bf_instr| {
+                        bf_to_assembly(bf_instr, cell_width, eof_behavior, label_counter)
+                    })
+                    .collect::<Vec<Vec<String>>>()
+                    .concat(),
+                vec![label(&end_label)],
+            ]
+            .concat()
+        }
+
+        Zero(_, _) => [cell_setup, vec![instr(format!("{store} wzr, {operand}"))]].concat(),
+
+        Set(_, value, _) => {
+            let value = *value as u32 & cell_width.mask();
+            [
+                cell_setup,
+                vec![
+                    // `mov` can only encode a 16-bit immediate
+                    instr(if value <= u16::MAX as u32 {
+                        format!("mov w9, #{}", value)
+                    } else {
+                        format!("ldr w9, ={}", value)
+                    }),
+                    instr(format!("{store} w9, {operand}")),
+                ],
+            ]
+            .concat()
+        }
+
+        // Byte offsets from the start of the tape are compared as unsigned values,
+        // so negative offsets are too large
+        CheckBounds(probes, _) => {
+            let report_label = mk_label();
+            let end_label = mk_label();
+
+            // Sets `x10` to the byte offset of the probed cell, and compares it against `x21`
+            let probe = |offset: i32| {
+                [
+                    vec![instr("sub x10, x19, x20")],
+                    add_immediate("x10", offset * cell_bytes),
+                    vec![instr("cmp x10, x21")],
+                ]
+                .concat()
+            };
+
+            // With more than two probes, only the lowest and highest are checked,
+            // unless one fails
+            let fast = if probes.len() > 2 {
+                let slow_label = mk_label();
+                let min = probes.iter().map(|probe| probe.0).min().unwrap_or(0);
+                let max = probes.iter().map(|probe| probe.0).max().unwrap_or(0);
+                [
+                    probe(min),
+                    vec![instr(format!("b.hs {}", slow_label))],
+                    probe(max),
+                    vec![instr(format!("b.lo {}", end_label)), label(&slow_label)],
+                ]
+                .concat()
+            } else {
+                vec![]
+            };
+
+            // Checks each probe in order, with its position in `x9`, until one is off the tape
+            let slow = probes
+                .iter()
+                .map(|(offset, position)| {
+                    [
+                        // `mov` can only encode a 16-bit immediate
+                        vec![instr(if *position <= u16::MAX as usize {
+                            format!("mov x9, #{}", position)
+                        } else {
+                            format!("ldr x9, ={}", position)
+                        })],
+                        probe(*offset),
+                        vec![instr(format!("b.hs {}", report_label))],
+                    ]
+                    .concat()
+                })
+                .collect::<Vec<Vec<String>>>()
+                .concat();
+
+            // Reports the probed cell as a cell index
+            let shift = cell_bytes.trailing_zeros();
+            [
+                fast,
+                slow,
+                vec![
+                    instr(format!("b {}", end_label)),
+                    label(&report_label),
+                    instr(if shift == 0 {
+                        "mov x1, x10".to_string()
+                    } else {
+                        format!("asr x1, x10, #{}", shift)
+                    }),
+                    instr("mov x0, x9"),
+                    instr("bl bf_tape_overflow"),
+                    label(&end_label),
+                ],
+            ]
+            .concat()
+        }
+
+        Scan(stride, _) => {
+            let scan_label = mk_label();
+            let end_label = mk_label();
+            match (cell_width, stride) {
+                (CellWidth::Bits8, -4 | -2 | -1 | 1 | 2 | 4) => {
+                    scan(*stride, &scan_label, &end_label)
+                }
+                // Other strides and wider cells don't line up with the vector lanes,
+                // so check one cell at a time
+                _ => [
+                    vec![
+                        label(&scan_label),
+                        instr(format!("{load} w9, [x19]")),
+                        instr(format!("cbz w9, {}", end_label)),
+                    ],
+                    add_immediate("x19", *stride * cell_bytes),
+                    vec![instr(format!("b {}", scan_label)), label(&end_label)],
+                ]
+                .concat(),
+            }
+        }
+    };
+    [vec![instr(format!("// {}", bf_instr.span()))], code].concat()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::generate_instrs;
+    use crate::instruction::IntermediateInstruction;
+    use crate::instruction::IntermediateInstruction::*;
+    use crate::{CellWidth, EofBehavior, Span};
+
+    /// Generates assembly for the given instructions, without the source position comments
+    fn code(
+        src: &[IntermediateInstruction],
+        cell_width: CellWidth,
+        eof_behavior: EofBehavior,
+    ) -> Vec<String> {
+        generate_instrs(src, cell_width, eof_behavior)
+            .into_iter()
+            .filter(|line| !line.trim_start().starts_with("//"))
+            .collect()
+    }
+
+    #[test]
+    fn test_generate_basic_instrs() {
+        let span = Span::default();
+        let src = vec![
+            Add(0, -1, span),
+            Move(5000, span),
+            Move(-3, span),
+            Read(0, span),
+            Write(0, span),
+            Zero(0, span),
+        ];
+        assert_eq!(
+            code(&src, CellWidth::Bits8, EofBehavior::MinusOne).join("\n"),
+            r#"    ldrb w9, [x19]
+    add w9, w9, #255
+    strb w9, [x19]
+    ldr x12, =5000
+    add x19, x19, x12
+    sub x19, x19, #3
+    bl getchar
+    strb w0, [x19]
+    ldrb w0, [x19]
+    bl putchar
+    strb wzr, [x19]"#
+        );
+    }
+
+    #[test]
+    fn test_generate_loops() {
+        let span = Span::default();
+        let src = vec![
+            Loop(
+                0,
+                vec![Move(1, span), Loop(0, vec![Write(0, span)], span)],
+                span,
+            ),
+            SimpleLoop(
+                vec![
+                    AddDynamic(-2, 3, span),
+                    AddDynamic(5000, -1, span),
+                    Zero(0, span),
+                ],
+                span,
+            ),
+        ];
+        assert_eq!(
+            code(&src, CellWidth::Bits8, EofBehavior::MinusOne).join("\n"),
+            r#"    ldrb w9, [x19]
+    cbz w9, .label_1
+.label_0:
+    add x19, x19, #1
+    ldrb w9, [x19]
+    cbz w9, .label_3
+.label_2:
+    ldrb w0, [x19]
+    bl putchar
+    ldrb w9, [x19]
+    cbnz w9, .label_2
+.label_3:
+    ldrb w9, [x19]
+    cbnz w9, .label_0
+.label_1:
+    ldrb w9, [x19]
+    cbz w9, .label_4
+    ldrb w9, [x19]
+    mov w10, #3
+    mul w9, w9, w10
+    ldrb w10, [x19, #-2]
+    add w10, w10, w9
+    strb w10, [x19, #-2]
+    mov x11, x19
+    ldr x12, =5000
+    add x11, x11, x12
+    ldrb w9, [x19]
+    mov w10, #255
+    mul w9, w9, w10
+    ldrb w10, [x11]
+    add w10, w10, w9
+    strb w10, [x11]
+    strb wzr, [x19]
+.label_4:"#
+        );
+    }
+
+    #[test]
+    fn test_generate_scans() {
+        let span = Span::default();
+        let src = vec![Scan(2, span), Scan(-1, span), Scan(3, span)];
+        assert_eq!(
+            code(&src, CellWidth::Bits8, EofBehavior::MinusOne).join("\n"),
+            r#"    movi v2.2d, #0x00ff00ff00ff00ff
+.label_0:
+    ld1 {v0.16b}, [x19]
+    cmeq v0.16b, v0.16b, #0
+    and v0.16b, v0.16b, v2.16b
+    umaxv b1, v0.16b
+    fmov w9, s1
+    cbnz w9, .label_1
+    add x19, x19, #16
+    b .label_0
+.label_1:
+    shrn v0.8b, v0.8h, #4
+    fmov x9, d0
+    rbit x9, x9
+    clz x9, x9
+    add x19, x19, x9, lsr #2
+    sub x19, x19, #15
+.label_2:
+    ld1 {v0.16b}, [x19]
+    cmeq v0.16b, v0.16b, #0
+    umaxv b1, v0.16b
+    fmov w9, s1
+    cbnz w9, .label_3
+    sub x19, x19, #16
+    b .label_2
+.label_3:
+    shrn v0.8b, v0.8h, #4
+    fmov x9, d0
+    clz x9, x9
+    sub x19, x19, x9, lsr #2
+    add x19, x19, #15
+.label_4:
+    ldrb w9, [x19]
+    cbz w9, .label_5
+    add x19, x19, #3
+    b .label_4
+.label_5:"#
+        );
+    }
+
+    #[test]
+    fn test_generate_wide_cells() {
+        let span = Span::default();
+        let src = vec![
+            Add(0, -1, span),
+            Move(-3, span),
+            AddDynamic(2, -3, span),
+            Scan(1, span),
+            Zero(0, span),
+        ];
+        assert_eq!(
+            code(&src, CellWidth::Bits32, EofBehavior::MinusOne).join("\n"),
+            r#"    ldr w9, [x19]
+    sub w9, w9, #1
+    str w9, [x19]
+    sub x19, x19, #12
+    ldr w9, [x19]
+    ldr w10, =4294967293
+    mul w9, w9, w10
+    ldr w10, [x19, #8]
+    add w10, w10, w9
+    str w10, [x19, #8]
+.label_0:
+    ldr w9, [x19]
+    cbz w9, .label_1
+    add x19, x19, #4
+    b .label_0
+.label_1:
+    str wzr, [x19]"#
+        );
+    }
+
+    #[test]
+    fn test_generate_offsets() {
+        let span = Span::default();
+        let src = vec![
+            Add(1, 2, span),
+            Read(5000, span),
+            Write(-1, span),
+            Zero(3, span),
+        ];
+        assert_eq!(
+            code(&src, CellWidth::Bits16, EofBehavior::MinusOne).join("\n"),
+            r#"    ldrh w9, [x19, #2]
+    add w9, w9, #2
+    strh w9, [x19, #2]
+    bl getchar
+    mov x11, x19
+    ldr x12, =10000
+    add x11, x11, x12
+    strh w0, [x11]
+    ldrh w0, [x19, #-2]
+    bl putchar
+    strh wzr, [x19, #6]"#
+        );
+    }
+
+    #[test]
+    fn test_generate_bounds_checks() {
+        let span = Span::default();
+        let src = vec![CheckBounds(vec![(2, 0), (-1, 70000)], span), Move(2, span)];
+        assert_eq!(
+            code(&src, CellWidth::Bits16, EofBehavior::MinusOne).join("\n"),
+            r#"    mov x9, #0
+    sub x10, x19, x20
+    add x10, x10, #4
+    cmp x10, x21
+    b.hs .label_0
+    ldr x9, =70000
+    sub x10, x19, x20
+    sub x10, x10, #2
+    cmp x10, x21
+    b.hs .label_0
+    b .label_1
+.label_0:
+    asr x1, x10, #1
+    mov x0, x9
+    bl bf_tape_overflow
+.label_1:
+    add x19, x19, #4"#
+        );
+    }
+
+    #[test]
+    fn test_generate_source_comments() {
+        let src = IntermediateInstruction::parse_instrs(b"+\n [-]").unwrap();
+        assert_eq!(
+            generate_instrs(&src, CellWidth::Bits8, EofBehavior::MinusOne).join("\n"),
+            r#"    // 1:1
+    ldrb w9, [x19]
+    add w9, w9, #1
+    strb w9, [x19]
+    // 2:2
+    ldrb w9, [x19]
+    cbz w9, .label_1
+.label_0:
+    // 2:3
+    ldrb w9, [x19]
+    add w9, w9, #255
+    strb w9, [x19]
+    ldrb w9, [x19]
+    cbnz w9, .label_0
+.label_1:"#
+        );
+    }
+}
@@ -0,0 +1,37 @@
+//! Assembly generation for the `main` function
+
+use crate::CellWidth;
+
+/// Generates assembly for the `main` function
+///
+/// Calls `calloc` from the C standard library to allocate program memory.
+/// Passes a pointer to the center of the resulting memory tape, a pointer to its start,
+/// and its size in bytes as arguments to function `bf_prog`
+pub(crate) fn generate_main(mem_size: usize, cell_width: CellWidth) -> String {
+    format!(
+        r#"// <<<Begin main>>>
+main:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+
+    ldr x0, ={}
+    mov x1, #{}
+    bl calloc
+
+    mov x1, x0
+    ldr x2, ={}
+    ldr x3, ={}
+    add x0, x0, x3
+    bl bf_prog
+
+    mov w0, #0
+    ldp x29, x30, [sp], #16
+    ret
+    .ltorg
+// <<<End main>>>"#,
+        mem_size,
+        cell_width.bytes(),
+        mem_size * cell_width.bytes(),
+        mem_size / 2 * cell_width.bytes(),
+    )
+}
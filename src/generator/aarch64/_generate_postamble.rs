@@ -0,0 +1,11 @@
+//! Generates the postamble (assembly after the body) for AArch64
+
+/// Generates the postamble (assembly after the body) for AArch64
+///
+/// Marks the stack as non-executable
+pub(crate) fn generate_postamble() -> String {
+    r#"// <<<Begin postamble>>>
+    .section ".note.GNU-stack","",@progbits
+// <<<End postamble>>>"#
+        .to_string()
+}
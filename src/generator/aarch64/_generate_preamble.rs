@@ -0,0 +1,11 @@
+//! Generates the preamble (assembly before the main function) for AArch64
+
+/// Generates the preamble (assembly before the main function) for AArch64
+pub(crate) fn generate_preamble() -> String {
+    r#"// <<<Begin preamble>>>
+    .text
+    .globl main
+    .p2align 4
+// <<<End preamble>>>"#
+        .to_string()
+}
@@ -0,0 +1,27 @@
+//! Generates the function that reports a tape overflow for AArch64
+
+/// Generates the function `bf_tape_overflow` for AArch64
+///
+/// Accepts 2 arguments: the position in the source of the failing check, and the
+/// index of the cell it probed. Prints an error to stderr and exits with status 1
+pub(crate) fn generate_tape_overflow() -> String {
+    r#"// <<<Begin tape overflow>>>
+bf_tape_overflow:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+    mov x3, x0
+    mov x2, x1
+    mov w0, #2
+    adrp x1, tape_overflow_message
+    add x1, x1, :lo12:tape_overflow_message
+    bl dprintf
+    mov w0, #1
+    bl exit
+
+    .section .rodata
+tape_overflow_message:
+    .asciz "error: the data pointer moved off the memory tape, to cell %ld (at offset %ld)\n"
+    .text
+// <<<End tape overflow>>>"#
+        .to_string()
+}
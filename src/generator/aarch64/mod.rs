@@ -0,0 +1,17 @@
+//! Assembly generation for the AArch64 architecture
+
+mod _generate;
+pub use _generate::*;
+
+mod _generate_bf_prog;
+pub(crate) use _generate_bf_prog::*;
+mod _generate_instrs;
+pub(crate) use _generate_instrs::*;
+mod _generate_main;
+pub(crate) use _generate_main::*;
+mod _generate_postamble;
+pub(crate) use _generate_postamble::*;
+mod _generate_preamble;
+pub(crate) use _generate_preamble::*;
+mod _generate_tape_overflow;
+pub(crate) use _generate_tape_overflow::*;
@@ -1,19 +1,68 @@
-use super::{generate_bf_prog, generate_main, generate_postamble, generate_preamble};
+use super::{
+    flush_before_tape_overflow, generate_bf_prog, generate_buffered_getchar,
+    generate_buffered_putchar, generate_flush, generate_main, generate_postamble,
+    generate_preamble, generate_tape_overflow, OutputBuffer, OUTPUT_BUFFER_BYTES,
+};
+use crate::assembly::amd64::{AMD64Instruction, Function};
 use crate::instruction::IntermediateInstruction;
-use crate::BFResult;
+use crate::{BFResult, CellWidth, EofBehavior};
 
 pub fn generate(
     src: &[IntermediateInstruction],
-    _partial_evaluation: bool,
     mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    buffered: bool,
 ) -> BFResult<String> {
-    let libc_funcs = vec!["getchar".into(), "putchar".into()];
-    Ok(vec![
+    let mut libc_funcs = vec!["getchar".into()];
+    let mut label_counter = 0;
+    let mut sections = vec![
         generate_preamble(),
-        generate_main(mem_size),
-        generate_bf_prog(src),
-        generate_postamble(&libc_funcs),
-    ]
-    .join("\n\n")
-        + "\n")
+        generate_main(
+            mem_size,
+            cell_width,
+            if buffered { OUTPUT_BUFFER_BYTES } else { 0 },
+        ),
+        generate_bf_prog(src, cell_width, eof_behavior, buffered, &mut label_counter),
+    ];
+    if buffered {
+        let buffer = OutputBuffer::AfterTape;
+        let routines = [
+            (Function::Flush, generate_flush(buffer)),
+            (Function::BufferedPutChar, generate_buffered_putchar(buffer)),
+            (Function::BufferedGetChar, generate_buffered_getchar()),
+        ];
+        let lines = routines
+            .into_iter()
+            .flat_map(|(func, routine)| {
+                [
+                    vec![format!("{}:", func)],
+                    AMD64Instruction::block_to_assembly(routine, &mut label_counter),
+                ]
+                .concat()
+            })
+            .collect::<Vec<String>>();
+        sections.push(format!(
+            ";# <<<Begin output buffer>>>\n{}\n;# <<<End output buffer>>>",
+            lines.join("\n")
+        ));
+    } else {
+        libc_funcs.push("putchar".into());
+    }
+    if IntermediateInstruction::has_bounds_checks(src) {
+        let tape_overflow = generate_tape_overflow(true);
+        let tape_overflow = if buffered {
+            flush_before_tape_overflow(tape_overflow)
+        } else {
+            tape_overflow
+        };
+        let lines = AMD64Instruction::block_to_assembly(tape_overflow, &mut label_counter);
+        sections.push(format!(
+            ";# <<<Begin tape overflow>>>\nbf_tape_overflow:\n{}\n;# <<<End tape overflow>>>",
+            lines.join("\n")
+        ));
+        libc_funcs.push("exit".into());
+    }
+    sections.push(generate_postamble(&libc_funcs));
+    Ok(sections.join("\n\n") + "\n")
 }
@@ -2,22 +2,35 @@
 
 use super::generate_instrs;
 use crate::instruction::IntermediateInstruction;
+use crate::{CellWidth, EofBehavior};
 
 /// Generates the BF program body as a single function, `bf_prog`
 ///
-/// `bf_prog` accepts 1 argument: a pointer to the center of a tape of memory
-pub(crate) fn generate_bf_prog(src: &[IntermediateInstruction]) -> String {
+/// `bf_prog` accepts 3 arguments: a pointer to the center of a tape of memory,
+/// a pointer to the start of the tape, and the size of the tape in bytes.
+/// With `buffered` output, the output buffer follows the tape, and is written out before
+/// `bf_prog` returns
+pub(crate) fn generate_bf_prog(
+    src: &[IntermediateInstruction],
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    buffered: bool,
+    label_counter: &mut usize,
+) -> String {
     format!(
         r#";# <<<Begin BF Program>>>
 bf_prog:
     mov r12, rdi
+    mov r14, rsi
+    mov r15, rdx
     vmovdqu ymm1, [rip + mask_1]
     vmovdqu ymm2, [rip + mask_2]
     vmovdqu ymm4, [rip + mask_4]
     vpxor ymm0, ymm0, ymm0
-{}
+{}{}
     ret
 ;# <<<End BF Program>>>"#,
-        generate_instrs(src).join("\n")
+        generate_instrs(src, cell_width, eof_behavior, buffered, label_counter).join("\n"),
+        if buffered { "\n    call bf_flush" } else { "" }
     )
 }
@@ -0,0 +1,218 @@
+//! Generates a statically-linked executable for AMD64 Linux
+
+use super::{
+    flush_before_tape_overflow, generate_buffered_putchar, generate_flush, generate_flush_call,
+    generate_tape_overflow, OutputBuffer, OUTPUT_BUFFER_BYTES,
+};
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register, Function};
+use crate::elf::{ElfExecutable, TEXT_ADDRESS};
+use crate::instruction::IntermediateInstruction;
+use crate::{BFError, BFResult, CellWidth, EofBehavior};
+use AMD64Instruction::*;
+use AMD64Operand::*;
+use AMD64Register::*;
+
+/// Extra zeroed bytes on either end of the memory tape,
+/// so that a vectorized scan near the end of the tape doesn't read outside the segment
+const TAPE_PADDING: usize = 32;
+
+/// The runtime replacement for `getchar`: reads one byte from stdin with the `read` syscall
+///
+/// Returns the byte in `RAX`, or -1 at the end of the input
+fn runtime_getchar() -> Vec<AMD64Instruction> {
+    vec![
+        // `read` is syscall 0; the zeroed register also serves as a buffer on the stack
+        Xor(Register(EAX), Register(EAX)),
+        Push(Register(RAX)),
+        Xor(Register(EDI), Register(EDI)),
+        Mov(Register(RSI), Register(RSP)),
+        Mov(Register(EDX), Immediate(1)),
+        Syscall(),
+        // Exactly one byte should have been read; otherwise, return -1
+        Cmp(Register(RAX), Immediate(1)),
+        Pop(Register(RAX)),
+        Je(1, None),
+        Mov(Register(RAX), Immediate(-1)),
+        Ret(),
+    ]
+}
+
+/// The runtime replacement for `putchar`: writes the byte in `DIL` to stdout with the `write`
+/// syscall
+fn runtime_putchar() -> Vec<AMD64Instruction> {
+    vec![
+        // The argument is pushed to serve as a buffer on the stack
+        Push(Register(RDI)),
+        Mov(Register(EAX), Immediate(1)),
+        Mov(Register(EDI), Immediate(1)),
+        Mov(Register(RSI), Register(RSP)),
+        Mov(Register(EDX), Immediate(1)),
+        Syscall(),
+        Pop(Register(RDI)),
+        Ret(),
+    ]
+}
+
+/// Generates the program entry point: sets up the memory tape, runs the program, and exits
+///
+/// The tape starts at the given address; bounds checks expect its address in `R14`
+/// and its size in bytes in `R15`. With `buffered` output, the output buffer is flushed
+/// before exiting
+fn generate_start(
+    src: &[IntermediateInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    tape_address: u64,
+    buffered: bool,
+) -> Vec<AMD64Instruction> {
+    let tape_center = tape_address + (mem_size / 2 * cell_width.bytes()) as u64;
+    [
+        vec![
+            Mov(Register(R12), Immediate(tape_center as isize)),
+            Mov(Register(R14), Immediate(tape_address as isize)),
+            Mov(
+                Register(R15),
+                Immediate((mem_size * cell_width.bytes()) as isize),
+            ),
+        ],
+        AMD64Instruction::convert_instructions(src, cell_width, eof_behavior).concat(),
+        if buffered {
+            vec![Call(Function::Flush)]
+        } else {
+            vec![]
+        },
+        // `exit` is syscall 60
+        vec![
+            Mov(Register(EAX), Immediate(60)),
+            Xor(Register(EDI), Register(EDI)),
+            Syscall(),
+        ],
+    ]
+    .concat()
+}
+
+/// Generates the runtime routines and the entry point of an executable, in order: `getchar`,
+/// `putchar`, `bf_flush`, `bf_tape_overflow`, and the entry point
+///
+/// Routines that the program doesn't need are empty. With `buffered` output, the output buffer
+/// at the given address is flushed before reading input and before reporting a failed bounds
+/// check.
+fn generate_blocks(
+    src: &[IntermediateInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    tape_address: u64,
+    buffer: Option<u64>,
+) -> BFResult<[Vec<AMD64Instruction>; 5]> {
+    let tape_overflow = if IntermediateInstruction::has_bounds_checks(src) {
+        generate_tape_overflow(false)
+    } else {
+        vec![]
+    };
+    let start = generate_start(
+        src,
+        mem_size,
+        cell_width,
+        eof_behavior,
+        tape_address,
+        buffer.is_some(),
+    );
+
+    let Some(buffer) = buffer else {
+        return Ok([
+            runtime_getchar(),
+            runtime_putchar(),
+            vec![],
+            tape_overflow,
+            start,
+        ]);
+    };
+
+    let tape_overflow = if tape_overflow.is_empty() {
+        tape_overflow
+    } else {
+        flush_before_tape_overflow(tape_overflow)
+    };
+    let buffer = OutputBuffer::At(buffer);
+    Ok([
+        [generate_flush_call(), runtime_getchar()].concat(),
+        generate_buffered_putchar(buffer),
+        generate_flush(buffer),
+        tape_overflow,
+        start,
+    ])
+}
+
+/// Generates a statically-linked ELF executable for the given program, memory size,
+/// cell width, and EOF behavior
+///
+/// The program is encoded directly to machine code, so no assembler or linker is needed.
+/// Rather than linking against the C standard library, the executable contains a tiny runtime
+/// that provides `getchar` and `putchar` (and reports failed bounds checks) through Linux syscalls.
+/// The memory tape lives in the zero-initialized `.bss` section.
+///
+/// If `buffered` is set, the output is collected in a buffer right after the tape, and written
+/// out when the buffer is full, before reading input, and at exit. Otherwise, every byte is
+/// written out as soon as it is output.
+pub fn generate_executable(
+    src: &[IntermediateInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    buffered: bool,
+) -> BFResult<Vec<u8>> {
+    // The size of each block doesn't depend on the addresses of the tape and the buffer,
+    // so the blocks can be encoded once to find where those will be
+    let placeholder = buffered.then_some(0);
+    let text_size = generate_blocks(src, mem_size, cell_width, eof_behavior, 0, placeholder)?
+        .iter()
+        .map(|block| AMD64Instruction::encode_block(block).map(|bytes| bytes.len()))
+        .sum::<BFResult<usize>>()?;
+    let bss_address = ElfExecutable::bss_address(text_size);
+    let tape_size = mem_size * cell_width.bytes();
+    let tape_address = bss_address + TAPE_PADDING as u64;
+    let buffer_address = tape_address + (tape_size + TAPE_PADDING) as u64;
+
+    let blocks = generate_blocks(
+        src,
+        mem_size,
+        cell_width,
+        eof_behavior,
+        tape_address,
+        buffered.then_some(buffer_address),
+    )?;
+    let mut encoded = blocks
+        .iter()
+        .map(|block| AMD64Instruction::encode_block(block))
+        .collect::<BFResult<Vec<_>>>()?;
+    let mut addresses = vec![TEXT_ADDRESS];
+    for bytes in &encoded {
+        addresses.push(addresses[addresses.len() - 1] + bytes.len() as u64);
+    }
+
+    for (block, bytes) in blocks.iter().zip(&mut encoded) {
+        for (offset, func) in AMD64Instruction::call_sites(block)? {
+            let address = match func {
+                Function::GetChar => addresses[0],
+                Function::PutChar => addresses[1],
+                Function::Flush => addresses[2],
+                Function::TapeOverflow => addresses[3],
+                Function::Calloc
+                | Function::Exit
+                | Function::GrowTape
+                | Function::BufferedGetChar
+                | Function::BufferedPutChar => return Err(BFError::EncodeError(Call(func))),
+            };
+            AMD64Instruction::patch_call_absolute(bytes, offset, address);
+        }
+    }
+
+    let executable = ElfExecutable {
+        entry: (addresses[4] - TEXT_ADDRESS) as usize,
+        text: encoded.concat(),
+        bss_size: tape_size + 2 * TAPE_PADDING + if buffered { OUTPUT_BUFFER_BYTES } else { 0 },
+    };
+    Ok(executable.to_bytes())
+}
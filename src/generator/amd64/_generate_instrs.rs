@@ -1,13 +1,30 @@
 //! Assembly generation for the BF instructions for AMD64
 
+use super::call_buffered_io;
 use crate::assembly::amd64::AMD64Instruction;
 use crate::instruction::IntermediateInstruction;
+use crate::{CellWidth, EofBehavior};
 
-/// Generates (string) assembly instructions for the given abstract BF instructions
-pub(crate) fn generate_instrs(src: &[IntermediateInstruction]) -> Vec<String> {
-    let mut label_counter = 0;
-    src.iter()
-        .map(|instr| AMD64Instruction::bf_to_assembly(instr, &mut label_counter))
-        .collect::<Vec<Vec<String>>>()
-        .concat()
+/// Generates (string) assembly instructions for the given abstract BF instructions,
+/// numbering labels from the given counter
+///
+/// With `buffered` output, input and output go through `bf_getchar` and `bf_putchar`
+pub(crate) fn generate_instrs(
+    src: &[IntermediateInstruction],
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    buffered: bool,
+    label_counter: &mut usize,
+) -> Vec<String> {
+    if !buffered {
+        return src
+            .iter()
+            .map(|instr| {
+                AMD64Instruction::bf_to_assembly(instr, cell_width, eof_behavior, label_counter)
+            })
+            .collect::<Vec<Vec<String>>>()
+            .concat();
+    }
+    let instrs = AMD64Instruction::convert_instructions(src, cell_width, eof_behavior).concat();
+    AMD64Instruction::block_to_assembly(call_buffered_io(instrs), label_counter)
 }
@@ -1,11 +1,14 @@
 //! Assembly generation for the `main` function
 
+use crate::CellWidth;
+
 /// Generates assembly for the `main` function
 ///
-/// Calls `calloc` from the C standard library to allocate program memory.
-/// Passes a pointer to the center of the resulting memory tape
-/// as an argument to function `bf_prog`
-pub(crate) fn generate_main(mem_size: usize) -> String {
+/// Calls `calloc` from the C standard library to allocate program memory, with the given
+/// number of extra bytes after the memory tape.
+/// Passes a pointer to the center of the resulting memory tape, a pointer to its start,
+/// and its size in bytes as arguments to function `bf_prog`
+pub(crate) fn generate_main(mem_size: usize, cell_width: CellWidth, extra_bytes: usize) -> String {
     format!(
         r#";# <<<Begin main>>>
 main:
@@ -13,9 +16,11 @@ main:
     mov rbp, rsp
 
     mov rdi, {}
-    mov rsi, 1
+    mov rsi, {}
     call calloc
 
+    mov rsi, rax
+    mov rdx, {}
     lea rdi, [rax + {}]
     call bf_prog
 
@@ -25,7 +30,9 @@ main:
     mov rax, 0
     ret
 ;# <<<End main>>>"#,
-        mem_size,
-        mem_size / 2,
+        mem_size * cell_width.bytes() + extra_bytes,
+        1,
+        mem_size * cell_width.bytes(),
+        mem_size / 2 * cell_width.bytes(),
     )
 }
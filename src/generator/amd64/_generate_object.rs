@@ -0,0 +1,122 @@
+//! Generates a relocatable object file for AMD64 Linux
+
+use super::{
+    call_buffered_io, flush_before_tape_overflow, generate_buffered_getchar,
+    generate_buffered_putchar, generate_flush, generate_tape_overflow, OutputBuffer,
+    OUTPUT_BUFFER_BYTES,
+};
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register, Function};
+use crate::elf::ElfObjectWriter;
+use crate::instruction::IntermediateInstruction;
+use crate::{BFResult, CellWidth, EofBehavior};
+use AMD64Instruction::*;
+use AMD64Operand::*;
+use AMD64Register::*;
+
+/// Generates the `main` function: allocates the memory tape with `calloc` and runs the program
+///
+/// With `buffered` output, the output buffer is allocated right after the tape, and written out
+/// before returning
+fn generate_object_main(
+    src: &[IntermediateInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    buffered: bool,
+) -> Vec<AMD64Instruction> {
+    let tape_size = mem_size * cell_width.bytes();
+    let body = AMD64Instruction::convert_instructions(src, cell_width, eof_behavior).concat();
+    [
+        vec![
+            Push(Register(RBP)),
+            Mov(Register(RBP), Register(RSP)),
+            // Save the callee-saved registers used by the program body,
+            // keeping the stack 16-byte aligned for calls
+            Push(Register(RBX)),
+            Push(Register(R12)),
+            Push(Register(R13)),
+            Push(Register(R14)),
+            Push(Register(R15)),
+            Mov(
+                Register(RDI),
+                Immediate((tape_size + if buffered { OUTPUT_BUFFER_BYTES } else { 0 }) as isize),
+            ),
+            Mov(Register(RSI), Immediate(1)),
+            Call(Function::Calloc),
+            // Bounds checks expect the address of the tape in `R14` and its size in `R15`
+            Mov(Register(R14), Register(RAX)),
+            Mov(Register(R15), Immediate(tape_size as isize)),
+            Mov(Register(R12), Register(RAX)),
+            Add(
+                Register(R12),
+                Immediate((mem_size / 2 * cell_width.bytes()) as isize),
+            ),
+        ],
+        if buffered {
+            [call_buffered_io(body), vec![Call(Function::Flush)]].concat()
+        } else {
+            body
+        },
+        vec![
+            Pop(Register(R15)),
+            Pop(Register(R14)),
+            Pop(Register(R13)),
+            Pop(Register(R12)),
+            Pop(Register(RBX)),
+            Pop(Register(RBP)),
+            Xor(Register(EAX), Register(EAX)),
+            Ret(),
+        ],
+    ]
+    .concat()
+}
+
+/// Generates a relocatable ELF object file for the given program, memory size, cell width,
+/// and EOF behavior
+///
+/// The object defines a global `main` function, and references `calloc`, `getchar`, and
+/// `putchar` from the C standard library through relocations,
+/// so it can be linked with C code by any system linker.
+/// A program with bounds checks also defines `bf_tape_overflow`, which references `exit`
+///
+/// If `buffered` is set, the output is collected in a buffer right after the tape instead of
+/// going through `putchar`, and written out with the `write` syscall when the buffer is full,
+/// before reading input, and before exiting. The object then also defines `bf_flush`,
+/// `bf_putchar`, and `bf_getchar`
+pub fn generate_object(
+    src: &[IntermediateInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    buffered: bool,
+) -> BFResult<Vec<u8>> {
+    let mut writer = ElfObjectWriter::new();
+    // Calls are only resolved to functions that are already defined,
+    // so each function comes after those it calls
+    if buffered {
+        let buffer = OutputBuffer::AfterTape;
+        writer.add_function(&Function::Flush.to_string(), &generate_flush(buffer))?;
+        writer.add_function(
+            &Function::BufferedPutChar.to_string(),
+            &generate_buffered_putchar(buffer),
+        )?;
+        writer.add_function(
+            &Function::BufferedGetChar.to_string(),
+            &generate_buffered_getchar(),
+        )?;
+    }
+    if IntermediateInstruction::has_bounds_checks(src) {
+        let tape_overflow = generate_tape_overflow(true);
+        let tape_overflow = if buffered {
+            flush_before_tape_overflow(tape_overflow)
+        } else {
+            tape_overflow
+        };
+        writer.add_function(&Function::TapeOverflow.to_string(), &tape_overflow)?;
+    }
+    writer.add_function(
+        "main",
+        &generate_object_main(src, mem_size, cell_width, eof_behavior, buffered),
+    )?;
+    Ok(writer.to_bytes())
+}
@@ -0,0 +1,177 @@
+//! Generates the runtime routines that buffer a program's output for AMD64
+
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register, Function, MemorySize};
+use AMD64Instruction::*;
+use AMD64Operand::*;
+use AMD64Register::*;
+
+/// The number of bytes of output that are buffered before they are written out
+pub const OUTPUT_BUFFER_SIZE: usize = 4096;
+
+/// The size of an output buffer in memory, in bytes
+///
+/// The buffer starts with the number of buffered bytes, as a quadword, followed by the bytes
+pub const OUTPUT_BUFFER_BYTES: usize = 8 + OUTPUT_BUFFER_SIZE;
+
+/// Where the output buffer is in memory
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum OutputBuffer {
+    /// At the given address
+    At(u64),
+
+    /// Right after the end of the memory tape, which starts at `R14` and is `R15` bytes long
+    AfterTape,
+}
+
+impl OutputBuffer {
+    /// Generates instructions that load the address of the buffer into `RSI`
+    fn load(self) -> Vec<AMD64Instruction> {
+        match self {
+            OutputBuffer::At(address) => vec![Mov(Register(RSI), Immediate(address as isize))],
+            OutputBuffer::AfterTape => vec![
+                Mov(Register(RSI), Register(R14)),
+                Add(Register(RSI), Register(R15)),
+            ],
+        }
+    }
+}
+
+/// Returns the quadword that holds the number of bytes in the buffer at `RSI`
+fn buffer_len() -> AMD64Operand {
+    Memory(Some(MemorySize::QWord), Some(RSI), None, None, Some(0))
+}
+
+/// Generates a call to [`Function::Flush`] that keeps the stack 16-byte aligned, like any other
+/// call
+pub(crate) fn generate_flush_call() -> Vec<AMD64Instruction> {
+    vec![
+        Add(Register(RSP), Immediate(-8)),
+        Call(Function::Flush),
+        Add(Register(RSP), Immediate(8)),
+    ]
+}
+
+/// Redirects the calls to `getchar` and `putchar` in the given instructions to the buffered
+/// routines, [`Function::BufferedGetChar`] and [`Function::BufferedPutChar`]
+pub(crate) fn call_buffered_io(instrs: Vec<AMD64Instruction>) -> Vec<AMD64Instruction> {
+    instrs
+        .into_iter()
+        .map(|instr| match instr {
+            Call(Function::GetChar) => Call(Function::BufferedGetChar),
+            Call(Function::PutChar) => Call(Function::BufferedPutChar),
+            instr => instr,
+        })
+        .collect()
+}
+
+/// Generates the buffered replacement for `getchar` in a program linked with the C standard
+/// library: writes out the output buffer, then calls `getchar`
+pub(crate) fn generate_buffered_getchar() -> Vec<AMD64Instruction> {
+    vec![
+        Add(Register(RSP), Immediate(-8)),
+        Call(Function::Flush),
+        Call(Function::GetChar),
+        Add(Register(RSP), Immediate(8)),
+        Ret(),
+    ]
+}
+
+/// Wraps the given routine that reports a failed bounds check, so that it writes out the output
+/// buffer first
+pub(crate) fn flush_before_tape_overflow(
+    tape_overflow: Vec<AMD64Instruction>,
+) -> Vec<AMD64Instruction> {
+    [
+        vec![Push(Register(RDI)), Push(Register(RSI))],
+        generate_flush_call(),
+        vec![Pop(Register(RSI)), Pop(Register(RDI))],
+        tape_overflow,
+    ]
+    .concat()
+}
+
+/// Generates the buffered replacement for `putchar`: appends the byte in `DIL` to the given
+/// output buffer, and calls [`Function::Flush`] once the buffer is full
+pub(crate) fn generate_buffered_putchar(buffer: OutputBuffer) -> Vec<AMD64Instruction> {
+    [
+        buffer.load(),
+        vec![
+            Mov(Register(RAX), buffer_len()),
+            Mov(
+                Memory(
+                    Some(MemorySize::Byte),
+                    Some(RSI),
+                    Some(RAX),
+                    Some(1),
+                    Some(8),
+                ),
+                Register(DIL),
+            ),
+            Add(Register(RAX), Immediate(1)),
+            Mov(buffer_len(), Register(RAX)),
+            Cmp(Register(RAX), Immediate(OUTPUT_BUFFER_SIZE as isize)),
+            Jne(3, None),
+        ],
+        // Keep the stack 16-byte aligned for the call
+        generate_flush_call(),
+        vec![Ret()],
+    ]
+    .concat()
+}
+
+/// Generates the runtime routine that writes out the given output buffer to stdout with the
+/// `write` syscall, and empties it
+///
+/// A failed write drops the rest of the buffered output, just as `putchar` would.
+pub(crate) fn generate_flush(buffer: OutputBuffer) -> Vec<AMD64Instruction> {
+    [
+        buffer.load(),
+        vec![
+            Mov(Register(RDX), buffer_len()),
+            Add(Register(RSI), Immediate(8)),
+            Test(Register(RDX), Register(RDX)),
+            Je(10, None),
+            // `write` is syscall 1; stdout is file descriptor 1. It may write fewer bytes than
+            // asked, so it is repeated until the whole buffer is written, or until it fails
+            Mov(Register(EAX), Immediate(1)),
+            Mov(Register(EDI), Immediate(1)),
+            Syscall(),
+            // Stop if nothing was written, or if everything (or an error code, which is a huge
+            // unsigned number) was
+            Test(Register(RAX), Register(RAX)),
+            Je(5, None),
+            Cmp(Register(RAX), Register(RDX)),
+            Jae(3, None),
+            Add(Register(RSI), Register(RAX)),
+            Sub(Register(RDX), Register(RAX)),
+            Jmp(-10, None),
+        ],
+        buffer.load(),
+        vec![Mov(buffer_len(), Immediate(0)), Ret()],
+    ]
+    .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        flush_before_tape_overflow, generate_buffered_getchar, generate_buffered_putchar,
+        generate_flush, OutputBuffer,
+    };
+    use crate::assembly::amd64::AMD64Instruction;
+
+    #[test]
+    fn test_output_buffer_encodes() {
+        for buffer in [OutputBuffer::At(0x1234_5678), OutputBuffer::AfterTape] {
+            for routine in [generate_buffered_putchar(buffer), generate_flush(buffer)] {
+                assert!(AMD64Instruction::encode_block(&routine).is_ok());
+            }
+        }
+        for routine in [
+            generate_buffered_getchar(),
+            flush_before_tape_overflow(vec![]),
+        ] {
+            assert!(AMD64Instruction::encode_block(&routine).is_ok());
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Generates the runtime routine that reports a failed bounds check for AMD64
+
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register, Function, MemorySize};
+use AMD64Instruction::*;
+use AMD64Operand::*;
+use AMD64Register::*;
+
+/// The size of the stack buffer that the message is built in, in bytes
+const BUFFER_SIZE: i32 = 128;
+
+/// Returns the memory operand at the given offset from the given register
+fn memory(size: Option<MemorySize>, base: AMD64Register, offset: i32) -> AMD64Operand {
+    Memory(size, Some(base), None, None, Some(offset))
+}
+
+/// Generates instructions that prepend the given text to the message at `RBX`
+fn prepend(text: &str) -> Vec<AMD64Instruction> {
+    [
+        vec![Lea(Register(RBX), memory(None, RBX, -(text.len() as i32)))],
+        text.bytes()
+            .enumerate()
+            .map(|(index, byte)| {
+                Mov(
+                    memory(Some(MemorySize::Byte), RBX, index as i32),
+                    Immediate(byte as isize),
+                )
+            })
+            .collect(),
+    ]
+    .concat()
+}
+
+/// Generates instructions that prepend the decimal digits of the unsigned value in `RAX`
+/// to the message at `RBX`
+fn prepend_digits() -> Vec<AMD64Instruction> {
+    vec![
+        Mov(Register(ECX), Immediate(10)),
+        // Divide by 10 until the quotient is 0, prepending each remainder as a digit
+        Xor(Register(EDX), Register(EDX)),
+        Div(Register(RCX)),
+        Add(Register(DL), Immediate(b'0' as isize)),
+        Add(Register(RBX), Immediate(-1)),
+        Mov(memory(Some(MemorySize::Byte), RBX, 0), Register(DL)),
+        Test(Register(RAX), Register(RAX)),
+        Jne(-7, None),
+    ]
+}
+
+/// Generates the runtime routine that reports a failed bounds check, `bf_tape_overflow`
+///
+/// Takes the position of the instruction in `RDI` and the cell index in `RSI`, and writes
+/// `error: the data pointer moved off the memory tape, to cell <index> (at offset <position>)`
+/// to stderr, as the error reads without a source snippet. Then exits with status 1: through
+/// `exit` from the C standard library if `libc` is set, so that buffered output is flushed, or
+/// with the `exit` syscall
+pub(crate) fn generate_tape_overflow(libc: bool) -> Vec<AMD64Instruction> {
+    [
+        vec![
+            // This never returns, so the stack can be realigned for the call to `exit`
+            And(Register(RSP), Immediate(-16)),
+            Add(Register(RSP), Immediate(-BUFFER_SIZE as isize)),
+            // The message is built backwards from the end of the buffer
+            Lea(Register(RBX), memory(None, RSP, BUFFER_SIZE)),
+            Mov(Register(R8), Register(RDI)),
+        ],
+        prepend(")\n"),
+        vec![Mov(Register(RAX), Register(R8))],
+        prepend_digits(),
+        prepend(" (at offset "),
+        // Prepend the absolute value of the index
+        vec![
+            Mov(Register(RAX), Register(RSI)),
+            Not(Register(RAX)),
+            Add(Register(RAX), Immediate(1)),
+            Cmp(Register(RSI), Immediate(0)),
+            Cmovge(Register(RAX), Register(RSI)),
+        ],
+        prepend_digits(),
+        // Prepend a minus sign, then skip it again if the index isn't negative
+        vec![
+            Add(Register(RBX), Immediate(-1)),
+            Mov(
+                memory(Some(MemorySize::Byte), RBX, 0),
+                Immediate(b'-' as isize),
+            ),
+            Lea(Register(RAX), memory(None, RBX, 1)),
+            Cmp(Register(RSI), Immediate(0)),
+            Cmovge(Register(RBX), Register(RAX)),
+        ],
+        prepend("error: the data pointer moved off the memory tape, to cell "),
+        // `write` is syscall 1; stderr is file descriptor 2
+        vec![
+            Lea(Register(RDX), memory(None, RSP, BUFFER_SIZE)),
+            Sub(Register(RDX), Register(RBX)),
+            Mov(Register(RSI), Register(RBX)),
+            Mov(Register(EDI), Immediate(2)),
+            Mov(Register(EAX), Immediate(1)),
+            Syscall(),
+        ],
+        if libc {
+            vec![Mov(Register(EDI), Immediate(1)), Call(Function::Exit)]
+        } else {
+            // `exit` is syscall 60
+            vec![
+                Mov(Register(EAX), Immediate(60)),
+                Mov(Register(EDI), Immediate(1)),
+                Syscall(),
+            ]
+        },
+    ]
+    .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate_tape_overflow;
+    use crate::assembly::amd64::AMD64Instruction;
+
+    #[test]
+    fn test_tape_overflow_encodes() {
+        for libc in [false, true] {
+            let routine = generate_tape_overflow(libc);
+            assert!(AMD64Instruction::encode_block(&routine).is_ok());
+        }
+    }
+}
@@ -3,16 +3,25 @@
 mod _generate;
 pub use _generate::*;
 
+mod _generate_executable;
+pub use _generate_executable::*;
+mod _generate_object;
+pub use _generate_object::*;
+
 mod _generate_bf_prog;
 pub(crate) use _generate_bf_prog::*;
 mod _generate_instrs;
 pub(crate) use _generate_instrs::*;
 mod _generate_main;
 pub(crate) use _generate_main::*;
+mod _generate_output_buffer;
+pub(crate) use _generate_output_buffer::*;
 mod _generate_postamble;
 pub(crate) use _generate_postamble::*;
 mod _generate_preamble;
 pub(crate) use _generate_preamble::*;
+mod _generate_tape_overflow;
+pub(crate) use _generate_tape_overflow::*;
 
 /// Assembly generator for the AMD64 architecture
 pub struct AMD64Generator {
@@ -0,0 +1,535 @@
+use crate::instruction::IntermediateInstruction;
+use crate::optimizer::invariant_loads;
+use crate::{BFResult, CellWidth, EofBehavior};
+use std::collections::HashMap;
+
+/// Formats a statement that adds the given constant to the given lvalue
+fn add_statement(lvalue: &str, value: i32) -> String {
+    if value < 0 {
+        format!("{} -= {};", lvalue, -(value as i64))
+    } else {
+        format!("{} += {};", lvalue, value)
+    }
+}
+
+/// Returns the lvalue of the cell at the given offset from the data pointer
+fn cell(offset: i32) -> String {
+    match offset {
+        0 => "*p".into(),
+        _ => format!("p[{}]", offset),
+    }
+}
+
+/// Returns the name of the local that holds the cell at the given offset, once it is loaded
+/// before a loop
+fn local(offset: i32) -> String {
+    match offset {
+        _ if offset < 0 => format!("cell_m{}", -(offset as i64)),
+        _ => format!("cell_{}", offset),
+    }
+}
+
+/// Formats the given bytes as a C string literal
+///
+/// Bytes other than printable ASCII are written as octal escapes, which never run on into
+/// the next character, and `?` is escaped so that it can't start a trigraph
+fn string_literal(bytes: &[u8]) -> String {
+    let mut literal = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' | b'?' => {
+                literal.push('\\');
+                literal.push(byte as char);
+            }
+            b' '..=b'~' => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Formats a block statement (`while`, `if`) with the given header and body
+fn block_statement(
+    header: &str,
+    instrs: &[IntermediateInstruction],
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    depth: usize,
+    loaded: &HashMap<i32, String>,
+    lines: &mut Vec<String>,
+) {
+    let indent = "    ".repeat(depth);
+    lines.push(format!("{}{} {{", indent, header));
+    generate_statements(instrs, cell_width, eof_behavior, depth + 1, loaded, lines);
+    lines.push(format!("{}}}", indent));
+}
+
+/// Returns the C type of a cell of the given width
+fn cell_type(cell_width: CellWidth) -> &'static str {
+    match cell_width {
+        CellWidth::Bits8 => "unsigned char",
+        CellWidth::Bits16 => "uint16_t",
+        CellWidth::Bits32 => "uint32_t",
+    }
+}
+
+/// Appends C statements for the given abstract BF instructions, indented to the given depth
+///
+/// The data pointer is `p`, a pointer to a cell of the tape.
+/// The cells that an enclosing loop loaded into locals are read from those locals instead
+fn generate_statements(
+    instrs: &[IntermediateInstruction],
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    depth: usize,
+    loaded: &HashMap<i32, String>,
+    lines: &mut Vec<String>,
+) {
+    use IntermediateInstruction::*;
+
+    let indent = "    ".repeat(depth);
+    // The value of the cell at the given offset
+    let value = |offset: i32| loaded.get(&offset).cloned().unwrap_or_else(|| cell(offset));
+    for instr in instrs {
+        let cell = cell(instr.cell_offset());
+        let statement = match instr {
+            // The cells that every iteration reads but never changes are loaded into locals once
+            // the loop's cell is checked, so that they're only loaded if the loop runs at all
+            Loop(offset, body, _) => {
+                let condition = value(*offset);
+                let loads = invariant_loads(body)
+                    .into_iter()
+                    .filter(|offset| !loaded.contains_key(offset))
+                    .collect::<Vec<_>>();
+                if loads.is_empty() {
+                    let header = format!("while ({})", condition);
+                    block_statement(
+                        &header,
+                        body,
+                        cell_width,
+                        eof_behavior,
+                        depth,
+                        loaded,
+                        lines,
+                    );
+                } else {
+                    let mut loaded = loaded.clone();
+                    lines.push(format!("{}if ({}) {{", indent, condition));
+                    for offset in loads {
+                        lines.push(format!(
+                            "{}    const {} {} = {};",
+                            indent,
+                            cell_type(cell_width),
+                            local(offset),
+                            value(offset)
+                        ));
+                        loaded.insert(offset, local(offset));
+                    }
+                    lines.push(format!("{}    do {{", indent));
+                    generate_statements(body, cell_width, eof_behavior, depth + 2, &loaded, lines);
+                    lines.push(format!("{}    }} while ({});", indent, condition));
+                    lines.push(format!("{}}}", indent));
+                }
+                continue;
+            }
+            // Only runs its body once, since the body zeroes the current cell
+            SimpleLoop(body, _) => {
+                let header = format!("if ({})", value(0));
+                block_statement(
+                    &header,
+                    body,
+                    cell_width,
+                    eof_behavior,
+                    depth,
+                    loaded,
+                    lines,
+                );
+                continue;
+            }
+
+            Move(offset, _) => add_statement("p", *offset),
+            Add(_, delta, _) => add_statement(&cell, *delta),
+            // `getchar` returns `EOF` (-1) at the end of input, so every bit of the cell is set
+            Read(_, _) if eof_behavior == EofBehavior::MinusOne => {
+                format!("{} = ({})getchar();", cell, cell_type(cell_width))
+            }
+            Read(_, _) if eof_behavior == EofBehavior::Zero => format!(
+                "{{ int c = getchar(); {} = c == EOF ? 0 : ({})c; }}",
+                cell,
+                cell_type(cell_width)
+            ),
+            Read(_, _) => format!(
+                "{{ int c = getchar(); if (c != EOF) {} = ({})c; }}",
+                cell,
+                cell_type(cell_width)
+            ),
+            Write(offset, _) => format!("putchar({});", value(*offset)),
+            Output(bytes, _) => format!(
+                "fwrite({}, 1, {}, stdout);",
+                string_literal(bytes),
+                bytes.len()
+            ),
+            AddDynamic(target, 1, _) => format!("p[{}] += {};", target, value(0)),
+            AddDynamic(target, -1, _) => format!("p[{}] -= {};", target, value(0)),
+            // Only the low byte of the multiplier matters, which keeps the product within an `int`
+            AddDynamic(target, multiplier, _) if cell_width == CellWidth::Bits8 => {
+                format!("p[{}] += {} * {};", target, value(0), *multiplier as i8)
+            }
+            // Wider cells multiply as unsigned, so that the product wraps instead of overflowing
+            AddDynamic(target, multiplier, _) => format!(
+                "p[{}] += {} * {}u;",
+                target,
+                value(0),
+                *multiplier as u32 & cell_width.mask()
+            ),
+            Zero(_, _) => format!("{} = 0;", cell),
+            Set(_, value, _) => {
+                format!("{} = {};", cell, *value as u32 & cell_width.mask())
+            }
+
+            Scan(1, _) if cell_width == CellWidth::Bits8 => {
+                "p = memchr(p, 0, (size_t)(tape + MEM_SIZE - p));".into()
+            }
+            Scan(stride, _) => format!("while (*p) {}", add_statement("p", *stride)),
+
+            // Negative indices convert to very large sizes, so one comparison checks both ends
+            CheckBounds(probes, _) => {
+                for (offset, position) in probes {
+                    let index = match offset {
+                        0 => "p - tape".to_string(),
+                        _ if *offset < 0 => format!("p - tape - {}", -(*offset as i64)),
+                        _ => format!("p - tape + {}", offset),
+                    };
+                    lines.push(format!(
+                        "{}if ((size_t)({}) >= MEM_SIZE) tape_overflow({}, (long)({}));",
+                        indent, index, position, index
+                    ));
+                }
+                continue;
+            }
+        };
+        lines.push(format!("{}{}", indent, statement));
+    }
+}
+
+/// Generates a standalone C99 source file for the given program, memory size, cell width,
+/// and EOF behavior
+///
+/// The tape is a zero-initialized `static` array of `unsigned char`, `uint16_t`, or `uint32_t`,
+/// and the program reads and writes with `getchar` and `putchar`.
+/// A failed bounds check prints the same error as the interpreter and exits with status 1
+pub fn generate(
+    src: &[IntermediateInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+) -> BFResult<String> {
+    let cell = cell_type(cell_width);
+    let mut lines = vec!["#include <stdio.h>".to_string()];
+    if cell_width != CellWidth::Bits8 {
+        lines.push("#include <stdint.h>".into());
+    }
+    let checked = IntermediateInstruction::has_bounds_checks(src);
+    if checked {
+        lines.push("#include <stdlib.h>".into());
+    }
+    lines.extend([
+        "#include <string.h>".into(),
+        "".into(),
+        format!("#define MEM_SIZE {}", mem_size),
+        "".into(),
+        format!("static {} tape[MEM_SIZE];", cell),
+        "".into(),
+    ]);
+    if checked {
+        lines.extend([
+            "static void tape_overflow(long position, long ptr) {".into(),
+            "    fflush(stdout);".into(),
+            "    fprintf(".into(),
+            "        stderr,".into(),
+            "        \"error: the data pointer moved off the memory tape, to cell %ld (at offset %ld)\\n\",".into(),
+            "        ptr,".into(),
+            "        position".into(),
+            "    );".into(),
+            "    exit(1);".into(),
+            "}".into(),
+            "".into(),
+        ]);
+    }
+    lines.extend([
+        "int main(void) {".into(),
+        format!("    {} *p = tape + MEM_SIZE / 2;", cell),
+        "".into(),
+    ]);
+    generate_statements(
+        src,
+        cell_width,
+        eof_behavior,
+        1,
+        &HashMap::new(),
+        &mut lines,
+    );
+    lines.push("".into());
+    lines.push("    return 0;".into());
+    lines.push("}".into());
+    Ok(lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate;
+    use crate::instruction::IntermediateInstruction::*;
+    use crate::{CellWidth, EofBehavior, Span};
+
+    #[test]
+    fn test_generate_c() {
+        let span = Span::default();
+        let src = vec![
+            Read(0, span),
+            Loop(
+                0,
+                vec![
+                    Add(0, -1, span),
+                    Move(3, span),
+                    Scan(1, span),
+                    Scan(-4, span),
+                    SimpleLoop(
+                        vec![
+                            AddDynamic(1, 1, span),
+                            AddDynamic(-2, -1, span),
+                            AddDynamic(5, 3, span),
+                            Zero(0, span),
+                        ],
+                        span,
+                    ),
+                    Move(-1, span),
+                ],
+                span,
+            ),
+            Add(0, 65, span),
+            Write(0, span),
+        ];
+        assert_eq!(
+            generate(&src, 1024, CellWidth::Bits8, EofBehavior::MinusOne).unwrap(),
+            r#"#include <stdio.h>
+#include <string.h>
+
+#define MEM_SIZE 1024
+
+static unsigned char tape[MEM_SIZE];
+
+int main(void) {
+    unsigned char *p = tape + MEM_SIZE / 2;
+
+    *p = (unsigned char)getchar();
+    while (*p) {
+        *p -= 1;
+        p += 3;
+        p = memchr(p, 0, (size_t)(tape + MEM_SIZE - p));
+        while (*p) p -= 4;
+        if (*p) {
+            p[1] += *p;
+            p[-2] -= *p;
+            p[5] += *p * 3;
+            *p = 0;
+        }
+        p -= 1;
+    }
+    *p += 65;
+    putchar(*p);
+
+    return 0;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_generate_c_wide_cells() {
+        let span = Span::default();
+        let src = vec![
+            Read(0, span),
+            SimpleLoop(vec![AddDynamic(1, -3, span), Zero(0, span)], span),
+            Scan(1, span),
+            Write(0, span),
+        ];
+        assert_eq!(
+            generate(&src, 64, CellWidth::Bits16, EofBehavior::MinusOne).unwrap(),
+            r#"#include <stdio.h>
+#include <stdint.h>
+#include <string.h>
+
+#define MEM_SIZE 64
+
+static uint16_t tape[MEM_SIZE];
+
+int main(void) {
+    uint16_t *p = tape + MEM_SIZE / 2;
+
+    *p = (uint16_t)getchar();
+    if (*p) {
+        p[1] += *p * 65533u;
+        *p = 0;
+    }
+    while (*p) p += 1;
+    putchar(*p);
+
+    return 0;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_generate_c_eof() {
+        let span = Span::default();
+        let read_statement = |eof_behavior| {
+            generate(&[Read(0, span)], 64, CellWidth::Bits8, eof_behavior)
+                .unwrap()
+                .lines()
+                .find(|line| line.contains("getchar"))
+                .unwrap()
+                .trim()
+                .to_string()
+        };
+        assert_eq!(
+            read_statement(EofBehavior::Unchanged),
+            "{ int c = getchar(); if (c != EOF) *p = (unsigned char)c; }"
+        );
+        assert_eq!(
+            read_statement(EofBehavior::Zero),
+            "{ int c = getchar(); *p = c == EOF ? 0 : (unsigned char)c; }"
+        );
+        assert_eq!(
+            read_statement(EofBehavior::MinusOne),
+            "*p = (unsigned char)getchar();"
+        );
+    }
+
+    #[test]
+    fn test_generate_c_output() {
+        let src = vec![Output(b"Hi \"?\\\n\0".to_vec(), Span::default())];
+        let code = generate(&src, 64, CellWidth::Bits8, EofBehavior::Zero).unwrap();
+        assert!(code.contains(r#"    fwrite("Hi \"\?\\\012\000", 1, 8, stdout);"#));
+    }
+
+    #[test]
+    fn test_generate_c_offsets() {
+        let span = Span::default();
+        let src = vec![
+            Add(2, -3, span),
+            Zero(-1, span),
+            Read(1, span),
+            Write(-2, span),
+            Move(4, span),
+        ];
+        let code = generate(&src, 64, CellWidth::Bits8, EofBehavior::Zero).unwrap();
+        let body = code
+            .lines()
+            .skip_while(|line| !line.starts_with("int main"))
+            .skip(3)
+            .take(5)
+            .map(str::trim)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            body,
+            [
+                "p[2] -= 3;",
+                "p[-1] = 0;",
+                "{ int c = getchar(); p[1] = c == EOF ? 0 : (unsigned char)c; }",
+                "putchar(p[-2]);",
+                "p += 4;",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generate_c_invariant_loads() {
+        let span = Span::default();
+        let src = vec![Loop(
+            3,
+            vec![
+                Write(-1, span),
+                Loop(1, vec![Write(-1, span), Add(1, -1, span)], span),
+                Write(2, span),
+                Add(2, 1, span),
+                Add(3, -1, span),
+            ],
+            span,
+        )];
+        let code = generate(&src, 64, CellWidth::Bits8, EofBehavior::Zero).unwrap();
+        let body = code
+            .lines()
+            .skip_while(|line| !line.starts_with("int main"))
+            .skip(3)
+            .take(14)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            body,
+            [
+                "    if (p[3]) {",
+                "        const unsigned char cell_m1 = p[-1];",
+                "        do {",
+                "            putchar(cell_m1);",
+                "            while (p[1]) {",
+                "                putchar(cell_m1);",
+                "                p[1] -= 1;",
+                "            }",
+                "            putchar(p[2]);",
+                "            p[2] += 1;",
+                "            p[3] -= 1;",
+                "        } while (p[3]);",
+                "    }",
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generate_c_checked() {
+        let span = Span::default();
+        let src = vec![
+            CheckBounds(vec![(1, 0), (2, 1), (-1, 5)], span),
+            Move(2, span),
+            Add(0, 1, span),
+            Move(-3, span),
+        ];
+        assert_eq!(
+            generate(&src, 64, CellWidth::Bits16, EofBehavior::MinusOne).unwrap(),
+            r#"#include <stdio.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define MEM_SIZE 64
+
+static uint16_t tape[MEM_SIZE];
+
+static void tape_overflow(long position, long ptr) {
+    fflush(stdout);
+    fprintf(
+        stderr,
+        "error: the data pointer moved off the memory tape, to cell %ld (at offset %ld)\n",
+        ptr,
+        position
+    );
+    exit(1);
+}
+
+int main(void) {
+    uint16_t *p = tape + MEM_SIZE / 2;
+
+    if ((size_t)(p - tape + 1) >= MEM_SIZE) tape_overflow(0, (long)(p - tape + 1));
+    if ((size_t)(p - tape + 2) >= MEM_SIZE) tape_overflow(1, (long)(p - tape + 2));
+    if ((size_t)(p - tape - 1) >= MEM_SIZE) tape_overflow(5, (long)(p - tape - 1));
+    p += 2;
+    *p += 1;
+    p -= 3;
+
+    return 0;
+}
+"#
+        );
+    }
+}
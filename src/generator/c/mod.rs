@@ -0,0 +1,4 @@
+//! Translation of BF programs into portable C99 source code
+
+mod _generate;
+pub use _generate::*;
@@ -1,18 +1,71 @@
 //! Defines & implements assembly generation types and methods
 
+pub mod aarch64;
 pub mod amd64;
+pub mod c;
+pub mod wasm;
 
 use crate::instruction::IntermediateInstruction;
-use crate::{Architecture, BFResult};
+use crate::{Architecture, BFError, BFResult, CellWidth, EofBehavior};
 
-/// Generate assembly for the given program, memory size, and target architecture
+/// Generate assembly for the given program, memory size, cell width, EOF behavior,
+/// and target architecture
+///
+/// If `buffered` is set, AMD64 assembly buffers its output. AArch64 assembly writes each byte
+/// with `putchar`, and WebAssembly with the host's `write`
 pub fn generate(
     src: &[IntermediateInstruction],
-    partial_evaluation: bool,
     mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    buffered: bool,
     arch: Architecture,
 ) -> BFResult<String> {
     match arch {
-        Architecture::AMD64 => amd64::generate(src, partial_evaluation, mem_size),
+        Architecture::AArch64 => aarch64::generate(src, mem_size, cell_width, eof_behavior),
+        Architecture::AMD64 => amd64::generate(src, mem_size, cell_width, eof_behavior, buffered),
+        Architecture::WASM => wasm::generate(src, mem_size, cell_width, eof_behavior),
+    }
+}
+
+/// Generate a standalone executable for the given program, memory size, cell width,
+/// EOF behavior, and target architecture
+///
+/// If `buffered` is set, an AMD64 executable buffers its output. A WebAssembly module writes
+/// each byte with the host's `write`
+pub fn generate_executable(
+    src: &[IntermediateInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    buffered: bool,
+    arch: Architecture,
+) -> BFResult<Vec<u8>> {
+    match arch {
+        Architecture::AMD64 => {
+            amd64::generate_executable(src, mem_size, cell_width, eof_behavior, buffered)
+        }
+        Architecture::WASM => wasm::generate_module(src, mem_size, cell_width, eof_behavior),
+        _ => Err(BFError::UnsupportedArchitecture(arch)),
+    }
+}
+
+/// Generate a relocatable object file for the given program, memory size, cell width,
+/// EOF behavior, and target architecture
+///
+/// If `buffered` is set, the object buffers its output
+pub fn generate_object(
+    src: &[IntermediateInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    buffered: bool,
+    arch: Architecture,
+) -> BFResult<Vec<u8>> {
+    match arch {
+        Architecture::AMD64 => {
+            amd64::generate_object(src, mem_size, cell_width, eof_behavior, buffered)
+        }
+        _ => Err(BFError::UnsupportedArchitecture(arch)),
     }
 }
@@ -0,0 +1,77 @@
+use super::{memory_pages, IMPORT_MODULE, MEMORY_EXPORT, RUN_EXPORT};
+use crate::assembly::wasm::{WasmImport, WasmInstruction, POINTER_LOCAL, TAPE_SIZE_LOCAL};
+use crate::instruction::IntermediateInstruction;
+use crate::{BFResult, CellWidth, EofBehavior};
+
+/// Generates a WebAssembly text (WAT) module for the given program, memory size, cell width,
+/// and EOF behavior
+pub fn generate(
+    src: &[IntermediateInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+) -> BFResult<String> {
+    let checked = IntermediateInstruction::has_bounds_checks(src);
+    let mut lines = vec![
+        "(module".to_string(),
+        format!(
+            "  (import \"{}\" \"{}\" (func ${} (result i32)))",
+            IMPORT_MODULE,
+            WasmImport::Read,
+            WasmImport::Read
+        ),
+        format!(
+            "  (import \"{}\" \"{}\" (func ${} (param i32)))",
+            IMPORT_MODULE,
+            WasmImport::Write,
+            WasmImport::Write
+        ),
+    ];
+    if checked {
+        lines.push(format!(
+            "  (import \"{}\" \"{}\" (func ${} (param i32 i32)))",
+            IMPORT_MODULE,
+            WasmImport::TapeOverflow,
+            WasmImport::TapeOverflow
+        ));
+    }
+    lines.push(format!(
+        "  (memory (export \"{}\") {})",
+        MEMORY_EXPORT,
+        memory_pages(mem_size * cell_width.bytes())
+    ));
+    if checked {
+        lines.push(format!(
+            "  (func (export \"{}\") (local i32 i32 i32)",
+            RUN_EXPORT
+        ));
+        lines.push(format!("    i32.const {}", mem_size * cell_width.bytes()));
+        lines.push(format!("    local.set {}", TAPE_SIZE_LOCAL));
+    } else {
+        lines.push(format!(
+            "  (func (export \"{}\") (local i32 i32)",
+            RUN_EXPORT
+        ));
+    }
+    lines.push(format!(
+        "    i32.const {}",
+        mem_size / 2 * cell_width.bytes()
+    ));
+    lines.push(format!("    local.set {}", POINTER_LOCAL));
+
+    // Indent each instruction by its block depth
+    let mut depth = 2;
+    for instr in WasmInstruction::convert_instructions(src, cell_width, eof_behavior) {
+        if let WasmInstruction::End = instr {
+            depth -= 1;
+        }
+        lines.push(format!("{}{}", "  ".repeat(depth), instr));
+        if let WasmInstruction::Block | WasmInstruction::Loop | WasmInstruction::If = instr {
+            depth += 1;
+        }
+    }
+
+    lines.push("  )".into());
+    lines.push(")".into());
+    Ok(lines.join("\n") + "\n")
+}
@@ -0,0 +1,245 @@
+use super::{memory_pages, IMPORT_MODULE, MEMORY_EXPORT, RUN_EXPORT};
+use crate::assembly::wasm::{
+    encode_unsigned, WasmImport, WasmInstruction, POINTER_LOCAL, TAPE_SIZE_LOCAL,
+};
+use crate::instruction::IntermediateInstruction;
+use crate::{BFResult, CellWidth, EofBehavior};
+
+/// The value type `i32`
+const I32: u8 = 0x7F;
+
+/// The type constructor of a function type
+const FUNC_TYPE: u8 = 0x60;
+
+/// Import and export kinds: a function, or a linear memory
+const FUNC_KIND: u8 = 0x00;
+const MEMORY_KIND: u8 = 0x02;
+
+/// The IDs of the sections of a WebAssembly module
+const TYPE_SECTION: u8 = 1;
+const IMPORT_SECTION: u8 = 2;
+const FUNCTION_SECTION: u8 = 3;
+const MEMORY_SECTION: u8 = 5;
+const EXPORT_SECTION: u8 = 7;
+const CODE_SECTION: u8 = 10;
+
+/// Encodes a length-prefixed vector of already-encoded entries
+fn encode_vec(entries: Vec<Vec<u8>>) -> Vec<u8> {
+    [encode_unsigned(entries.len() as u64), entries.concat()].concat()
+}
+
+/// Encodes a length-prefixed UTF-8 name
+fn encode_name(name: &str) -> Vec<u8> {
+    [encode_unsigned(name.len() as u64), name.as_bytes().to_vec()].concat()
+}
+
+/// Encodes a section with the given ID and contents
+fn encode_section(id: u8, contents: Vec<u8>) -> Vec<u8> {
+    [vec![id], encode_unsigned(contents.len() as u64), contents].concat()
+}
+
+/// Generates a binary WebAssembly module for the given program, memory size, cell width,
+/// and EOF behavior
+///
+/// Function type 0 is the type of `read`, 1 is the type of `write`,
+/// 2 is the type of `run`, and 3 is the type of `tape_overflow`
+pub fn generate_module(
+    src: &[IntermediateInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+) -> BFResult<Vec<u8>> {
+    let imported = WasmImport::imports(src);
+    let checked = imported.len() > 2;
+
+    let mut types = vec![
+        vec![FUNC_TYPE, 0, 1, I32],
+        vec![FUNC_TYPE, 1, I32, 0],
+        vec![FUNC_TYPE, 0, 0],
+    ];
+    if checked {
+        types.push(vec![FUNC_TYPE, 2, I32, I32, 0]);
+    }
+    let types = encode_vec(types);
+
+    let imports = encode_vec(
+        imported
+            .iter()
+            .map(|import| {
+                let type_index = match import {
+                    WasmImport::Read => 0,
+                    WasmImport::Write => 1,
+                    WasmImport::TapeOverflow => 3,
+                };
+                [
+                    encode_name(IMPORT_MODULE),
+                    encode_name(&import.to_string()),
+                    vec![FUNC_KIND],
+                    encode_unsigned(type_index),
+                ]
+                .concat()
+            })
+            .collect(),
+    );
+
+    let functions = encode_vec(vec![encode_unsigned(2)]);
+
+    // A memory with only a minimum size
+    let memories = encode_vec(vec![[
+        vec![0x00],
+        encode_unsigned(memory_pages(mem_size * cell_width.bytes()) as u64),
+    ]
+    .concat()]);
+
+    let run_index = imported.len() as u64;
+    let exports = encode_vec(vec![
+        [
+            encode_name(RUN_EXPORT),
+            vec![FUNC_KIND],
+            encode_unsigned(run_index),
+        ]
+        .concat(),
+        [
+            encode_name(MEMORY_EXPORT),
+            vec![MEMORY_KIND],
+            encode_unsigned(0),
+        ]
+        .concat(),
+    ]);
+
+    // Checked programs also keep the size of the tape in a local
+    let tape_size = if checked {
+        vec![
+            WasmInstruction::I32Const((mem_size * cell_width.bytes()) as i32),
+            WasmInstruction::LocalSet(TAPE_SIZE_LOCAL),
+        ]
+    } else {
+        vec![]
+    };
+
+    let body = [
+        // Two locals: the data pointer, starting at the center of the tape,
+        // and the character just read
+        encode_vec(vec![[
+            encode_unsigned(if checked { 3 } else { 2 }),
+            vec![I32],
+        ]
+        .concat()]),
+        [
+            tape_size,
+            vec![
+                WasmInstruction::I32Const((mem_size / 2 * cell_width.bytes()) as i32),
+                WasmInstruction::LocalSet(POINTER_LOCAL),
+            ],
+            WasmInstruction::convert_instructions(src, cell_width, eof_behavior),
+            vec![WasmInstruction::End],
+        ]
+        .concat()
+        .iter()
+        .map(|instr| instr.to_binary())
+        .collect::<Vec<Vec<u8>>>()
+        .concat(),
+    ]
+    .concat();
+    let code = encode_vec(vec![[encode_unsigned(body.len() as u64), body].concat()]);
+
+    Ok([
+        b"\0asm".to_vec(),
+        1_u32.to_le_bytes().to_vec(),
+        encode_section(TYPE_SECTION, types),
+        encode_section(IMPORT_SECTION, imports),
+        encode_section(FUNCTION_SECTION, functions),
+        encode_section(MEMORY_SECTION, memories),
+        encode_section(EXPORT_SECTION, exports),
+        encode_section(CODE_SECTION, code),
+    ]
+    .concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate_module;
+    use crate::instruction::IntermediateInstruction;
+    use crate::{CellWidth, EofBehavior};
+
+    /// Decodes an unsigned LEB128 integer, advancing the position past it
+    fn decode_unsigned(bytes: &[u8], position: &mut usize) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = bytes[*position];
+            *position += 1;
+            value |= ((byte & 0x7F) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+        }
+    }
+
+    /// Decodes a length-prefixed name, advancing the position past it
+    fn decode_name(bytes: &[u8], position: &mut usize) -> String {
+        let len = decode_unsigned(bytes, position) as usize;
+        let name = String::from_utf8(bytes[*position..*position + len].to_vec()).unwrap();
+        *position += len;
+        name
+    }
+
+    #[test]
+    fn test_module_sections() {
+        let src = IntermediateInstruction::parse_instrs(b"+[->+<],.").unwrap();
+        let module =
+            generate_module(&src, 100_000, CellWidth::Bits8, EofBehavior::MinusOne).unwrap();
+
+        assert_eq!(&module[0..8], b"\0asm\x01\0\0\0");
+
+        // Split the module into its sections
+        let mut sections = vec![];
+        let mut position = 8;
+        while position < module.len() {
+            let id = module[position];
+            position += 1;
+            let len = decode_unsigned(&module, &mut position) as usize;
+            sections.push((id, &module[position..position + len]));
+            position += len;
+        }
+        assert_eq!(position, module.len());
+        assert_eq!(
+            sections.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![1, 2, 3, 5, 7, 10]
+        );
+
+        // Imports: `env.read` and `env.write`, as functions of types 0 and 1
+        let imports = sections[1].1;
+        let mut position = 0;
+        assert_eq!(decode_unsigned(imports, &mut position), 2);
+        for (index, name) in ["read", "write"].iter().enumerate() {
+            assert_eq!(decode_name(imports, &mut position), "env");
+            assert_eq!(decode_name(imports, &mut position), *name);
+            assert_eq!(imports[position..position + 2], [0x00, index as u8]);
+            position += 2;
+        }
+
+        // Memory: 2 pages are needed to hold 100,000 cells
+        assert_eq!(sections[3].1, [0x01, 0x00, 0x02]);
+
+        // Exports: `run` (function 2) and `memory`
+        let exports = sections[4].1;
+        let mut position = 0;
+        assert_eq!(decode_unsigned(exports, &mut position), 2);
+        assert_eq!(decode_name(exports, &mut position), "run");
+        assert_eq!(exports[position..position + 2], [0x00, 0x02]);
+        position += 2;
+        assert_eq!(decode_name(exports, &mut position), "memory");
+        assert_eq!(exports[position..position + 2], [0x02, 0x00]);
+
+        // Code: a single body, which declares two `i32` locals, and ends with `end`
+        let code = sections[5].1;
+        let mut position = 0;
+        assert_eq!(decode_unsigned(code, &mut position), 1);
+        let body_len = decode_unsigned(code, &mut position) as usize;
+        assert_eq!(position + body_len, code.len());
+        assert_eq!(code[position..position + 3], [0x01, 0x02, 0x7F]);
+        assert_eq!(code.last(), Some(&0x0B));
+    }
+}
@@ -0,0 +1,27 @@
+//! Code generation for WebAssembly
+//!
+//! The generated module imports `read: () -> i32` and `write: (i32) -> ()` from the `env`
+//! namespace, exports its linear memory as `memory`, and exports the program as `run: () -> ()`.
+//! The memory tape lives at the start of linear memory.
+
+mod _generate;
+pub use _generate::*;
+mod _generate_module;
+pub use _generate_module::*;
+
+/// The size of a page of WebAssembly linear memory
+const PAGE_SIZE: usize = 0x10000;
+
+/// The module name of the imported host functions
+const IMPORT_MODULE: &str = "env";
+
+/// The name of the exported program function
+const RUN_EXPORT: &str = "run";
+
+/// The name of the exported linear memory
+const MEMORY_EXPORT: &str = "memory";
+
+/// Returns the number of pages of linear memory needed for a tape of the given size, in bytes
+fn memory_pages(tape_bytes: usize) -> usize {
+    tape_bytes.div_ceil(PAGE_SIZE).max(1)
+}
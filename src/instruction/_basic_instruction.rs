@@ -2,7 +2,7 @@
 //!
 //! Author: Cayden Lund (cayden.lund@utah.edu)
 
-use crate::{BFError, BFParseError, BFResult};
+use crate::{BFError, BFParseError, BFResult, SourceLocation};
 
 /// The semantics of a single BF instruction
 ///
@@ -72,12 +72,15 @@ impl BasicInstruction {
     /// Given a slice of bytes, parses it into a vector of [`BasicInstruction`]s
     ///
     /// Each byte in the source input is read and individually handled.
-    /// This method will panic if the source input contains unmatched
-    /// [`BasicInstruction::LBrace`] or [`BasicInstruction::RBrace`] instructions
+    /// Returns a [`BFParseError`] with the location of the first unmatched `]`,
+    /// or of the last unmatched `[`
     pub fn parse_instrs(src: &[u8]) -> BFResult<Vec<BasicInstruction>> {
         let mut instrs: Vec<BasicInstruction> = vec![];
         let mut open: Vec<usize> = vec![];
 
+        // The index of the `[` of the last loop at the top level
+        let mut last_loop: Option<usize> = None;
+
         for position in 0..src.len() {
             let ch = src[position];
             let instr = match ch {
@@ -93,8 +96,15 @@ impl BasicInstruction {
                 }
                 b']' => {
                     let Some(old_open) = open.pop() else {
-                        return Err(BFError::ParseError(BFParseError::UnmatchedRBrace(position)));
+                        return Err(BFError::ParseError(BFParseError::UnmatchedRBrace {
+                            location: SourceLocation::new(src, position),
+                            partner: last_loop
+                                .map(|lbrace| SourceLocation::new(src, instrs[lbrace].position)),
+                        }));
                     };
+                    if open.is_empty() {
+                        last_loop = Some(old_open);
+                    }
 
                     instrs[old_open].instr = BasicInstructionType::LBrace(instrs.len());
                     Some(BasicInstructionType::RBrace(old_open))
@@ -112,7 +122,10 @@ impl BasicInstruction {
         }
 
         if let Some(idx) = open.pop() {
-            return Err(BFError::ParseError(BFParseError::UnmatchedLBrace(idx)));
+            return Err(BFError::ParseError(BFParseError::UnmatchedLBrace {
+                location: SourceLocation::new(src, instrs[idx].position),
+                partner: SourceLocation::end(src),
+            }));
         }
 
         Ok(instrs)
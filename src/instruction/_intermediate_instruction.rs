@@ -1,87 +1,210 @@
 //! Types to represent instructions in the BF intermediate representation
 
-use crate::{BFError, BFParseError, BFResult};
+use crate::{BFError, BFParseError, BFResult, SourceLocation, Span};
 
 /// A single instruction in the BF intermediate representation
+///
+/// Every instruction ends with the [`Span`] of the source program that it was parsed from
 #[derive(Clone, Debug)]
 pub enum IntermediateInstruction {
-    /// A loop of instructions
-    Loop(Vec<IntermediateInstruction>),
+    /// A loop of instructions that repeats while the cell at the given offset from the data
+    /// pointer is nonzero
+    ///
+    /// `(offset, body)`
+    Loop(i32, Vec<IntermediateInstruction>, Span),
 
     /// Adds the multiplied value of the source cell to the target cell
     ///
     /// `(target, multiplier)`
-    AddDynamic(i32, i32),
+    AddDynamic(i32, i32, Span),
 
-    /// Sets the given cell to 0
-    Zero,
+    /// Sets the cell at the given offset from the data pointer to 0
+    Zero(i32, Span),
 
     /// A simple loop of instructions
-    SimpleLoop(Vec<IntermediateInstruction>),
+    SimpleLoop(Vec<IntermediateInstruction>, Span),
 
     /// Moves the data pointer by the given offset
-    Move(i32),
+    Move(i32, Span),
 
-    /// Adds the given offset to the cell at the current data pointer
-    Add(i32),
+    /// Adds the given delta to the cell at the given offset from the data pointer
+    ///
+    /// `(offset, delta)`
+    Add(i32, i32, Span),
 
-    /// Reads a value from stdin into the current cell
-    Read,
+    /// Reads a value from stdin into the cell at the given offset from the data pointer
+    Read(i32, Span),
 
-    /// Writes the value at the current cell to stdout
-    Write,
+    /// Writes the value of the cell at the given offset from the data pointer to stdout
+    Write(i32, Span),
 
     /// Performs a memory scan of the given stride
-    Scan(i32),
+    Scan(i32, Span),
+
+    /// Checks that the cells at the given offsets from the data pointer are on the tape
+    ///
+    /// Each `(offset, position)` probe pairs an offset with the position of the `<` or `>`
+    /// instruction that moves there. If any probe is off the tape, the first one is reported
+    CheckBounds(Vec<(i32, usize)>, Span),
+
+    /// Writes the given bytes to stdout, as computed ahead of time by partial evaluation
+    Output(Vec<u8>, Span),
+
+    /// Sets the cell at the given offset from the data pointer to the given value
+    ///
+    /// `(offset, value)`
+    Set(i32, i32, Span),
 }
 
 impl IntermediateInstruction {
     /// Given an array of bytes, parse it into a vector of instructions
     pub fn parse_instrs(src: &[u8]) -> BFResult<Vec<IntermediateInstruction>> {
+        Self::parse(src, false)
+    }
+
+    /// Given an array of bytes, parse it into a vector of instructions,
+    /// with a [`IntermediateInstruction::CheckBounds`] before every move
+    pub fn parse_instrs_checked(src: &[u8]) -> BFResult<Vec<IntermediateInstruction>> {
+        Self::parse(src, true)
+    }
+
+    /// Returns whether the given instructions contain any bounds checks
+    pub fn has_bounds_checks(instrs: &[IntermediateInstruction]) -> bool {
+        instrs.iter().any(|instr| match instr {
+            IntermediateInstruction::Loop(_, sub_instrs, _)
+            | IntermediateInstruction::SimpleLoop(sub_instrs, _) => {
+                Self::has_bounds_checks(sub_instrs)
+            }
+            IntermediateInstruction::CheckBounds(_, _) => true,
+            _ => false,
+        })
+    }
+
+    /// Returns the span of the source program that this instruction was parsed from
+    pub fn span(&self) -> Span {
+        match self {
+            IntermediateInstruction::Loop(_, _, span)
+            | IntermediateInstruction::AddDynamic(_, _, span)
+            | IntermediateInstruction::Zero(_, span)
+            | IntermediateInstruction::SimpleLoop(_, span)
+            | IntermediateInstruction::Move(_, span)
+            | IntermediateInstruction::Add(_, _, span)
+            | IntermediateInstruction::Read(_, span)
+            | IntermediateInstruction::Write(_, span)
+            | IntermediateInstruction::Scan(_, span)
+            | IntermediateInstruction::CheckBounds(_, span)
+            | IntermediateInstruction::Output(_, span)
+            | IntermediateInstruction::Set(_, _, span) => *span,
+        }
+    }
+
+    /// Returns the offset from the data pointer of the cell that this instruction acts on
+    ///
+    /// This is 0 for every instruction but [`Add`](IntermediateInstruction::Add),
+    /// [`Zero`](IntermediateInstruction::Zero), [`Set`](IntermediateInstruction::Set),
+    /// [`Read`](IntermediateInstruction::Read), [`Write`](IntermediateInstruction::Write),
+    /// and [`Loop`](IntermediateInstruction::Loop), whose cell is the one it tests
+    pub fn cell_offset(&self) -> i32 {
+        match self {
+            IntermediateInstruction::Add(offset, _, _)
+            | IntermediateInstruction::Zero(offset, _)
+            | IntermediateInstruction::Set(offset, _, _)
+            | IntermediateInstruction::Read(offset, _)
+            | IntermediateInstruction::Write(offset, _)
+            | IntermediateInstruction::Loop(offset, _, _) => *offset,
+            _ => 0,
+        }
+    }
+
+    fn parse(src: &[u8], checked: bool) -> BFResult<Vec<IntermediateInstruction>> {
         let mut instrs: Vec<Vec<IntermediateInstruction>> = vec![];
         let mut cur_instrs: Vec<IntermediateInstruction> = vec![];
 
-        for position in 0..src.len() {
-            match src[position] {
+        // The locations of the open `[`s, and of the `[` of the last loop at the top level
+        let mut open: Vec<SourceLocation> = vec![];
+        let mut last_loop: Option<SourceLocation> = None;
+
+        // The location of the current character, which is tracked as the source is read
+        let mut location = SourceLocation {
+            offset: 0,
+            line: 1,
+            column: 1,
+        };
+
+        for (position, &byte) in src.iter().enumerate() {
+            location.offset = position;
+            let span = Span::new(location, 1);
+            match byte {
                 b'<' => {
-                    cur_instrs.push(IntermediateInstruction::Move(-1));
+                    if checked {
+                        cur_instrs.push(IntermediateInstruction::CheckBounds(
+                            vec![(-1, position)],
+                            span,
+                        ));
+                    }
+                    cur_instrs.push(IntermediateInstruction::Move(-1, span));
                 }
                 b'>' => {
-                    cur_instrs.push(IntermediateInstruction::Move(1));
+                    if checked {
+                        cur_instrs.push(IntermediateInstruction::CheckBounds(
+                            vec![(1, position)],
+                            span,
+                        ));
+                    }
+                    cur_instrs.push(IntermediateInstruction::Move(1, span));
                 }
                 b'-' => {
-                    cur_instrs.push(IntermediateInstruction::Add(-1));
+                    cur_instrs.push(IntermediateInstruction::Add(0, -1, span));
                 }
                 b'+' => {
-                    cur_instrs.push(IntermediateInstruction::Add(1));
+                    cur_instrs.push(IntermediateInstruction::Add(0, 1, span));
                 }
                 b',' => {
-                    cur_instrs.push(IntermediateInstruction::Read);
+                    cur_instrs.push(IntermediateInstruction::Read(0, span));
                 }
                 b'.' => {
-                    cur_instrs.push(IntermediateInstruction::Write);
+                    cur_instrs.push(IntermediateInstruction::Write(0, span));
                 }
                 b'[' => {
                     instrs.push(cur_instrs);
                     cur_instrs = vec![];
+                    open.push(location);
                 }
                 b']' => {
-                    let new_instr = IntermediateInstruction::Loop(cur_instrs);
-                    if let Some(old_instrs) = instrs.pop() {
+                    if let (Some(old_instrs), Some(lbrace)) = (instrs.pop(), open.pop()) {
+                        let loop_span = Span::new(lbrace, position + 1 - lbrace.offset);
+                        let new_instr = IntermediateInstruction::Loop(0, cur_instrs, loop_span);
                         cur_instrs = old_instrs;
                         cur_instrs.push(new_instr);
+                        if open.is_empty() {
+                            last_loop = Some(lbrace);
+                        }
                     } else {
-                        return Err(BFError::ParseError(BFParseError::UnmatchedRBrace(position)));
+                        return Err(BFError::ParseError(BFParseError::UnmatchedRBrace {
+                            location,
+                            partner: last_loop,
+                        }));
                     }
                 }
                 _ => {}
             };
+
+            if byte == b'\n' {
+                location.line += 1;
+                location.column = 1;
+            } else if byte & 0xC0 != 0x80 {
+                // Only the first byte of a UTF-8 character starts a new column
+                location.column += 1;
+            }
         }
 
-        if instrs.len() == 0 {
-            Ok(cur_instrs)
+        if let Some(lbrace) = open.pop() {
+            Err(BFError::ParseError(BFParseError::UnmatchedLBrace {
+                location: lbrace,
+                partner: SourceLocation::end(src),
+            }))
         } else {
-            Err(BFError::ParseError(BFParseError::UnmatchedLBrace(0)))
+            Ok(cur_instrs)
         }
     }
 }
@@ -0,0 +1,521 @@
+//! A textual form of the intermediate representation, with a printer and a parser
+//!
+//! Each instruction is a keyword followed by its operands, such as `add 3`, `move -2`,
+//! `mul @+1 *-2`, or `scan +4`. Loops are blocks, `loop { ... }` or `loop @+2 { ... }` for a
+//! loop that tests another cell, and `#` starts a comment that runs to the end of the line.
+
+use super::IntermediateInstruction;
+use crate::optimizer::{loop_balance, LoopBalance};
+use crate::{BFError, BFParseError, BFResult, SourceLocation, Span};
+use std::fmt::Write;
+
+/// The number of cells that the moves and offsets in IR text must add up to less than
+///
+/// Every offset that the optimizer works out adds up some of them, so it stays well within an
+/// `i32`, as it does for a BF program, whose moves add up to at most its length
+const MAX_DISTANCE: u64 = 1 << 28;
+
+/// Returns the given bytes as a quoted string, escaping anything but printable ASCII
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\t' => quoted.push_str("\\t"),
+            b' '..=b'~' => quoted.push(byte as char),
+            _ => write!(quoted, "\\x{:02x}", byte).unwrap(),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns the operand for a cell at the given offset from the data pointer, if it isn't 0
+fn at(offset: i32) -> String {
+    match offset {
+        0 => String::new(),
+        _ => format!(" @{:+}", offset),
+    }
+}
+
+/// Writes the given instructions to the given string, indented to the given depth
+fn print_block(instrs: &[IntermediateInstruction], depth: usize, out: &mut String) {
+    use IntermediateInstruction::*;
+
+    for instr in instrs {
+        let indent = "    ".repeat(depth);
+        let offset = instr.cell_offset();
+        match instr {
+            Loop(_, body, _) | SimpleLoop(body, _) => {
+                let (keyword, note) = match instr {
+                    SimpleLoop(_, _) => ("if", ""),
+                    _ if loop_balance(body) == LoopBalance::Balanced => ("loop", " # balanced"),
+                    _ => ("loop", ""),
+                };
+                writeln!(out, "{}{}{} {{{}", indent, keyword, at(offset), note).unwrap();
+                print_block(body, depth + 1, out);
+                writeln!(out, "{}}}", indent).unwrap();
+            }
+            Add(_, delta, _) => writeln!(out, "{}add{} {}", indent, at(offset), delta).unwrap(),
+            Zero(_, _) => writeln!(out, "{}zero{}", indent, at(offset)).unwrap(),
+            Set(_, value, _) => writeln!(out, "{}set{} {}", indent, at(offset), value).unwrap(),
+            Read(_, _) => writeln!(out, "{}read{}", indent, at(offset)).unwrap(),
+            Write(_, _) => writeln!(out, "{}write{}", indent, at(offset)).unwrap(),
+            AddDynamic(target, multiplier, _) => {
+                writeln!(out, "{}mul @{:+} *{}", indent, target, multiplier).unwrap()
+            }
+            Move(stride, _) => writeln!(out, "{}move {:+}", indent, stride).unwrap(),
+            Scan(stride, _) => writeln!(out, "{}scan {:+}", indent, stride).unwrap(),
+            Output(bytes, _) => writeln!(out, "{}output {}", indent, quote(bytes)).unwrap(),
+            CheckBounds(probes, _) => {
+                let probes = probes
+                    .iter()
+                    .map(|(offset, _)| format!(" @{:+}", offset))
+                    .collect::<String>();
+                writeln!(out, "{}check{}", indent, probes).unwrap()
+            }
+        }
+    }
+}
+
+/// Reads IR text, keeping track of the location of the next byte
+struct Lexer<'a> {
+    /// The IR text
+    src: &'a [u8],
+
+    /// The location of the next byte
+    location: SourceLocation,
+
+    /// The byte offset just past the last token read
+    end: usize,
+
+    /// How many cells the moves and offsets read so far add up to
+    distance: u64,
+}
+
+impl<'a> Lexer<'a> {
+    /// Returns an error at the current location, with the given message
+    fn error<T>(&self, message: &'static str) -> BFResult<T> {
+        Err(BFError::ParseError(BFParseError::InvalidIr {
+            location: self.location,
+            message,
+        }))
+    }
+
+    /// Returns the next byte, without reading it
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.location.offset).copied()
+    }
+
+    /// Reads the next byte
+    fn advance(&mut self) {
+        if let Some(byte) = self.peek() {
+            if byte == b'\n' {
+                self.location.line += 1;
+                self.location.column = 1;
+            } else if byte & 0xC0 != 0x80 {
+                // Only the first byte of a UTF-8 character starts a new column
+                self.location.column += 1;
+            }
+            self.location.offset += 1;
+        }
+    }
+
+    /// Skips whitespace and comments
+    fn skip_blank(&mut self) {
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'#' => {
+                    while self.peek().is_some_and(|byte| byte != b'\n') {
+                        self.advance();
+                    }
+                }
+                _ if byte.is_ascii_whitespace() => self.advance(),
+                _ => break,
+            }
+        }
+    }
+
+    /// Reads the given byte, if it is next
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_blank();
+        let found = self.peek() == Some(byte);
+        if found {
+            self.advance();
+            self.end = self.location.offset;
+        }
+        found
+    }
+
+    /// Reads a keyword
+    fn word(&mut self) -> &'a [u8] {
+        self.skip_blank();
+        let start = self.location.offset;
+        while self
+            .peek()
+            .is_some_and(|byte| byte.is_ascii_lowercase() || byte == b'-')
+        {
+            self.advance();
+        }
+        self.end = self.location.offset;
+        &self.src[start..self.end]
+    }
+
+    /// Reads a number, with an optional sign
+    fn number(&mut self) -> BFResult<i32> {
+        self.skip_blank();
+        let start = self.location;
+        if matches!(self.peek(), Some(b'+' | b'-')) {
+            self.advance();
+        }
+        while self.peek().is_some_and(|byte| byte.is_ascii_digit()) {
+            self.advance();
+        }
+        let text = std::str::from_utf8(&self.src[start.offset..self.location.offset]).unwrap();
+        match text.strip_prefix('+').unwrap_or(text).parse() {
+            Ok(number) => {
+                self.end = self.location.offset;
+                Ok(number)
+            }
+            Err(_) => {
+                self.location = start;
+                self.error("expected a number")
+            }
+        }
+    }
+
+    /// Reads a number of cells, such as a move or an offset, which counts towards the distance
+    fn distance(&mut self) -> BFResult<i32> {
+        self.skip_blank();
+        let start = self.location;
+        let cells = self.number()?;
+        self.distance += cells.unsigned_abs() as u64;
+        if self.distance >= MAX_DISTANCE {
+            self.location = start;
+            return self.error("expected moves and offsets under 2^28 cells in all");
+        }
+        Ok(cells)
+    }
+
+    /// Reads a cell operand, `@` followed by an offset, if it is next
+    fn offset(&mut self) -> BFResult<Option<i32>> {
+        match self.eat(b'@') {
+            true => self.distance().map(Some),
+            false => Ok(None),
+        }
+    }
+
+    /// Reads a quoted string
+    fn string(&mut self) -> BFResult<Vec<u8>> {
+        if !self.eat(b'"') {
+            return self.error("expected a string");
+        }
+        let mut bytes = vec![];
+        loop {
+            let Some(byte) = self.peek() else {
+                return self.error("expected the end of the string");
+            };
+            self.advance();
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.peek();
+                    self.advance();
+                    match escape {
+                        Some(b'n') => bytes.push(b'\n'),
+                        Some(b't') => bytes.push(b'\t'),
+                        Some(b'"') => bytes.push(b'"'),
+                        Some(b'\\') => bytes.push(b'\\'),
+                        Some(b'x') => {
+                            let digits =
+                                self.src.get(self.location.offset..self.location.offset + 2);
+                            let Some(byte) = digits
+                                .and_then(|digits| std::str::from_utf8(digits).ok())
+                                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                            else {
+                                return self.error("expected two hexadecimal digits");
+                            };
+                            bytes.push(byte);
+                            self.advance();
+                            self.advance();
+                        }
+                        _ => return self.error("expected an escape sequence"),
+                    }
+                }
+                _ => bytes.push(byte),
+            }
+        }
+        self.end = self.location.offset;
+        Ok(bytes)
+    }
+}
+
+/// Parses instructions up to the end of the IR text, or up to the `}` that closes the block
+/// opened at the given location
+fn parse_block(
+    lexer: &mut Lexer,
+    open: Option<SourceLocation>,
+) -> BFResult<Vec<IntermediateInstruction>> {
+    let mut instrs = vec![];
+    loop {
+        lexer.skip_blank();
+        match (lexer.peek(), open) {
+            (None, None) => return Ok(instrs),
+            (None, Some(open)) => {
+                lexer.location = open;
+                return lexer.error("this `{` is never closed");
+            }
+            (Some(b'}'), Some(_)) => {
+                lexer.eat(b'}');
+                return Ok(instrs);
+            }
+            (Some(b'}'), None) => return lexer.error("this `}` has no matching `{`"),
+            _ => instrs.push(parse_instr(lexer)?),
+        }
+    }
+}
+
+/// Parses a single instruction, which spans its keyword and operands
+fn parse_instr(lexer: &mut Lexer) -> BFResult<IntermediateInstruction> {
+    use IntermediateInstruction::*;
+
+    lexer.skip_blank();
+    let start = lexer.location;
+    let span = |lexer: &Lexer| Span::new(start, lexer.end - start.offset);
+
+    let instr = match lexer.word() {
+        keyword @ (b"loop" | b"if") => {
+            let cell = match keyword {
+                b"loop" => lexer.offset()?.unwrap_or(0),
+                _ => 0,
+            };
+            lexer.skip_blank();
+            let open = lexer.location;
+            if !lexer.eat(b'{') {
+                return lexer.error("expected `{`");
+            }
+            let body = parse_block(lexer, Some(open))?;
+            match keyword {
+                b"loop" => Loop(cell, body, span(lexer)),
+                _ => SimpleLoop(body, span(lexer)),
+            }
+        }
+        b"add" => Add(lexer.offset()?.unwrap_or(0), lexer.number()?, span(lexer)),
+        b"zero" => Zero(lexer.offset()?.unwrap_or(0), span(lexer)),
+        b"set" => Set(lexer.offset()?.unwrap_or(0), lexer.number()?, span(lexer)),
+        b"read" => Read(lexer.offset()?.unwrap_or(0), span(lexer)),
+        b"write" => Write(lexer.offset()?.unwrap_or(0), span(lexer)),
+        b"mul" => {
+            let Some(target) = lexer.offset()? else {
+                return lexer.error("expected `@` and the target cell's offset");
+            };
+            if !lexer.eat(b'*') {
+                return lexer.error("expected `*` and the multiplier");
+            }
+            AddDynamic(target, lexer.number()?, span(lexer))
+        }
+        b"move" => Move(lexer.distance()?, span(lexer)),
+        // A scan by 0 would never move off a nonzero cell
+        b"scan" => {
+            lexer.skip_blank();
+            let stride_start = lexer.location;
+            let stride = lexer.distance()?;
+            if stride == 0 {
+                lexer.location = stride_start;
+                return lexer.error("expected a nonzero stride");
+            }
+            Scan(stride, span(lexer))
+        }
+        b"output" => Output(lexer.string()?, span(lexer)),
+        // A failing check points at the check itself
+        b"check" => {
+            let mut probes = vec![];
+            while let Some(offset) = lexer.offset()? {
+                probes.push((offset, start.offset));
+            }
+            CheckBounds(probes, span(lexer))
+        }
+        _ => {
+            lexer.location = start;
+            return lexer.error("expected an instruction");
+        }
+    };
+    Ok(instr)
+}
+
+impl IntermediateInstruction {
+    /// Returns the given instructions as IR text
+    ///
+    /// Balanced loops are marked with a comment
+    pub fn print_ir(instrs: &[IntermediateInstruction]) -> String {
+        let mut out = String::new();
+        print_block(instrs, 0, &mut out);
+        out
+    }
+
+    /// Given IR text, parse it into a vector of instructions
+    ///
+    /// Each instruction spans its text, and each probe of a bounds check is at the check's
+    /// position in the text. Parsing the text that [`print_ir`](Self::print_ir) returns gives
+    /// back the same instructions, but for their spans.
+    pub fn parse_ir(src: &[u8]) -> BFResult<Vec<IntermediateInstruction>> {
+        let mut lexer = Lexer {
+            src,
+            location: SourceLocation {
+                offset: 0,
+                line: 1,
+                column: 1,
+            },
+            end: 0,
+            distance: 0,
+        };
+        parse_block(&mut lexer, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instruction::IntermediateInstruction;
+    use crate::instruction::IntermediateInstruction::*;
+    use crate::optimizer::{optimize, OptimizerOptions};
+    use crate::{BFError, BFParseError};
+
+    #[test]
+    fn test_print_ir() {
+        let src = b",[>+<-]>[-<++>]<[>>+<<[-]]>>[<.>>],[.,]";
+        let instrs = optimize(
+            IntermediateInstruction::parse_instrs(src).unwrap(),
+            OptimizerOptions::new().coalesce(true).simple_loops(true),
+        );
+        assert_eq!(
+            IntermediateInstruction::print_ir(&instrs),
+            [
+                "read",
+                "if {",
+                "    mul @+1 *1",
+                "    zero",
+                "}",
+                "move +1",
+                "if {",
+                "    mul @-1 *2",
+                "    zero",
+                "}",
+                "move -1",
+                "if {",
+                "    move +2",
+                "    add 1",
+                "    move -2",
+                "    zero",
+                "}",
+                "move +2",
+                "loop {",
+                "    move -1",
+                "    write",
+                "    move +2",
+                "}",
+                "read",
+                "loop { # balanced",
+                "    write",
+                "    read",
+                "}",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_ir_round_trip() {
+        let instrs = vec![
+            Add(0, -3, Default::default()),
+            Add(2, 7, Default::default()),
+            Set(-1, 300, Default::default()),
+            Set(0, 1, Default::default()),
+            Zero(4, Default::default()),
+            Read(1, Default::default()),
+            Write(-2, Default::default()),
+            Output(b"Hi \"there\"\n\\\x00\xff".to_vec(), Default::default()),
+            CheckBounds(vec![(1, 0), (-5, 0)], Default::default()),
+            Loop(
+                0,
+                vec![
+                    Scan(-4, Default::default()),
+                    SimpleLoop(vec![], Default::default()),
+                    Loop(
+                        0,
+                        vec![Move(1, Default::default()), Read(0, Default::default())],
+                        Default::default(),
+                    ),
+                    AddDynamic(-3, -7, Default::default()),
+                ],
+                Default::default(),
+            ),
+            Loop(3, vec![Add(3, -1, Default::default())], Default::default()),
+        ];
+        let text = IntermediateInstruction::print_ir(&instrs);
+        let parsed = IntermediateInstruction::parse_ir(text.as_bytes()).unwrap();
+        assert_eq!(IntermediateInstruction::print_ir(&parsed), text);
+        assert!(text.contains("loop @+3 { # balanced\n    add @+3 -1\n}"));
+        assert!(text.contains("output \"Hi \\\"there\\\"\\n\\\\\\x00\\xff\""));
+    }
+
+    #[test]
+    fn test_parse_ir() {
+        let src = b"# comment\nadd +2 move -1\nloop { # balanced\n  write @+0 }\n";
+        let instrs = IntermediateInstruction::parse_ir(src).unwrap();
+        let [Add(0, 2, add_span), Move(-1, _), Loop(0, body, loop_span)] = &instrs[..] else {
+            panic!("expected an add, a move, and a loop, got {:?}", instrs);
+        };
+        assert!(matches!(body[..], [Write(0, _)]));
+        assert_eq!((add_span.start.line, add_span.start.column), (2, 1));
+        assert_eq!(&src[add_span.start.offset..add_span.end], b"add +2");
+        assert_eq!(loop_span.end, src.len() - 1);
+    }
+
+    #[test]
+    fn test_parse_ir_extremes() {
+        // Additions wrap around like the cells, and moves and offsets are bounded,
+        // so optimizing extreme IR doesn't overflow
+        let src = b"add 2147483647 add 1 move +50000000 add @+50000000 1 move -50000000 write";
+        let instrs = optimize(
+            IntermediateInstruction::parse_ir(src).unwrap(),
+            OptimizerOptions::new().opt_level(2),
+        );
+        assert!(matches!(
+            instrs[..],
+            [Add(0, -2147483648, _), Add(100000000, 1, _), Write(0, _)]
+        ));
+    }
+
+    #[test]
+    fn test_parse_ir_errors() {
+        let error = |src: &[u8]| match IntermediateInstruction::parse_ir(src) {
+            Err(BFError::ParseError(BFParseError::InvalidIr { location, message })) => {
+                (location.line, location.column, message)
+            }
+            result => panic!("expected an IR error, got {:?}", result),
+        };
+        assert_eq!(error(b"add 1\nfoo"), (2, 1, "expected an instruction"));
+        assert_eq!(error(b"add @+1"), (1, 8, "expected a number"));
+        assert_eq!(
+            error(b"mul @1 2"),
+            (1, 8, "expected `*` and the multiplier")
+        );
+        assert_eq!(
+            error(b"loop {\n  add 1"),
+            (1, 6, "this `{` is never closed")
+        );
+        assert_eq!(error(b"add 1 }"), (1, 7, "this `}` has no matching `{`"));
+        assert_eq!(
+            error(b"output \"\\q\""),
+            (1, 11, "expected an escape sequence")
+        );
+        assert_eq!(error(b"scan +0"), (1, 6, "expected a nonzero stride"));
+        assert_eq!(
+            error(b"move +268435455\nadd @-1 1"),
+            (2, 6, "expected moves and offsets under 2^28 cells in all")
+        );
+    }
+}
@@ -6,5 +6,6 @@ mod _basic_instruction;
 pub use _basic_instruction::*;
 mod _intermediate_instruction;
 pub use _intermediate_instruction::*;
+mod _ir_text;
 
 mod tests;
@@ -200,7 +200,9 @@ fn parse_instrs_neg_unpaired_lbrace_1() {
     if let Err(err) = BasicInstruction::parse_instrs(source) {
         match err {
             crate::BFError::ParseError(err) => match err {
-                crate::BFParseError::UnmatchedLBrace(idx) => assert_eq!(idx, 0),
+                crate::BFParseError::UnmatchedLBrace { location, .. } => {
+                    assert_eq!(location.offset, 0)
+                }
                 _ => panic!("Wrong parse error type"),
             },
             _ => panic!("Wrong error type"),
@@ -214,7 +216,9 @@ fn parse_instrs_neg_unpaired_lbrace_2() {
     if let Err(err) = BasicInstruction::parse_instrs(source) {
         match err {
             crate::BFError::ParseError(err) => match err {
-                crate::BFParseError::UnmatchedLBrace(idx) => assert_eq!(idx, 0),
+                crate::BFParseError::UnmatchedLBrace { location, .. } => {
+                    assert_eq!(location.offset, 0)
+                }
                 _ => panic!("Wrong parse error type"),
             },
             _ => panic!("Wrong error type"),
@@ -228,7 +232,9 @@ fn parse_instrs_neg_unpaired_lbrace_3() {
     if let Err(err) = BasicInstruction::parse_instrs(source) {
         match err {
             crate::BFError::ParseError(err) => match err {
-                crate::BFParseError::UnmatchedLBrace(idx) => assert_eq!(idx, 4),
+                crate::BFParseError::UnmatchedLBrace { location, .. } => {
+                    assert_eq!(location.offset, 4)
+                }
                 _ => panic!("Wrong parse error type"),
             },
             _ => panic!("Wrong error type"),
@@ -242,7 +248,9 @@ fn parse_instrs_neg_unpaired_rbrace_1() {
     if let Err(err) = BasicInstruction::parse_instrs(source) {
         match err {
             crate::BFError::ParseError(err) => match err {
-                crate::BFParseError::UnmatchedRBrace(idx) => assert_eq!(idx, 0),
+                crate::BFParseError::UnmatchedRBrace { location, .. } => {
+                    assert_eq!(location.offset, 0)
+                }
                 _ => panic!("Wrong parse error type"),
             },
             _ => panic!("Wrong error type"),
@@ -256,7 +264,9 @@ fn parse_instrs_neg_unpaired_rbrace_2() {
     if let Err(err) = BasicInstruction::parse_instrs(source) {
         match err {
             crate::BFError::ParseError(err) => match err {
-                crate::BFParseError::UnmatchedRBrace(idx) => assert_eq!(idx, 6),
+                crate::BFParseError::UnmatchedRBrace { location, .. } => {
+                    assert_eq!(location.offset, 6)
+                }
                 _ => panic!("Wrong parse error type"),
             },
             _ => panic!("Wrong error type"),
@@ -270,10 +280,42 @@ fn parse_instrs_neg_unpaired_rbrace_3() {
     if let Err(err) = BasicInstruction::parse_instrs(source) {
         match err {
             crate::BFError::ParseError(err) => match err {
-                crate::BFParseError::UnmatchedRBrace(idx) => assert_eq!(idx, 2),
+                crate::BFParseError::UnmatchedRBrace { location, .. } => {
+                    assert_eq!(location.offset, 2)
+                }
                 _ => panic!("Wrong parse error type"),
             },
             _ => panic!("Wrong error type"),
         }
     };
 }
+
+#[test]
+fn parse_intermediate_neg_unpaired_lbrace() {
+    let source = "+[-]\n>[[-]<\n".as_bytes();
+    match super::IntermediateInstruction::parse_instrs(source) {
+        Err(crate::BFError::ParseError(crate::BFParseError::UnmatchedLBrace {
+            location,
+            partner,
+        })) => {
+            assert_eq!((location.offset, location.line, location.column), (6, 2, 2));
+            assert_eq!((partner.offset, partner.line, partner.column), (11, 2, 7));
+        }
+        _ => panic!("Wrong result"),
+    }
+}
+
+#[test]
+fn parse_intermediate_neg_unpaired_rbrace() {
+    let source = "[[-]>]\n<]".as_bytes();
+    match super::IntermediateInstruction::parse_instrs(source) {
+        Err(crate::BFError::ParseError(crate::BFParseError::UnmatchedRBrace {
+            location,
+            partner: Some(partner),
+        })) => {
+            assert_eq!((location.offset, location.line, location.column), (8, 2, 2));
+            assert_eq!(partner.offset, 0);
+        }
+        _ => panic!("Wrong result"),
+    }
+}
@@ -0,0 +1,434 @@
+//! Defines a debugger that steps through a BF program one instruction at a time
+//!
+//! Author: Cayden Lund (cayden.lund@utah.edu)
+
+use super::{step, RuntimeState};
+use crate::instruction::{BasicInstruction, BasicInstructionType};
+use crate::{BFError, BFResult, CellWidth, EofBehavior, MemoryIo, SourceLocation};
+use std::collections::{BTreeSet, VecDeque};
+use std::mem;
+
+/// The most instructions that are recorded for reverse stepping
+const MAX_HISTORY: usize = 1 << 20;
+
+/// Why the debugger stopped running the program
+#[derive(Debug)]
+pub enum Stop {
+    /// The requested number of instructions were executed
+    Stepped,
+
+    /// The next instruction has a breakpoint
+    Breakpoint(usize),
+
+    /// The value of a watched cell changed
+    Watchpoint {
+        /// The index of the watched cell
+        cell: usize,
+
+        /// The value of the cell before the change
+        old: u32,
+
+        /// The value of the cell after the change
+        new: u32,
+    },
+
+    /// The program ran to completion
+    Finished,
+
+    /// The next instruction failed, and was not executed
+    Error(BFError),
+}
+
+/// The part of the state that a single instruction changed, so it can be undone
+#[derive(Debug)]
+struct Change {
+    /// The instruction pointer before the instruction
+    instr: usize,
+
+    /// The memory pointer before the instruction
+    ptr: usize,
+
+    /// The value of the current cell before the instruction
+    cell: u32,
+
+    /// The length of the input that was consumed before the instruction
+    input_pos: usize,
+
+    /// The length of the output before the instruction
+    output_len: usize,
+}
+
+/// A debugger for a BF program, which runs on the unoptimized instructions so that every
+/// source character can be stepped through
+///
+/// Each executed instruction is recorded, so that execution can be stepped backwards.
+/// The tape does not grow, so that the recorded changes stay valid.
+pub struct Debugger {
+    /// The source program
+    pub src: Vec<u8>,
+
+    /// The instructions of the program
+    pub instrs: Vec<BasicInstruction>,
+
+    /// The state of the program
+    pub state: RuntimeState,
+
+    /// The input that the program reads
+    input: Vec<u8>,
+
+    /// The length of the input that the program has read
+    input_pos: usize,
+
+    /// The output that the program has written
+    pub output: Vec<u8>,
+
+    /// The instructions with breakpoints
+    breakpoints: BTreeSet<usize>,
+
+    /// The watched cells, with their last seen values
+    watchpoints: Vec<(usize, u32)>,
+
+    /// The changes made by the most recent instructions, oldest first
+    history: VecDeque<Change>,
+}
+
+impl Debugger {
+    /// Parses the given program, to be run on the given input
+    ///
+    /// Every `#` marker in the source program sets a breakpoint on the instruction after it
+    pub fn new(
+        src: &[u8],
+        input: Vec<u8>,
+        mem_size: usize,
+        cell_width: CellWidth,
+        eof_behavior: EofBehavior,
+    ) -> BFResult<Self> {
+        let instrs = BasicInstruction::parse_instrs(src)?;
+        let mut debugger = Self {
+            src: src.to_vec(),
+            instrs,
+            state: RuntimeState::new(mem_size, cell_width, eof_behavior),
+            input,
+            input_pos: 0,
+            output: vec![],
+            breakpoints: BTreeSet::new(),
+            watchpoints: vec![],
+            history: VecDeque::new(),
+        };
+        for (offset, _) in src.iter().enumerate().filter(|(_, &ch)| ch == b'#') {
+            debugger.add_breakpoint(offset);
+        }
+        Ok(debugger)
+    }
+
+    /// Returns whether the program has run to completion
+    pub fn finished(&self) -> bool {
+        self.state.instr >= self.instrs.len()
+    }
+
+    /// Returns the location in the source program of the next instruction,
+    /// or of the end of the program if it has finished
+    pub fn location(&self) -> SourceLocation {
+        match self.instrs.get(self.state.instr) {
+            Some(instr) => SourceLocation::new(&self.src, instr.position),
+            None => SourceLocation::end(&self.src),
+        }
+    }
+
+    /// Sets a breakpoint on the first instruction at or after the given source offset
+    ///
+    /// Returns the index of the instruction, or `None` if there is no instruction there
+    pub fn add_breakpoint(&mut self, offset: usize) -> Option<usize> {
+        let index = self
+            .instrs
+            .iter()
+            .position(|instr| instr.position >= offset)?;
+        self.breakpoints.insert(index);
+        Some(index)
+    }
+
+    /// Removes the breakpoint on the given instruction, returning whether there was one
+    pub fn remove_breakpoint(&mut self, index: usize) -> bool {
+        self.breakpoints.remove(&index)
+    }
+
+    /// Returns the instructions with breakpoints, in order
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Watches the given cell, stopping whenever its value changes
+    ///
+    /// Returns whether the cell is on the tape
+    pub fn add_watchpoint(&mut self, cell: usize) -> bool {
+        if cell >= self.cells() {
+            return false;
+        }
+        self.watchpoints.retain(|&(watched, _)| watched != cell);
+        self.watchpoints.push((cell, self.state.cell(cell)));
+        true
+    }
+
+    /// Stops watching the given cell, returning whether it was watched
+    pub fn remove_watchpoint(&mut self, cell: usize) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|&(watched, _)| watched != cell);
+        self.watchpoints.len() != len
+    }
+
+    /// Returns the watched cells
+    pub fn watchpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.watchpoints.iter().map(|&(cell, _)| cell)
+    }
+
+    /// Returns the number of cells on the tape
+    pub fn cells(&self) -> usize {
+        self.state.memory.len() / self.state.cell_width.bytes()
+    }
+
+    /// Executes the next instruction, and records its changes
+    fn step_one(&mut self) -> BFResult<()> {
+        let change = Change {
+            instr: self.state.instr,
+            ptr: self.state.ptr,
+            cell: self.state.cell(self.state.ptr),
+            input_pos: self.input_pos,
+            output_len: self.output.len(),
+        };
+
+        let mut io = MemoryIo {
+            input: &self.input[self.input_pos..],
+            output: mem::take(&mut self.output),
+        };
+        let result = step(&self.instrs, &mut self.state, &mut io);
+        self.input_pos = self.input.len() - io.input.len();
+        self.output = io.output;
+        result?;
+
+        if self.history.len() == MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(change);
+        Ok(())
+    }
+
+    /// Checks the watchpoints after an instruction, updating their last seen values
+    fn check_watchpoints(&mut self) -> Option<Stop> {
+        let mut stop = None;
+        for (cell, last) in &mut self.watchpoints {
+            let value = self.state.cell(*cell);
+            if value != *last && stop.is_none() {
+                stop = Some(Stop::Watchpoint {
+                    cell: *cell,
+                    old: *last,
+                    new: value,
+                });
+            }
+            *last = value;
+        }
+        stop
+    }
+
+    /// Executes instructions until `done` returns true before an instruction,
+    /// or until a breakpoint or watchpoint is hit
+    ///
+    /// At least one instruction is executed, even if it has a breakpoint
+    pub fn run_until(&mut self, mut done: impl FnMut(&Self) -> bool) -> Stop {
+        loop {
+            if self.finished() {
+                return Stop::Finished;
+            }
+            if let Err(err) = self.step_one() {
+                return Stop::Error(err);
+            }
+            if let Some(stop) = self.check_watchpoints() {
+                return stop;
+            }
+            if self.finished() {
+                return Stop::Finished;
+            }
+            if self.breakpoints.contains(&self.state.instr) {
+                return Stop::Breakpoint(self.state.instr);
+            }
+            if done(self) {
+                return Stop::Stepped;
+            }
+        }
+    }
+
+    /// Executes the given number of instructions
+    pub fn step(&mut self, count: usize) -> Stop {
+        if count == 0 {
+            return Stop::Stepped;
+        }
+        let mut remaining = count;
+        self.run_until(|_| {
+            remaining -= 1;
+            remaining == 0
+        })
+    }
+
+    /// Executes the next instruction, or the whole loop if the next instruction starts one
+    pub fn step_over(&mut self) -> Stop {
+        match self.instrs.get(self.state.instr).map(|instr| instr.instr) {
+            Some(BasicInstructionType::LBrace(end)) => {
+                self.run_until(|debugger| debugger.state.instr > end)
+            }
+            _ => self.step(1),
+        }
+    }
+
+    /// Executes instructions until the innermost loop around the next instruction exits
+    ///
+    /// If the next instruction isn't in a loop, runs the program to completion
+    pub fn step_out(&mut self) -> Stop {
+        let current = self.state.instr;
+        let end = self.instrs[..current.min(self.instrs.len())]
+            .iter()
+            .rev()
+            .find_map(|instr| match instr.instr {
+                BasicInstructionType::LBrace(end) if end >= current => Some(end),
+                _ => None,
+            });
+        match end {
+            Some(end) => self.run_until(|debugger| debugger.state.instr > end),
+            None => self.run_until(|_| false),
+        }
+    }
+
+    /// Executes instructions until a breakpoint or watchpoint is hit,
+    /// or the program runs to completion
+    pub fn cont(&mut self) -> Stop {
+        self.run_until(|_| false)
+    }
+
+    /// Undoes the given number of the most recent instructions
+    ///
+    /// Returns the number of instructions that were undone, which is smaller if the history
+    /// runs out. Output that was already written stays written, but is forgotten
+    pub fn reverse_step(&mut self, count: usize) -> usize {
+        for undone in 0..count {
+            let Some(change) = self.history.pop_back() else {
+                return undone;
+            };
+            self.state.instr = change.instr;
+            self.state.ptr = change.ptr;
+            self.state.set_cell(change.ptr, change.cell);
+            self.input_pos = change.input_pos;
+            self.output.truncate(change.output_len);
+        }
+        for (cell, last) in &mut self.watchpoints {
+            *last = self.state.cell(*cell);
+        }
+        count
+    }
+
+    /// Formats the cells within the given distance of the memory pointer,
+    /// with the current cell in brackets
+    pub fn tape(&self, radius: usize) -> String {
+        let start = self.state.ptr.saturating_sub(radius);
+        let end = (self.state.ptr + radius + 1).min(self.cells());
+        (start..end)
+            .map(|cell| {
+                if cell == self.state.ptr {
+                    format!("[{}: {}]", cell, self.state.cell(cell))
+                } else {
+                    format!("{}: {}", cell, self.state.cell(cell))
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("  ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Debugger, Stop};
+    use crate::{BFError, CellWidth, EofBehavior};
+
+    fn new_debugger(src: &[u8], input: &[u8]) -> Debugger {
+        Debugger::new(src, input.to_vec(), 8, CellWidth::Bits8, EofBehavior::Zero).unwrap()
+    }
+
+    #[test]
+    fn test_step_and_reverse_step() {
+        let mut debugger = new_debugger(b"+>,.<-", b"A");
+        assert!(matches!(debugger.step(4), Stop::Stepped));
+        assert_eq!(debugger.output, b"A");
+        assert_eq!(debugger.state.ptr, 5);
+        assert_eq!(debugger.tape(1), "4: 1  [5: 65]  6: 0");
+
+        assert_eq!(debugger.reverse_step(3), 3);
+        assert_eq!(debugger.state.instr, 1);
+        assert_eq!(debugger.state.ptr, 4);
+        assert_eq!(debugger.state.cell(5), 0);
+        assert!(debugger.output.is_empty());
+        assert_eq!(debugger.reverse_step(5), 1);
+
+        // The input is read again after stepping back over it
+        assert!(matches!(debugger.cont(), Stop::Finished));
+        assert_eq!(debugger.output, b"A");
+        assert_eq!(debugger.state.cell(4), 0);
+    }
+
+    #[test]
+    fn test_breakpoints_and_markers() {
+        let mut debugger = new_debugger(b"++[->+<]#>.", b"");
+        assert_eq!(debugger.breakpoints().collect::<Vec<usize>>(), vec![8]);
+        assert_eq!(debugger.add_breakpoint(4), Some(4));
+        assert_eq!(debugger.location().column, 1);
+
+        assert!(matches!(debugger.cont(), Stop::Breakpoint(4)));
+        assert!(matches!(debugger.cont(), Stop::Breakpoint(4)));
+        assert!(debugger.remove_breakpoint(4));
+        assert!(matches!(debugger.cont(), Stop::Breakpoint(8)));
+        assert_eq!(debugger.location().column, 10);
+        assert_eq!(debugger.state.cell(5), 2);
+        assert!(matches!(debugger.cont(), Stop::Finished));
+        assert_eq!(debugger.output, [2]);
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let mut debugger = new_debugger(b"++[->+<]>[-]", b"");
+        debugger.step(2);
+        assert!(matches!(debugger.step_over(), Stop::Stepped));
+        assert_eq!(debugger.state.instr, 8);
+        assert_eq!(debugger.state.cell(5), 2);
+
+        debugger.step(2);
+        assert!(matches!(debugger.step_out(), Stop::Finished));
+        assert_eq!(debugger.state.cell(5), 0);
+
+        let mut debugger = new_debugger(b"+[[-]>+<]", b"");
+        debugger.step(3);
+        assert!(matches!(debugger.step_out(), Stop::Stepped));
+        assert_eq!(debugger.state.instr, 5);
+    }
+
+    #[test]
+    fn test_watchpoints_and_errors() {
+        let mut debugger = new_debugger(b"+>+>+<<-<<<<<", b"");
+        assert!(debugger.add_watchpoint(5));
+        assert!(!debugger.add_watchpoint(8));
+        assert!(matches!(
+            debugger.cont(),
+            Stop::Watchpoint {
+                cell: 5,
+                old: 0,
+                new: 1
+            }
+        ));
+        assert_eq!(debugger.state.instr, 3);
+        assert!(debugger.remove_watchpoint(5));
+        assert!(matches!(
+            debugger.cont(),
+            Stop::Error(BFError::TapeOverflow {
+                position: 12,
+                ptr: -1
+            })
+        ));
+        assert_eq!(debugger.state.ptr, 0);
+        assert_eq!(debugger.state.instr, 12);
+    }
+}
@@ -4,98 +4,384 @@
 
 use super::RuntimeState;
 use crate::instruction::{BasicInstruction, BasicInstructionType, IntermediateInstruction};
-use libc::c_int;
+use crate::optimizer::LoopBalance;
+use crate::{BFResult, BfIo, CellWidth, EofBehavior};
 use std::io::Read;
 
-fn interp2_rec(src: &Vec<IntermediateInstruction>, state: &mut RuntimeState) {
-    while state.instr < src.len() {
-        match &src[state.instr] {
-            IntermediateInstruction::Loop(sub_instrs) => {
-                while state.memory[state.ptr] > 0 {
-                    let instr = state.instr;
-                    state.instr = 0;
-                    interp2_rec(sub_instrs, state);
-                    state.instr = instr;
+/// Mask selecting the high bit of every byte in a 64-bit word
+const HIGH_BITS: u64 = 0x8080_8080_8080_8080;
+
+/// Mask selecting the low 7 bits of every byte in a 64-bit word
+const LOW_BITS: u64 = 0x7F7F_7F7F_7F7F_7F7F;
+
+/// Returns a word with the high bit set in exactly those bytes of `word` that are zero
+fn zero_bytes(word: u64) -> u64 {
+    !(((word & LOW_BITS) + LOW_BITS) | word | LOW_BITS)
+}
+
+/// Performs a memory scan: finds the first zero cell at `ptr`, `ptr + stride`, `ptr + 2*stride`...
+///
+/// Strides of +-1, +-2, and +-4 check 8 cells at a time; any other stride falls back to checking
+/// one cell at a time. Panics if the scan runs off either end of the tape.
+pub fn scan(memory: &[u8], ptr: usize, stride: i32) -> usize {
+    let mut ptr = ptr;
+
+    // For a forward scan, the cell at `ptr` is the lowest byte of the word;
+    // for a backward scan, it is the highest byte
+    let mask = match stride {
+        1 | -1 => Some(HIGH_BITS),
+        2 => Some(0x0080_0080_0080_0080),
+        -2 => Some(0x8000_8000_8000_8000),
+        4 => Some(0x0000_0080_0000_0080),
+        -4 => Some(0x8000_0000_8000_0000),
+        _ => None,
+    };
+
+    if let Some(mask) = mask {
+        if stride > 0 {
+            while ptr + 8 <= memory.len() {
+                let word = u64::from_le_bytes(memory[ptr..ptr + 8].try_into().unwrap());
+                let found = zero_bytes(word) & mask;
+                if found != 0 {
+                    return ptr + (found.trailing_zeros() / 8) as usize;
                 }
+                ptr += 8;
             }
-            IntermediateInstruction::AddDynamic(target, scale) => {
-                let idx = (state.ptr as i32 + *target) as usize;
-                state.memory[idx] =
-                    state.memory[idx].wrapping_add((*scale * state.memory[state.ptr] as i32) as u8);
+        } else {
+            while ptr >= 7 && ptr < memory.len() {
+                let word = u64::from_le_bytes(memory[ptr - 7..=ptr].try_into().unwrap());
+                let found = zero_bytes(word) & mask;
+                if found != 0 {
+                    return ptr - (found.leading_zeros() / 8) as usize;
+                }
+                ptr -= 8;
             }
-            IntermediateInstruction::Zero => state.memory[state.ptr] = 0,
-            IntermediateInstruction::SimpleLoop(sub_instrs) => {
-                let instr = state.instr;
-                state.instr = 0;
-                interp2_rec(sub_instrs, state);
-                state.instr = instr;
+        }
+    }
+
+    while memory[ptr] != 0 {
+        ptr = (ptr as isize + stride as isize) as usize;
+    }
+    ptr
+}
+
+/// A loop body that is being executed, or the whole program
+struct Frame<'a> {
+    /// The instructions in the body
+    instrs: &'a [IntermediateInstruction],
+
+    /// The index of the next instruction to execute in the body
+    index: usize,
+
+    /// The offset of the cell that the body repeats while it is nonzero, as for a
+    /// [`Loop`](IntermediateInstruction::Loop), or `None` if the body runs once, as for a
+    /// [`SimpleLoop`](IntermediateInstruction::SimpleLoop)
+    repeats: Option<i32>,
+}
+
+/// An interpreter for intermediate BF instructions that can be paused and resumed
+///
+/// Instead of recursing into loop bodies, the interpreter keeps a stack of the bodies that it is
+/// in, so it can stop after any instruction. Entering or skipping a loop counts as one instruction,
+/// and so does every instruction in its body.
+///
+/// The program reads and writes through the given [`BfIo`], which can be a `&mut` reference
+/// so that the caller keeps it.
+pub struct Interpreter<'a, I: BfIo> {
+    state: RuntimeState,
+    frames: Vec<Frame<'a>>,
+    io: I,
+}
+
+impl<'a, I: BfIo> Interpreter<'a, I> {
+    /// Creates an interpreter that runs the given instructions, starting from the given state
+    pub fn new(src: &'a [IntermediateInstruction], state: RuntimeState, io: I) -> Self {
+        let mut interpreter = Self {
+            state,
+            io,
+            frames: vec![Frame {
+                instrs: src,
+                index: 0,
+                repeats: None,
+            }],
+        };
+        interpreter.state.instr = 0;
+        interpreter.leave_finished_bodies();
+        interpreter
+    }
+
+    /// Returns the state of the memory tape
+    ///
+    /// `instr` is the index of the next instruction in the innermost loop body being executed
+    pub fn state(&self) -> &RuntimeState {
+        &self.state
+    }
+
+    /// Returns the program's input and output
+    pub fn io(&self) -> &I {
+        &self.io
+    }
+
+    /// Returns the program's input and output, mutably
+    pub fn io_mut(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    /// Returns whether the whole program has been executed
+    pub fn finished(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Returns the instruction that is executed next, if the program hasn't finished
+    pub fn current(&self) -> Option<&'a IntermediateInstruction> {
+        self.frames.last().map(|frame| &frame.instrs[frame.index])
+    }
+
+    /// Returns how many loop bodies the next instruction is nested in
+    pub fn depth(&self) -> usize {
+        self.frames.len().saturating_sub(1)
+    }
+
+    /// Executes the next instruction
+    ///
+    /// Returns `false` without doing anything if the program has already finished. If the
+    /// instruction fails, the interpreter stays at that instruction.
+    pub fn step(&mut self) -> BFResult<bool> {
+        let Some(instr) = self.current() else {
+            return Ok(false);
+        };
+        let state = &mut self.state;
+        // The index of the cell that the instruction acts on
+        let cell = (state.ptr as isize + instr.cell_offset() as isize) as usize;
+
+        match instr {
+            IntermediateInstruction::Loop(offset, body, _) => {
+                if state.cell(cell) != 0 {
+                    // An empty loop around a nonzero cell never exits, so it stays put
+                    if !body.is_empty() {
+                        self.enter(body, Some(*offset));
+                    }
+                    return Ok(true);
+                }
+            }
+            IntermediateInstruction::SimpleLoop(body, _) => {
+                if state.cell(state.ptr) != 0 && !body.is_empty() {
+                    self.enter(body, None);
+                    return Ok(true);
+                }
+            }
+            IntermediateInstruction::AddDynamic(target, scale, _) => {
+                let idx = (state.ptr as i32 + *target) as usize;
+                state.add_cell(idx, (*scale as u32).wrapping_mul(state.cell(state.ptr)));
             }
-            IntermediateInstruction::Move(stride) => {
+            IntermediateInstruction::Zero(_, _) => state.set_cell(cell, 0),
+            IntermediateInstruction::Set(_, value, _) => state.set_cell(cell, *value as u32),
+            IntermediateInstruction::Move(stride, _) => {
                 state.ptr = (state.ptr as isize + *stride as isize) as usize
             }
-            IntermediateInstruction::Add(displacement) => {
-                state.memory[state.ptr] = state.memory[state.ptr].wrapping_add(*displacement as u8)
+            IntermediateInstruction::Add(_, displacement, _) => {
+                state.add_cell(cell, *displacement as u32)
+            }
+            IntermediateInstruction::Read(_, _) => {
+                state.read_cell_at(cell, self.io.read_byte().map_or(-1, i32::from))
+            }
+            IntermediateInstruction::Write(_, _) => self.io.write_byte(state.cell(cell) as u8),
+            IntermediateInstruction::Output(bytes, _) => {
+                for byte in bytes {
+                    self.io.write_byte(*byte);
+                }
+            }
+            IntermediateInstruction::Scan(stride, _) => {
+                state.ptr = match state.cell_width {
+                    CellWidth::Bits8 => scan(&state.memory, state.ptr, *stride),
+                    _ => {
+                        let mut ptr = state.ptr;
+                        while state.cell(ptr) != 0 {
+                            ptr = (ptr as isize + *stride as isize) as usize;
+                        }
+                        ptr
+                    }
+                };
+            }
+            IntermediateInstruction::CheckBounds(probes, _) => {
+                for (offset, position) in probes {
+                    state.check_bounds(*offset, *position)?;
+                }
             }
-            IntermediateInstruction::Read => unsafe {
-                state.memory[state.ptr] = libc::getchar().max(-1) as u8;
-            },
-            IntermediateInstruction::Write => unsafe {
-                libc::putchar(state.memory[state.ptr] as c_int);
-            },
-            IntermediateInstruction::Scan(_) => todo!(),
         }
-        state.instr += 1;
+
+        if let Some(frame) = self.frames.last_mut() {
+            frame.index += 1;
+        }
+        self.leave_finished_bodies();
+        Ok(true)
+    }
+
+    /// Executes at most `count` instructions, stopping early if the program finishes
+    ///
+    /// Returns the number of instructions executed
+    pub fn run_for(&mut self, count: usize) -> BFResult<usize> {
+        for executed in 0..count {
+            if !self.step()? {
+                return Ok(executed);
+            }
+        }
+        Ok(count)
+    }
+
+    /// Executes instructions until `done` returns `true` or the program finishes
+    ///
+    /// `done` is checked before every instruction, including the first. Returns whether `done`
+    /// stopped the interpreter.
+    pub fn run_until(&mut self, mut done: impl FnMut(&Self) -> bool) -> BFResult<bool> {
+        loop {
+            if done(self) {
+                return Ok(true);
+            }
+            if !self.step()? {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Executes instructions until the program finishes, then flushes the output
+    ///
+    /// The output is flushed even if an instruction fails
+    pub fn run(&mut self) -> BFResult<()> {
+        let result = self.run_until(|_| false);
+        self.io.flush();
+        result.map(|_| ())
     }
-}
 
-pub fn interp2(src: &Vec<IntermediateInstruction>, mem_size: usize) {
-    let mut state = RuntimeState::new(mem_size);
-    interp2_rec(src, &mut state);
+    /// Starts executing the given loop body
+    fn enter(&mut self, body: &'a [IntermediateInstruction], repeats: Option<i32>) {
+        self.frames.push(Frame {
+            instrs: body,
+            index: 0,
+            repeats,
+        });
+        self.state.instr = 0;
+    }
+
+    /// Leaves every loop body that has run out of instructions, either by repeating it or by
+    /// moving past the loop, so that the innermost frame points at the next instruction
+    fn leave_finished_bodies(&mut self) {
+        while let Some(frame) = self.frames.last_mut() {
+            if frame.index < frame.instrs.len() {
+                self.state.instr = frame.index;
+                return;
+            }
+            let repeats = frame.repeats.is_some_and(|offset| {
+                let cell = (self.state.ptr as isize + offset as isize) as usize;
+                self.state.cell(cell) != 0
+            });
+            if repeats {
+                frame.index = 0;
+                self.state.instr = 0;
+                return;
+            }
+            self.frames.pop();
+            if let Some(frame) = self.frames.last_mut() {
+                frame.index += 1;
+            }
+        }
+    }
 }
 
-/// Interprets the given BF instructions
-pub fn interpret(src: &Vec<BasicInstruction>, mem_size: usize) {
-    let mut state = RuntimeState::new(mem_size);
+/// Interprets the given (optionally optimized) intermediate BF instructions
+///
+/// If `growable` is set, the tape grows whenever a bounds check fails. Otherwise, returns a [`BFError::TapeOverflow`](crate::BFError::TapeOverflow) if a bounds check fails
+pub fn interp2(
+    src: &[IntermediateInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    growable: bool,
+    io: impl BfIo,
+) -> BFResult<()> {
+    let state = RuntimeState {
+        growable,
+        ..RuntimeState::new(mem_size, cell_width, eof_behavior)
+    };
+    Interpreter::new(src, state, io).run()
+}
 
-    while state.instr < src.len() {
-        match src[state.instr].instr {
-            BasicInstructionType::Left => state.ptr -= 1,
-            BasicInstructionType::Right => state.ptr += 1,
-            BasicInstructionType::Decr => {
-                state.memory[state.ptr] = state.memory[state.ptr].wrapping_sub(1)
-            }
-            BasicInstructionType::Incr => {
-                state.memory[state.ptr] = state.memory[state.ptr].wrapping_add(1)
-            }
-            BasicInstructionType::Read => unsafe {
-                state.memory[state.ptr] = libc::getchar().max(-1) as u8;
-            },
-            BasicInstructionType::Write => unsafe {
-                libc::putchar(state.memory[state.ptr] as c_int);
-            },
-            BasicInstructionType::LBrace(instr) => {
-                if state.memory[state.ptr] == 0 {
-                    state.instr = instr
-                }
+/// Executes the BF instruction at the instruction pointer, then advances the instruction pointer
+///
+/// Input is read from, and the low byte of the current cell is written to, the given [`BfIo`].
+/// Every move is checked; if it fails, the state is left as it was before the instruction
+pub fn step(
+    src: &[BasicInstruction],
+    state: &mut RuntimeState,
+    io: &mut impl BfIo,
+) -> BFResult<()> {
+    match src[state.instr].instr {
+        BasicInstructionType::Left => {
+            state.check_bounds(-1, src[state.instr].position)?;
+            state.ptr -= 1
+        }
+        BasicInstructionType::Right => {
+            state.check_bounds(1, src[state.instr].position)?;
+            state.ptr += 1
+        }
+        BasicInstructionType::Decr => state.add_cell(state.ptr, u32::MAX),
+        BasicInstructionType::Incr => state.add_cell(state.ptr, 1),
+        BasicInstructionType::Read => state.read_cell(io.read_byte().map_or(-1, i32::from)),
+        BasicInstructionType::Write => io.write_byte(state.cell(state.ptr) as u8),
+        BasicInstructionType::LBrace(instr) => {
+            if state.cell(state.ptr) == 0 {
+                state.instr = instr
             }
-            BasicInstructionType::RBrace(instr) => {
-                if state.memory[state.ptr] != 0 {
-                    state.instr = instr
-                }
+        }
+        BasicInstructionType::RBrace(instr) => {
+            if state.cell(state.ptr) != 0 {
+                state.instr = instr
             }
         }
-        state.instr += 1;
     }
+    state.instr += 1;
+    Ok(())
+}
+
+/// Interprets the given BF instructions
+///
+/// Every move is checked, since the instructions are interpreted one at a time anyway.
+/// If `growable` is set, the tape grows instead of overflowing
+pub fn interpret(
+    src: &[BasicInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    growable: bool,
+    mut io: impl BfIo,
+) -> BFResult<()> {
+    let mut state = RuntimeState {
+        growable,
+        ..RuntimeState::new(mem_size, cell_width, eof_behavior)
+    };
+
+    let mut result = Ok(());
+    while state.instr < src.len() && result.is_ok() {
+        result = step(src, &mut state, &mut io);
+    }
+    io.flush();
+    result
 }
 
 /// Interprets the given BF instructions, with added profiling
-pub fn interpret_profile(src: &Vec<BasicInstruction>, mem_size: usize) {
+pub fn interpret_profile(
+    src: &[BasicInstruction],
+    mem_size: usize,
+    cell_width: CellWidth,
+    eof_behavior: EofBehavior,
+    growable: bool,
+) -> BFResult<()> {
     let (simple_loops, non_simple_loops) = {
         let mut simple_loops: Vec<(usize, usize)> = vec![];
         // (loop_start, ptr_change, data_change)
         let mut simple_loop: (Option<usize>, i32, i32) = (None, 0, 0);
 
-        let mut non_simple_loops: Vec<(usize, usize)> = vec![];
+        let mut non_simple_loops: Vec<(usize, usize, LoopBalance)> = vec![];
         let mut non_simple_loop: Option<usize> = None;
 
         for idx in 0..src.len() {
@@ -111,14 +397,18 @@ pub fn interpret_profile(src: &Vec<BasicInstruction>, mem_size: usize) {
                     non_simple_loop = Some(idx)
                 }
                 BasicInstructionType::RBrace(_) => {
+                    let balance = match simple_loop.1 {
+                        0 => LoopBalance::Balanced,
+                        _ => LoopBalance::Unbalanced,
+                    };
                     if let (Some(old_idx), ptr_change, data_change) = simple_loop {
                         if ptr_change == 0 && (data_change == 1 || data_change == -1) {
                             simple_loops.push((old_idx, idx));
                         } else if let Some(old_idx) = non_simple_loop {
-                            non_simple_loops.push((old_idx, idx));
+                            non_simple_loops.push((old_idx, idx, balance));
                         }
                     } else if let Some(old_idx) = non_simple_loop {
-                        non_simple_loops.push((old_idx, idx));
+                        non_simple_loops.push((old_idx, idx, balance));
                     }
                     simple_loop.0 = None;
                     non_simple_loop = None;
@@ -129,7 +419,10 @@ pub fn interpret_profile(src: &Vec<BasicInstruction>, mem_size: usize) {
         (simple_loops, non_simple_loops)
     };
 
-    let mut state = RuntimeState::new(mem_size);
+    let mut state = RuntimeState {
+        growable,
+        ..RuntimeState::new(mem_size, cell_width, eof_behavior)
+    };
 
     let mut counts: Vec<usize> = vec![0; src.len()];
 
@@ -137,30 +430,32 @@ pub fn interpret_profile(src: &Vec<BasicInstruction>, mem_size: usize) {
         counts[state.instr] += 1;
 
         match src[state.instr].instr {
-            BasicInstructionType::Left => state.ptr -= 1,
-            BasicInstructionType::Right => state.ptr += 1,
-            BasicInstructionType::Decr => {
-                state.memory[state.ptr] = state.memory[state.ptr].wrapping_sub(1)
+            BasicInstructionType::Left => {
+                state.check_bounds(-1, src[state.instr].position)?;
+                state.ptr -= 1
             }
-            BasicInstructionType::Incr => {
-                state.memory[state.ptr] = state.memory[state.ptr].wrapping_add(1)
+            BasicInstructionType::Right => {
+                state.check_bounds(1, src[state.instr].position)?;
+                state.ptr += 1
             }
+            BasicInstructionType::Decr => state.add_cell(state.ptr, u32::MAX),
+            BasicInstructionType::Incr => state.add_cell(state.ptr, 1),
             BasicInstructionType::Read => {
                 if let Some(Ok(ch)) = std::io::stdin().bytes().next() {
-                    state.memory[state.ptr] = ch;
+                    state.read_cell(ch as i32);
                 } else {
-                    state.memory[state.ptr] = 0;
+                    state.read_cell(-1);
                 };
             }
-            BasicInstructionType::Write => print!("{}", state.memory[state.ptr] as char),
+            BasicInstructionType::Write => print!("{}", state.cell(state.ptr) as u8 as char),
             BasicInstructionType::LBrace(instr) => {
-                if state.memory[state.ptr] == 0 {
+                if state.cell(state.ptr) == 0 {
                     counts[instr] += 1;
                     state.instr = instr
                 }
             }
             BasicInstructionType::RBrace(instr) => {
-                if state.memory[state.ptr] != 0 {
+                if state.cell(state.ptr) != 0 {
                     counts[instr] += 1;
                     state.instr = instr
                 }
@@ -203,20 +498,195 @@ pub fn interpret_profile(src: &Vec<BasicInstruction>, mem_size: usize) {
     println!("Non-simple innermost loops:");
 
     let non_simple_loops = {
-        let mut non_simple_loops = non_simple_loops.iter().collect::<Vec<&(usize, usize)>>();
+        let mut non_simple_loops = non_simple_loops
+            .iter()
+            .collect::<Vec<&(usize, usize, LoopBalance)>>();
         non_simple_loops.sort_by(|l1, l2| (&counts[l2.0]).cmp(&counts[l1.0]));
         non_simple_loops
     };
     for non_simple_loop in non_simple_loops {
         println!(
-            r"    {}: `{}`: {}",
+            r"    {}: `{}`: {} ({})",
             non_simple_loop.0,
             src[non_simple_loop.0..=non_simple_loop.1]
                 .iter()
                 .map(|instr| (instr.ch as char).to_string())
                 .collect::<Vec<String>>()
                 .join(""),
-            counts[non_simple_loop.0]
+            counts[non_simple_loop.0],
+            non_simple_loop.2
         );
     }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{scan, Interpreter};
+    use crate::instruction::IntermediateInstruction;
+    use crate::interpreter::RuntimeState;
+    use crate::optimizer::{optimize, OptimizerOptions};
+    use crate::{CellWidth, EofBehavior, MemoryIo};
+
+    fn new_state() -> RuntimeState {
+        RuntimeState::new(16, CellWidth::Bits8, EofBehavior::Zero)
+    }
+
+    #[test]
+    fn test_interpreter_step() {
+        let instrs = IntermediateInstruction::parse_instrs(b"++[->+<]").unwrap();
+        let mut interpreter = Interpreter::new(&instrs, new_state(), MemoryIo::default());
+        let start = interpreter.state().ptr;
+
+        assert_eq!(interpreter.run_for(2).unwrap(), 2);
+        assert_eq!(interpreter.state().cell(start), 2);
+        assert_eq!(interpreter.depth(), 0);
+
+        // Entering the loop is one instruction
+        assert!(interpreter.step().unwrap());
+        assert_eq!(interpreter.depth(), 1);
+        assert!(matches!(
+            interpreter.current(),
+            Some(IntermediateInstruction::Add(0, -1, _))
+        ));
+
+        // Two more runs through the body
+        assert_eq!(interpreter.run_for(100).unwrap(), 8);
+        assert!(interpreter.finished());
+        assert!(!interpreter.step().unwrap());
+        assert_eq!(interpreter.state().cell(start), 0);
+        assert_eq!(interpreter.state().cell(start + 1), 2);
+    }
+
+    #[test]
+    fn test_interpreter_run_until() {
+        let instrs = IntermediateInstruction::parse_instrs(b"+++[>+++<-]>[-]").unwrap();
+        let instrs = optimize(instrs, OptimizerOptions::new().coalesce(true));
+        let mut interpreter = Interpreter::new(&instrs, new_state(), MemoryIo::default());
+        let start = interpreter.state().ptr;
+
+        assert!(interpreter
+            .run_until(|interpreter| interpreter.state().cell(start + 1) == 6)
+            .unwrap());
+        assert_eq!(interpreter.state().cell(start), 2);
+
+        assert!(!interpreter.run_until(|_| false).unwrap());
+        assert!(interpreter.finished());
+        assert_eq!(interpreter.state().cell(start + 1), 0);
+        assert_eq!(interpreter.state().ptr, start + 1);
+    }
+
+    #[test]
+    fn test_interpreter_empty_program() {
+        let mut interpreter = Interpreter::new(&[], new_state(), MemoryIo::default());
+        assert!(interpreter.finished());
+        assert!(interpreter.current().is_none());
+        assert_eq!(interpreter.run_for(10).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_interpreter_io() {
+        // Echoes the input with every byte incremented
+        let instrs = IntermediateInstruction::parse_instrs(b",[+.,]").unwrap();
+        let mut io = MemoryIo::new(b"HAL");
+        Interpreter::new(&instrs, new_state(), &mut io)
+            .run()
+            .unwrap();
+        assert_eq!(io.output, b"IBM");
+        assert!(io.input.is_empty());
+    }
+
+    #[test]
+    fn test_interpreter_offsets() {
+        // Copies the input to the cell two to the right, and prints it from there
+        let src = b">>>+<<<,[->>+<<]>>.>.";
+        let run = |offsets: bool| {
+            let instrs = IntermediateInstruction::parse_instrs(src).unwrap();
+            let instrs = optimize(
+                instrs,
+                OptimizerOptions::new().coalesce(true).offsets(offsets),
+            );
+            let mut io = MemoryIo::new(b"A");
+            let mut interpreter = Interpreter::new(&instrs, new_state(), &mut io);
+            interpreter.run_until(|_| false).unwrap();
+            let ptr = interpreter.state().ptr;
+            (ptr, io.output)
+        };
+        assert_eq!(run(true), run(false));
+        assert_eq!(run(true).1, b"A\x01");
+    }
+
+    #[test]
+    fn test_interpreter_simple_loops() {
+        // Multiplies the input by the inverse of -3, sets a cell to 2, then runs a loop once
+        let src = b",[--->+>[-]++<<]>.>.+[[-]>>+<<]>>.";
+        let run = |simple_loops: bool| {
+            let instrs = IntermediateInstruction::parse_instrs(src).unwrap();
+            let instrs = optimize(
+                instrs,
+                OptimizerOptions::new()
+                    .coalesce(true)
+                    .simple_loops(simple_loops),
+            );
+            let mut io = MemoryIo::new(b"A");
+            Interpreter::new(&instrs, new_state(), &mut io)
+                .run()
+                .unwrap();
+            io.output
+        };
+        assert_eq!(run(true), run(false));
+        assert_eq!(run(true), [107, 2, 1]);
+    }
+
+    #[test]
+    fn test_scan_matches_naive() {
+        let mut memory = vec![1u8; 256];
+        for zero in [3, 17, 64, 100, 101, 130, 200, 231, 252] {
+            memory[zero] = 0;
+        }
+
+        for stride in [-4, -2, -1, 1, 2, 4] {
+            for start in 0..memory.len() {
+                // Scan one cell at a time, for comparison
+                let mut ptr = start as isize;
+                let found = loop {
+                    if ptr < 0 || ptr as usize >= memory.len() {
+                        break None;
+                    }
+                    if memory[ptr as usize] == 0 {
+                        break Some(ptr as usize);
+                    }
+                    ptr += stride as isize;
+                };
+                if let Some(expected) = found {
+                    assert_eq!(
+                        expected,
+                        scan(&memory, start, stride),
+                        "stride {} from {}",
+                        stride,
+                        start
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_scan_zero_at_start() {
+        let memory = vec![0u8; 64];
+        for stride in [-4, -2, -1, 1, 2, 4] {
+            assert_eq!(32, scan(&memory, 32, stride));
+        }
+    }
+
+    #[test]
+    fn test_scan_other_stride() {
+        let mut memory = vec![1u8; 64];
+        memory[5] = 0;
+        memory[29] = 0;
+        assert_eq!(29, scan(&memory, 8, 3));
+        assert_eq!(29, scan(&memory, 50, -3));
+        assert_eq!(5, scan(&memory, 26, -3));
+    }
 }
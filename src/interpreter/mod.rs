@@ -2,6 +2,9 @@
 //!
 //! Author: Cayden Lund (cayden.lund@utah.edu)
 
+mod debugger;
+pub use debugger::*;
+
 mod interpret;
 pub use interpret::*;
 
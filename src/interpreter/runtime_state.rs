@@ -2,26 +2,201 @@
 //!
 //! Author: Cayden Lund (cayden.lund@utah.edu)
 
+use crate::{BFError, BFResult, CellWidth, EofBehavior};
+
 /// The current state of the interpreter at runtime
 #[derive(Debug)]
 pub struct RuntimeState {
     /// The instruction pointer, as an index into an array of instructions
     pub instr: usize,
 
-    /// The array of memory
+    /// The array of memory, with each cell stored in little-endian order
     pub memory: Vec<u8>,
 
-    /// The memory pointer, as an index into `self.memory`
+    /// The memory pointer, as a cell index into `self.memory`
     pub ptr: usize,
+
+    /// The width of each cell in `self.memory`
+    pub cell_width: CellWidth,
+
+    /// What reading at the end of the input does to the current cell
+    pub eof_behavior: EofBehavior,
+
+    /// Whether the tape grows when a bounds check fails, instead of raising an error
+    pub growable: bool,
 }
 
 impl RuntimeState {
     /// Initializes a new [`RuntimeState`] object
-    pub fn new(mem_size: usize) -> Self {
+    pub fn new(mem_size: usize, cell_width: CellWidth, eof_behavior: EofBehavior) -> Self {
         Self {
             instr: 0,
-            memory: vec![0; mem_size],
+            memory: vec![0; mem_size * cell_width.bytes()],
             ptr: mem_size / 2,
+            cell_width,
+            eof_behavior,
+            growable: false,
+        }
+    }
+
+    /// Returns the value of the cell at the given index
+    pub fn cell(&self, index: usize) -> u32 {
+        match self.cell_width {
+            CellWidth::Bits8 => self.memory[index] as u32,
+            CellWidth::Bits16 => {
+                u16::from_le_bytes([self.memory[2 * index], self.memory[2 * index + 1]]) as u32
+            }
+            CellWidth::Bits32 => {
+                u32::from_le_bytes(self.memory[4 * index..4 * index + 4].try_into().unwrap())
+            }
+        }
+    }
+
+    /// Sets the cell at the given index to the given value, wrapped to the cell width
+    pub fn set_cell(&mut self, index: usize, value: u32) {
+        match self.cell_width {
+            CellWidth::Bits8 => self.memory[index] = value as u8,
+            CellWidth::Bits16 => {
+                self.memory[2 * index..2 * index + 2].copy_from_slice(&(value as u16).to_le_bytes())
+            }
+            CellWidth::Bits32 => {
+                self.memory[4 * index..4 * index + 4].copy_from_slice(&value.to_le_bytes())
+            }
+        }
+    }
+
+    /// Stores the given result of `getchar` (a byte, or -1 at the end of the input)
+    /// in the current cell, according to `self.eof_behavior`
+    pub fn read_cell(&mut self, ch: i32) {
+        self.read_cell_at(self.ptr, ch);
+    }
+
+    /// Stores the given result of `getchar` in the cell at the given index,
+    /// according to `self.eof_behavior`
+    pub fn read_cell_at(&mut self, index: usize, ch: i32) {
+        let value = self.eof_behavior.apply(ch, self.cell(index));
+        self.set_cell(index, value);
+    }
+
+    /// Adds the given value to the cell at the given index, wrapping around
+    pub fn add_cell(&mut self, index: usize, value: u32) {
+        self.set_cell(index, self.cell(index).wrapping_add(value));
+    }
+
+    /// Checks that the cell at the given offset from the memory pointer is on the tape,
+    /// where the offset was reached by the instruction at the given position
+    ///
+    /// If the tape is growable, it grows to include the cell instead
+    pub fn check_bounds(&mut self, offset: i32, position: usize) -> BFResult<()> {
+        let ptr = self.ptr as isize + offset as isize;
+        let cells = self.memory.len() / self.cell_width.bytes();
+        if ptr >= 0 && (ptr as usize) < cells {
+            return Ok(());
         }
+        if !self.growable {
+            return Err(BFError::TapeOverflow { position, ptr });
+        }
+
+        // The tape at least doubles, so that a wandering program grows it only a few times
+        let bytes = self.cell_width.bytes();
+        if ptr < 0 {
+            let grow_by = cells.max(ptr.unsigned_abs());
+            self.memory
+                .splice(0..0, std::iter::repeat_n(0, grow_by * bytes));
+            self.ptr += grow_by;
+        } else {
+            let grow_by = cells.max(ptr as usize + 1 - cells);
+            self.memory.resize((cells + grow_by) * bytes, 0);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RuntimeState;
+    use crate::{BFError, CellWidth, EofBehavior};
+
+    #[test]
+    fn test_cells_wrap_at_width() {
+        for (cell_width, max) in [
+            (CellWidth::Bits8, 0xFF),
+            (CellWidth::Bits16, 0xFFFF),
+            (CellWidth::Bits32, 0xFFFF_FFFF),
+        ] {
+            let mut state = RuntimeState::new(4, cell_width, EofBehavior::default());
+            state.add_cell(1, u32::MAX);
+            assert_eq!(state.cell(1), max);
+            state.add_cell(1, 1);
+            assert_eq!(state.cell(1), 0);
+
+            // Neighbouring cells are untouched
+            state.set_cell(2, 0x1234_5678);
+            assert_eq!(state.cell(2), 0x1234_5678 & max);
+            assert_eq!(state.cell(1), 0);
+            assert_eq!(state.cell(3), 0);
+        }
+    }
+
+    #[test]
+    fn test_read_at_eof() {
+        for (eof_behavior, expected) in [
+            (EofBehavior::Unchanged, 7),
+            (EofBehavior::Zero, 0),
+            (EofBehavior::MinusOne, 0xFFFF),
+        ] {
+            let mut state = RuntimeState::new(4, CellWidth::Bits16, eof_behavior);
+            state.set_cell(state.ptr, 7);
+            state.read_cell(-1);
+            assert_eq!(state.cell(state.ptr), expected);
+            state.read_cell(b'a' as i32);
+            assert_eq!(state.cell(state.ptr), b'a' as u32);
+        }
+    }
+
+    #[test]
+    fn test_check_bounds() {
+        let mut state = RuntimeState::new(4, CellWidth::Bits8, EofBehavior::default());
+        state.ptr = 1;
+        assert!(state.check_bounds(-1, 0).is_ok());
+        assert!(state.check_bounds(2, 0).is_ok());
+        assert!(matches!(
+            state.check_bounds(3, 5),
+            Err(BFError::TapeOverflow {
+                position: 5,
+                ptr: 4
+            })
+        ));
+        assert!(matches!(
+            state.check_bounds(-2, 7),
+            Err(BFError::TapeOverflow {
+                position: 7,
+                ptr: -1
+            })
+        ));
+    }
+
+    #[test]
+    fn test_check_bounds_growable() {
+        let mut state = RuntimeState {
+            growable: true,
+            ..RuntimeState::new(4, CellWidth::Bits16, EofBehavior::default())
+        };
+        state.ptr = 1;
+        state.set_cell(1, 0x1234);
+
+        // Growing to the right keeps the memory pointer
+        assert!(state.check_bounds(5, 0).is_ok());
+        assert_eq!(state.memory.len(), 16);
+        assert_eq!(state.ptr, 1);
+
+        // Growing to the left moves the memory pointer along with its cell
+        assert!(state.check_bounds(-11, 0).is_ok());
+        assert_eq!(state.memory.len(), 36);
+        assert_eq!(state.ptr, 11);
+        assert_eq!(state.cell(state.ptr), 0x1234);
+        assert!(state.check_bounds(-11, 0).is_ok());
+        assert!(state.check_bounds(6, 0).is_ok());
+        assert_eq!(state.memory.len(), 36);
     }
 }
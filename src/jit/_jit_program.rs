@@ -1,14 +1,58 @@
 //! Driver for compiling and running a BF program just-in-time
 
-use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register};
+use crate::assembly::amd64::{AMD64Instruction, AMD64Operand, AMD64Register, Function, MemorySize};
+use crate::generator::amd64::{generate_buffered_putchar, OutputBuffer, OUTPUT_BUFFER_BYTES};
 use crate::instruction::IntermediateInstruction;
-use crate::jit::JitMem;
-use crate::BFResult;
+use crate::jit::{grow_tape, JitMem, JitTape, TapeState};
+use crate::{BFError, BFResult, BfIo, CellWidth, EofBehavior};
 use AMD64Instruction::*;
 use AMD64Operand::*;
 use AMD64Register::*;
 
-use std::mem;
+use std::{mem, ptr, slice};
+
+/// Writes out the bytes in the given output buffer, and empties it
+///
+/// The buffer starts with the number of buffered bytes, followed by the bytes
+fn drain(buffer: &mut [u64], io: &mut dyn BfIo) {
+    let len = buffer[0] as usize;
+    let bytes = unsafe { slice::from_raw_parts(buffer[1..].as_ptr() as *const u8, len) };
+    for &byte in bytes {
+        io.write_byte(byte);
+    }
+    buffer[0] = 0;
+}
+
+/// Writes out the output buffer at the given address for JIT-compiled code, to the
+/// `&mut dyn BfIo` at the given address
+extern "C" fn flush_output(io: *mut libc::c_void, buffer: *mut u64) {
+    let io = unsafe { &mut *(io as *mut &mut dyn BfIo) };
+    drain(
+        unsafe { slice::from_raw_parts_mut(buffer, OUTPUT_BUFFER_BYTES / 8) },
+        &mut **io,
+    );
+}
+
+/// Reads a byte of input for JIT-compiled code, from the `&mut dyn BfIo` at the given address,
+/// after writing out the output buffer at the given address
+///
+/// Returns the byte, or -1 at the end of the input, like `getchar`
+extern "C" fn read_byte(io: *mut libc::c_void, buffer: *mut u64) -> i32 {
+    flush_output(io, buffer);
+    let io = unsafe { &mut *(io as *mut &mut dyn BfIo) };
+    io.read_byte().map_or(-1, i32::from)
+}
+
+/// Writes the low byte of the given value for JIT-compiled code, to the `&mut dyn BfIo` at the
+/// given address
+extern "C" fn write_byte(io: *mut libc::c_void, value: u32) {
+    let io = unsafe { &mut *(io as *mut &mut dyn BfIo) };
+    io.write_byte(value as u8);
+}
+
+/// The signature of the function that JIT-compiled code is run through
+type JitFn =
+    extern "C" fn(*mut libc::c_void, *mut libc::c_void, usize, *mut TapeState) -> *mut libc::c_void;
 
 /// A JIT-compiled program
 pub struct JitProgram {
@@ -17,54 +61,296 @@ pub struct JitProgram {
 
     /// A callable function pointer
     ///
-    /// The argument is a pointer to a pointer to the current location in the memory tape.
-    /// This makes the memory tape location preservable across function calls.
-    fn_ptr: fn(*mut libc::c_void) -> *mut libc::c_void,
+    /// The arguments are a pointer to the current location in the memory tape, a pointer to the
+    /// start of the tape, the size of the tape in bytes, and the state of a growable tape (or
+    /// null). Returns the new location in the memory tape, which makes it preservable across
+    /// function calls, or null if a bounds check failed.
+    fn_ptr: JitFn,
 
     /// Blocks of non-encoded assembly instructions
     instr_blocks: Vec<Vec<AMD64Instruction>>,
+
+    /// The address of the routine that handles a failed bounds check
+    tape_overflow: u64,
+
+    /// The address of the routine that grows a growable tape when a bounds check fails
+    grow_tape: u64,
+
+    /// The position and cell index of the most recent failed bounds check,
+    /// as written by the `tape_overflow` routine
+    overflow: Box<[i64; 2]>,
+
+    /// The address of the routine that JIT-compiled code calls instead of `getchar`
+    read_stub: u64,
+
+    /// The address of the routine that JIT-compiled code calls instead of `putchar`
+    write_stub: u64,
+
+    /// The address of the `&mut dyn BfIo` that the running program reads and writes through,
+    /// as read by the I/O routines
+    io: Box<*mut libc::c_void>,
+
+    /// The output buffer: the number of buffered bytes, followed by the bytes
+    output: Box<[u64]>,
+
+    /// The width of each memory cell
+    cell_width: CellWidth,
 }
 
 impl JitProgram {
-    /// Creates a new JIT-compiled program for the given instructions
-    pub fn new(instrs: &[IntermediateInstruction], num_pages: usize) -> BFResult<Self> {
+    /// Creates a new JIT-compiled program for the given instructions, cell width,
+    /// and EOF behavior
+    ///
+    /// If `buffered` is set, the program collects its output in a buffer, which is written out
+    /// when it is full, before reading input, and once the program stops. Otherwise, every byte
+    /// is written as soon as it is output.
+    pub fn new(
+        instrs: &[IntermediateInstruction],
+        cell_width: CellWidth,
+        eof_behavior: EofBehavior,
+        num_pages: usize,
+        buffered: bool,
+    ) -> BFResult<Self> {
         // Should be plenty of room
         let mut fn_mem = JitMem::new(num_pages);
 
+        // With a growable tape, a failed bounds check calls this routine, which grows the tape
+        // and reloads its address and size. The check is then repeated.
+        // If the tape can't grow, it falls through to the `tape_overflow` routine below
+        let tape_state =
+            |offset: i32| Memory(Some(MemorySize::QWord), Some(RBX), None, None, Some(offset));
+        let grow_stub = vec![
+            // Keep the arguments in case the tape can't grow, and keep the stack 16-byte aligned
+            Push(Register(RDI)),
+            Push(Register(RSI)),
+            Add(Register(RSP), Immediate(-8)),
+            Mov(Register(RDI), Register(RBX)),
+            Mov(Register(RDX), Immediate(cell_width.bytes() as isize)),
+            Call(Function::GrowTape),
+            Add(Register(RSP), Immediate(8)),
+            Pop(Register(RSI)),
+            Pop(Register(RDI)),
+            Mov(Register(R14), tape_state(0)),
+            Mov(Register(R15), tape_state(8)),
+            Test(Register(RAX), Register(RAX)),
+            Je(1, None),
+            Ret(),
+        ];
+
+        // A failed bounds check calls this routine, which saves its arguments
+        // and returns null from the function
+        let overflow = Box::new([0; 2]);
+        let overflow_record =
+            |offset: i32| Memory(Some(MemorySize::QWord), Some(R13), None, None, Some(offset));
+        let tape_overflow = vec![
+            Mov(Register(R13), Immediate(overflow.as_ptr() as isize)),
+            Mov(overflow_record(0), Register(RDI)),
+            Mov(overflow_record(8), Register(RSI)),
+            // Discard the return address of the call
+            Add(Register(RSP), Immediate(8)),
+            Xor(Register(EAX), Register(EAX)),
+            Pop(Register(R15)),
+            Pop(Register(R14)),
+            Pop(Register(R13)),
+            Pop(Register(R12)),
+            Pop(Register(RBX)),
+            Ret(),
+        ];
+
+        // Reads and writes call these routines, which pass the program's `BfIo` (and the output
+        // buffer) to the trampolines above. The value to write is already in `EDI`. With buffered
+        // output, writes go to the buffer instead, which calls the flush routine once it is full
+        let io: Box<*mut libc::c_void> = Box::new(ptr::null_mut());
+        let output = vec![0; OUTPUT_BUFFER_BYTES / 8].into_boxed_slice();
+        let buffer = output.as_ptr() as u64;
+        let call_trampoline = |func: Function| {
+            vec![
+                Mov(Register(RDI), Immediate(&*io as *const _ as isize)),
+                Mov(
+                    Register(RDI),
+                    Memory(Some(MemorySize::QWord), Some(RDI), None, None, Some(0)),
+                ),
+                Call(func),
+                Add(Register(RSP), Immediate(8)),
+                Ret(),
+            ]
+        };
+        let read_stub = [
+            vec![
+                Add(Register(RSP), Immediate(-8)),
+                Mov(Register(RSI), Immediate(buffer as isize)),
+            ],
+            call_trampoline(Function::GetChar),
+        ]
+        .concat();
+        let write_stub = if buffered {
+            generate_buffered_putchar(OutputBuffer::At(buffer))
+        } else {
+            [
+                vec![
+                    Add(Register(RSP), Immediate(-8)),
+                    Mov(Register(RSI), Register(RDI)),
+                ],
+                call_trampoline(Function::PutChar),
+            ]
+            .concat()
+        };
+        let flush_stub = [
+            vec![
+                Add(Register(RSP), Immediate(-8)),
+                Mov(Register(RSI), Immediate(buffer as isize)),
+            ],
+            call_trampoline(Function::Flush),
+        ]
+        .concat();
+
+        let stubs = [grow_stub, tape_overflow, read_stub, write_stub, flush_stub];
+        let mut stub_bytes = stubs
+            .iter()
+            .map(|stub| AMD64Instruction::encode_block(stub))
+            .collect::<BFResult<Vec<_>>>()?;
+        let mut addresses = vec![fn_mem.contents as u64];
+        for bytes in &stub_bytes {
+            addresses.push(addresses[addresses.len() - 1] + bytes.len() as u64);
+        }
+        for (index, (stub, bytes)) in stubs.iter().zip(&mut stub_bytes).enumerate() {
+            for (offset, func) in AMD64Instruction::call_sites(stub)? {
+                let address = match func {
+                    Function::GrowTape => grow_tape as *const () as u64,
+                    Function::GetChar => read_byte as *const () as u64,
+                    Function::PutChar => write_byte as *const () as u64,
+                    // The buffered `putchar` calls the flush routine, which calls the trampoline
+                    Function::Flush if index == 4 => flush_output as *const () as u64,
+                    Function::Flush => addresses[4],
+                    _ => continue,
+                };
+                AMD64Instruction::patch_call_absolute(bytes, offset, address);
+            }
+        }
+        fn_mem.extend(stub_bytes.concat().into_iter());
+
         let fn_prologue = AMD64Instruction::encode_block(&vec![
-            // Copy the given memory tape location (fn argument) into register R12
+            // Copy the given memory tape location (fn argument) into register R12,
+            // the tape's address and size into registers R14 and R15 for bounds checks,
+            // and the growable tape's state into register RBX.
+            // Saving five registers keeps the stack 16-byte aligned for calls
+            Push(Register(RBX)),
             Push(Register(R12)),
             Push(Register(R13)),
+            Push(Register(R14)),
+            Push(Register(R15)),
             Mov(Register(R12), Register(RDI)),
+            Mov(Register(R14), Register(RSI)),
+            Mov(Register(R15), Register(RDX)),
+            Mov(Register(RBX), Register(RCX)),
         ])?;
+        let fn_contents = unsafe { fn_mem.contents.add(fn_mem.position) };
         fn_mem.extend(fn_prologue.into_iter());
 
-        let fn_ptr = unsafe { mem::transmute(fn_mem.contents) };
-        let instr_blocks = AMD64Instruction::convert_instructions(instrs)
+        let fn_ptr = unsafe { mem::transmute::<*mut u8, JitFn>(fn_contents) };
+        let instr_blocks = AMD64Instruction::convert_instructions(instrs, cell_width, eof_behavior)
             .into_iter()
             .rev()
             .collect::<Vec<_>>();
 
         Ok(Self {
+            grow_tape: addresses[0],
+            tape_overflow: addresses[1],
+            read_stub: addresses[2],
+            write_stub: addresses[3],
+            io,
+            output,
             fn_mem,
             fn_ptr,
             instr_blocks,
+            overflow,
+            cell_width,
+        })
+    }
+
+    /// Runs this JIT-compiled program on the given memory tape, reading and writing through the
+    /// given [`BfIo`]
+    ///
+    /// The output is flushed once the program stops.
+    /// Returns a [`BFError::TapeOverflow`] if a bounds check fails
+    pub fn run(&mut self, memory: &mut [u8], io: &mut dyn BfIo) -> BFResult<()> {
+        let tape_address = memory.as_mut_ptr() as *mut libc::c_void;
+        let tape_size = memory.len();
+        let cell_bytes = self.cell_width.bytes();
+
+        // This starts at the center of the memory tape
+        let memory_ptr = unsafe { tape_address.add(tape_size / cell_bytes / 2 * cell_bytes) };
+
+        let result = self.run_blocks(
+            memory_ptr,
+            ptr::null_mut(),
+            || (tape_address, tape_size),
+            io,
+        );
+        drain(&mut self.output, io);
+        io.flush();
+        result
+    }
+
+    /// Runs this JIT-compiled program on the given growable memory tape, reading and writing
+    /// through the given [`BfIo`]
+    ///
+    /// The output is flushed once the program stops.
+    /// Returns a [`BFError::TapeOverflow`] if a bounds check fails and the tape can't grow,
+    /// with the cell index counted from the start of the tape's initial window
+    pub fn run_growable(&mut self, tape: &mut JitTape, io: &mut dyn BfIo) -> BFResult<()> {
+        let state: *mut TapeState = &mut *tape.state;
+        let window = || unsafe { ((*state).start as *mut libc::c_void, (*state).len) };
+        let cell_bytes = self.cell_width.bytes();
+
+        // This starts at the center of the initial window
+        let (tape_address, tape_size) = window();
+        let memory_ptr = unsafe { tape_address.add(tape_size / cell_bytes / 2 * cell_bytes) };
+
+        let result = self.run_blocks(memory_ptr, state, window, io);
+        drain(&mut self.output, io);
+        io.flush();
+        result.map_err(|err| match err {
+            BFError::TapeOverflow { position, ptr } => {
+                let grown = unsafe { (*state).origin.offset_from((*state).start) };
+                BFError::TapeOverflow {
+                    position,
+                    ptr: ptr - grown / cell_bytes as isize,
+                }
+            }
+            err => err,
         })
     }
 
-    /// Runs this JIT-compiled program
-    pub fn run(&mut self, memory_center: *mut libc::c_void) -> BFResult<()> {
-        // A mutable pointer to the current location in the memory tape.
-        // This starts at the center of the memory tape.
-        let mut memory_ptr = memory_center;
+    /// Encodes and runs each block of this program in turn, on the tape with the given window
+    ///
+    /// The window is read again before each block, since a growable tape may have moved
+    fn run_blocks(
+        &mut self,
+        mut memory_ptr: *mut libc::c_void,
+        state: *mut TapeState,
+        window: impl Fn() -> (*mut libc::c_void, usize),
+        mut io: &mut dyn BfIo,
+    ) -> BFResult<()> {
+        // The I/O routines find the program's `BfIo` through this, which lives until we return
+        *self.io = &mut io as *mut &mut dyn BfIo as *mut libc::c_void;
 
+        // A growable tape grows on a failed bounds check, instead of overflowing
+        let tape_overflow = if state.is_null() {
+            self.tape_overflow
+        } else {
+            self.grow_tape
+        };
         // This will be added to the end of the function every time a new block is encoded.
         // It returns the current memory tape pointer in register RAX and restores saved registers
         // before returning.
         let fn_epilogue = AMD64Instruction::encode_block(&vec![
             Mov(Register(RAX), Register(R12)),
+            Pop(Register(R15)),
+            Pop(Register(R14)),
             Pop(Register(R13)),
             Pop(Register(R12)),
+            Pop(Register(RBX)),
             Ret(),
         ])?;
 
@@ -86,7 +372,16 @@ impl JitProgram {
             }
 
             // JIT-compile the instructions in the new block
-            let bytes = AMD64Instruction::encode_block(&*block)?;
+            let mut bytes = AMD64Instruction::encode_block(&block)?;
+            for (offset, func) in AMD64Instruction::call_sites(&block)? {
+                let address = match func {
+                    Function::TapeOverflow => tape_overflow,
+                    Function::GetChar => self.read_stub,
+                    Function::PutChar => self.write_stub,
+                    _ => continue,
+                };
+                AMD64Instruction::patch_call_absolute(&mut bytes, offset, address);
+            }
 
             // Save the encoded instructions to the executable memory
             self.fn_mem.position = next_block_position;
@@ -96,9 +391,109 @@ impl JitProgram {
             self.fn_mem.extend(fn_epilogue.clone().into_iter());
 
             // Finally, we can just call this as an FFI function
-            memory_ptr = (self.fn_ptr)(memory_ptr);
+            let (tape_address, tape_size) = window();
+            memory_ptr = (self.fn_ptr)(memory_ptr, tape_address, tape_size, state);
+            if memory_ptr.is_null() {
+                return Err(BFError::TapeOverflow {
+                    position: self.overflow[0] as usize,
+                    ptr: self.overflow[1] as isize,
+                });
+            }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::JitProgram;
+    use crate::instruction::IntermediateInstruction;
+    use crate::jit::JitTape;
+    use crate::optimizer::{optimize, OptimizerOptions};
+    use crate::{CellWidth, EofBehavior, MemoryIo};
+
+    /// Runs the given program on the given input, and returns its output
+    fn run(
+        src: &[u8],
+        input: &[u8],
+        cell_width: CellWidth,
+        growable: bool,
+        buffered: bool,
+    ) -> Vec<u8> {
+        let instrs = IntermediateInstruction::parse_instrs(src).unwrap();
+        let instrs = optimize(instrs, OptimizerOptions::new().coalesce(true));
+        let mut program =
+            JitProgram::new(&instrs, cell_width, EofBehavior::Zero, 16, buffered).unwrap();
+        let mut io = MemoryIo::new(input);
+        let tape_size = 64 * cell_width.bytes();
+        if growable {
+            let mut tape = JitTape::new(tape_size, JitTape::DEFAULT_RESERVED).unwrap();
+            program.run_growable(&mut tape, &mut io).unwrap();
+        } else {
+            program.run(&mut vec![0; tape_size], &mut io).unwrap();
+        }
+        io.output
+    }
+
+    #[test]
+    fn test_io() {
+        // Echoes the input with every byte incremented
+        for cell_width in [CellWidth::Bits8, CellWidth::Bits16, CellWidth::Bits32] {
+            for buffered in [false, true] {
+                assert_eq!(run(b",[+.,]", b"HAL", cell_width, false, buffered), b"IBM");
+            }
+        }
+        assert_eq!(run(b",[+.,]", b"HAL", CellWidth::Bits8, true, true), b"IBM");
+    }
+
+    #[test]
+    fn test_buffered_output() {
+        // Writes the bytes 1, 2, 3, ... 5000 times, which is more than the output buffer holds
+        let src = b"++++++++++[>++++++++++[>+++++ +++++ +++++ +++++ +++++ +++++ +++++ +++++ +++++ +++++[>+.<-]<-]<-]";
+        let expected: Vec<u8> = (1..=5000).map(|i| i as u8).collect();
+        for buffered in [false, true] {
+            assert_eq!(run(src, b"", CellWidth::Bits8, false, buffered), expected);
+        }
+    }
+
+    #[test]
+    fn test_backward_scans() {
+        // Every cell is nonzero except for the ones at offsets -1, -3 and -4 from the start, so
+        // a scan of stride -2 or -4 has to skip the odd offsets and stop at offset -4
+        for (stride, src) in [(-2, &b"[<<]+++++++"[..]), (-4, b"[<<<<]+++++++")] {
+            let instrs = IntermediateInstruction::parse_instrs(src).unwrap();
+            let instrs = optimize(instrs, OptimizerOptions::new().coalesce(true).scans(true));
+            let mut program =
+                JitProgram::new(&instrs, CellWidth::Bits8, EofBehavior::Zero, 16, false).unwrap();
+            let mut memory = vec![1; 256];
+            let center = memory.len() / 2;
+            for offset in [1, 3, 4] {
+                memory[center - offset] = 0;
+            }
+            program.run(&mut memory, &mut MemoryIo::new(b"")).unwrap();
+            assert_eq!(memory[center - 4], 7, "stride {}", stride);
+            assert_eq!(memory[center - 3], 0, "stride {}", stride);
+            assert_eq!(memory[center - 1], 0, "stride {}", stride);
+        }
+    }
+
+    #[test]
+    fn test_scans_of_any_stride() {
+        // Strides that don't line up with the vector lanes are scanned one cell at a time
+        for stride in [3, -3, 5, 8, -8] {
+            let src = format!(
+                "set 1 set @{:+} 1 scan {:+} add 65 write move {:+} write",
+                stride,
+                stride,
+                -2 * stride
+            );
+            let instrs = IntermediateInstruction::parse_ir(src.as_bytes()).unwrap();
+            let mut program =
+                JitProgram::new(&instrs, CellWidth::Bits8, EofBehavior::Zero, 16, false).unwrap();
+            let mut io = MemoryIo::new(b"");
+            program.run(&mut vec![0; 64], &mut io).unwrap();
+            assert_eq!(io.output, b"A\x01", "stride {}", stride);
+        }
+    }
+}
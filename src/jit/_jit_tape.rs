@@ -0,0 +1,159 @@
+//! A memory tape for JIT-compiled programs that grows on demand
+
+use crate::jit::PAGE_SIZE;
+use crate::{BFError, BFResult};
+use std::ptr;
+
+/// The state of a [`JitTape`], as read by JIT-compiled code
+///
+/// JIT-compiled code reloads `start` and `len` after growing the tape,
+/// so they must stay the first two fields
+#[repr(C)]
+pub(crate) struct TapeState {
+    /// The start of the accessible window of the tape
+    pub(crate) start: *mut u8,
+
+    /// The size of the accessible window of the tape, in bytes
+    pub(crate) len: usize,
+
+    /// The start of the reserved region
+    base: *mut u8,
+
+    /// The size of the reserved region, in bytes
+    reserved: usize,
+
+    /// The start of the initial window, from which cell indices are reported
+    pub(crate) origin: *mut u8,
+}
+
+impl TapeState {
+    /// Makes the window with the given start and size accessible
+    fn resize(&mut self, start: *mut u8, len: usize) -> bool {
+        let result = unsafe {
+            libc::mprotect(
+                start as *mut libc::c_void,
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+            )
+        };
+        if result != 0 {
+            return false;
+        }
+        self.start = start;
+        self.len = len;
+        true
+    }
+
+    /// Grows the window until it includes the byte at the given offset from its start
+    ///
+    /// Returns whether the window could grow that far
+    pub fn grow(&mut self, offset: isize) -> bool {
+        let base = self.base as usize;
+        let limit = base + self.reserved;
+        let mut start = self.start as usize;
+        let mut end = start + self.len;
+        let target = start as isize + offset;
+
+        while target < start as isize || target >= end as isize {
+            let len = end - start;
+            if target < start as isize {
+                if start == base {
+                    return false;
+                }
+                start = start.saturating_sub(len).max(base);
+            } else {
+                if end == limit {
+                    return false;
+                }
+                end = (end + len).min(limit);
+            }
+        }
+
+        self.resize(start as *mut u8, end - start)
+    }
+}
+
+/// A memory tape that grows on demand
+///
+/// The tape reserves a large region of address space, of which only a window around the center
+/// is accessible. The rest of the region is left inaccessible, so a stray access faults instead
+/// of corrupting other memory. When a bounds check fails, the window doubles in the direction of
+/// the failing cell, until the region is exhausted.
+pub struct JitTape {
+    /// The tape's state, which is kept at a fixed address for JIT-compiled code
+    pub(crate) state: Box<TapeState>,
+}
+
+impl JitTape {
+    /// The default size of the reserved region: 4 GiB
+    pub const DEFAULT_RESERVED: usize = 1 << 32;
+
+    /// Reserves a region of the given size, in bytes, with an accessible window of the given
+    /// initial size at its center
+    ///
+    /// Both sizes are rounded up to a whole number of pages
+    pub fn new(size: usize, reserved: usize) -> BFResult<Self> {
+        let round = |bytes: usize| bytes.div_ceil(PAGE_SIZE).max(1) * PAGE_SIZE;
+        let size = round(size);
+        let reserved = round(reserved).max(size);
+
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                reserved,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(BFError::TapeAllocationError(reserved));
+        }
+        let base = base as *mut u8;
+
+        let start = unsafe { base.add((reserved - size) / 2 / PAGE_SIZE * PAGE_SIZE) };
+        let mut tape = Self {
+            state: Box::new(TapeState {
+                start,
+                len: 0,
+                base,
+                reserved,
+                origin: start,
+            }),
+        };
+        if !tape.state.resize(start, size) {
+            return Err(BFError::TapeAllocationError(size));
+        }
+        Ok(tape)
+    }
+
+    /// Grows the window until it includes the byte at the given offset from its start
+    ///
+    /// Returns whether the window could grow that far
+    pub fn grow(&mut self, offset: isize) -> bool {
+        self.state.grow(offset)
+    }
+
+    /// Returns the bytes of the accessible window
+    pub fn window(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.state.start, self.state.len) }
+    }
+}
+
+impl Drop for JitTape {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.state.base as *mut libc::c_void, self.state.reserved);
+        }
+    }
+}
+
+/// Grows the given tape to include the cell at the given index from the start of its window,
+/// for JIT-compiled code
+///
+/// Returns 1 if the tape grew, or 0 if it couldn't
+pub(crate) extern "C" fn grow_tape(state: *mut TapeState, index: isize, cell_bytes: usize) -> u64 {
+    let state = unsafe { &mut *state };
+    state.grow(index * cell_bytes as isize) as u64
+}
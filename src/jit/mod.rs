@@ -3,3 +3,6 @@ pub use _jit_mem::*;
 
 mod _jit_program;
 pub use _jit_program::*;
+
+mod _jit_tape;
+pub use _jit_tape::*;
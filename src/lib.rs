@@ -6,12 +6,23 @@
 
 mod _architecture;
 pub use _architecture::*;
+mod _bf_io;
+pub use _bf_io::*;
+mod _cell_width;
+pub use _cell_width::*;
+mod _diagnostic;
+pub use _diagnostic::*;
+mod _eof_behavior;
+pub use _eof_behavior::*;
 mod _error;
 pub use _error::*;
 mod _io;
 pub use _io::*;
+mod _source;
+pub use _source::*;
 
 pub mod assembly;
+pub mod elf;
 pub mod generator;
 pub mod instruction;
 pub mod interpreter;
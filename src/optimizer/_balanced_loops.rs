@@ -0,0 +1,195 @@
+//! Balanced loop analysis: classifies loops by how they move the data pointer
+
+use crate::instruction::IntermediateInstruction;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Display;
+
+/// How each iteration of a loop moves the data pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopBalance {
+    /// Every iteration leaves the data pointer where it started,
+    /// so each cell in the loop's body is at a fixed offset from the loop's cell
+    Balanced,
+
+    /// Some iteration may move the data pointer, by a fixed stride or one that depends on the tape
+    Unbalanced,
+}
+
+impl Display for LoopBalance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoopBalance::Balanced => write!(f, "balanced"),
+            LoopBalance::Unbalanced => write!(f, "unbalanced"),
+        }
+    }
+}
+
+/// Returns how far running the given instructions moves the data pointer,
+/// or `None` if that depends on the tape, as for scans and unbalanced loops
+pub fn net_move(instrs: &[IntermediateInstruction]) -> Option<i32> {
+    use IntermediateInstruction::*;
+
+    instrs.iter().try_fold(0, |total, instr| match instr {
+        Move(stride, _) => Some(total + stride),
+        Loop(_, body, _) | SimpleLoop(body, _) => {
+            (loop_balance(body) == LoopBalance::Balanced).then_some(total)
+        }
+        Scan(_, _) => None,
+        _ => Some(total),
+    })
+}
+
+/// Classifies the loop with the given body
+pub fn loop_balance(body: &[IntermediateInstruction]) -> LoopBalance {
+    match net_move(body) {
+        Some(0) => LoopBalance::Balanced,
+        _ => LoopBalance::Unbalanced,
+    }
+}
+
+/// Returns the offsets, from the data pointer before them, of the cells that running the given
+/// instructions may change, or `None` if that depends on the tape
+///
+/// Every cell that isn't returned holds the same value after the instructions as before them.
+/// In a balanced loop, those cells are invariant: they hold the same value in every iteration.
+pub fn changed_cells(instrs: &[IntermediateInstruction]) -> Option<HashSet<i32>> {
+    use IntermediateInstruction::*;
+
+    let mut cells = HashSet::new();
+    let mut ptr = 0;
+    for instr in instrs {
+        match instr {
+            Move(stride, _) => ptr += stride,
+            Loop(_, body, _) | SimpleLoop(body, _) => {
+                if loop_balance(body) != LoopBalance::Balanced {
+                    return None;
+                }
+                cells.extend(changed_cells(body)?.into_iter().map(|cell| ptr + cell));
+            }
+            Scan(_, _) => return None,
+            AddDynamic(target, _, _) => {
+                cells.insert(ptr + target);
+            }
+            Add(_, _, _) | Zero(_, _) | Set(_, _, _) | Read(_, _) => {
+                cells.insert(ptr + instr.cell_offset());
+            }
+            Write(_, _) | Output(_, _) | CheckBounds(_, _) => {}
+        }
+    }
+    Some(cells)
+}
+
+/// Returns whether running the given instructions may move the data pointer at any point,
+/// even if it ends up back where it started
+fn moves_pointer(instrs: &[IntermediateInstruction]) -> bool {
+    use IntermediateInstruction::*;
+
+    instrs.iter().any(|instr| match instr {
+        Move(_, _) | Scan(_, _) => true,
+        Loop(_, body, _) | SimpleLoop(body, _) => moves_pointer(body),
+        _ => false,
+    })
+}
+
+/// Returns the offsets of the cells that every iteration of the loop with the given body reads
+/// but never changes, so that they can be loaded once before the loop's first iteration
+///
+/// Only loops whose bodies never move the data pointer, as with offset addressing, and have no
+/// bounds checks load cells ahead of time. The cells that inner loops read are left to them,
+/// as those loops might not run.
+pub fn invariant_loads(body: &[IntermediateInstruction]) -> BTreeSet<i32> {
+    use IntermediateInstruction::*;
+
+    if moves_pointer(body) || IntermediateInstruction::has_bounds_checks(body) {
+        return BTreeSet::new();
+    }
+    let Some(changed) = changed_cells(body) else {
+        return BTreeSet::new();
+    };
+    body.iter()
+        .filter_map(|instr| match instr {
+            Loop(offset, _, _) | Write(offset, _) => Some(*offset),
+            SimpleLoop(_, _) | AddDynamic(_, _, _) => Some(0),
+            _ => None,
+        })
+        .filter(|offset| !changed.contains(offset))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changed_cells, invariant_loads, loop_balance, LoopBalance};
+    use crate::instruction::IntermediateInstruction;
+    use crate::optimizer::{apply_offsets, make_scans};
+
+    fn body(src: &[u8]) -> Vec<IntermediateInstruction> {
+        let instrs = IntermediateInstruction::parse_instrs(src).unwrap();
+        let [IntermediateInstruction::Loop(0, body, _)] = &instrs[..] else {
+            panic!("expected a loop, got {:?}", instrs);
+        };
+        body.clone()
+    }
+
+    #[test]
+    fn test_loop_balance() {
+        assert_eq!(loop_balance(&body(b"[>,<-]")), LoopBalance::Balanced);
+        assert_eq!(loop_balance(&body(b"[>[>.<-]<-]")), LoopBalance::Balanced);
+        assert_eq!(loop_balance(&body(b"[>,]")), LoopBalance::Unbalanced);
+
+        // An unbalanced inner loop might leave the data pointer anywhere
+        assert_eq!(loop_balance(&body(b"[>[>]<<]")), LoopBalance::Unbalanced);
+        let (instrs, _) = make_scans(IntermediateInstruction::parse_instrs(b"[>[>]<]").unwrap());
+        let [IntermediateInstruction::Loop(0, body, _)] = &instrs[..] else {
+            panic!("expected a loop, got {:?}", instrs);
+        };
+        assert_eq!(loop_balance(body), LoopBalance::Unbalanced);
+    }
+
+    #[test]
+    fn test_changed_cells() {
+        let mut cells = changed_cells(&body(b"[>+>[>,<-]<<-.]"))
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+        cells.sort();
+        assert_eq!(cells, vec![0, 1, 2, 3]);
+
+        // Offsets don't change which cells are changed
+        let (offset_body, _) = apply_offsets(body(b"[>+>[>,<-]<<-.]"));
+        assert_eq!(
+            changed_cells(&offset_body),
+            Some(cells.into_iter().collect())
+        );
+
+        assert_eq!(changed_cells(&body(b"[>[>+]<-]")), None);
+    }
+
+    #[test]
+    fn test_invariant_loads() {
+        let (instrs, _) =
+            apply_offsets(IntermediateInstruction::parse_instrs(b">,>,>>+<[<<.>.>>.+<-]").unwrap());
+        let [_, _, _, IntermediateInstruction::Loop(3, loop_body, _), _] = &instrs[..] else {
+            panic!("expected a loop at +3, got {:?}", instrs);
+        };
+        assert_eq!(
+            invariant_loads(loop_body).into_iter().collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        // A body that moves the data pointer, or checks bounds, loads every cell where it is
+        assert!(invariant_loads(&body(b"[>.<-]")).is_empty());
+        let (instrs, _) =
+            apply_offsets(IntermediateInstruction::parse_instrs_checked(b"+>+<[>.<-]").unwrap());
+        let Some(IntermediateInstruction::Loop(_, loop_body, _)) = instrs
+            .iter()
+            .find(|instr| matches!(instr, IntermediateInstruction::Loop(_, _, _)))
+        else {
+            panic!("expected a loop, got {:?}", instrs);
+        };
+        assert!(invariant_loads(loop_body).is_empty());
+
+        // Inner loops load the cells they read themselves, as they might not run
+        let (instrs, _) = apply_offsets(body(b"[>[>.<-]<-]"));
+        assert!(invariant_loads(&instrs).is_empty());
+    }
+}
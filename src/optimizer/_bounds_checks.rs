@@ -0,0 +1,183 @@
+//! Bounds check hoisting: merges the bounds checks in each run of moves into a single check
+
+use crate::instruction::IntermediateInstruction;
+use crate::Span;
+
+/// Adds a probe to the given probes, unless it's within the range already probed
+///
+/// The cell at the start of the run is on the tape, so the range always includes it.
+/// If a probe within the range is off the tape, then so is an earlier probe,
+/// which is reported first
+fn add_probe(probes: &mut Vec<(i32, usize)>, range: &mut (i32, i32), offset: i32, position: usize) {
+    if offset < range.0 || offset > range.1 {
+        probes.push((offset, position));
+        *range = (range.0.min(offset), range.1.max(offset));
+    }
+}
+
+/// Ends a run of instructions, inserting its merged check (if any) at the start of the run
+///
+/// The merged check covers the spans of the checks it replaces
+fn end_run(
+    new_instrs: &mut Vec<IntermediateInstruction>,
+    run_start: usize,
+    probes: &mut Vec<(i32, usize)>,
+    range: &mut (i32, i32),
+    span: &mut Option<Span>,
+) {
+    *range = (0, 0);
+    if let Some(span) = span.take() {
+        new_instrs.insert(
+            run_start,
+            IntermediateInstruction::CheckBounds(std::mem::take(probes), span),
+        );
+    }
+}
+
+/// Hoist bounds checks
+///
+/// A run of instructions without loops or I/O checks every cell it moves to with a single
+/// check at the start of the run. The moves in the run can then be coalesced, and a loop body
+/// without nested loops or I/O is checked once per iteration
+pub fn hoist_bounds_checks(
+    instrs: Vec<IntermediateInstruction>,
+) -> (Vec<IntermediateInstruction>, bool) {
+    let mut new_instrs = vec![];
+    let mut changed = false;
+
+    // The start of the current run in `new_instrs`, the pointer offset from the start of the
+    // run, and the probes of the run's check and the range they cover, relative to the start
+    // of the run, along with the span of the run's checks
+    let mut run_start = 0;
+    let mut offset = 0;
+    let mut probes = vec![];
+    let mut range = (0, 0);
+    let mut span: Option<Span> = None;
+
+    for instr in instrs {
+        match instr {
+            IntermediateInstruction::CheckBounds(check_probes, check_span) => {
+                if span.is_some() || new_instrs.len() > run_start {
+                    changed = true;
+                }
+                span = Some(span.map_or(check_span, |span| span.merge(check_span)));
+                for (probe_offset, position) in check_probes {
+                    add_probe(&mut probes, &mut range, offset + probe_offset, position);
+                }
+            }
+            IntermediateInstruction::Move(stride, _) => {
+                offset += stride;
+                new_instrs.push(instr);
+            }
+            IntermediateInstruction::Add(_, _, _)
+            | IntermediateInstruction::AddDynamic(_, _, _)
+            | IntermediateInstruction::Zero(_, _)
+            | IntermediateInstruction::Set(_, _, _)
+            | IntermediateInstruction::Output(_, _) => new_instrs.push(instr),
+            IntermediateInstruction::Loop(cell, sub_instrs, loop_span) => {
+                end_run(
+                    &mut new_instrs,
+                    run_start,
+                    &mut probes,
+                    &mut range,
+                    &mut span,
+                );
+                let (new_sub_instrs, new_changed) = hoist_bounds_checks(sub_instrs);
+                if new_changed {
+                    changed = true;
+                }
+                new_instrs.push(IntermediateInstruction::Loop(
+                    cell,
+                    new_sub_instrs,
+                    loop_span,
+                ));
+                run_start = new_instrs.len();
+                offset = 0;
+            }
+            IntermediateInstruction::SimpleLoop(sub_instrs, loop_span) => {
+                end_run(
+                    &mut new_instrs,
+                    run_start,
+                    &mut probes,
+                    &mut range,
+                    &mut span,
+                );
+                let (new_sub_instrs, new_changed) = hoist_bounds_checks(sub_instrs);
+                if new_changed {
+                    changed = true;
+                }
+                new_instrs.push(IntermediateInstruction::SimpleLoop(
+                    new_sub_instrs,
+                    loop_span,
+                ));
+                run_start = new_instrs.len();
+                offset = 0;
+            }
+            IntermediateInstruction::Read(_, _)
+            | IntermediateInstruction::Write(_, _)
+            | IntermediateInstruction::Scan(_, _) => {
+                end_run(
+                    &mut new_instrs,
+                    run_start,
+                    &mut probes,
+                    &mut range,
+                    &mut span,
+                );
+                new_instrs.push(instr);
+                run_start = new_instrs.len();
+                offset = 0;
+            }
+        }
+    }
+    end_run(
+        &mut new_instrs,
+        run_start,
+        &mut probes,
+        &mut range,
+        &mut span,
+    );
+
+    (new_instrs, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hoist_bounds_checks;
+    use crate::instruction::IntermediateInstruction;
+    use crate::instruction::IntermediateInstruction::*;
+    use crate::{SourceLocation, Span};
+
+    #[test]
+    fn test_hoist_bounds_checks() {
+        let src = b">>+<[>]<.";
+        let at = |offset: usize, len: usize| Span::new(SourceLocation::new(src, offset), len);
+        let (instrs, changed) =
+            hoist_bounds_checks(IntermediateInstruction::parse_instrs_checked(src).unwrap());
+        assert!(changed);
+        assert_eq!(
+            format!("{:?}", instrs),
+            format!(
+                "{:?}",
+                vec![
+                    CheckBounds(vec![(1, 0), (2, 1)], at(0, 4)),
+                    Move(1, at(0, 1)),
+                    Move(1, at(1, 1)),
+                    Add(0, 1, at(2, 1)),
+                    Move(-1, at(3, 1)),
+                    Loop(
+                        0,
+                        vec![CheckBounds(vec![(1, 5)], at(5, 1)), Move(1, at(5, 1))],
+                        at(4, 3)
+                    ),
+                    CheckBounds(vec![(-1, 7)], at(7, 1)),
+                    Move(-1, at(7, 1)),
+                    Write(0, at(8, 1)),
+                ]
+            )
+        );
+
+        // Hoisting is idempotent
+        let (_, changed) = hoist_bounds_checks(instrs);
+        assert!(!changed);
+    }
+}
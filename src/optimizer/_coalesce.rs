@@ -3,40 +3,52 @@
 use crate::instruction::IntermediateInstruction;
 
 /// Coalesce adjacent instructions
+///
+/// A coalesced instruction covers the spans of the instructions it replaces
 pub fn coalesce(instrs: Vec<IntermediateInstruction>) -> (Vec<IntermediateInstruction>, bool) {
     let mut new_instrs = vec![];
     let mut changed = false;
 
     for instr in instrs {
         match instr {
-            IntermediateInstruction::Loop(sub_instrs) => {
+            IntermediateInstruction::Loop(cell, sub_instrs, span) => {
                 let (new_sub_instrs, new_changed) = coalesce(sub_instrs);
                 if new_changed { changed = true; }
-                new_instrs.push(IntermediateInstruction::Loop(new_sub_instrs));
+                new_instrs.push(IntermediateInstruction::Loop(cell, new_sub_instrs, span));
             }
-            IntermediateInstruction::Move(new_offset) => {
+            IntermediateInstruction::Move(new_offset, new_span) => {
                 if new_instrs.is_empty() {
                     new_instrs.push(instr.clone());
                 } else {
                     let last_ind = new_instrs.len() - 1;
-                    if let IntermediateInstruction::Move(offset) = new_instrs[last_ind] {
-                        new_instrs[last_ind] = IntermediateInstruction::Move(offset + new_offset);
+                    // Moves whose total doesn't fit are left as they are
+                    let merged = match new_instrs[last_ind] {
+                        IntermediateInstruction::Move(offset, span) => offset
+                            .checked_add(new_offset)
+                            .map(|offset| IntermediateInstruction::Move(offset, span.merge(new_span))),
+                        _ => None,
+                    };
+                    if let Some(merged) = merged {
+                        new_instrs[last_ind] = merged;
                         changed = true;
                     } else {
                         new_instrs.push(instr.clone());
                     }
                 }
             }
-            IntermediateInstruction::Add(new_offset) => {
+            IntermediateInstruction::Add(new_cell, new_offset, new_span) => {
                 if new_instrs.is_empty() {
                     new_instrs.push(instr.clone());
                 } else {
                     let last_ind = new_instrs.len() - 1;
-                    if let IntermediateInstruction::Add(offset) = new_instrs[last_ind] {
-                        new_instrs[last_ind] = IntermediateInstruction::Add(offset + new_offset);
-                        changed = true;
-                    } else {
-                        new_instrs.push(instr.clone());
+                    // Only additions to the same cell are combined
+                    match new_instrs[last_ind] {
+                        IntermediateInstruction::Add(cell, offset, span) if cell == new_cell => {
+                            new_instrs[last_ind] =
+                                IntermediateInstruction::Add(cell, offset.wrapping_add(new_offset), span.merge(new_span));
+                            changed = true;
+                        }
+                        _ => new_instrs.push(instr.clone()),
                     }
                 }
             }
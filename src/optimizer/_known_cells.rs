@@ -0,0 +1,424 @@
+//! Known cells optimization: tracks the values of cells to remove instructions that do nothing
+
+use super::{changed_cells, loop_balance, LoopBalance};
+use crate::instruction::IntermediateInstruction;
+use crate::Span;
+use std::collections::HashMap;
+
+/// What is known about the cells at some point in the program
+///
+/// Values are known modulo 2^32, so a cell that is known to be 0 is 0 whatever the cell width,
+/// but one that is known to be 256 may still be 0 in an 8-bit cell
+#[derive(Clone, Debug)]
+struct KnownCells {
+    /// The value of each cell that has one recorded (`None` if it is unknown),
+    /// by the cell's offset from where the data pointer started
+    values: HashMap<i32, Option<i32>>,
+
+    /// Whether every cell without a recorded value is known to be 0
+    zeroed: bool,
+
+    /// The offset of the data pointer from where it started
+    ptr: i32,
+}
+
+impl KnownCells {
+    /// Nothing is known about any cell
+    fn unknown() -> Self {
+        Self {
+            values: HashMap::new(),
+            zeroed: false,
+            ptr: 0,
+        }
+    }
+
+    /// Every cell is known to be 0, as at the start of the program
+    fn zeroed() -> Self {
+        Self {
+            zeroed: true,
+            ..Self::unknown()
+        }
+    }
+
+    /// Only the current cell is known to be 0, as after a loop or a scan
+    fn current_zero() -> Self {
+        let mut known = Self::unknown();
+        known.set(0, Some(0));
+        known
+    }
+
+    /// Returns the value of the cell at the given offset from the data pointer, if it is known
+    fn get(&self, offset: i32) -> Option<i32> {
+        match self.values.get(&(self.ptr + offset)) {
+            Some(value) => *value,
+            None => self.zeroed.then_some(0),
+        }
+    }
+
+    /// Records the value of the cell at the given offset from the data pointer
+    fn set(&mut self, offset: i32, value: Option<i32>) {
+        self.values.insert(self.ptr + offset, value);
+    }
+
+    /// Returns what is known on both of two paths through the program
+    fn join(&self, other: &Self) -> Self {
+        if self.ptr != other.ptr {
+            return Self::unknown();
+        }
+        let mut joined = Self {
+            zeroed: self.zeroed && other.zeroed,
+            ..Self::unknown()
+        };
+        joined.ptr = self.ptr;
+        for cell in self.values.keys().chain(other.values.keys()) {
+            let offset = cell - self.ptr;
+            let value = self
+                .get(offset)
+                .filter(|value| other.get(offset) == Some(*value));
+            joined.set(offset, value);
+        }
+        joined
+    }
+}
+
+/// Returns an instruction that sets the cell at the given offset to the given value
+fn assignment(offset: i32, value: i32, span: Span) -> IntermediateInstruction {
+    match value {
+        0 => IntermediateInstruction::Zero(offset, span),
+        _ => IntermediateInstruction::Set(offset, value, span),
+    }
+}
+
+/// Returns the last instruction, if it only changes the cell at the given offset without reading
+/// any other cell
+fn last_change(
+    new_instrs: &[IntermediateInstruction],
+    offset: i32,
+) -> Option<&IntermediateInstruction> {
+    new_instrs.last().filter(|instr| {
+        matches!(
+            instr,
+            IntermediateInstruction::Add(_, _, _)
+                | IntermediateInstruction::Zero(_, _)
+                | IntermediateInstruction::Set(_, _, _)
+        ) && instr.cell_offset() == offset
+    })
+}
+
+/// Adds the given delta to the cell at the given offset
+///
+/// If the cell was just set to a known value, the assignment takes the sum instead
+fn add(
+    new_instrs: &mut Vec<IntermediateInstruction>,
+    known: &mut KnownCells,
+    offset: i32,
+    delta: i32,
+    span: Span,
+    changed: &mut bool,
+) {
+    if delta == 0 {
+        *changed = true;
+        return;
+    }
+    let value = known.get(offset).map(|value| value.wrapping_add(delta));
+    known.set(offset, value);
+
+    let assignment_span = last_change(new_instrs, offset)
+        .filter(|instr| !matches!(instr, IntermediateInstruction::Add(_, _, _)))
+        .map(|instr| instr.span().merge(span));
+    match (value, assignment_span) {
+        (Some(value), Some(span)) => {
+            new_instrs.pop();
+            new_instrs.push(assignment(offset, value, span));
+            *changed = true;
+        }
+        _ => new_instrs.push(IntermediateInstruction::Add(offset, delta, span)),
+    }
+}
+
+/// Sets the cell at the given offset to the given value, unless it already holds that value
+///
+/// A change to the cell just before this one is overwritten, so it is removed
+fn assign(
+    new_instrs: &mut Vec<IntermediateInstruction>,
+    known: &mut KnownCells,
+    offset: i32,
+    value: i32,
+    span: Span,
+    changed: &mut bool,
+) {
+    if known.get(offset) == Some(value) {
+        *changed = true;
+        return;
+    }
+    known.set(offset, Some(value));
+
+    if last_change(new_instrs, offset).is_some() {
+        new_instrs.pop();
+        *changed = true;
+    }
+    new_instrs.push(assignment(offset, value, span));
+}
+
+/// Apply known cells to the given instructions, starting with what is known about the cells
+///
+/// Returns the new instructions, what is known about the cells after them, and whether any
+/// instruction changed
+fn fold_block(
+    instrs: Vec<IntermediateInstruction>,
+    mut known: KnownCells,
+) -> (Vec<IntermediateInstruction>, KnownCells, bool) {
+    use IntermediateInstruction::*;
+
+    let mut new_instrs = vec![];
+    let mut changed = false;
+
+    for instr in instrs {
+        let offset = instr.cell_offset();
+        match instr {
+            // A loop over a cell that is known to be 0 never runs
+            Loop(_, _, _) | SimpleLoop(_, _) if known.get(offset) == Some(0) => changed = true,
+            // The cells that a balanced loop never changes hold the same values in every iteration
+            // and after the loop, while nothing is known about the cells an unbalanced loop visits
+            Loop(_, body, span) => {
+                let invariant = match changed_cells(&body) {
+                    Some(cells) if loop_balance(&body) == LoopBalance::Balanced => {
+                        let mut invariant = known;
+                        for cell in cells.into_iter().chain([offset]) {
+                            invariant.set(cell, None);
+                        }
+                        invariant
+                    }
+                    _ => KnownCells::unknown(),
+                };
+                let (new_body, _, new_changed) = fold_block(body, invariant.clone());
+                if new_changed {
+                    changed = true;
+                }
+                new_instrs.push(Loop(offset, new_body, span));
+                known = invariant;
+                known.set(offset, Some(0));
+            }
+            // A simple loop over a cell that is known not to be 0 (whatever the cell width)
+            // always runs its body, once
+            SimpleLoop(body, _) if known.get(0).is_some_and(|value| value as u8 != 0) => {
+                let (new_body, after, _) = fold_block(body, known);
+                new_instrs.extend(new_body);
+                known = after;
+                changed = true;
+            }
+            // The body runs at most once, so what is known before it still holds when it runs
+            SimpleLoop(body, span) => {
+                let (new_body, after, new_changed) = fold_block(body, known.clone());
+                if new_changed {
+                    changed = true;
+                }
+                new_instrs.push(SimpleLoop(new_body, span));
+                known = known.join(&after);
+                known.set(0, Some(0));
+            }
+            Move(0, _) => changed = true,
+            Move(stride, _) => {
+                known.ptr += stride;
+                new_instrs.push(instr);
+            }
+            Add(_, delta, span) => {
+                add(
+                    &mut new_instrs,
+                    &mut known,
+                    offset,
+                    delta,
+                    span,
+                    &mut changed,
+                );
+            }
+            Zero(_, span) => {
+                assign(&mut new_instrs, &mut known, offset, 0, span, &mut changed);
+            }
+            Set(_, value, span) => {
+                assign(
+                    &mut new_instrs,
+                    &mut known,
+                    offset,
+                    value,
+                    span,
+                    &mut changed,
+                );
+            }
+            // A known multiplicand makes a constant addition
+            AddDynamic(target, multiplier, span) => match known.get(0) {
+                Some(value) => {
+                    let delta = value.wrapping_mul(multiplier);
+                    add(
+                        &mut new_instrs,
+                        &mut known,
+                        target,
+                        delta,
+                        span,
+                        &mut changed,
+                    );
+                    changed = true;
+                }
+                None => {
+                    known.set(target, None);
+                    new_instrs.push(instr);
+                }
+            },
+            Read(_, _) => {
+                known.set(offset, None);
+                new_instrs.push(instr);
+            }
+            Scan(_, _) => {
+                known = KnownCells::current_zero();
+                new_instrs.push(instr);
+            }
+            Write(_, _) | Output(_, _) | CheckBounds(_, _) => new_instrs.push(instr),
+        }
+    }
+
+    (new_instrs, known, changed)
+}
+
+/// Apply known cells
+///
+/// Every cell is 0 at the start of the program, and the current cell is 0 after each loop.
+/// A balanced loop keeps what is known about the cells it never changes, in its body and after it.
+/// Loops over cells that are known to be 0 are removed, and simple loops over cells that are known
+/// not to be 0 are replaced with their bodies. So are assignments of the value a cell already
+/// holds, additions of 0, moves by 0, and changes to a cell that are overwritten straight away.
+/// An addition to a cell that was just set takes the place of the assignment, as a
+/// [`Set`](IntermediateInstruction::Set) of the sum.
+pub fn fold_known_cells(
+    instrs: Vec<IntermediateInstruction>,
+) -> (Vec<IntermediateInstruction>, bool) {
+    let (new_instrs, _, changed) = fold_block(instrs, KnownCells::zeroed());
+    (new_instrs, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instruction::IntermediateInstruction;
+    use crate::instruction::IntermediateInstruction::*;
+    use crate::optimizer::{fold_known_cells, optimize, OptimizerOptions};
+
+    fn fold(src: &[u8]) -> Vec<IntermediateInstruction> {
+        let instrs = IntermediateInstruction::parse_instrs(src).unwrap();
+        optimize(
+            instrs,
+            OptimizerOptions::new()
+                .coalesce(true)
+                .simple_loops(true)
+                .known_cells(true),
+        )
+    }
+
+    #[test]
+    fn test_known_cells_dead_loops() {
+        // Every cell starts at 0
+        assert!(matches!(fold(b"[.>]+.")[..], [Add(0, 1, _), Write(0, _)]));
+
+        // The current cell is 0 after a loop
+        assert!(matches!(
+            fold(b",[.,][.,]>")[..],
+            [Read(0, _), Loop(0, _, _), Move(1, _)]
+        ));
+
+        // A balanced loop never changes the cell after its own, so that cell is still 0
+        let instrs = fold(b",[>[.]<,.]>[.]");
+        let [Read(0, _), Loop(0, body, _), Move(1, _)] = &instrs[..] else {
+            panic!("expected a loop, got {:?}", instrs);
+        };
+        assert!(matches!(body[..], [Read(0, _), Write(0, _)]));
+
+        // ... while an unbalanced loop might
+        assert!(matches!(
+            fold(b",[>[.]<<,.]>[.]")[..],
+            [Read(0, _), Loop(0, _, _), Move(1, _), Loop(0, _, _)]
+        ));
+    }
+
+    #[test]
+    fn test_known_cells_ir() {
+        let before = [
+            "read",
+            "zero @+1",
+            "add @+1 2",
+            "set @+2 5",
+            "add @+2 1",
+            "loop {",
+            "    move +3",
+            "    loop {",
+            "        write",
+            "    }",
+            "    move -3",
+            "    read",
+            "}",
+            "",
+        ]
+        .join("\n");
+        let instrs = IntermediateInstruction::parse_ir(before.as_bytes()).unwrap();
+        let (instrs, _) = fold_known_cells(instrs);
+        assert_eq!(
+            IntermediateInstruction::print_ir(&instrs),
+            [
+                "read",
+                "add @+1 2",
+                "set @+2 6",
+                "loop { # balanced",
+                "    move +3",
+                "    move -3",
+                "    read",
+                "}",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_known_cells_assignments() {
+        assert!(matches!(fold(b",[-][-]")[..], [Read(0, _), Zero(0, _)]));
+        assert!(matches!(
+            fold(b",[-]+++.")[..],
+            [Read(0, _), Set(0, 3, _), Write(0, _)]
+        ));
+        assert!(matches!(
+            fold(b",+[-]-.")[..],
+            [Read(0, _), Set(0, -1, _), Write(0, _)]
+        ));
+        assert!(matches!(fold(b",++--.")[..], [Read(0, _), Write(0, _)]));
+        assert!(matches!(fold(b",><.")[..], [Read(0, _), Write(0, _)]));
+
+        // What is known before a simple loop still holds in its body
+        let instrs = fold(b",>[-]<[->[-]++<]");
+        let [Read(0, _), SimpleLoop(body, _)] = &instrs[..] else {
+            panic!("expected a simple loop, got {:?}", instrs);
+        };
+        assert!(matches!(
+            body[..],
+            [Move(1, _), Add(0, 2, _), Move(-1, _), Zero(0, _)]
+        ));
+    }
+
+    #[test]
+    fn test_known_cells_simple_loops() {
+        // A simple loop over a known, nonzero cell always runs, and multiplies by a known value
+        assert!(matches!(
+            fold(b"+++[->++<].")[..],
+            [Add(0, 3, _), Add(1, 6, _), Zero(0, _), Write(0, _)]
+        ));
+
+        // Whether a loop over 256 runs depends on the cell width
+        assert!(matches!(
+            fold(b",[-]++++++++++++++++[->++++++++++++++++<]>[-<+>]")[..],
+            [
+                Read(0, _),
+                Set(0, 16, _),
+                Add(1, 256, _),
+                Zero(0, _),
+                Move(1, _),
+                SimpleLoop(_, _)
+            ]
+        ));
+    }
+}
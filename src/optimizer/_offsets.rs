@@ -0,0 +1,302 @@
+//! Offset addressing: defers the moves in each run of instructions to the end of the run
+
+use crate::instruction::IntermediateInstruction;
+use crate::optimizer::{loop_balance, LoopBalance};
+use crate::Span;
+
+/// Returns the given instruction, acting on the cell at the given offset from its own cell
+///
+/// A loop tests the shifted cell, and every instruction in its body is shifted too.
+fn shift(instr: IntermediateInstruction, offset: i32) -> IntermediateInstruction {
+    use IntermediateInstruction::*;
+
+    let cell = instr.cell_offset() + offset;
+    match instr {
+        Loop(_, body, span) => Loop(
+            cell,
+            body.into_iter().map(|instr| shift(instr, offset)).collect(),
+            span,
+        ),
+        Add(_, delta, span) => Add(cell, delta, span),
+        Zero(_, span) => Zero(cell, span),
+        Set(_, value, span) => Set(cell, value, span),
+        Read(_, span) => Read(cell, span),
+        Write(_, span) => Write(cell, span),
+        CheckBounds(probes, span) => CheckBounds(
+            probes
+                .into_iter()
+                .map(|(probe, position)| (probe + offset, position))
+                .collect(),
+            span,
+        ),
+        _ => instr,
+    }
+}
+
+/// Returns whether every instruction in the given loop body can be shifted, which isn't the
+/// case for simple loops, multiplications, and scans, as they act on the current cell
+fn can_shift(instrs: &[IntermediateInstruction]) -> bool {
+    use IntermediateInstruction::*;
+
+    instrs.iter().all(|instr| match instr {
+        Loop(_, body, _) => can_shift(body),
+        SimpleLoop(_, _) | AddDynamic(_, _, _) | Scan(_, _) => false,
+        _ => true,
+    })
+}
+
+/// Merges two additions to the same cell into one, covering both spans
+fn merge_adds(
+    last: &IntermediateInstruction,
+    next: &IntermediateInstruction,
+) -> Option<IntermediateInstruction> {
+    use IntermediateInstruction::*;
+
+    match (last, next) {
+        (Add(offset, delta, span), Add(next_offset, next_delta, next_span))
+            if offset == next_offset =>
+        {
+            Some(Add(
+                *offset,
+                delta.wrapping_add(*next_delta),
+                span.merge(*next_span),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Ends a run of instructions, with a single move by the run's total offset (if any)
+///
+/// The move covers the spans of the moves it replaces
+fn end_run(
+    new_instrs: &mut Vec<IntermediateInstruction>,
+    offset: &mut i32,
+    moves: &mut usize,
+    span: &mut Option<Span>,
+    changed: &mut bool,
+) {
+    if let Some(span) = span.take() {
+        if *offset != 0 {
+            new_instrs.push(IntermediateInstruction::Move(*offset, span));
+        }
+        if *moves > 1 || *offset == 0 {
+            *changed = true;
+        }
+    }
+    *offset = 0;
+    *moves = 0;
+}
+
+/// Apply offset addressing
+///
+/// Within a run of instructions without scans or unbalanced loops, every cell is addressed by its
+/// offset from the data pointer at the start of the run, so the run only moves the data pointer
+/// once, at its end. Adjacent additions to the same cell are merged.
+/// Bounds checks are kept in place, with their probes shifted along with the cells.
+///
+/// A balanced loop stays in the run, testing the cell at its offset, with its body shifted along
+/// with it, unless the body has a simple loop, a multiplication, or a scan.
+pub fn apply_offsets(instrs: Vec<IntermediateInstruction>) -> (Vec<IntermediateInstruction>, bool) {
+    use IntermediateInstruction::*;
+
+    let mut new_instrs = vec![];
+    let mut changed = false;
+
+    // The pointer offset from the start of the current run, the number of moves in the run,
+    // and the span of those moves
+    let mut offset = 0;
+    let mut moves = 0;
+    let mut span: Option<Span> = None;
+
+    for instr in instrs {
+        match instr {
+            Move(stride, move_span) => {
+                offset += stride;
+                moves += 1;
+                span = Some(span.map_or(move_span, |span| span.merge(move_span)));
+            }
+            Add(_, _, _)
+            | Zero(_, _)
+            | Set(_, _, _)
+            | Read(_, _)
+            | Write(_, _)
+            | Output(_, _)
+            | CheckBounds(_, _) => {
+                if offset != 0 {
+                    changed = true;
+                }
+                let instr = shift(instr, offset);
+                if let Some(merged) = new_instrs.last().and_then(|last| merge_adds(last, &instr)) {
+                    new_instrs.pop();
+                    new_instrs.push(merged);
+                    changed = true;
+                } else {
+                    new_instrs.push(instr);
+                }
+            }
+            Loop(cell, sub_instrs, loop_span) => {
+                let (new_sub_instrs, new_changed) = apply_offsets(sub_instrs);
+                if new_changed {
+                    changed = true;
+                }
+                let in_run = loop_balance(&new_sub_instrs) == LoopBalance::Balanced
+                    && can_shift(&new_sub_instrs);
+                let new_loop = Loop(cell, new_sub_instrs, loop_span);
+                if in_run {
+                    if offset != 0 {
+                        changed = true;
+                    }
+                    new_instrs.push(shift(new_loop, offset));
+                } else {
+                    end_run(
+                        &mut new_instrs,
+                        &mut offset,
+                        &mut moves,
+                        &mut span,
+                        &mut changed,
+                    );
+                    new_instrs.push(new_loop);
+                }
+            }
+            SimpleLoop(sub_instrs, loop_span) => {
+                end_run(
+                    &mut new_instrs,
+                    &mut offset,
+                    &mut moves,
+                    &mut span,
+                    &mut changed,
+                );
+                let (new_sub_instrs, new_changed) = apply_offsets(sub_instrs);
+                if new_changed {
+                    changed = true;
+                }
+                new_instrs.push(SimpleLoop(new_sub_instrs, loop_span));
+            }
+            AddDynamic(_, _, _) | Scan(_, _) => {
+                end_run(
+                    &mut new_instrs,
+                    &mut offset,
+                    &mut moves,
+                    &mut span,
+                    &mut changed,
+                );
+                new_instrs.push(instr);
+            }
+        }
+    }
+    end_run(
+        &mut new_instrs,
+        &mut offset,
+        &mut moves,
+        &mut span,
+        &mut changed,
+    );
+
+    (new_instrs, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::apply_offsets;
+    use crate::instruction::IntermediateInstruction;
+    use crate::instruction::IntermediateInstruction::*;
+    use crate::optimizer::{coalesce, hoist_bounds_checks};
+    use crate::{SourceLocation, Span};
+
+    #[test]
+    fn test_apply_offsets() {
+        let src = b">+>-<.<,[>>[-]<+<]>";
+        let at = |offset: usize, len: usize| Span::new(SourceLocation::new(src, offset), len);
+        let (instrs, changed) = apply_offsets(IntermediateInstruction::parse_instrs(src).unwrap());
+        assert!(changed);
+        assert_eq!(
+            format!("{:?}", instrs),
+            format!(
+                "{:?}",
+                vec![
+                    Add(1, 1, at(1, 1)),
+                    Add(2, -1, at(3, 1)),
+                    Write(1, at(5, 1)),
+                    Read(0, at(7, 1)),
+                    Loop(
+                        0,
+                        vec![
+                            Loop(2, vec![Add(2, -1, at(12, 1))], at(11, 3)),
+                            Add(1, 1, at(15, 1)),
+                        ],
+                        at(8, 10)
+                    ),
+                    Move(1, at(0, 19)),
+                ]
+            )
+        );
+
+        // Applying offsets is idempotent
+        let (_, changed) = apply_offsets(instrs);
+        assert!(!changed);
+    }
+
+    #[test]
+    fn test_apply_offsets_balanced_loops() {
+        // A balanced loop tests and changes the cells at the run's offset,
+        // and the move is deferred past it
+        let (instrs, changed) =
+            apply_offsets(IntermediateInstruction::parse_instrs(b">>[-<+>]<.").unwrap());
+        assert!(changed);
+        let [Loop(2, body, _), Write(1, _), Move(1, _)] = &instrs[..] else {
+            panic!(
+                "expected a loop at +2, a write, and a move, got {:?}",
+                instrs
+            );
+        };
+        assert!(matches!(body[..], [Add(2, -1, _), Add(1, 1, _)]));
+        let (_, changed) = apply_offsets(instrs);
+        assert!(!changed);
+
+        // An unbalanced loop, or one with a multiplication, still ends the run
+        let (instrs, _) = apply_offsets(IntermediateInstruction::parse_instrs(b">[>]>").unwrap());
+        assert!(matches!(
+            instrs[..],
+            [Move(1, _), Loop(0, _, _), Move(1, _)]
+        ));
+        let span = Span::default();
+        let src = vec![
+            Move(1, span),
+            Loop(0, vec![AddDynamic(1, 2, span), Add(0, -1, span)], span),
+        ];
+        let (instrs, _) = apply_offsets(src);
+        assert!(matches!(instrs[..], [Move(1, _), Loop(0, _, _)]));
+    }
+
+    #[test]
+    fn test_apply_offsets_merges_adds() {
+        let (instrs, _) = apply_offsets(IntermediateInstruction::parse_instrs(b">+<>+").unwrap());
+        assert!(matches!(instrs[..], [Add(1, 2, _), Move(1, _)]));
+
+        let (instrs, _) = apply_offsets(IntermediateInstruction::parse_instrs(b">+<><-").unwrap());
+        assert!(matches!(instrs[..], [Add(1, 1, _), Add(0, -1, _)]));
+    }
+
+    #[test]
+    fn test_apply_offsets_checked() {
+        let src = IntermediateInstruction::parse_instrs_checked(b">+>+<<.").unwrap();
+        let (instrs, _) = hoist_bounds_checks(src);
+        let (instrs, _) = coalesce(instrs);
+        let (instrs, _) = apply_offsets(instrs);
+        assert_eq!(
+            format!(
+                "{:?}",
+                instrs
+                    .iter()
+                    .map(|instr| instr.cell_offset())
+                    .collect::<Vec<_>>()
+            ),
+            "[0, 1, 2, 0]"
+        );
+        let CheckBounds(probes, _) = &instrs[0] else {
+            panic!("expected a bounds check, got {:?}", instrs[0]);
+        };
+        assert_eq!(probes, &vec![(1, 0), (2, 2)]);
+    }
+}
@@ -1,9 +1,18 @@
 //! Options to control the optimizer
 
+use super::PassKind;
+use crate::CellWidth;
+
 /// Options to control the optimizer
 ///
 /// Uses the Builder Rust pattern
 pub struct OptimizerOptions {
+    /// The optimization level, which turns on the passes of [`PassKind::preset`]
+    pub opt_level: u8,
+
+    /// The passes to run, in order, instead of those of the optimization level
+    pub passes: Option<Vec<PassKind>>,
+
     /// Whether to coalesce adjacent matching instructions
     pub coalesce: bool,
 
@@ -12,6 +21,24 @@ pub struct OptimizerOptions {
 
     /// Whether to apply vector scans
     pub scans: bool,
+
+    /// Whether to hoist bounds checks to the start of each run of moves
+    pub hoist_checks: bool,
+
+    /// Whether to address cells by their offsets, deferring moves to the end of each run
+    pub offsets: bool,
+
+    /// Whether to track the values of cells, removing loops that never run and redundant changes
+    pub known_cells: bool,
+
+    /// Whether to run the start of the program ahead of time
+    pub partial_evaluation: bool,
+
+    /// The size of the memory tape that the program runs on
+    pub mem_size: usize,
+
+    /// The width of each memory cell that the program runs on
+    pub cell_width: CellWidth,
 }
 
 impl OptimizerOptions {
@@ -20,12 +47,32 @@ impl OptimizerOptions {
     /// All optimizations are off by default
     pub fn new() -> Self {
         Self {
+            opt_level: 0,
+            passes: None,
             coalesce: false,
             simple_loops: false,
             scans: false,
+            hoist_checks: false,
+            offsets: false,
+            known_cells: false,
+            partial_evaluation: false,
+            mem_size: 8192,
+            cell_width: CellWidth::Bits8,
         }
     }
 
+    /// Sets the `opt_level` field to the given value
+    pub fn opt_level(mut self, opt_level: u8) -> Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    /// Sets the `passes` field to the given value
+    pub fn passes(mut self, passes: Option<Vec<PassKind>>) -> Self {
+        self.passes = passes;
+        self
+    }
+
     /// Sets the `coalesce` field to the given value
     pub fn coalesce(mut self, coalesce: bool) -> Self {
         self.coalesce = coalesce;
@@ -43,4 +90,40 @@ impl OptimizerOptions {
         self.scans = scans;
         self
     }
+
+    /// Sets the `hoist_checks` field to the given value
+    pub fn hoist_checks(mut self, hoist_checks: bool) -> Self {
+        self.hoist_checks = hoist_checks;
+        self
+    }
+
+    /// Sets the `offsets` field to the given value
+    pub fn offsets(mut self, offsets: bool) -> Self {
+        self.offsets = offsets;
+        self
+    }
+
+    /// Sets the `known_cells` field to the given value
+    pub fn known_cells(mut self, known_cells: bool) -> Self {
+        self.known_cells = known_cells;
+        self
+    }
+
+    /// Sets the `partial_evaluation` field to the given value
+    pub fn partial_evaluation(mut self, partial_evaluation: bool) -> Self {
+        self.partial_evaluation = partial_evaluation;
+        self
+    }
+
+    /// Sets the `mem_size` field to the given value
+    pub fn mem_size(mut self, mem_size: usize) -> Self {
+        self.mem_size = mem_size;
+        self
+    }
+
+    /// Sets the `cell_width` field to the given value
+    pub fn cell_width(mut self, cell_width: CellWidth) -> Self {
+        self.cell_width = cell_width;
+        self
+    }
 }
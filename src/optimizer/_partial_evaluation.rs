@@ -0,0 +1,253 @@
+//! Partial evaluation: runs the start of the program ahead of time
+
+use crate::instruction::IntermediateInstruction;
+use crate::{CellWidth, Span};
+
+/// The most instructions (including loop iterations) that partial evaluation runs
+const BUDGET: usize = 1 << 20;
+
+/// Runs instructions on a tape that starts out all-zero, as the compiled program would
+///
+/// Every method that runs instructions returns `None` if the program can't be run any further
+/// ahead of time: it reads input, runs past the budget, or steps off the tape.
+struct Evaluator {
+    /// The memory tape
+    tape: Vec<u32>,
+
+    /// The memory pointer, as a cell index into `self.tape`
+    ptr: isize,
+
+    /// The largest value that a cell can hold
+    mask: u32,
+
+    /// The output written so far
+    output: Vec<u8>,
+
+    /// The number of instructions run so far
+    steps: usize,
+
+    /// The cells changed by the current top-level instruction, with their previous values
+    undo: Vec<(usize, u32)>,
+}
+
+impl Evaluator {
+    fn new(mem_size: usize, cell_width: CellWidth) -> Self {
+        Self {
+            tape: vec![0; mem_size],
+            ptr: (mem_size / 2) as isize,
+            mask: cell_width.mask(),
+            output: vec![],
+            steps: 0,
+            undo: vec![],
+        }
+    }
+
+    /// Returns the index of the cell at the given offset from the memory pointer,
+    /// if it is on the tape
+    fn index(&self, offset: i32) -> Option<usize> {
+        let index = self.ptr + offset as isize;
+        (index >= 0 && (index as usize) < self.tape.len()).then_some(index as usize)
+    }
+
+    /// Returns the value of the cell at the given offset from the memory pointer
+    fn get(&self, offset: i32) -> Option<u32> {
+        self.index(offset).map(|index| self.tape[index])
+    }
+
+    /// Sets the cell at the given offset from the memory pointer, wrapped to the cell width
+    fn set(&mut self, offset: i32, value: u32) -> Option<()> {
+        let index = self.index(offset)?;
+        self.undo.push((index, self.tape[index]));
+        self.tape[index] = value & self.mask;
+        Some(())
+    }
+
+    /// Counts one more instruction against the budget
+    fn step(&mut self) -> Option<()> {
+        self.steps += 1;
+        (self.steps <= BUDGET).then_some(())
+    }
+
+    fn run(&mut self, instrs: &[IntermediateInstruction]) -> Option<()> {
+        instrs.iter().try_for_each(|instr| self.run_instr(instr))
+    }
+
+    fn run_instr(&mut self, instr: &IntermediateInstruction) -> Option<()> {
+        use IntermediateInstruction::*;
+
+        self.step()?;
+        let offset = instr.cell_offset();
+        match instr {
+            Loop(_, body, _) => {
+                while self.get(offset)? != 0 {
+                    self.run(body)?;
+                    self.step()?;
+                }
+            }
+            SimpleLoop(body, _) => {
+                if self.get(0)? != 0 {
+                    self.run(body)?;
+                }
+            }
+            AddDynamic(target, multiplier, _) => {
+                let product = (*multiplier as u32).wrapping_mul(self.get(0)?);
+                self.set(*target, self.get(*target)?.wrapping_add(product))?;
+            }
+            Zero(_, _) => self.set(offset, 0)?,
+            Set(_, value, _) => self.set(offset, *value as u32)?,
+            Move(stride, _) => self.ptr += *stride as isize,
+            Add(_, delta, _) => self.set(offset, self.get(offset)?.wrapping_add(*delta as u32))?,
+            Read(_, _) => return None,
+            Write(_, _) => {
+                let value = self.get(offset)?;
+                self.output.push(value as u8);
+            }
+            Output(bytes, _) => self.output.extend(bytes),
+            Scan(stride, _) => {
+                while self.get(0)? != 0 {
+                    self.step()?;
+                    self.ptr += *stride as isize;
+                }
+            }
+            // A failing check is left for the compiled program to report
+            CheckBounds(probes, _) => {
+                for (offset, _) in probes {
+                    self.index(*offset)?;
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Undoes the changes made by the current top-level instruction,
+    /// given the output length and memory pointer from before it
+    fn roll_back(&mut self, output_len: usize, ptr: isize) {
+        while let Some((index, value)) = self.undo.pop() {
+            self.tape[index] = value;
+        }
+        self.output.truncate(output_len);
+        self.ptr = ptr;
+    }
+}
+
+/// Apply partial evaluation, for a program with the given memory size and cell width
+///
+/// Runs the program's top-level instructions ahead of time, up to the first one that reads input,
+/// runs past a budget of instructions, or steps off the tape. Those instructions are replaced
+/// with an [`Output`](IntermediateInstruction::Output) of everything they write, followed by
+/// additions that set up the cells they leave nonzero, and a move to where they leave the memory
+/// pointer. All of these take the span of the instructions that were run.
+pub fn partially_evaluate(
+    instrs: Vec<IntermediateInstruction>,
+    mem_size: usize,
+    cell_width: CellWidth,
+) -> (Vec<IntermediateInstruction>, bool) {
+    let mut evaluator = Evaluator::new(mem_size, cell_width);
+    let mut evaluated = 0;
+    let mut span: Option<Span> = None;
+
+    for instr in &instrs {
+        let (output_len, ptr) = (evaluator.output.len(), evaluator.ptr);
+        evaluator.undo.clear();
+        if evaluator.run_instr(instr).is_none() {
+            evaluator.roll_back(output_len, ptr);
+            break;
+        }
+        evaluated += 1;
+        span = Some(span.map_or(instr.span(), |span| span.merge(instr.span())));
+    }
+    let Some(span) = span else {
+        return (instrs, false);
+    };
+
+    let mut new_instrs = vec![];
+    if !evaluator.output.is_empty() {
+        new_instrs.push(IntermediateInstruction::Output(evaluator.output, span));
+    }
+    let start = (mem_size / 2) as isize;
+    for (index, value) in evaluator.tape.into_iter().enumerate() {
+        let offset = (index as isize - start) as i32;
+        match value {
+            0 => {}
+            _ => new_instrs.push(IntermediateInstruction::Add(offset, value as i32, span)),
+        }
+    }
+    if evaluator.ptr != start {
+        new_instrs.push(IntermediateInstruction::Move(
+            (evaluator.ptr - start) as i32,
+            span,
+        ));
+    }
+    new_instrs.extend(instrs.into_iter().skip(evaluated));
+
+    (new_instrs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::partially_evaluate;
+    use crate::instruction::IntermediateInstruction;
+    use crate::instruction::IntermediateInstruction::*;
+    use crate::CellWidth;
+
+    fn evaluate(src: &[u8], cell_width: CellWidth) -> Vec<IntermediateInstruction> {
+        let instrs = IntermediateInstruction::parse_instrs(src).unwrap();
+        partially_evaluate(instrs, 100, cell_width).0
+    }
+
+    #[test]
+    fn test_partially_evaluate_output() {
+        // Prints "Hi" with a loop
+        let instrs = evaluate(
+            b"++++++++[>+++++++++<-]>.>+++++++++++[<+++>-]<.",
+            CellWidth::Bits8,
+        );
+        let [Output(bytes, _), Add(1, 105, _), Move(1, _)] = &instrs[..] else {
+            panic!("expected a single output, got {:?}", instrs);
+        };
+        assert_eq!(bytes, b"Hi");
+    }
+
+    #[test]
+    fn test_partially_evaluate_stops_at_input() {
+        let instrs = evaluate(b"-->+.<[>,<-]>.", CellWidth::Bits16);
+        assert!(matches!(
+            instrs[..],
+            [
+                Output(_, _),
+                Add(0, 65534, _),
+                Add(1, 1, _),
+                Loop(0, _, _),
+                Move(1, _),
+                Write(0, _)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_partially_evaluate_stops_at_budget() {
+        // Never exits the loop
+        let instrs = evaluate(b"+.+[]-.", CellWidth::Bits8);
+        assert!(matches!(
+            instrs[..],
+            [
+                Output(_, _),
+                Add(0, 2, _),
+                Loop(0, _, _),
+                Add(0, -1, _),
+                Write(0, _)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_partially_evaluate_stops_off_tape() {
+        // Moving off the tape is fine, but changing a cell there isn't
+        let src = [b"+".as_slice(), &[b'<'; 60], b"+."].concat();
+        let instrs = evaluate(&src, CellWidth::Bits8);
+        assert!(matches!(
+            instrs[..],
+            [Add(0, 1, _), Move(-60, _), Add(0, 1, _), Write(0, _)]
+        ));
+    }
+}
@@ -0,0 +1,459 @@
+//! The pass manager: runs a pipeline of optimization passes, and records what each one does
+
+use super::{
+    apply_offsets, coalesce, fold_known_cells, hoist_bounds_checks, make_scans, make_simple_loops,
+    partially_evaluate, OptimizerOptions,
+};
+use crate::instruction::IntermediateInstruction;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// An optimization pass over a BF program
+pub trait Pass {
+    /// The pass's name, as reported in statistics
+    fn name(&self) -> &'static str;
+
+    /// Whether the pass runs together with the other repeating passes until none of them changes
+    /// the program, rather than once after them
+    fn repeats(&self) -> bool {
+        true
+    }
+
+    /// Applies the pass, returning the new instructions and whether any instruction changed
+    fn run(&self, instrs: Vec<IntermediateInstruction>) -> (Vec<IntermediateInstruction>, bool);
+}
+
+/// A pass that applies one of the optimizer's functions
+struct FnPass<F> {
+    name: &'static str,
+    repeats: bool,
+    apply: F,
+}
+
+impl<F> Pass for FnPass<F>
+where
+    F: Fn(Vec<IntermediateInstruction>) -> (Vec<IntermediateInstruction>, bool),
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn repeats(&self) -> bool {
+        self.repeats
+    }
+
+    fn run(&self, instrs: Vec<IntermediateInstruction>) -> (Vec<IntermediateInstruction>, bool) {
+        (self.apply)(instrs)
+    }
+}
+
+/// The optimizer's built-in passes, in the order that the optimization levels run them
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PassKind {
+    /// Merges the bounds checks in each run of moves into a single check
+    HoistChecks,
+    /// Coalesces adjacent matching instructions
+    Coalesce,
+    /// Flattens simple loops
+    SimpleLoops,
+    /// Vectorizes memory scans
+    Scans,
+    /// Tracks the values of cells, removing loops that never run and redundant changes
+    KnownCells,
+    /// Runs the program ahead of time until it first reads input (once, after the repeating passes)
+    PartialEvaluation,
+    /// Addresses cells by their offsets, deferring moves to the end of each run (once, after the
+    /// repeating passes)
+    Offsets,
+}
+
+impl PassKind {
+    /// Every pass, in order
+    pub const ALL: [PassKind; 7] = [
+        PassKind::HoistChecks,
+        PassKind::Coalesce,
+        PassKind::SimpleLoops,
+        PassKind::Scans,
+        PassKind::KnownCells,
+        PassKind::PartialEvaluation,
+        PassKind::Offsets,
+    ];
+
+    /// Returns the passes of the given optimization level
+    ///
+    /// Level 0 runs no passes, 1 coalesces instructions, 2 also flattens simple loops, vectorizes
+    /// scans and defers moves, and 3 (or higher) also tracks known cells and evaluates the start
+    /// of the program ahead of time
+    pub fn preset(level: u8) -> Vec<PassKind> {
+        use PassKind::*;
+
+        match level {
+            0 => vec![],
+            1 => vec![Coalesce],
+            2 => vec![Coalesce, SimpleLoops, Scans, Offsets],
+            _ => vec![
+                Coalesce,
+                SimpleLoops,
+                Scans,
+                KnownCells,
+                PartialEvaluation,
+                Offsets,
+            ],
+        }
+    }
+
+    /// Returns the name of this pass, as given to `--passes`
+    pub fn name(self) -> &'static str {
+        match self {
+            PassKind::HoistChecks => "hoist-checks",
+            PassKind::Coalesce => "coalesce",
+            PassKind::SimpleLoops => "simple-loops",
+            PassKind::Scans => "scans",
+            PassKind::KnownCells => "known-cells",
+            PassKind::PartialEvaluation => "partial-evaluation",
+            PassKind::Offsets => "offsets",
+        }
+    }
+
+    /// Returns whether the given options turn this pass on
+    fn enabled(self, opts: &OptimizerOptions) -> bool {
+        match self {
+            PassKind::HoistChecks => opts.hoist_checks,
+            PassKind::Coalesce => opts.coalesce,
+            PassKind::SimpleLoops => opts.simple_loops,
+            PassKind::Scans => opts.scans,
+            PassKind::KnownCells => opts.known_cells,
+            PassKind::PartialEvaluation => opts.partial_evaluation,
+            PassKind::Offsets => opts.offsets,
+        }
+    }
+
+    /// Returns the pass of this kind, for a program run with the given options
+    pub fn pass(self, opts: &OptimizerOptions) -> Box<dyn Pass> {
+        let name = self.name();
+        match self {
+            PassKind::HoistChecks => fn_pass(name, true, hoist_bounds_checks),
+            PassKind::Coalesce => fn_pass(name, true, coalesce),
+            PassKind::SimpleLoops => fn_pass(name, true, make_simple_loops),
+            PassKind::Scans => fn_pass(name, true, make_scans),
+            PassKind::KnownCells => fn_pass(name, true, fold_known_cells),
+            PassKind::PartialEvaluation => {
+                let (mem_size, cell_width) = (opts.mem_size, opts.cell_width);
+                fn_pass(name, false, move |instrs| {
+                    partially_evaluate(instrs, mem_size, cell_width)
+                })
+            }
+            // Cells at offsets hide the moves that the other passes match,
+            // so this runs once they are done
+            PassKind::Offsets => fn_pass(name, false, apply_offsets),
+        }
+    }
+}
+
+/// Boxes a pass that applies the given function
+fn fn_pass<F>(name: &'static str, repeats: bool, apply: F) -> Box<dyn Pass>
+where
+    F: Fn(Vec<IntermediateInstruction>) -> (Vec<IntermediateInstruction>, bool) + 'static,
+{
+    Box::new(FnPass {
+        name,
+        repeats,
+        apply,
+    })
+}
+
+/// What one pass did over a run of the pass manager
+#[derive(Clone, Debug)]
+pub struct PassStats {
+    /// The pass's name
+    pub name: &'static str,
+
+    /// The number of times the pass ran
+    pub runs: usize,
+
+    /// The number of times the pass changed the program
+    pub changes: usize,
+
+    /// The number of instructions the pass removed, net of those it added
+    pub removed: isize,
+
+    /// The number of instructions the pass replaced with others
+    pub rewritten: usize,
+
+    /// The total time the pass took
+    pub time: Duration,
+}
+
+/// What the passes did over a run of the pass manager
+#[derive(Clone, Debug)]
+pub struct OptimizerStats {
+    /// What each pass did, in the order of the pipeline
+    pub passes: Vec<PassStats>,
+
+    /// The number of times the repeating passes ran
+    pub iterations: usize,
+}
+
+impl Display for OptimizerStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<20} {:>6} {:>8} {:>8} {:>10} {:>12}",
+            "pass", "runs", "changes", "removed", "rewritten", "time"
+        )?;
+        for stats in &self.passes {
+            writeln!(
+                f,
+                "{:<20} {:>6} {:>8} {:>8} {:>10} {:>12}",
+                stats.name,
+                stats.runs,
+                stats.changes,
+                stats.removed,
+                stats.rewritten,
+                format!("{:.3?}", stats.time)
+            )?;
+        }
+        writeln!(
+            f,
+            "{} iteration(s) of the repeating passes",
+            self.iterations
+        )
+    }
+}
+
+/// Counts the instructions in the given program, by everything but the bodies of loops
+///
+/// Two programs' counts tell which instructions a pass removed, added, or left alone
+fn census(instrs: &[IntermediateInstruction], counts: &mut HashMap<String, isize>) {
+    for instr in instrs {
+        let key = match instr {
+            IntermediateInstruction::Loop(cell, body, span) => {
+                census(body, counts);
+                format!("Loop({}, {:?})", cell, span)
+            }
+            IntermediateInstruction::SimpleLoop(body, span) => {
+                census(body, counts);
+                format!("SimpleLoop({:?})", span)
+            }
+            _ => format!("{:?}", instr),
+        };
+        *counts.entry(key).or_default() += 1;
+    }
+}
+
+/// Runs a pipeline of optimization passes
+///
+/// The repeating passes run in order, over and over, until none of them changes the program
+/// or they have run the maximum number of times. Then the other passes run once each, in order.
+///
+/// Uses the Builder Rust pattern
+pub struct PassManager {
+    /// The passes, in order
+    passes: Vec<Box<dyn Pass>>,
+
+    /// The most times that the repeating passes run
+    max_iterations: usize,
+}
+
+impl PassManager {
+    /// Instantiates a new `PassManager` without any passes
+    pub fn new() -> Self {
+        Self {
+            passes: vec![],
+            max_iterations: 100,
+        }
+    }
+
+    /// Instantiates a new `PassManager` with the passes that the given options turn on
+    ///
+    /// These are the passes listed in the options (or else those of the optimization level),
+    /// followed by any other pass that the options turn on, in [`PassKind`]'s order
+    pub fn from_options(opts: &OptimizerOptions) -> Self {
+        let mut kinds = match &opts.passes {
+            Some(kinds) => kinds.clone(),
+            None => {
+                let preset = PassKind::preset(opts.opt_level);
+                PassKind::ALL
+                    .into_iter()
+                    .filter(|kind| preset.contains(kind) || kind.enabled(opts))
+                    .collect()
+            }
+        };
+        for kind in PassKind::ALL {
+            if kind.enabled(opts) && !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+
+        kinds
+            .into_iter()
+            .fold(Self::new(), |manager, kind| manager.pass(kind.pass(opts)))
+    }
+
+    /// Adds the given pass to the end of the pipeline
+    pub fn pass(mut self, pass: Box<dyn Pass>) -> Self {
+        self.passes.push(pass);
+        self
+    }
+
+    /// Sets the most times that the repeating passes run
+    pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Runs the given pass, recording what it did
+    fn run_pass(
+        pass: &dyn Pass,
+        instrs: Vec<IntermediateInstruction>,
+        stats: &mut PassStats,
+    ) -> (Vec<IntermediateInstruction>, bool) {
+        let mut before = HashMap::new();
+        census(&instrs, &mut before);
+
+        let start = Instant::now();
+        let (instrs, changed) = pass.run(instrs);
+        stats.time += start.elapsed();
+        stats.runs += 1;
+
+        if changed {
+            stats.changes += 1;
+            let mut counts = before;
+            let mut after = HashMap::new();
+            census(&instrs, &mut after);
+            for (key, count) in after {
+                *counts.entry(key).or_default() -= count;
+            }
+            let gone: isize = counts.values().filter(|count| **count > 0).sum();
+            let new: isize = -counts.values().filter(|count| **count < 0).sum::<isize>();
+            stats.removed += gone - new;
+            stats.rewritten += gone.min(new) as usize;
+        }
+        (instrs, changed)
+    }
+
+    /// Runs the pipeline over the given program
+    ///
+    /// Returns the optimized program, and what each pass did
+    pub fn run(
+        &self,
+        instrs: Vec<IntermediateInstruction>,
+    ) -> (Vec<IntermediateInstruction>, OptimizerStats) {
+        let mut instrs = instrs;
+        let mut stats = OptimizerStats {
+            passes: self
+                .passes
+                .iter()
+                .map(|pass| PassStats {
+                    name: pass.name(),
+                    runs: 0,
+                    changes: 0,
+                    removed: 0,
+                    rewritten: 0,
+                    time: Duration::ZERO,
+                })
+                .collect(),
+            iterations: 0,
+        };
+
+        let mut changed = true;
+        while changed && stats.iterations < self.max_iterations {
+            changed = false;
+            stats.iterations += 1;
+            for (pass, pass_stats) in self.passes.iter().zip(&mut stats.passes) {
+                if pass.repeats() {
+                    let (new_instrs, new_changed) = Self::run_pass(&**pass, instrs, pass_stats);
+                    instrs = new_instrs;
+                    if new_changed {
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        for (pass, pass_stats) in self.passes.iter().zip(&mut stats.passes) {
+            if !pass.repeats() {
+                instrs = Self::run_pass(&**pass, instrs, pass_stats).0;
+            }
+        }
+
+        (instrs, stats)
+    }
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PassKind, PassManager};
+    use crate::instruction::IntermediateInstruction;
+    use crate::instruction::IntermediateInstruction::*;
+    use crate::optimizer::OptimizerOptions;
+
+    #[test]
+    fn test_pass_manager_pipelines() {
+        let names = |opts: OptimizerOptions| {
+            PassManager::from_options(&opts)
+                .passes
+                .iter()
+                .map(|pass| pass.name())
+                .collect::<Vec<_>>()
+        };
+        assert!(names(OptimizerOptions::new()).is_empty());
+        assert_eq!(
+            names(OptimizerOptions::new().opt_level(2).hoist_checks(true)),
+            [
+                "hoist-checks",
+                "coalesce",
+                "simple-loops",
+                "scans",
+                "offsets"
+            ]
+        );
+
+        // Listed passes replace the optimization level, and keep their order
+        assert_eq!(
+            names(
+                OptimizerOptions::new()
+                    .opt_level(3)
+                    .passes(Some(vec![PassKind::Scans, PassKind::Coalesce]))
+                    .simple_loops(true)
+            ),
+            ["scans", "coalesce", "simple-loops"]
+        );
+    }
+
+    #[test]
+    fn test_pass_manager_stats() {
+        let instrs = IntermediateInstruction::parse_instrs(b"+++[-]>><").unwrap();
+        let (instrs, stats) =
+            PassManager::from_options(&OptimizerOptions::new().opt_level(2)).run(instrs);
+        assert!(matches!(instrs[..], [Add(0, 3, _), Zero(0, _), Move(1, _)]));
+
+        // Coalescing turns 3 adds and 3 moves into 1 add and 1 move, then finds nothing more
+        let coalesce = &stats.passes[0];
+        assert_eq!((coalesce.runs, coalesce.changes), (2, 1));
+        assert_eq!((coalesce.removed, coalesce.rewritten), (4, 2));
+
+        // The loop and its body become a `Zero`
+        let simple_loops = &stats.passes[1];
+        assert_eq!((simple_loops.removed, simple_loops.rewritten), (1, 1));
+        assert_eq!(stats.iterations, 2);
+    }
+
+    #[test]
+    fn test_pass_manager_max_iterations() {
+        let instrs = IntermediateInstruction::parse_instrs(b"+++[-]").unwrap();
+        let (instrs, stats) = PassManager::from_options(&OptimizerOptions::new().opt_level(2))
+            .max_iterations(1)
+            .run(instrs);
+        assert_eq!(stats.iterations, 1);
+        assert!(matches!(instrs[..], [Add(0, 3, _), Zero(0, _)]));
+    }
+}
@@ -1,11 +1,12 @@
 use crate::instruction::IntermediateInstruction;
+use crate::Span;
 
-/// Attempt to match a scan and apply it
-fn make_scan(instrs: &Vec<IntermediateInstruction>) -> Option<IntermediateInstruction> {
+/// Attempt to match a scan and apply it, with the span of the loop
+fn make_scan(instrs: &Vec<IntermediateInstruction>, span: Span) -> Option<IntermediateInstruction> {
     let mut stride = 0;
     for instr in instrs {
         match instr {
-            IntermediateInstruction::Move(shift) => stride += shift,
+            IntermediateInstruction::Move(shift, _) => stride += shift,
             _ => return None,
         }
     }
@@ -13,7 +14,7 @@ fn make_scan(instrs: &Vec<IntermediateInstruction>) -> Option<IntermediateInstru
     if !vec![-4, -2, -1, 1, 2, 4].contains(&stride) {
         return None;
     }
-    Some(IntermediateInstruction::Scan(stride))
+    Some(IntermediateInstruction::Scan(stride, span))
 }
 
 /// Find scans and apply them
@@ -23,18 +24,26 @@ pub fn make_scans(instrs: Vec<IntermediateInstruction>) -> (Vec<IntermediateInst
 
     for instr in instrs {
         match instr {
-            IntermediateInstruction::Loop(sub_instrs) => {
-                if let Some(next_instr) = make_scan(&sub_instrs) {
+            IntermediateInstruction::Loop(0, sub_instrs, span) => {
+                if let Some(next_instr) = make_scan(&sub_instrs, span) {
                     new_instrs.push(next_instr);
                     changed = true;
                 } else {
                     let (next_instrs, next_changed) = make_scans(sub_instrs);
-                    new_instrs.push(IntermediateInstruction::Loop(next_instrs));
+                    new_instrs.push(IntermediateInstruction::Loop(0, next_instrs, span));
                     if next_changed {
                         changed = true;
                     }
                 }
             }
+            // A loop that tests another cell can't be replaced, but its body can be
+            IntermediateInstruction::Loop(cell, sub_instrs, span) => {
+                let (next_instrs, next_changed) = make_scans(sub_instrs);
+                new_instrs.push(IntermediateInstruction::Loop(cell, next_instrs, span));
+                if next_changed {
+                    changed = true;
+                }
+            }
             _ => new_instrs.push(instr),
         }
     }
@@ -1,44 +1,97 @@
 //! Simple loops optimization: identifies and simplifies simple loops
 
 use crate::instruction::IntermediateInstruction;
-use std::collections::HashSet;
+use crate::Span;
 
-fn make_simple_loop(instrs: &Vec<IntermediateInstruction>) -> Option<IntermediateInstruction> {
-    let mut current_delta = 0;
+/// The net effect of one pass through a simple loop's body on a cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Effect {
+    /// Adds the given delta to the cell
+    Add(i32),
+    /// Sets the cell to the given value
+    Set(i32),
+}
+
+impl Effect {
+    /// Returns this effect followed by adding the given delta
+    fn add(self, delta: i32) -> Self {
+        match self {
+            Effect::Add(value) => Effect::Add(value.wrapping_add(delta)),
+            Effect::Set(value) => Effect::Set(value.wrapping_add(delta)),
+        }
+    }
+}
+
+/// Returns the multiplicative inverse of the given odd number, modulo 2^32
+///
+/// Since every cell width divides 32 bits, this is also the inverse modulo every cell's range
+fn inverse(value: i32) -> i32 {
+    // Each step of Newton's method doubles the number of correct low bits,
+    // and an odd number is its own inverse modulo 8
+    let mut inverse = value;
+    for _ in 0..4 {
+        inverse = inverse.wrapping_mul(2i32.wrapping_sub(value.wrapping_mul(inverse)));
+    }
+    inverse
+}
+
+/// Returns the effect on the cell at the given offset, covering the given span
+///
+/// A cell that hasn't been changed yet starts out with an empty addition
+fn effect_at(effects: &mut Vec<(i32, Effect, Span)>, offset: i32, span: Span) -> &mut Effect {
+    let index = match effects.iter().position(|(target, _, _)| *target == offset) {
+        Some(index) => {
+            effects[index].2 = effects[index].2.merge(span);
+            index
+        }
+        None => {
+            effects.push((offset, Effect::Add(0), span));
+            effects.len() - 1
+        }
+    };
+    &mut effects[index].1
+}
+
+/// Attempt to match a simple loop and apply it, with the span of the loop
+///
+/// A simple loop only adds to and clears cells, returning to the cell it started at.
+/// Either the loop's own cell changes by an odd step, so that the body runs a number of times
+/// proportional to that cell's value, or the body clears the loop's cell and only runs once.
+/// Each cell's instructions are folded into one addition or one assignment, which keeps the span
+/// of the instructions it came from, and the final `Zero` takes the span of the loop.
+fn make_simple_loop(
+    instrs: &Vec<IntermediateInstruction>,
+    span: Span,
+) -> Option<IntermediateInstruction> {
     let mut current_offset = 0;
 
-    let mut instructions = vec![];
+    // The effect of the body on each cell it changes, in the order those cells are first changed
+    let mut effects = vec![];
 
-    let mut zeroes = HashSet::new();
+    // The body only runs once, so every bounds check moves to its start
+    let mut probes = vec![];
 
     for instr in instrs {
         match instr {
-            IntermediateInstruction::Zero => {
-                if current_offset == 0 {
-                    return None;
-                }
-                zeroes.insert(current_offset);
-                instructions.extend(vec![
-                    IntermediateInstruction::Move(current_offset),
-                    IntermediateInstruction::Zero,
-                    IntermediateInstruction::Move(-current_offset),
-                ]);
+            IntermediateInstruction::Zero(offset, zero_span) => {
+                *effect_at(&mut effects, current_offset + offset, *zero_span) = Effect::Set(0);
             }
-            IntermediateInstruction::Move(stride) => {
+            IntermediateInstruction::Set(offset, value, set_span) => {
+                *effect_at(&mut effects, current_offset + offset, *set_span) = Effect::Set(*value);
+            }
+            IntermediateInstruction::Move(stride, _) => {
                 current_offset += stride;
             }
-            IntermediateInstruction::Add(delta) => {
-                if current_offset == 0 {
-                    current_delta += delta;
-                } else {
-                    if zeroes.contains(&current_offset) {
-                        return None;
-                    }
-                    instructions.push(IntermediateInstruction::AddDynamic(current_offset, *delta));
-                }
+            IntermediateInstruction::Add(offset, delta, add_span) => {
+                let effect = effect_at(&mut effects, current_offset + offset, *add_span);
+                *effect = effect.add(*delta);
             }
-            IntermediateInstruction::AddDynamic(_target, _delta) => {
-                return None;
+            IntermediateInstruction::CheckBounds(check_probes, _) => {
+                probes.extend(
+                    check_probes
+                        .iter()
+                        .map(|(offset, position)| (current_offset + offset, *position)),
+                );
             }
             _ => return None,
         }
@@ -46,26 +99,54 @@ fn make_simple_loop(instrs: &Vec<IntermediateInstruction>) -> Option<Intermediat
     if current_offset != 0 {
         return None;
     }
-    let sign = match current_delta {
-        -1 => 1,
-        1 => -1,
+
+    // How much each addition is multiplied by the loop's cell, or `None` if the body only runs once
+    let multiplier = match effects.iter().find(|(target, _, _)| *target == 0) {
+        Some((_, Effect::Add(delta), _)) if delta % 2 != 0 => Some(inverse(*delta).wrapping_neg()),
+        Some((_, Effect::Set(0), _)) => None,
+        // Any other loop either never exits or might not
         _ => return None,
     };
-    instructions.push(IntermediateInstruction::Zero);
-
-    Some(IntermediateInstruction::SimpleLoop(
-        instructions
-            .into_iter()
-            .map(|instr| {
-                match instr {
-                    IntermediateInstruction::AddDynamic(target, multiplier) => {
-                        IntermediateInstruction::AddDynamic(target, sign * multiplier)
-                    }
-                    _ => instr,
+
+    let mut instructions = vec![];
+    if !probes.is_empty() {
+        instructions.push(IntermediateInstruction::CheckBounds(probes, span));
+    }
+    for (target, effect, effect_span) in effects {
+        match (effect, multiplier) {
+            _ if target == 0 => {}
+            (Effect::Add(0), _) => {}
+            (Effect::Add(delta), Some(multiplier)) => {
+                instructions.push(IntermediateInstruction::AddDynamic(
+                    target,
+                    delta.wrapping_mul(multiplier),
+                    effect_span,
+                ))
+            }
+            (Effect::Add(delta), None) => instructions.extend(vec![
+                IntermediateInstruction::Move(target, effect_span),
+                IntermediateInstruction::Add(0, delta, effect_span),
+                IntermediateInstruction::Move(-target, effect_span),
+            ]),
+            (Effect::Set(value), _) => {
+                instructions.extend(vec![
+                    IntermediateInstruction::Move(target, effect_span),
+                    IntermediateInstruction::Zero(0, effect_span),
+                ]);
+                if value != 0 {
+                    instructions.push(IntermediateInstruction::Add(0, value, effect_span));
                 }
-            })
-            .collect::<Vec<IntermediateInstruction>>(),
-    ))
+                instructions.push(IntermediateInstruction::Move(-target, effect_span));
+            }
+        }
+    }
+    instructions.push(IntermediateInstruction::Zero(0, span));
+
+    // A loop that only clears its cell doesn't need to check that cell first
+    if let [zero] = &instructions[..] {
+        return Some(zero.clone());
+    }
+    Some(IntermediateInstruction::SimpleLoop(instructions, span))
 }
 
 /// Apply simple loops
@@ -77,21 +158,116 @@ pub fn make_simple_loops(
 
     for instr in instrs {
         match instr {
-            IntermediateInstruction::Loop(sub_instrs) => {
-                if let Some(next_instr) = make_simple_loop(&sub_instrs) {
+            IntermediateInstruction::Loop(0, sub_instrs, span) => {
+                if let Some(next_instr) = make_simple_loop(&sub_instrs, span) {
                     new_instrs.push(next_instr);
                     changed = true;
                 } else {
                     let (next_instrs, next_changed) = make_simple_loops(sub_instrs);
-                    new_instrs.push(IntermediateInstruction::Loop(next_instrs));
+                    new_instrs.push(IntermediateInstruction::Loop(0, next_instrs, span));
                     if next_changed {
                         changed = true;
                     }
                 }
             }
+            // A loop that tests another cell can't be replaced, but its body can be
+            IntermediateInstruction::Loop(cell, sub_instrs, span) => {
+                let (next_instrs, next_changed) = make_simple_loops(sub_instrs);
+                new_instrs.push(IntermediateInstruction::Loop(cell, next_instrs, span));
+                if next_changed {
+                    changed = true;
+                }
+            }
             _ => new_instrs.push(instr),
         }
     }
 
     (new_instrs, changed)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{inverse, make_simple_loops};
+    use crate::instruction::IntermediateInstruction;
+    use crate::instruction::IntermediateInstruction::*;
+    use crate::optimizer::coalesce;
+
+    fn simplify(src: &[u8]) -> Vec<IntermediateInstruction> {
+        let (instrs, _) = coalesce(IntermediateInstruction::parse_instrs(src).unwrap());
+        let (instrs, _) = make_simple_loops(instrs);
+        let (instrs, _) = make_simple_loops(instrs);
+        instrs
+    }
+
+    #[test]
+    fn test_inverse() {
+        for value in (-1001i32..1001).step_by(2) {
+            assert_eq!(value.wrapping_mul(inverse(value)), 1);
+        }
+        assert_eq!(inverse(3) as u8, 171);
+    }
+
+    #[test]
+    fn test_simple_loops_odd_steps() {
+        let instrs = simplify(b"[>+<--->>--<<]");
+        let [SimpleLoop(body, _)] = &instrs[..] else {
+            panic!("expected a simple loop, got {:?}", instrs);
+        };
+        let [AddDynamic(1, first, _), AddDynamic(2, second, _), Zero(0, _)] = body[..] else {
+            panic!("expected two multiplications, got {:?}", body);
+        };
+        // Each run through the body subtracts 3, so it runs `*p * inverse(3)` times
+        assert_eq!(first, inverse(3));
+        assert_eq!(second, inverse(3).wrapping_mul(-2));
+
+        // Even steps might never reach zero
+        assert!(matches!(simplify(b"[>+<--]")[..], [Loop(0, _, _)]));
+    }
+
+    #[test]
+    fn test_simple_loops_repeated_targets() {
+        let instrs = simplify(b"[->+>+<+<]");
+        let [SimpleLoop(body, _)] = &instrs[..] else {
+            panic!("expected a simple loop, got {:?}", instrs);
+        };
+        assert!(matches!(
+            body[..],
+            [AddDynamic(1, 2, _), AddDynamic(2, 1, _), Zero(0, _)]
+        ));
+    }
+
+    #[test]
+    fn test_simple_loops_sets() {
+        let instrs = simplify(b"[->+>[-]+++<<]");
+        let [SimpleLoop(body, _)] = &instrs[..] else {
+            panic!("expected a simple loop, got {:?}", instrs);
+        };
+        assert!(matches!(
+            body[..],
+            [
+                AddDynamic(1, 1, _),
+                Move(2, _),
+                Zero(0, _),
+                Add(0, 3, _),
+                Move(-2, _),
+                Zero(0, _),
+            ]
+        ));
+
+        // A loop that clears its own cell only runs once
+        let instrs = simplify(b"[>+<[-]]");
+        let [SimpleLoop(body, _)] = &instrs[..] else {
+            panic!("expected a simple loop, got {:?}", instrs);
+        };
+        assert!(matches!(
+            body[..],
+            [Move(1, _), Add(0, 1, _), Move(-1, _), Zero(0, _)]
+        ));
+
+        // ... unless it sets its cell to something else
+        assert!(matches!(simplify(b"[>+<[-]+]")[..], [Loop(0, _, _)]));
+
+        // A loop that only clears its cell is a plain `Zero`
+        assert!(matches!(simplify(b"[-]")[..], [Zero(0, _)]));
+    }
+}
@@ -1,9 +1,21 @@
 //! BF program optimization passes
 
+mod _balanced_loops;
+pub use _balanced_loops::*;
+mod _bounds_checks;
+pub use _bounds_checks::*;
 mod _coalesce;
 pub use _coalesce::*;
+mod _known_cells;
+pub use _known_cells::*;
+mod _offsets;
+pub use _offsets::*;
 mod _options;
 pub use _options::*;
+mod _pass_manager;
+pub use _pass_manager::*;
+mod _partial_evaluation;
+pub use _partial_evaluation::*;
 mod _scans;
 pub use _scans::*;
 mod _simple_loops;
@@ -16,35 +28,5 @@ pub fn optimize(
     instrs: Vec<IntermediateInstruction>,
     opts: OptimizerOptions,
 ) -> Vec<IntermediateInstruction> {
-    let mut instrs = instrs;
-    let mut changed = true;
-
-    let optimizers = {
-        let mut optimizers: Vec<
-            fn(Vec<IntermediateInstruction>) -> (Vec<IntermediateInstruction>, bool),
-        > = vec![];
-        if opts.coalesce {
-            optimizers.push(coalesce);
-        }
-        if opts.simple_loops {
-            optimizers.push(make_simple_loops);
-        }
-        if opts.scans {
-            optimizers.push(make_scans);
-        }
-        optimizers
-    };
-
-    while changed {
-        changed = false;
-        for optimizer in &optimizers {
-            let (new_instrs, new_changed) = optimizer(instrs);
-            instrs = new_instrs;
-            if new_changed {
-                changed = true;
-            }
-        }
-    }
-
-    instrs
+    PassManager::from_options(&opts).run(instrs).0
 }